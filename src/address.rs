@@ -0,0 +1,348 @@
+use crate::{bech32, error::WalletError, hash, interpreter, opcodes, script::Builder};
+use serde::Deserialize;
+use std::fmt::Display;
+use std::str::FromStr;
+
+/// Bitcoin network an address is valid on
+#[derive(Debug, Clone, Copy, PartialEq, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Network {
+    #[default]
+    Bitcoin,
+    Testnet,
+}
+
+impl Network {
+    /// Human-readable part of segwit addresses
+    pub fn hrp(self) -> &'static str {
+        match self {
+            Network::Bitcoin => "bc",
+            Network::Testnet => "tb",
+        }
+    }
+
+    /// Base58 version byte of P2PKH addresses
+    pub fn p2pkh_prefix(self) -> u8 {
+        match self {
+            Network::Bitcoin => 0x00,
+            Network::Testnet => 0x6f,
+        }
+    }
+
+    /// Base58 version byte of P2SH addresses
+    pub fn p2sh_prefix(self) -> u8 {
+        match self {
+            Network::Bitcoin => 0x05,
+            Network::Testnet => 0xc4,
+        }
+    }
+
+    /// Base58 version byte of WIF private keys
+    pub fn wif_prefix(self) -> u8 {
+        match self {
+            Network::Bitcoin => 0x80,
+            Network::Testnet => 0xef,
+        }
+    }
+}
+
+/// How a script is wrapped into an output
+#[derive(Debug, Clone, Copy, PartialEq, Default, Deserialize)]
+pub enum ScriptType {
+    #[serde(rename = "p2sh")]
+    P2sh,
+    #[default]
+    #[serde(rename = "p2wsh")]
+    P2wsh,
+    #[serde(rename = "p2sh-p2wsh")]
+    P2shP2wsh,
+}
+
+/// What an address pays to
+#[derive(Debug, Clone, PartialEq)]
+pub enum Payload {
+    PubkeyHash([u8; 20]),
+    ScriptHash([u8; 20]),
+    WitnessProgram { version: u8, program: Vec<u8> },
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Address {
+    pub network: Network,
+    pub payload: Payload,
+}
+
+impl Address {
+    /// Pay-to-public-key-hash, prefix with "1"
+    pub fn p2pkh(public_key: &[u8], network: Network) -> Self {
+        Self {
+            network,
+            payload: Payload::PubkeyHash(hash::hash160(public_key)),
+        }
+    }
+
+    /// Pay-to-script-hash of `redeem_script`, prefix with "3"
+    pub fn p2sh(redeem_script: &[u8], network: Network) -> Self {
+        Self {
+            network,
+            payload: Payload::ScriptHash(hash::hash160(redeem_script)),
+        }
+    }
+
+    /// Pay-to-witness-public-key-hash, prefix with "bc1q"
+    pub fn p2wpkh(public_key: &[u8], network: Network) -> Self {
+        Self {
+            network,
+            payload: Payload::WitnessProgram {
+                version: 0,
+                program: hash::hash160(public_key).to_vec(),
+            },
+        }
+    }
+
+    /// Pay-to-witness-script-hash of `witness_script`, prefix with "bc1q"
+    pub fn p2wsh(witness_script: &[u8], network: Network) -> Self {
+        Self {
+            network,
+            payload: Payload::WitnessProgram {
+                version: 0,
+                program: hash::sha256(witness_script).to_vec(),
+            },
+        }
+    }
+
+    /// Pay-to-taproot of an already tweaked x-only `output_key`, prefix with "bc1p"
+    pub fn p2tr_tweaked(output_key: [u8; 32], network: Network) -> Self {
+        Self {
+            network,
+            payload: Payload::WitnessProgram {
+                version: 1,
+                program: output_key.to_vec(),
+            },
+        }
+    }
+
+    /// P2WPKH nested in P2SH, for senders that cannot pay to bech32 addresses
+    pub fn p2shwpkh(public_key: &[u8], network: Network) -> Self {
+        Self::p2sh(&Self::p2wpkh(public_key, network).script_pubkey(), network)
+    }
+
+    /// P2WSH nested in P2SH, for senders that cannot pay to bech32 addresses
+    pub fn p2shwsh(witness_script: &[u8], network: Network) -> Self {
+        Self::p2sh(
+            &Self::p2wsh(witness_script, network).script_pubkey(),
+            network,
+        )
+    }
+
+    /// Wrap `script` as requested by `script_type`
+    pub fn from_script(script: &[u8], script_type: ScriptType, network: Network) -> Self {
+        match script_type {
+            ScriptType::P2sh => Self::p2sh(script, network),
+            ScriptType::P2wsh => Self::p2wsh(script, network),
+            ScriptType::P2shP2wsh => Self::p2shwsh(script, network),
+        }
+    }
+
+    /// Recognise the standard output templates
+    pub fn from_script_pubkey(script_pubkey: &[u8], network: Network) -> Option<Self> {
+        let payload = if script_pubkey.len() == 25
+            && script_pubkey[..3]
+                == [
+                    opcodes::OP_DUP,
+                    opcodes::OP_HASH160,
+                    opcodes::OP_PUSHBYTES_20,
+                ]
+            && script_pubkey[23..] == [opcodes::OP_EQUALVERIFY, opcodes::OP_CHECKSIG]
+        {
+            let mut hash = [0u8; 20];
+            hash.copy_from_slice(&script_pubkey[3..23]);
+            Payload::PubkeyHash(hash)
+        } else if interpreter::is_p2sh(script_pubkey) {
+            let mut hash = [0u8; 20];
+            hash.copy_from_slice(&script_pubkey[2..22]);
+            Payload::ScriptHash(hash)
+        } else if let Some((version, program)) = interpreter::witness_program(script_pubkey) {
+            Payload::WitnessProgram {
+                version,
+                program: program.to_vec(),
+            }
+        } else {
+            return None;
+        };
+        Some(Self { network, payload })
+    }
+
+    /// The output script paying to this address
+    pub fn script_pubkey(&self) -> Vec<u8> {
+        match &self.payload {
+            Payload::PubkeyHash(hash) => Builder::new()
+                .push_opcode(opcodes::OP_DUP)
+                .push_opcode(opcodes::OP_HASH160)
+                .push_slice(hash)
+                .push_opcode(opcodes::OP_EQUALVERIFY)
+                .push_opcode(opcodes::OP_CHECKSIG)
+                .into_script(),
+            Payload::ScriptHash(hash) => Builder::new()
+                .push_opcode(opcodes::OP_HASH160)
+                .push_slice(hash)
+                .push_opcode(opcodes::OP_EQUAL)
+                .into_script(),
+            Payload::WitnessProgram { version, program } => Builder::new()
+                .push_int(*version as i64)
+                .push_slice(program)
+                .into_script(),
+        }
+    }
+}
+
+/// Base58Check encode `payload` behind a `version` byte
+pub fn base58check_encode(version: u8, payload: &[u8]) -> String {
+    let mut data = vec![version];
+    data.extend(payload);
+    let checksum = hash::sha256d(&data);
+    data.extend(&checksum[..4]);
+    bs58::encode(data).into_string()
+}
+
+/// Decode a Base58Check string into its version byte and payload
+pub fn base58check_decode(encoded: &str) -> Option<(u8, Vec<u8>)> {
+    let data = bs58::decode(encoded).into_vec().ok()?;
+    if data.len() < 5 {
+        return None;
+    }
+    let (data, checksum) = data.split_at(data.len() - 4);
+    if hash::sha256d(data)[..4] != checksum[..] {
+        return None;
+    }
+    Some((data[0], data[1..].to_vec()))
+}
+
+impl FromStr for Address {
+    type Err = WalletError;
+
+    /// Parse a mainnet or testnet address
+    fn from_str(address: &str) -> Result<Self, Self::Err> {
+        for network in [Network::Bitcoin, Network::Testnet].iter() {
+            if let Some((version, program)) = bech32::decode_segwit_address(network.hrp(), address)
+            {
+                return Ok(Self {
+                    network: *network,
+                    payload: Payload::WitnessProgram { version, program },
+                });
+            }
+        }
+        let (version, payload) = base58check_decode(address).ok_or(WalletError::InvalidAddress)?;
+        let mut hash = [0u8; 20];
+        if payload.len() != hash.len() {
+            return Err(WalletError::InvalidAddress);
+        }
+        hash.copy_from_slice(&payload);
+        for network in [Network::Bitcoin, Network::Testnet].iter() {
+            if version == network.p2pkh_prefix() {
+                return Ok(Self {
+                    network: *network,
+                    payload: Payload::PubkeyHash(hash),
+                });
+            }
+            if version == network.p2sh_prefix() {
+                return Ok(Self {
+                    network: *network,
+                    payload: Payload::ScriptHash(hash),
+                });
+            }
+        }
+        Err(WalletError::InvalidAddress)
+    }
+}
+
+impl Display for Address {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match &self.payload {
+            Payload::PubkeyHash(hash) => write!(
+                f,
+                "{}",
+                base58check_encode(self.network.p2pkh_prefix(), hash)
+            ),
+            Payload::ScriptHash(hash) => write!(
+                f,
+                "{}",
+                base58check_encode(self.network.p2sh_prefix(), hash)
+            ),
+            Payload::WitnessProgram { version, program } => write!(
+                f,
+                "{}",
+                bech32::encode_segwit_address(self.network.hrp(), *version, program)
+            ),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PUBLIC_KEY: &str = "0279be667ef9dcbbac55a06295ce870b07029bfcdb2dce28d959f2815b16f81798";
+
+    #[test]
+    fn test_key_addresses() {
+        let public_key = hex::decode(PUBLIC_KEY).unwrap();
+        assert_eq!(
+            Address::p2pkh(&public_key, Network::Bitcoin).to_string(),
+            "1BgGZ9tcN4rm9KBzDn7KprQz87SZ26SAMH"
+        );
+        assert_eq!(
+            Address::p2wpkh(&public_key, Network::Bitcoin).to_string(),
+            "bc1qw508d6qejxtdg4y5r3zarvary0c5xw7kv8f3t4"
+        );
+        assert_eq!(
+            Address::p2wpkh(&public_key, Network::Testnet).to_string(),
+            "tb1qw508d6qejxtdg4y5r3zarvary0c5xw7kxpjzsx"
+        );
+    }
+
+    #[test]
+    fn test_p2wsh_address() {
+        // BIP173: <pubkey> OP_CHECKSIG
+        let witness_script = Builder::new()
+            .push_slice(&hex::decode(PUBLIC_KEY).unwrap())
+            .push_opcode(opcodes::OP_CHECKSIG)
+            .into_script();
+        assert_eq!(
+            Address::p2wsh(&witness_script, Network::Bitcoin).to_string(),
+            "bc1qrp33g0q5c5txsp9arysrx4k6zdkfs4nce4xj0gdcccefvpysxf3qccfmv3"
+        );
+        assert_eq!(
+            Address::p2wsh(&witness_script, Network::Testnet).to_string(),
+            "tb1qrp33g0q5c5txsp9arysrx4k6zdkfs4nce4xj0gdcccefvpysxf3q0sl5k7"
+        );
+    }
+
+    #[test]
+    fn test_from_script_pubkey() {
+        let address = Address::p2shwpkh(&hex::decode(PUBLIC_KEY).unwrap(), Network::Bitcoin);
+        assert_eq!(
+            Address::from_script_pubkey(&address.script_pubkey(), Network::Bitcoin),
+            Some(address)
+        );
+        assert_eq!(
+            Address::from_script_pubkey(&[opcodes::OP_RETURN], Network::Bitcoin),
+            None
+        );
+    }
+
+    #[test]
+    fn test_from_str() {
+        for address in [
+            "1BgGZ9tcN4rm9KBzDn7KprQz87SZ26SAMH",
+            "3Bzxiixsr6ZKyJk9H5MLc52R7LZw3uzBuy",
+            "tb1qw508d6qejxtdg4y5r3zarvary0c5xw7kxpjzsx",
+            "bc1p0xlxvlhemja6c4dqv22uapctqupfhlxm9h8z3k2e72q4k9hcz7vqzk5jj0",
+        ]
+        .iter()
+        {
+            assert_eq!(Address::from_str(address).unwrap().to_string(), *address);
+        }
+        assert!(Address::from_str("1BgGZ9tcN4rm9KBzDn7KprQz87SZ26SAMh").is_err());
+    }
+}
@@ -11,6 +11,7 @@ use serde::{Deserialize, Serialize};
 // use jsonwebtoken::{encode, decode, Header, Algorithm, Validation, EncodingKey, DecodingKey};
 
 /// Our claims struct, it needs to derive `Serialize` and/or `Deserialize`
+#[allow(dead_code)]
 #[derive(Debug, Serialize, Deserialize)]
 struct Claims {
     sub: String,
@@ -19,14 +20,11 @@ struct Claims {
 }
 
 pub async fn validator(req: ServiceRequest, credentials: BearerAuth) -> Result<ServiceRequest> {
-    let config = req
-        .app_data::<Config>()
-        .map(|data| data.clone())
-        .unwrap_or_else(Default::default);
+    let config = req.app_data::<Config>().cloned().unwrap_or_default();
     log::debug!("Received token: {:?}", &credentials.token());
     match validate_token(credentials.token()).await {
         Ok(res) => {
-            if res == true {
+            if res {
                 Ok(req)
             } else {
                 Err(AuthenticationError::from(config).into())
@@ -47,7 +45,7 @@ pub async fn validate_token(token: &str) -> Result<bool, ServiceError> {
     .await
     .expect("failed to fetch jwks");
     let validations = vec![Validation::Issuer(authority), Validation::SubjectPresent];
-    let kid = match token_kid(&token) {
+    let kid = match token_kid(token) {
         Ok(res) => res.expect("failed to decode kid"),
         Err(_) => return Err(ServiceError::JWKSFetchError),
     };
@@ -59,13 +57,13 @@ pub async fn validate_token(token: &str) -> Result<bool, ServiceError> {
 async fn fetch_jwks(uri: &str) -> Result<JWKS, Box<dyn std::error::Error>> {
     let res = reqwest::get(uri).await?;
     let val = res.json::<JWKS>().await?;
-    return Ok(val);
+    Ok(val)
 }
 
 #[actix_rt::test]
 async fn test_fetch_jwks() {
-    let authority =
-        std::env::var("AUTHORITY").expect(|_| "https://dev-babm2h9u.us.auth0.com/".to_string());
+    let authority = std::env::var("AUTHORITY")
+        .unwrap_or_else(|_| "https://dev-babm2h9u.us.auth0.com/".to_string());
     let jwks = fetch_jwks(&format!(
         "{}{}",
         authority.as_str(),
@@ -79,5 +77,5 @@ async fn test_fetch_jwks() {
 
 #[actix_rt::test]
 async fn test_validate_token() {
-    assert_eq!(validate_token("xxxxx").await.unwrap(), true)
+    assert!(validate_token("xxxxx").await.unwrap())
 }
@@ -0,0 +1,417 @@
+use crate::{
+    address::{Address, Network},
+    bip32::{ChildNumber, ExtendedPubKey, HARDENED_OFFSET},
+    chain::{ChainBackend, Utxo},
+    coinselect::parse_address,
+    error::BalanceError,
+    estimator::AddressType,
+    taproot,
+    transaction::sum_amounts,
+};
+use serde::{Deserialize, Serialize};
+use std::str::FromStr;
+
+/// Unused addresses in a row after which a chain of an xpub is assumed to end, as in BIP44
+pub const DEFAULT_GAP_LIMIT: u32 = 20;
+/// Largest gap limit accepted, bounding the addresses looked up per request
+pub const MAX_GAP_LIMIT: u32 = 1000;
+/// Chains of an account: receive addresses, then change addresses
+const CHAINS: [u32; 2] = [0, 1];
+
+#[derive(Deserialize, Debug)]
+// Get the address type, gap limit and network of the addresses of an xpub from user
+pub struct XpubQuery {
+    #[serde(default = "default_address_type")]
+    pub address_type: AddressType,
+    #[serde(default = "default_gap_limit")]
+    pub gap_limit: u32,
+    #[serde(default)]
+    pub network: Network,
+}
+
+fn default_address_type() -> AddressType {
+    AddressType::P2wpkh
+}
+
+fn default_gap_limit() -> u32 {
+    DEFAULT_GAP_LIMIT
+}
+
+/// Satoshis held, split by whether they are confirmed yet
+#[derive(Serialize, Debug, Clone, Copy, Default, PartialEq)]
+pub struct Balance {
+    pub confirmed: u64,
+    pub unconfirmed: u64,
+}
+
+#[derive(Serialize, Debug, PartialEq)]
+/// UtxoResponse is an unspent output of an address presented to user
+pub struct UtxoResponse {
+    pub txid: String,
+    pub vout: u32,
+    pub amount: u64,
+    pub address: String,
+    /// Derivation path below the xpub
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub path: Option<String>,
+    /// Height of the block confirming it, null while it is in the mempool
+    pub height: Option<u32>,
+}
+
+#[derive(Serialize, Debug)]
+/// XpubBalance is the balance and the UTXOs of every used address of an xpub presented to user
+pub struct XpubBalance {
+    #[serde(flatten)]
+    pub balance: Balance,
+    /// Index of the first receive address after the last used one
+    pub next_receive_index: u32,
+    /// Index of the first change address after the last used one
+    pub next_change_index: u32,
+    pub utxos: Vec<UtxoResponse>,
+}
+
+impl Balance {
+    pub fn new(utxos: &[Utxo]) -> Result<Self, BalanceError> {
+        let sum = |confirmed: bool| {
+            sum_amounts(
+                utxos
+                    .iter()
+                    .filter(|utxo| utxo.height.is_some() == confirmed)
+                    .map(|utxo| utxo.value),
+            )
+            .ok_or(BalanceError::AmountOutOfRange)
+        };
+        Ok(Self {
+            confirmed: sum(true)?,
+            unconfirmed: sum(false)?,
+        })
+    }
+}
+
+impl UtxoResponse {
+    fn new(utxo: &Utxo, address: String, path: Option<String>) -> Self {
+        Self {
+            txid: utxo.outpoint.txid.to_string(),
+            vout: utxo.outpoint.vout,
+            amount: utxo.value,
+            address,
+            path,
+            height: utxo.height,
+        }
+    }
+}
+
+/// A derived address of an xpub, with its path below the xpub
+struct Derived {
+    path: String,
+    address: Address,
+}
+
+/// Address of `address_type` paying to the single key `public_key`, key path only for taproot
+fn derive_address(
+    public_key: &[u8],
+    address_type: AddressType,
+    network: Network,
+) -> Result<Address, BalanceError> {
+    Ok(match address_type {
+        AddressType::P2pkh => Address::p2pkh(public_key, network),
+        AddressType::P2shP2wpkh => Address::p2shwpkh(public_key, network),
+        AddressType::P2wpkh => Address::p2wpkh(public_key, network),
+        AddressType::P2tr => {
+            let (output_key, _) = taproot::x_only_key(public_key)
+                .and_then(|internal_key| taproot::output_key(&internal_key, None))
+                .map_err(|_| BalanceError::InvalidExtendedKey)?;
+            Address::p2tr_tweaked(output_key, network)
+        }
+        _ => return Err(BalanceError::UnsupportedAddressType),
+    })
+}
+
+/// Used addresses of `chain` below `xpub`, up to `gap_limit` unused addresses in a row, and
+/// the index following the last used one
+async fn walk_chain(
+    backend: &dyn ChainBackend,
+    xpub: &ExtendedPubKey,
+    chain: u32,
+    query: &XpubQuery,
+) -> Result<(Vec<Derived>, u32), BalanceError> {
+    let chain_key = xpub
+        .ckd_pub(ChildNumber::Normal(chain))
+        .map_err(|_| BalanceError::InvalidExtendedKey)?;
+    let mut used = Vec::new();
+    let mut next = 0;
+    let mut start = 0;
+    'scan: while start < HARDENED_OFFSET {
+        let end = start.saturating_add(query.gap_limit).min(HARDENED_OFFSET);
+        let mut batch = Vec::new();
+        for index in start..end {
+            let key = chain_key
+                .ckd_pub(ChildNumber::Normal(index))
+                .map_err(|_| BalanceError::InvalidExtendedKey)?;
+            batch.push((
+                index,
+                derive_address(
+                    &key.public_key.serialize(),
+                    query.address_type,
+                    query.network,
+                )?,
+            ));
+        }
+        let script_pubkeys: Vec<Vec<u8>> = batch
+            .iter()
+            .map(|(_, address)| address.script_pubkey())
+            .collect();
+        let flags = backend.used(&script_pubkeys).await?;
+        for ((index, address), is_used) in batch.into_iter().zip(flags) {
+            if index >= next + query.gap_limit {
+                break 'scan;
+            }
+            if is_used {
+                next = index + 1;
+                used.push(Derived {
+                    path: format!("m/{}/{}", chain, index),
+                    address,
+                });
+            }
+        }
+        start = end;
+    }
+    Ok((used, next))
+}
+
+/// Unspent outputs paying to `address`
+pub async fn address_utxos(
+    backend: &dyn ChainBackend,
+    address: &str,
+) -> Result<Vec<UtxoResponse>, BalanceError> {
+    let script_pubkey = parse_address(address, BalanceError::InvalidAddress)?.script_pubkey();
+    Ok(backend
+        .utxos(&[script_pubkey])
+        .await?
+        .iter()
+        .map(|utxo| UtxoResponse::new(utxo, address.to_string(), None))
+        .collect())
+}
+
+/// Confirmed and unconfirmed satoshis held by `address`
+pub async fn address_balance(
+    backend: &dyn ChainBackend,
+    address: &str,
+) -> Result<Balance, BalanceError> {
+    let script_pubkey = parse_address(address, BalanceError::InvalidAddress)?.script_pubkey();
+    Balance::new(&backend.utxos(&[script_pubkey]).await?)
+}
+
+/// Index of the first address of `chain` below `xpub` after the last used one, walking the
+/// chain up to the default gap limit
+pub async fn next_index(
+    backend: &dyn ChainBackend,
+    xpub: &ExtendedPubKey,
+    chain: u32,
+    address_type: AddressType,
+    network: Network,
+) -> Result<u32, BalanceError> {
+    let query = XpubQuery {
+        address_type,
+        gap_limit: DEFAULT_GAP_LIMIT,
+        network,
+    };
+    Ok(walk_chain(backend, xpub, chain, &query).await?.1)
+}
+
+/// Balance and UTXOs of the receive and change addresses of the account `xpub`
+pub async fn xpub_balance(
+    backend: &dyn ChainBackend,
+    xpub: &str,
+    query: &XpubQuery,
+) -> Result<XpubBalance, BalanceError> {
+    let xpub = ExtendedPubKey::from_str(xpub).map_err(|_| BalanceError::InvalidExtendedKey)?;
+    account_balance(backend, &xpub, query).await
+}
+
+/// Balance and UTXOs of the receive and change addresses of the account `xpub`, already parsed
+/// or derived from a seed
+pub async fn account_balance(
+    backend: &dyn ChainBackend,
+    xpub: &ExtendedPubKey,
+    query: &XpubQuery,
+) -> Result<XpubBalance, BalanceError> {
+    if query.gap_limit == 0 || query.gap_limit > MAX_GAP_LIMIT {
+        return Err(BalanceError::InvalidGapLimit(MAX_GAP_LIMIT));
+    }
+    let mut used = Vec::new();
+    let mut next_indexes = Vec::new();
+    for &chain in CHAINS.iter() {
+        let (derived, next) = walk_chain(backend, xpub, chain, query).await?;
+        used.extend(derived);
+        next_indexes.push(next);
+    }
+
+    let script_pubkeys: Vec<Vec<u8>> = used
+        .iter()
+        .map(|derived| derived.address.script_pubkey())
+        .collect();
+    let utxos = match script_pubkeys.is_empty() {
+        true => Vec::new(),
+        false => backend.utxos(&script_pubkeys).await?,
+    };
+    Ok(XpubBalance {
+        balance: Balance::new(&utxos)?,
+        next_receive_index: next_indexes[0],
+        next_change_index: next_indexes[1],
+        utxos: utxos
+            .iter()
+            .filter_map(|utxo| {
+                let index = script_pubkeys
+                    .iter()
+                    .position(|script_pubkey| *script_pubkey == utxo.script_pubkey)?;
+                Some(UtxoResponse::new(
+                    utxo,
+                    used[index].address.to_string(),
+                    Some(used[index].path.clone()),
+                ))
+            })
+            .collect(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        bip32::DerivationPath,
+        chain::tests::MockChain,
+        transaction::{OutPoint, Txid, MAX_MONEY},
+        wallet::tests::derive,
+    };
+
+    fn account() -> ExtendedPubKey {
+        ExtendedPubKey::from_priv(&derive("m/84'/0'/0'"))
+    }
+
+    fn script_pubkey(chain: u32, index: u32) -> Vec<u8> {
+        let path = DerivationPath(vec![ChildNumber::Normal(chain), ChildNumber::Normal(index)]);
+        let key = account().derive_pub(&path).unwrap().public_key.serialize();
+        Address::p2wpkh(&key, Network::Bitcoin).script_pubkey()
+    }
+
+    fn utxo(byte: u8, value: u64, script_pubkey: Vec<u8>, height: Option<u32>) -> Utxo {
+        Utxo {
+            outpoint: OutPoint::new(Txid([byte; 32]), 0),
+            value,
+            script_pubkey,
+            height,
+        }
+    }
+
+    #[actix_rt::test]
+    async fn test_address_lookup() {
+        let address = "bc1qluv86ctujqmy8wwvfv0rd7lc0gpzen6t03jt09";
+        assert_eq!(
+            script_pubkey(0, 0),
+            parse_address(address, BalanceError::InvalidAddress)
+                .unwrap()
+                .script_pubkey()
+        );
+        let chain = MockChain {
+            utxos: vec![
+                utxo(1, 60_000, script_pubkey(0, 0), Some(700_000)),
+                utxo(2, 5_000, script_pubkey(0, 0), None),
+                utxo(3, 7_000, script_pubkey(0, 1), Some(700_001)),
+            ],
+            ..MockChain::default()
+        };
+        let utxos = address_utxos(&chain, address).await.unwrap();
+        assert_eq!(utxos.len(), 2);
+        assert_eq!(
+            utxos[0],
+            UtxoResponse {
+                txid: Txid([1; 32]).to_string(),
+                vout: 0,
+                amount: 60_000,
+                address: address.to_string(),
+                path: None,
+                height: Some(700_000),
+            }
+        );
+        assert_eq!(
+            address_balance(&chain, address).await,
+            Ok(Balance {
+                confirmed: 60_000,
+                unconfirmed: 5_000
+            })
+        );
+        assert_eq!(
+            address_balance(&chain, "bc1qinvalid").await,
+            Err(BalanceError::InvalidAddress("bc1qinvalid".to_string()))
+        );
+
+        // a backend reporting more coins than there can be
+        let chain = MockChain {
+            utxos: vec![
+                utxo(4, MAX_MONEY, script_pubkey(0, 0), Some(700_000)),
+                utxo(5, 1, script_pubkey(0, 0), Some(700_000)),
+            ],
+            ..MockChain::default()
+        };
+        assert_eq!(
+            address_balance(&chain, address).await,
+            Err(BalanceError::AmountOutOfRange)
+        );
+    }
+
+    #[actix_rt::test]
+    async fn test_xpub_gap_limit() {
+        let chain = MockChain {
+            utxos: vec![
+                utxo(1, 60_000, script_pubkey(0, 0), Some(700_000)),
+                utxo(2, 20_000, script_pubkey(0, 22), Some(700_010)),
+                // beyond 20 unused addresses after receive address 22
+                utxo(3, 99_000, script_pubkey(0, 43), Some(700_020)),
+                utxo(4, 10_000, script_pubkey(1, 0), None),
+            ],
+            // receive address 3 was used and emptied, which extends the scan up to 23
+            history: vec![script_pubkey(0, 3)],
+            ..MockChain::default()
+        };
+        let xpub = account().to_base58();
+        let query: XpubQuery = serde_json::from_str("{}").unwrap();
+        let balance = xpub_balance(&chain, &xpub, &query).await.unwrap();
+        assert_eq!(
+            balance.balance,
+            Balance {
+                confirmed: 80_000,
+                unconfirmed: 10_000
+            }
+        );
+        assert_eq!(balance.next_receive_index, 23);
+        assert_eq!(balance.next_change_index, 1);
+        let paths: Vec<_> = balance
+            .utxos
+            .iter()
+            .map(|utxo| utxo.path.clone().unwrap())
+            .collect();
+        assert_eq!(paths, ["m/0/0", "m/0/22", "m/1/0"]);
+        assert_eq!(
+            balance.utxos[0].address,
+            "bc1qluv86ctujqmy8wwvfv0rd7lc0gpzen6t03jt09"
+        );
+
+        // a wider gap finds the last receive address too
+        let query: XpubQuery = serde_json::from_str(r#"{"gap_limit": 21}"#).unwrap();
+        let balance = xpub_balance(&chain, &xpub, &query).await.unwrap();
+        assert_eq!(balance.balance.confirmed, 179_000);
+        assert_eq!(balance.next_receive_index, 44);
+
+        let query: XpubQuery = serde_json::from_str(r#"{"gap_limit": 0}"#).unwrap();
+        assert_eq!(
+            xpub_balance(&chain, &xpub, &query).await.unwrap_err(),
+            BalanceError::InvalidGapLimit(MAX_GAP_LIMIT)
+        );
+        let query: XpubQuery = serde_json::from_str(r#"{"address_type": "p2wsh"}"#).unwrap();
+        assert_eq!(
+            xpub_balance(&chain, &xpub, &query).await.unwrap_err(),
+            BalanceError::UnsupportedAddressType
+        );
+    }
+}
@@ -30,35 +30,124 @@ fn bech32_hrp_expand(hrp: &str) -> Vec<u8> {
 fn bech32_polymod(values: Vec<u8>) -> u32 {
     let generator: [u32; 5] = [0x3b6a57b2, 0x26508e6d, 0x1ea119fa, 0x3d4233dd, 0x2a1462b3];
     let mut chk: u32 = 1;
-    let mut top: u32 = 0;
     for value in values {
-        top = chk >> 25;
+        let top = chk >> 25;
         chk = (chk & 0x1ffffff) << 5 ^ (value as u32);
-        for i in 0..5 {
+        for (i, g) in generator.iter().enumerate() {
             if (top >> i) & 1 == 1 {
-                chk ^= generator[i];
-            } else {
-                chk ^= 0;
+                chk ^= g;
             }
         }
     }
     chk
 }
 
+/// Checksum constant of bech32m (BIP350), used from witness version 1 on
+const BECH32M_CONST: u32 = 0x2bc830a3;
+
 /// Compute the checksum values given HRP and data.
-pub fn bech32_create_checksum(hrp: &str, data: &Vec<u8>) -> Vec<u8> {
+pub fn bech32_create_checksum(hrp: &str, data: &[u8]) -> Vec<u8> {
+    create_checksum(hrp, data, 1)
+}
+
+/// Compute the bech32m checksum values given HRP and data.
+pub fn bech32m_create_checksum(hrp: &str, data: &[u8]) -> Vec<u8> {
+    create_checksum(hrp, data, BECH32M_CONST)
+}
+
+fn create_checksum(hrp: &str, data: &[u8], constant: u32) -> Vec<u8> {
     let mut values = vec![];
     values.extend(bech32_hrp_expand(hrp));
     values.extend(data.to_owned());
     values.extend([0, 0, 0, 0, 0, 0]);
-    let polymod = bech32_polymod(values) ^ 1;
+    let polymod = bech32_polymod(values) ^ constant;
     let mut checksum = vec![];
     for i in 0..6 {
-        checksum.push(((polymod >> 5 * (5 - i)) & 31) as u8);
+        checksum.push(((polymod >> (5 * (5 - i))) & 31) as u8);
     }
     checksum
 }
 
+/// Regroup `data` from `from`-bit values into `to`-bit values, padding the last group when `pad` is set
+pub fn convert_bits(data: &[u8], from: u32, to: u32, pad: bool) -> Option<Vec<u8>> {
+    let mut acc: u32 = 0;
+    let mut bits: u32 = 0;
+    let max_value = (1 << to) - 1;
+    let mut result = vec![];
+    for value in data {
+        if (*value as u32) >> from != 0 {
+            return None;
+        }
+        acc = (acc << from) | *value as u32;
+        bits += from;
+        while bits >= to {
+            bits -= to;
+            result.push(((acc >> bits) & max_value) as u8);
+        }
+    }
+    if pad {
+        if bits > 0 {
+            result.push(((acc << (to - bits)) & max_value) as u8);
+        }
+    } else if bits >= from || ((acc << (to - bits)) & max_value) != 0 {
+        return None;
+    }
+    Some(result)
+}
+
+/// Encode a segwit address for witness `version` and `program`, bech32 (BIP173) for version 0
+/// and bech32m (BIP350) for later versions
+pub fn encode_segwit_address(hrp: &str, version: u8, program: &[u8]) -> String {
+    let mut data = vec![version];
+    data.extend(convert_bits(program, 8, 5, true).expect("bytes are 8-bit values"));
+    let checksum = if version == 0 {
+        bech32_create_checksum(hrp, &data)
+    } else {
+        bech32m_create_checksum(hrp, &data)
+    };
+    data.extend(checksum);
+    let encoded = data
+        .into_iter()
+        .map(|x| CHARSET[x as usize])
+        .collect::<String>();
+    hrp.to_string() + &SEP.to_string() + &encoded
+}
+
+/// Decode a segwit address with human-readable part `hrp` into its witness version and program
+///
+/// The checksum has to be bech32 for version 0 and bech32m for later versions (BIP350), and
+/// mixed case is rejected.
+pub fn decode_segwit_address(hrp: &str, address: &str) -> Option<(u8, Vec<u8>)> {
+    if address.to_lowercase() != address && address.to_uppercase() != address {
+        return None;
+    }
+    let address = address.to_lowercase();
+    let (address_hrp, data) = address.split_at(address.rfind(SEP)?);
+    if address_hrp != hrp || data.len() < 8 || address.len() > 90 {
+        return None;
+    }
+    let data = data[1..]
+        .chars()
+        .map(|c| CHARSET.iter().position(|x| *x == c).map(|i| i as u8))
+        .collect::<Option<Vec<u8>>>()?;
+    let mut values = bech32_hrp_expand(hrp);
+    values.extend(&data);
+    let constant = bech32_polymod(values);
+    let (version, data) = (data[0], &data[1..data.len() - 6]);
+    match (version, constant) {
+        (0, 1) | (1..=16, BECH32M_CONST) => {}
+        _ => return None,
+    }
+    let program = convert_bits(data, 5, 8, false)?;
+    if program.len() < 2
+        || program.len() > 40
+        || (version == 0 && ![20, 32].contains(&program.len()))
+    {
+        return None;
+    }
+    Some((version, program))
+}
+
 #[cfg(test)]
 mod tests {
     // Note this useful idiom: importing names from outer (for mod tests) scope.
@@ -81,8 +170,64 @@ mod tests {
     #[test]
     fn test_bech32_create_checksum() {
         assert_eq!(
-            bech32_create_checksum("bc", &vec![0, 1]),
+            bech32_create_checksum("bc", &[0, 1]),
             vec![13, 11, 10, 5, 18, 27]
         );
     }
+
+    #[test]
+    fn test_encode_segwit_address() {
+        assert_eq!(
+            encode_segwit_address(
+                "bc",
+                0,
+                &hex::decode("751e76e8199196d454941c45d1b3a323f1433bd6").unwrap()
+            ),
+            "bc1qw508d6qejxtdg4y5r3zarvary0c5xw7kv8f3t4"
+        );
+        assert_eq!(
+            encode_segwit_address(
+                "bc",
+                1,
+                &hex::decode("79be667ef9dcbbac55a06295ce870b07029bfcdb2dce28d959f2815b16f81798")
+                    .unwrap()
+            ),
+            "bc1p0xlxvlhemja6c4dqv22uapctqupfhlxm9h8z3k2e72q4k9hcz7vqzk5jj0"
+        );
+    }
+
+    #[test]
+    fn test_decode_segwit_address() {
+        assert_eq!(
+            decode_segwit_address("bc", "BC1QW508D6QEJXTDG4Y5R3ZARVARY0C5XW7KV8F3T4"),
+            Some((
+                0,
+                hex::decode("751e76e8199196d454941c45d1b3a323f1433bd6").unwrap()
+            ))
+        );
+        assert_eq!(
+            decode_segwit_address(
+                "bc",
+                "bc1p0xlxvlhemja6c4dqv22uapctqupfhlxm9h8z3k2e72q4k9hcz7vqzk5jj0"
+            )
+            .map(|(version, _)| version),
+            Some(1)
+        );
+        // BIP350: version 1 with a bech32 checksum, and version 0 with a bech32m checksum
+        assert_eq!(
+            decode_segwit_address(
+                "bc",
+                "bc1p0xlxvlhemja6c4dqv22uapctqupfhlxm9h8z3k2e72q4k9hcz7vqh2y7hd"
+            ),
+            None
+        );
+        assert_eq!(
+            decode_segwit_address("bc", "BC1QW508D6QEJXTDG4Y5R3ZARVARY0C5XW7KEMEUZM"),
+            None
+        );
+        assert_eq!(
+            decode_segwit_address("tb", "bc1qw508d6qejxtdg4y5r3zarvary0c5xw7kv8f3t4"),
+            None
+        );
+    }
 }
@@ -0,0 +1,373 @@
+use crate::{
+    address::{self, Network},
+    error::WalletError,
+    hash,
+};
+use secp256k1::{PublicKey, Secp256k1, SecretKey};
+use std::fmt::Display;
+use std::str::FromStr;
+
+/// Child numbers from this one on are hardened
+pub const HARDENED_OFFSET: u32 = 1 << 31;
+/// Version bytes of mainnet extended public keys
+const XPUB_VERSION: [u8; 4] = [0x04, 0x88, 0xb2, 0x1e];
+/// Version bytes of testnet extended public keys
+const TPUB_VERSION: [u8; 4] = [0x04, 0x35, 0x87, 0xcf];
+
+/// One step of a derivation path
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ChildNumber {
+    Normal(u32),
+    Hardened(u32),
+}
+
+impl ChildNumber {
+    /// Index as serialized, with the hardened bit
+    pub fn index(self) -> u32 {
+        match self {
+            ChildNumber::Normal(index) => index,
+            ChildNumber::Hardened(index) => index | HARDENED_OFFSET,
+        }
+    }
+}
+
+impl From<u32> for ChildNumber {
+    /// Child number of a serialized index, hardened from `HARDENED_OFFSET` on
+    fn from(index: u32) -> Self {
+        if index & HARDENED_OFFSET != 0 {
+            ChildNumber::Hardened(index ^ HARDENED_OFFSET)
+        } else {
+            ChildNumber::Normal(index)
+        }
+    }
+}
+
+impl Display for ChildNumber {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            ChildNumber::Normal(index) => write!(f, "{}", index),
+            ChildNumber::Hardened(index) => write!(f, "{}'", index),
+        }
+    }
+}
+
+/// Derivation path such as `m/84'/0'/0'/0/0`, `h` is accepted for hardened steps too
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct DerivationPath(pub Vec<ChildNumber>);
+
+impl FromStr for DerivationPath {
+    type Err = WalletError;
+
+    fn from_str(path: &str) -> Result<Self, Self::Err> {
+        let mut steps = path.split('/');
+        if steps.next() != Some("m") {
+            return Err(WalletError::InvalidDerivationPath);
+        }
+        steps
+            .map(|step| {
+                let (index, hardened) = match step.strip_suffix(|c| c == '\'' || c == 'h') {
+                    Some(index) => (index, true),
+                    None => (step, false),
+                };
+                match index.parse::<u32>() {
+                    Ok(index) if index < HARDENED_OFFSET && hardened => {
+                        Ok(ChildNumber::Hardened(index))
+                    }
+                    Ok(index) if index < HARDENED_OFFSET => Ok(ChildNumber::Normal(index)),
+                    _ => Err(WalletError::InvalidDerivationPath),
+                }
+            })
+            .collect::<Result<Vec<_>, _>>()
+            .map(DerivationPath)
+    }
+}
+
+impl Display for DerivationPath {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "m")?;
+        for step in &self.0 {
+            write!(f, "/{}", step)?;
+        }
+        Ok(())
+    }
+}
+
+/// BIP32 extended private key
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ExtendedPrivKey {
+    pub secret_key: SecretKey,
+    pub chain_code: [u8; 32],
+    pub depth: u8,
+    pub parent_fingerprint: [u8; 4],
+    pub child_number: u32,
+}
+
+impl ExtendedPrivKey {
+    /// Master key of a BIP32 `seed`
+    pub fn new_master(seed: &[u8]) -> Result<Self, WalletError> {
+        let mac = hash::hmac_sha512(b"Bitcoin seed", seed);
+        Self::from_parts(&mac)
+    }
+
+    /// Master key from 64 bytes of key material: the secret key followed by the chain code
+    pub fn from_parts(material: &[u8; 64]) -> Result<Self, WalletError> {
+        let mut chain_code = [0u8; 32];
+        chain_code.copy_from_slice(&material[32..]);
+        Ok(Self {
+            secret_key: SecretKey::from_slice(&material[..32])
+                .map_err(|_| WalletError::InvalidSecretKey)?,
+            chain_code,
+            depth: 0,
+            parent_fingerprint: [0; 4],
+            child_number: 0,
+        })
+    }
+
+    /// Compressed public key of this node
+    pub fn public_key(&self) -> PublicKey {
+        PublicKey::from_secret_key(&Secp256k1::signing_only(), &self.secret_key)
+    }
+
+    /// First four bytes of the hash160 of the public key, identifying the node to its children
+    pub fn fingerprint(&self) -> [u8; 4] {
+        let mut fingerprint = [0u8; 4];
+        fingerprint.copy_from_slice(&hash::hash160(&self.public_key().serialize())[..4]);
+        fingerprint
+    }
+
+    /// CKDpriv: private child key derivation
+    pub fn ckd_priv(&self, child: ChildNumber) -> Result<Self, WalletError> {
+        let mut data = match child {
+            ChildNumber::Hardened(_) => {
+                let mut data = vec![0];
+                data.extend(&self.secret_key[..]);
+                data
+            }
+            ChildNumber::Normal(_) => self.public_key().serialize().to_vec(),
+        };
+        data.extend(child.index().to_be_bytes());
+        let mac = hash::hmac_sha512(&self.chain_code, &data);
+        // an out of range tweak has a probability below 2^-127, it is reported and not skipped
+        let mut secret_key = self.secret_key;
+        secret_key
+            .add_assign(&mac[..32])
+            .map_err(|_| WalletError::InvalidDerivationPath)?;
+        let mut chain_code = [0u8; 32];
+        chain_code.copy_from_slice(&mac[32..]);
+        Ok(Self {
+            secret_key,
+            chain_code,
+            depth: self
+                .depth
+                .checked_add(1)
+                .ok_or(WalletError::InvalidDerivationPath)?,
+            parent_fingerprint: self.fingerprint(),
+            child_number: child.index(),
+        })
+    }
+
+    /// Derive the node at `path` below this one
+    pub fn derive_priv(&self, path: &DerivationPath) -> Result<Self, WalletError> {
+        path.0
+            .iter()
+            .try_fold(*self, |key, child| key.ckd_priv(*child))
+    }
+
+    /// Base58Check `xprv` serialization
+    pub fn to_base58(&self) -> String {
+        let mut data = vec![0x04, 0x88, 0xad, 0xe4, self.depth];
+        data.extend(self.parent_fingerprint);
+        data.extend(self.child_number.to_be_bytes());
+        data.extend(self.chain_code);
+        data.push(0);
+        data.extend(&self.secret_key[..]);
+        base58check(&data)
+    }
+}
+
+/// BIP32 extended public key, deriving the non-hardened children of a node without its secret
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ExtendedPubKey {
+    pub public_key: PublicKey,
+    pub chain_code: [u8; 32],
+    pub depth: u8,
+    pub parent_fingerprint: [u8; 4],
+    pub child_number: u32,
+    /// `xpub` or `tpub` version, kept by derivation and serialization
+    pub network: Network,
+}
+
+impl ExtendedPubKey {
+    /// Neutered `xpriv`, a mainnet `xpub`
+    pub fn from_priv(xpriv: &ExtendedPrivKey) -> Self {
+        Self {
+            public_key: xpriv.public_key(),
+            chain_code: xpriv.chain_code,
+            depth: xpriv.depth,
+            parent_fingerprint: xpriv.parent_fingerprint,
+            child_number: xpriv.child_number,
+            network: Network::Bitcoin,
+        }
+    }
+
+    /// First four bytes of the hash160 of the public key, identifying the node to its children
+    pub fn fingerprint(&self) -> [u8; 4] {
+        let mut fingerprint = [0u8; 4];
+        fingerprint.copy_from_slice(&hash::hash160(&self.public_key.serialize())[..4]);
+        fingerprint
+    }
+
+    /// CKDpub: public child key derivation, only defined for non-hardened children
+    pub fn ckd_pub(&self, child: ChildNumber) -> Result<Self, WalletError> {
+        if let ChildNumber::Hardened(_) = child {
+            return Err(WalletError::HardenedDerivation);
+        }
+        let mut data = self.public_key.serialize().to_vec();
+        data.extend(child.index().to_be_bytes());
+        let mac = hash::hmac_sha512(&self.chain_code, &data);
+        let mut public_key = self.public_key;
+        public_key
+            .add_exp_assign(&Secp256k1::verification_only(), &mac[..32])
+            .map_err(|_| WalletError::InvalidDerivationPath)?;
+        let mut chain_code = [0u8; 32];
+        chain_code.copy_from_slice(&mac[32..]);
+        Ok(Self {
+            public_key,
+            chain_code,
+            depth: self
+                .depth
+                .checked_add(1)
+                .ok_or(WalletError::InvalidDerivationPath)?,
+            parent_fingerprint: self.fingerprint(),
+            child_number: child.index(),
+            network: self.network,
+        })
+    }
+
+    /// Derive the node at the non-hardened `path` below this one
+    pub fn derive_pub(&self, path: &DerivationPath) -> Result<Self, WalletError> {
+        path.0
+            .iter()
+            .try_fold(*self, |key, child| key.ckd_pub(*child))
+    }
+
+    /// Base58Check `xpub` or `tpub` serialization
+    pub fn to_base58(&self) -> String {
+        let mut data = match self.network {
+            Network::Bitcoin => XPUB_VERSION.to_vec(),
+            Network::Testnet => TPUB_VERSION.to_vec(),
+        };
+        data.push(self.depth);
+        data.extend(self.parent_fingerprint);
+        data.extend(self.child_number.to_be_bytes());
+        data.extend(self.chain_code);
+        data.extend(self.public_key.serialize());
+        base58check(&data)
+    }
+}
+
+impl FromStr for ExtendedPubKey {
+    type Err = WalletError;
+
+    /// Parse a mainnet `xpub` or testnet `tpub`
+    fn from_str(xpub: &str) -> Result<Self, Self::Err> {
+        let (version, payload) =
+            address::base58check_decode(xpub).ok_or(WalletError::InvalidExtendedKey)?;
+        let mut data = vec![version];
+        data.extend(payload);
+        if data.len() != 78 {
+            return Err(WalletError::InvalidExtendedKey);
+        }
+        let network = if data[..4] == XPUB_VERSION {
+            Network::Bitcoin
+        } else if data[..4] == TPUB_VERSION {
+            Network::Testnet
+        } else {
+            return Err(WalletError::InvalidExtendedKey);
+        };
+        let mut parent_fingerprint = [0u8; 4];
+        parent_fingerprint.copy_from_slice(&data[5..9]);
+        let mut child_number = [0u8; 4];
+        child_number.copy_from_slice(&data[9..13]);
+        let mut chain_code = [0u8; 32];
+        chain_code.copy_from_slice(&data[13..45]);
+        Ok(Self {
+            public_key: PublicKey::from_slice(&data[45..78])
+                .map_err(|_| WalletError::InvalidExtendedKey)?,
+            chain_code,
+            depth: data[4],
+            parent_fingerprint,
+            child_number: u32::from_be_bytes(child_number),
+            network,
+        })
+    }
+}
+
+/// Base58Check encoding of a serialized extended key, the first of its four version bytes
+/// standing in for the single version byte of addresses
+pub(crate) fn base58check(data: &[u8]) -> String {
+    address::base58check_encode(data[0], &data[1..])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_vector_1() {
+        // BIP32 test vector 1
+        let master =
+            ExtendedPrivKey::new_master(&hex::decode("000102030405060708090a0b0c0d0e0f").unwrap())
+                .unwrap();
+        assert_eq!(master.to_base58(), "xprv9s21ZrQH143K3QTDL4LXw2F7HEK3wJUD2nW2nRk4stbPy6cq3jPPqjiChkVvvNKmPGJxWUtg6LnF5kejMRNNU3TGtRBeJgk33yuGBxrMPHi");
+        let path = DerivationPath::from_str("m/0'/1/2h/2/1000000000").unwrap();
+        assert_eq!(path.to_string(), "m/0'/1/2'/2/1000000000");
+        assert_eq!(master.derive_priv(&path).unwrap().to_base58(), "xprvA41z7zogVVwxVSgdKUHDy1SKmdb533PjDz7J6N6mV6uS3ze1ai8FHa8kmHScGpWmj4WggLyQjgPie1rFSruoUihUZREPSL39UNdE3BBDu76");
+        let path = DerivationPath::from_str("m/0'/1").unwrap();
+        assert_eq!(master.derive_priv(&path).unwrap().to_base58(), "xprv9wTYmMFdV23N2TdNG573QoEsfRrWKQgWeibmLntzniatZvR9BmLnvSxqu53Kw1UmYPxLgboyZQaXwTCg8MSY3H2EU4pWcQDnRnrVA1xe8fs");
+    }
+
+    #[test]
+    fn test_public_derivation() {
+        let master =
+            ExtendedPrivKey::new_master(&hex::decode("000102030405060708090a0b0c0d0e0f").unwrap())
+                .unwrap();
+        assert_eq!(ExtendedPubKey::from_priv(&master).to_base58(), "xpub661MyMwAqRbcFtXgS5sYJABqqG9YLmC4Q1Rdap9gSE8NqtwybGhePY2gZ29ESFjqJoCu1Rupje8YtGqsefD265TMg7usUDFdp6W1EGMcet8");
+        let xpub = ExtendedPubKey::from_str("xpub6D4BDPcP2GT577Vvch3R8wDkScZWzQzMMUm3PWbmWvVJrZwQY4VUNgqFJPMM3No2dFDFGTsxxpG5uJh7n7epu4trkrX7x7DogT5Uv6fcLW5").unwrap();
+        assert_eq!(xpub.depth, 3);
+        // m/0'/1/2' to m/0'/1/2'/2/1000000000 without the secret keys
+        let path = DerivationPath::from_str("m/2/1000000000").unwrap();
+        assert_eq!(xpub.derive_pub(&path).unwrap().to_base58(), "xpub6H1LXWLaKsWFhvm6RVpEL9P4KfRZSW7abD2ttkWP3SSQvnyA8FSVqNTEcYFgJS2UaFcxupHiYkro49S8yGasTvXEYBVPamhGW6cFJodrTHy");
+        let path = DerivationPath::from_str("m/0'/1/2'/2").unwrap();
+        assert_eq!(
+            ExtendedPubKey::from_priv(&master.derive_priv(&path).unwrap()).public_key,
+            xpub.ckd_pub(ChildNumber::Normal(2)).unwrap().public_key
+        );
+        assert!(matches!(
+            xpub.ckd_pub(ChildNumber::Hardened(0)),
+            Err(WalletError::HardenedDerivation)
+        ));
+        assert!(ExtendedPubKey::from_str(&master.to_base58()).is_err());
+
+        // the same key as a testnet tpub stays one through derivation
+        let mut data = TPUB_VERSION.to_vec();
+        data.extend(&bs58::decode(xpub.to_base58()).into_vec().unwrap()[4..78]);
+        let tpub = ExtendedPubKey::from_str(&base58check(&data)).unwrap();
+        assert_eq!(tpub.network, Network::Testnet);
+        assert_eq!(tpub.to_base58(), base58check(&data));
+        assert!(tpub.to_base58().starts_with("tpub"));
+        let child = tpub.ckd_pub(ChildNumber::Normal(2)).unwrap();
+        assert!(child.to_base58().starts_with("tpub"));
+    }
+
+    #[test]
+    fn test_invalid_paths() {
+        for path in ["", "84'/0'", "m/84''", "m/2147483648", "m/-1", "m//0"].iter() {
+            assert!(DerivationPath::from_str(path).is_err(), "{}", path);
+        }
+        assert_eq!(
+            DerivationPath::from_str("m").unwrap(),
+            DerivationPath::default()
+        );
+    }
+}
@@ -0,0 +1,487 @@
+use crate::{
+    address::{Address, Network},
+    error::WalletError,
+    hash,
+    interpreter::{
+        self, lock_time_satisfied, sequence_satisfied, verify_ecdsa, verify_schnorr, ScriptFlags,
+        SigVersion, SignatureChecker,
+    },
+    message::SignedMessage,
+    opcodes,
+    script::Builder,
+    sighash::{self, ScriptPath, SIGHASH_ALL, SIGHASH_DEFAULT},
+    transaction::{OutPoint, Reader, Transaction, TxIn, TxOut, Witness},
+    wallet::Seed,
+};
+use secp256k1::{schnorrsig, Message, PublicKey, Secp256k1, SecretKey};
+use serde::Deserialize;
+use std::str::FromStr;
+
+/// Tag of the BIP340 tagged hash committing to the message
+pub const MESSAGE_TAG: &str = "BIP0322-signed-message";
+
+#[derive(Deserialize, Debug)]
+// Get the seed and the message to sign from user
+pub struct Bip322SignRequest {
+    #[serde(flatten)]
+    pub seed: Seed,
+    pub message: String,
+    #[serde(default)]
+    pub address_type: ProofAddressType,
+}
+
+/// Single key address types the server can sign for
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Default)]
+pub enum ProofAddressType {
+    #[default]
+    #[serde(rename = "p2wpkh")]
+    P2wpkh,
+    /// Needs a full proof, the simple one has no room for the scriptSig
+    #[serde(rename = "p2sh-p2wpkh")]
+    P2shP2wpkh,
+    /// Key path spend of the key tweaked without a script tree
+    #[serde(rename = "p2tr")]
+    P2tr,
+}
+
+/// BIP322 message hash, committed to by the `to_spend` transaction
+pub fn message_hash(message: &str) -> [u8; 32] {
+    hash::tagged_hash(MESSAGE_TAG, message.as_bytes())
+}
+
+/// `to_spend`: pays the message challenge `script_pubkey` from an input committing to `message`
+pub fn to_spend(script_pubkey: &[u8], message: &str) -> Transaction {
+    let mut input = TxIn::new(OutPoint::null(), 0);
+    input.script_sig = Builder::new()
+        .push_int(0)
+        .push_slice(&message_hash(message))
+        .into_script();
+    Transaction {
+        version: 0,
+        input: vec![input],
+        output: vec![TxOut {
+            value: 0,
+            script_pubkey: script_pubkey.to_vec(),
+        }],
+        lock_time: 0,
+    }
+}
+
+/// `to_sign`: spends `to_spend` with `script_sig` and `witness` to an `OP_RETURN` output
+pub fn to_sign(to_spend: &Transaction, script_sig: Vec<u8>, witness: Witness) -> Transaction {
+    let mut input = TxIn::new(OutPoint::new(to_spend.txid(), 0), 0);
+    input.script_sig = script_sig;
+    input.witness = witness;
+    Transaction {
+        version: 0,
+        input: vec![input],
+        output: vec![TxOut {
+            value: 0,
+            script_pubkey: vec![opcodes::OP_RETURN],
+        }],
+        lock_time: 0,
+    }
+}
+
+/// Whether `tx` has the shape of a `to_sign` transaction of `to_spend`
+fn is_to_sign(tx: &Transaction, to_spend: &Transaction) -> bool {
+    tx.input.len() == 1
+        && tx.input[0].previous_output == OutPoint::new(to_spend.txid(), 0)
+        && tx.output.len() == 1
+        && tx.output[0].value == 0
+        && tx.output[0].script_pubkey == [opcodes::OP_RETURN]
+}
+
+/// Segwit v0 signature hash (BIP143) of the `to_sign` input, spending a zero valued output
+fn bip143_sighash(to_sign: &Transaction, script_code: &[u8], hash_type: u8) -> [u8; 32] {
+    sighash::segwit_v0_sighash(to_sign, 0, script_code, 0, hash_type)
+        .expect("to_sign has one input")
+}
+
+/// Taproot signature hash (BIP341) of the `to_sign` input, spending a zero valued output
+/// locked by `prev_script_pubkey`
+fn bip341_sighash(
+    to_sign: &Transaction,
+    prev_script_pubkey: &[u8],
+    hash_type: u8,
+    annex: Option<&[u8]>,
+    script_path: Option<ScriptPath>,
+) -> [u8; 32] {
+    let prevout = TxOut {
+        value: 0,
+        script_pubkey: prev_script_pubkey.to_vec(),
+    };
+    sighash::taproot_sighash(to_sign, 0, &[prevout], hash_type, annex, script_path)
+        .expect("to_sign has one input and one output")
+}
+
+/// Checks the signatures of the `to_sign` input against its BIP143 or BIP341 signature hash
+struct ToSignChecker<'a> {
+    to_sign: &'a Transaction,
+    script_pubkey: &'a [u8],
+}
+
+impl<'a> SignatureChecker for ToSignChecker<'a> {
+    fn check_ecdsa_signature(
+        &self,
+        signature: &[u8],
+        pubkey: &[u8],
+        script_code: &[u8],
+        sig_version: SigVersion,
+    ) -> bool {
+        // legacy addresses are covered by BIP137
+        if sig_version != SigVersion::WitnessV0 || signature.last() != Some(&SIGHASH_ALL) {
+            return false;
+        }
+        let sighash = bip143_sighash(self.to_sign, script_code, SIGHASH_ALL);
+        verify_ecdsa(signature, pubkey, &sighash)
+    }
+
+    fn check_schnorr_signature(
+        &self,
+        signature: &[u8],
+        pubkey: &[u8],
+        annex: Option<&[u8]>,
+        script_path: Option<ScriptPath>,
+    ) -> bool {
+        let hash_type = match signature.len() {
+            64 => SIGHASH_DEFAULT,
+            _ if signature.last() == Some(&SIGHASH_ALL) => SIGHASH_ALL,
+            _ => return false,
+        };
+        let sighash = bip341_sighash(
+            self.to_sign,
+            self.script_pubkey,
+            hash_type,
+            annex,
+            script_path,
+        );
+        verify_schnorr(signature, pubkey, &sighash)
+    }
+
+    fn check_lock_time(&self, lock_time: i64) -> bool {
+        lock_time_satisfied(
+            self.to_sign.lock_time,
+            self.to_sign.input[0].sequence,
+            lock_time,
+        )
+    }
+
+    fn check_sequence(&self, sequence: i64) -> bool {
+        sequence_satisfied(
+            self.to_sign.version,
+            self.to_sign.input[0].sequence,
+            sequence,
+        )
+    }
+}
+
+/// Check a simple or full BIP322 `signature` over `message` for `address`
+///
+/// Proofs run through the script interpreter under standardness rules, taproot ones as key path
+/// or tapscript spends.
+pub fn verify_message(address: &str, message: &str, signature: &str) -> Result<bool, WalletError> {
+    let script_pubkey = Address::from_str(address)?.script_pubkey();
+    let data = base64::decode(signature).map_err(|_| WalletError::InvalidSignature)?;
+    let to_spend = to_spend(&script_pubkey, message);
+
+    let mut reader = Reader::new(&data);
+    let to_sign = match reader.witness() {
+        // simple proof, only meaningful for native segwit outputs
+        Ok(witness) if reader.is_empty() => {
+            if interpreter::witness_program(&script_pubkey).is_none() {
+                return Err(WalletError::UnsupportedProof);
+            }
+            to_sign(&to_spend, Vec::new(), witness)
+        }
+        _ => {
+            let to_sign =
+                Transaction::deserialize(&data).map_err(|_| WalletError::InvalidSignature)?;
+            if !is_to_sign(&to_sign, &to_spend) {
+                return Ok(false);
+            }
+            to_sign
+        }
+    };
+    let input = &to_sign.input[0];
+
+    match interpreter::witness_program(&script_pubkey) {
+        Some((1, output_key)) if output_key.len() == 32 => {}
+        Some((version, _)) if version > 0 => return Err(WalletError::UnsupportedProof),
+        _ => {}
+    }
+    let checker = ToSignChecker {
+        to_sign: &to_sign,
+        script_pubkey: &script_pubkey,
+    };
+    Ok(interpreter::verify_script(
+        &input.script_sig,
+        &input.witness.0,
+        &script_pubkey,
+        &ScriptFlags::default(),
+        &checker,
+    )
+    .is_ok())
+}
+
+/// Sign `message` for the `address_type` address of `secret_key`, as a base64 simple proof for
+/// native segwit and as a full proof for P2SH-P2WPKH
+pub fn sign_message(
+    secret_key: &SecretKey,
+    message: &str,
+    address_type: ProofAddressType,
+    network: Network,
+) -> Result<(Address, String), WalletError> {
+    let secp = Secp256k1::new();
+    let public_key = PublicKey::from_secret_key(&secp, secret_key).serialize();
+    match address_type {
+        ProofAddressType::P2wpkh | ProofAddressType::P2shP2wpkh => {
+            let address = match address_type {
+                ProofAddressType::P2wpkh => Address::p2wpkh(&public_key, network),
+                _ => Address::p2shwpkh(&public_key, network),
+            };
+            let to_spend = to_spend(&address.script_pubkey(), message);
+            let mut to_sign = to_sign(&to_spend, Vec::new(), Witness::default());
+            let script_code = Address::p2pkh(&public_key, network).script_pubkey();
+            let sighash = bip143_sighash(&to_sign, &script_code, SIGHASH_ALL);
+            let mut signature = secp
+                .sign(
+                    &Message::from_slice(&sighash).expect("32 bytes"),
+                    secret_key,
+                )
+                .serialize_der()
+                .to_vec();
+            signature.push(SIGHASH_ALL);
+            let input = &mut to_sign.input[0];
+            input.witness = Witness(vec![signature, public_key.to_vec()]);
+            if address_type == ProofAddressType::P2wpkh {
+                return Ok((address, base64::encode(input.witness.serialize())));
+            }
+            input.script_sig = Builder::new()
+                .push_slice(&Address::p2wpkh(&public_key, network).script_pubkey())
+                .into_script();
+            Ok((address, base64::encode(to_sign.serialize())))
+        }
+        ProofAddressType::P2tr => {
+            let mut internal_key = [0u8; 32];
+            internal_key.copy_from_slice(&public_key[1..]);
+            let (output_key, _) = crate::taproot::output_key(&internal_key, None)?;
+            let address = Address::p2tr_tweaked(output_key, network);
+            let to_spend = to_spend(&address.script_pubkey(), message);
+            let to_sign = to_sign(&to_spend, Vec::new(), Witness::default());
+
+            let mut keypair = schnorrsig::KeyPair::from_secret_key(&secp, *secret_key);
+            keypair
+                .tweak_add_assign(&secp, &hash::tagged_hash("TapTweak", &internal_key))
+                .map_err(|_| WalletError::InvalidPublicKey)?;
+            let sighash = bip341_sighash(
+                &to_sign,
+                &address.script_pubkey(),
+                SIGHASH_DEFAULT,
+                None,
+                None,
+            );
+            let signature = secp.schnorrsig_sign_no_aux_rand(
+                &Message::from_slice(&sighash).expect("32 bytes"),
+                &keypair,
+            );
+            let witness = Witness(vec![signature.as_ref().to_vec()]);
+            Ok((address, base64::encode(witness.serialize())))
+        }
+    }
+}
+
+/// Sign `message` with the master key of `seed`, along with the address it proves control of
+pub fn sign_with_seed(
+    seed: &Seed,
+    message: &str,
+    address_type: ProofAddressType,
+) -> Result<SignedMessage, WalletError> {
+    let (address, signature) =
+        sign_message(&seed.secret_key(), message, address_type, Network::Bitcoin)?;
+    Ok(SignedMessage {
+        address: address.to_string(),
+        message: message.to_string(),
+        signature,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::taproot::{TapLeaf, Taproot};
+
+    const ADDRESS: &str = "bc1q9vza2e8x573nczrlzms0wvx3gsqjx7vavgkx0l";
+
+    fn secret_key() -> SecretKey {
+        // L3VFeEujGtevx9w18HD1fhRbCH67Az2dpCymeRE1SoPK6XQtaN2k
+        let (_, data) = crate::address::base58check_decode(
+            "L3VFeEujGtevx9w18HD1fhRbCH67Az2dpCymeRE1SoPK6XQtaN2k",
+        )
+        .unwrap();
+        SecretKey::from_slice(&data[..32]).unwrap()
+    }
+
+    #[test]
+    fn test_message_hash_and_virtual_transactions() {
+        // BIP322 test vectors
+        assert_eq!(
+            hex::encode(message_hash("")),
+            "c90c269c4f8fcbe6880f72a721ddfbf1914268a794cbb21cfafee13770ae19f1"
+        );
+        assert_eq!(
+            hex::encode(message_hash("Hello World")),
+            "f0eb03b1a75ac6d9847f55c624a99169b5dccba2a31f5b23bea77ba270de0a7a"
+        );
+        let script_pubkey = Address::from_str(ADDRESS).unwrap().script_pubkey();
+        for (message, to_spend_id, to_sign_id) in [
+            (
+                "",
+                "c5680aa69bb8d860bf82d4e9cd3504b55dde018de765a91bb566283c545a99a7",
+                "1e9654e951a5ba44c8604c4de6c67fd78a27e81dcadcfe1edf638ba3aaebaed6",
+            ),
+            (
+                "Hello World",
+                "b79d196740ad5217771c1098fc4a4b51e0535c32236c71f1ea4d61a2d603352b",
+                "88737ae86f2077145f93cc4b153ae9a1cb8d56afa511988c149c5c8c9d93bddf",
+            ),
+        ]
+        .iter()
+        {
+            let to_spend = to_spend(&script_pubkey, message);
+            let to_sign = to_sign(&to_spend, Vec::new(), Witness::default());
+            assert_eq!(to_spend.txid().to_string(), *to_spend_id);
+            assert_eq!(to_sign.txid().to_string(), *to_sign_id);
+        }
+    }
+
+    #[test]
+    fn test_verify_known_signatures() {
+        // BIP322 test vectors
+        assert!(verify_message(
+            ADDRESS,
+            "Hello World",
+            "AkcwRAIgZRfIY3p7/DoVTty6YZbWS71bc5Vct9p9Fia83eRmw2QCICK/ENGfwLtptFluMGs2KsqoNSk89pO7F29zJLUx9a/sASECx/EgAxlkQpQ9hYjgGu6EBCPMVPwVIVJqO4XCsMvViHI="
+        )
+        .unwrap());
+        assert!(!verify_message(
+            ADDRESS,
+            "Hello World!",
+            "AkcwRAIgZRfIY3p7/DoVTty6YZbWS71bc5Vct9p9Fia83eRmw2QCICK/ENGfwLtptFluMGs2KsqoNSk89pO7F29zJLUx9a/sASECx/EgAxlkQpQ9hYjgGu6EBCPMVPwVIVJqO4XCsMvViHI="
+        )
+        .unwrap());
+        assert!(verify_message(
+            "bc1ppv609nr0vr25u07u95waq5lucwfm6tde4nydujnu8npg4q75mr5sxq8lt3",
+            "Hello World",
+            "AUHd69PrJQEv+oKTfZ8l+WROBHuy9HKrbFCJu7U1iK2iiEy1vMU5EfMtjc+VSHM7aU0SDbak5IUZRVno2P5mjSafAQ=="
+        )
+        .unwrap());
+    }
+
+    #[test]
+    fn test_sign_and_verify() {
+        for address_type in [
+            ProofAddressType::P2wpkh,
+            ProofAddressType::P2shP2wpkh,
+            ProofAddressType::P2tr,
+        ]
+        .iter()
+        {
+            let (address, signature) = sign_message(
+                &secret_key(),
+                "Hello World",
+                *address_type,
+                Network::Bitcoin,
+            )
+            .unwrap();
+            let address = address.to_string();
+            assert!(verify_message(&address, "Hello World", &signature).unwrap());
+            assert!(!verify_message(&address, "Hello World!", &signature).unwrap());
+        }
+        let (address, _) = sign_message(
+            &secret_key(),
+            "",
+            ProofAddressType::P2wpkh,
+            Network::Bitcoin,
+        )
+        .unwrap();
+        assert_eq!(address.to_string(), ADDRESS);
+    }
+
+    #[test]
+    fn test_verify_p2wsh_multisig() {
+        let secp = Secp256k1::new();
+        let secret_keys = [
+            SecretKey::from_slice(&[1; 32]).unwrap(),
+            SecretKey::from_slice(&[2; 32]).unwrap(),
+        ];
+        let mut builder = Builder::new().push_int(2);
+        for secret_key in secret_keys.iter() {
+            builder =
+                builder.push_slice(&PublicKey::from_secret_key(&secp, secret_key).serialize());
+        }
+        let witness_script = builder
+            .push_int(2)
+            .push_opcode(opcodes::OP_CHECKMULTISIG)
+            .into_script();
+        let address = Address::p2wsh(&witness_script, Network::Bitcoin);
+
+        let to_spend = to_spend(&address.script_pubkey(), "Hello World");
+        let to_sign = to_sign(&to_spend, Vec::new(), Witness::default());
+        let sighash = bip143_sighash(&to_sign, &witness_script, SIGHASH_ALL);
+        let mut witness = vec![Vec::new()];
+        for secret_key in secret_keys.iter() {
+            let mut signature = secp
+                .sign(&Message::from_slice(&sighash).unwrap(), secret_key)
+                .serialize_der()
+                .to_vec();
+            signature.push(SIGHASH_ALL);
+            witness.push(signature);
+        }
+        witness.push(witness_script);
+        let signature = base64::encode(Witness(witness.clone()).serialize());
+        assert!(verify_message(&address.to_string(), "Hello World", &signature).unwrap());
+
+        // Signatures in the wrong order fail OP_CHECKMULTISIG
+        witness.swap(1, 2);
+        let signature = base64::encode(Witness(witness.clone()).serialize());
+        assert!(!verify_message(&address.to_string(), "Hello World", &signature).unwrap());
+    }
+
+    #[test]
+    fn test_verify_taproot_script_path() {
+        let secp = Secp256k1::new();
+        let keypair = schnorrsig::KeyPair::from_secret_key(&secp, secret_key());
+        let x_only_key = schnorrsig::PublicKey::from_keypair(&secp, &keypair).serialize();
+        let leaf = TapLeaf::multi_a(1, &[x_only_key]).unwrap();
+        let taproot = Taproot::internal_key(None)
+            .leaf(1, leaf.clone())
+            .generate_tree()
+            .unwrap();
+        let address = taproot.address(Network::Bitcoin);
+
+        let to_spend = to_spend(&address.script_pubkey(), "Hello World");
+        let to_sign = to_sign(&to_spend, Vec::new(), Witness::default());
+        let script_path = ScriptPath {
+            leaf_hash: leaf.leaf_hash(),
+            code_separator_position: 0xffff_ffff,
+        };
+        let sighash = bip341_sighash(
+            &to_sign,
+            &address.script_pubkey(),
+            SIGHASH_DEFAULT,
+            None,
+            Some(script_path),
+        );
+        let signature =
+            secp.schnorrsig_sign_no_aux_rand(&Message::from_slice(&sighash).unwrap(), &keypair);
+        let witness = Witness(vec![
+            signature.as_ref().to_vec(),
+            leaf.script,
+            taproot.control_block(0),
+        ]);
+        let signature = base64::encode(witness.serialize());
+        assert!(verify_message(&address.to_string(), "Hello World", &signature).unwrap());
+        assert!(!verify_message(&address.to_string(), "Hello World!", &signature).unwrap());
+    }
+}
@@ -0,0 +1,513 @@
+use crate::{
+    chain::ChainBackend,
+    error::{BroadcastError, ChainError},
+    interpreter::{
+        self, lock_time_satisfied, sequence_satisfied, verify_ecdsa, verify_schnorr, ScriptFlags,
+        SigVersion, SignatureChecker,
+    },
+    policy,
+    sighash::{self, ScriptPath, SIGHASH_DEFAULT},
+    transaction::{sum_amounts, OutPoint, Transaction, TxOut, Txid, MAX_MONEY},
+};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{hash_map::Entry, HashMap},
+    str::FromStr,
+};
+
+#[derive(Deserialize, Debug)]
+// Get the signed transaction from user, with the outputs it spends when the backend cannot
+// look them up
+pub struct BroadcastRequest {
+    /// Hex encoded raw transaction
+    pub tx: String,
+    #[serde(default)]
+    pub prevouts: Vec<Prevout>,
+}
+
+#[derive(Deserialize, Debug)]
+/// Output spent by an input of the transaction
+pub struct Prevout {
+    pub txid: String,
+    pub vout: u32,
+    pub amount: u64,
+    /// Hex encoded scriptPubKey
+    pub script_pubkey: String,
+}
+
+#[derive(Serialize, Debug, PartialEq)]
+/// BroadcastResponse is the txid of the relayed transaction presented to user
+pub struct BroadcastResponse {
+    pub txid: String,
+    /// Inputs whose scripts were verified before broadcasting
+    pub verified_inputs: usize,
+    /// Indexes of the inputs left to the node: unknown prevouts, and taproot inputs unless every
+    /// prevout is known
+    pub unverified_inputs: Vec<usize>,
+}
+
+/// Checks the signatures of input `index` of `tx`, spending `amount` satoshis, and those of
+/// taproot inputs once every output `tx` spends is known
+struct TxChecker<'a> {
+    tx: &'a Transaction,
+    index: usize,
+    amount: u64,
+    prevouts: Option<&'a [TxOut]>,
+}
+
+impl<'a> SignatureChecker for TxChecker<'a> {
+    fn check_ecdsa_signature(
+        &self,
+        signature: &[u8],
+        pubkey: &[u8],
+        script_code: &[u8],
+        sig_version: SigVersion,
+    ) -> bool {
+        let hash_type = match signature.last() {
+            Some(&hash_type) => hash_type,
+            None => return false,
+        };
+        let sighash = match sig_version {
+            SigVersion::Base => {
+                sighash::legacy_sighash(self.tx, self.index, script_code, hash_type.into())
+            }
+            SigVersion::WitnessV0 => {
+                sighash::segwit_v0_sighash(self.tx, self.index, script_code, self.amount, hash_type)
+            }
+            // tapscripts only check Schnorr signatures
+            SigVersion::Tapscript => return false,
+        };
+        matches!(sighash, Ok(sighash) if verify_ecdsa(signature, pubkey, &sighash))
+    }
+
+    fn check_schnorr_signature(
+        &self,
+        signature: &[u8],
+        pubkey: &[u8],
+        annex: Option<&[u8]>,
+        script_path: Option<ScriptPath>,
+    ) -> bool {
+        let prevouts = match self.prevouts {
+            Some(prevouts) => prevouts,
+            None => return false,
+        };
+        let hash_type = signature.get(64).copied().unwrap_or(SIGHASH_DEFAULT);
+        let sighash =
+            sighash::taproot_sighash(self.tx, self.index, prevouts, hash_type, annex, script_path);
+        matches!(sighash, Ok(sighash) if verify_schnorr(signature, pubkey, &sighash))
+    }
+
+    fn check_lock_time(&self, lock_time: i64) -> bool {
+        lock_time_satisfied(
+            self.tx.lock_time,
+            self.tx.input[self.index].sequence,
+            lock_time,
+        )
+    }
+
+    fn check_sequence(&self, sequence: i64) -> bool {
+        sequence_satisfied(
+            self.tx.version,
+            self.tx.input[self.index].sequence,
+            sequence,
+        )
+    }
+}
+
+impl Prevout {
+    fn parse(&self, index: usize) -> Result<(OutPoint, TxOut), BroadcastError> {
+        let txid = Txid::from_str(&self.txid).map_err(|_| BroadcastError::InvalidPrevout(index))?;
+        let script_pubkey =
+            hex::decode(&self.script_pubkey).map_err(|_| BroadcastError::InvalidPrevout(index))?;
+        if self.amount > MAX_MONEY {
+            return Err(BroadcastError::InvalidPrevout(index));
+        }
+        Ok((
+            OutPoint::new(txid, self.vout),
+            TxOut {
+                value: self.amount,
+                script_pubkey,
+            },
+        ))
+    }
+}
+
+/// Outputs spent by every input of `tx`: the given ones, then those of the previous
+/// transactions `backend` finds. `None` when neither knows the prevout of an input.
+async fn prevouts(
+    backend: &dyn ChainBackend,
+    tx: &Transaction,
+    given: &[Prevout],
+) -> Result<Vec<Option<TxOut>>, BroadcastError> {
+    let mut known = given
+        .iter()
+        .enumerate()
+        .map(|(index, prevout)| prevout.parse(index))
+        .collect::<Result<HashMap<_, _>, _>>()?;
+    let mut previous: HashMap<Txid, Option<Transaction>> = HashMap::new();
+    let mut prevouts = Vec::new();
+    for (index, input) in tx.input.iter().enumerate() {
+        let outpoint = input.previous_output;
+        if let Some(prevout) = known.remove(&outpoint) {
+            prevouts.push(Some(prevout));
+            continue;
+        }
+        if let Entry::Vacant(entry) = previous.entry(outpoint.txid) {
+            // a node without a transaction index only finds mempool transactions
+            entry.insert(backend.transaction(outpoint.txid).await.ok());
+        }
+        prevouts.push(match &previous[&outpoint.txid] {
+            Some(transaction) => Some(
+                transaction
+                    .output
+                    .get(outpoint.vout as usize)
+                    .cloned()
+                    .ok_or(BroadcastError::MissingPrevout(index))?,
+            ),
+            None => None,
+        });
+    }
+    Ok(prevouts)
+}
+
+/// Verify input `index` of `tx` under standardness rules, false when its prevout is unknown
+fn verify_input(
+    tx: &Transaction,
+    index: usize,
+    prevouts: &[Option<TxOut>],
+) -> Result<bool, BroadcastError> {
+    let prevout = match &prevouts[index] {
+        Some(prevout) => prevout,
+        None => return Ok(false),
+    };
+    let input = &tx.input[index];
+    // the taproot signature hash commits to every spent output
+    let all_prevouts = prevouts.iter().cloned().collect::<Option<Vec<_>>>();
+    match interpreter::witness_program(&prevout.script_pubkey) {
+        Some((1, output_key)) if output_key.len() == 32 && all_prevouts.is_none() => {
+            return Ok(false)
+        }
+        _ => {}
+    }
+    let checker = TxChecker {
+        tx,
+        index,
+        amount: prevout.value,
+        prevouts: all_prevouts.as_deref(),
+    };
+    interpreter::verify_script(
+        &input.script_sig,
+        &input.witness.0,
+        &prevout.script_pubkey,
+        &ScriptFlags::default(),
+        &checker,
+    )
+    .map_err(|error| BroadcastError::Script { index, error })?;
+    Ok(true)
+}
+
+/// Map the reason a node gave for not accepting a transaction to its mempool
+fn rejection(error: ChainError) -> BroadcastError {
+    let message = match &error {
+        ChainError::Rpc { message, .. } => message.clone(),
+        _ => return error.into(),
+    };
+    let reason = message.to_lowercase();
+    let matches = |patterns: &[&str]| patterns.iter().any(|pattern| reason.contains(pattern));
+    if matches(&["missingorspent", "missing inputs", "missing-inputs"]) {
+        BroadcastError::MissingInputs(message)
+    } else if matches(&["already in block chain", "already in utxo set"]) {
+        BroadcastError::AlreadyConfirmed(message)
+    } else if matches(&["txn-already-in-mempool", "txn-already-known"]) {
+        BroadcastError::AlreadyInMempool(message)
+    } else if matches(&["txn-mempool-conflict"]) {
+        BroadcastError::MempoolConflict(message)
+    } else if matches(&[
+        "min relay fee not met",
+        "mempool min fee not met",
+        "insufficient fee",
+    ]) {
+        BroadcastError::InsufficientFee(message)
+    } else if matches(&["non-final", "non-bip68-final"]) {
+        BroadcastError::NonFinal(message)
+    } else if matches(&["script-verify-flag"]) {
+        BroadcastError::ScriptRejected(message)
+    } else if matches(&[
+        "dust",
+        "scriptpubkey",
+        "scriptsig",
+        "tx-size",
+        "version",
+        "multi-op-return",
+        "bare-multisig",
+        "non-standard",
+    ]) {
+        BroadcastError::NonStandard(message)
+    } else {
+        BroadcastError::Rejected(message)
+    }
+}
+
+impl BroadcastRequest {
+    /// Check the transaction against the standardness rules and the scripts of the outputs it
+    /// spends, then relay it through `backend`
+    pub async fn broadcast(
+        &self,
+        backend: &dyn ChainBackend,
+    ) -> Result<BroadcastResponse, BroadcastError> {
+        let tx = Transaction::from_str(&self.tx)?;
+        policy::check_transaction(&tx)?;
+
+        let prevouts = prevouts(backend, &tx, &self.prevouts).await?;
+        if let Some(amounts) = prevouts
+            .iter()
+            .map(|prevout| prevout.as_ref().map(|prevout| prevout.value))
+            .collect::<Option<Vec<_>>>()
+        {
+            let inputs = sum_amounts(amounts).ok_or(BroadcastError::AmountOutOfRange)?;
+            let outputs = sum_amounts(tx.output.iter().map(|output| output.value))
+                .ok_or(BroadcastError::AmountOutOfRange)?;
+            if inputs < outputs {
+                return Err(BroadcastError::InsufficientInputs { inputs, outputs });
+            }
+        }
+        let mut unverified_inputs = Vec::new();
+        for index in 0..tx.input.len() {
+            if !verify_input(&tx, index, &prevouts)? {
+                unverified_inputs.push(index);
+            }
+        }
+
+        let txid = backend.broadcast(&tx).await.map_err(rejection)?;
+        Ok(BroadcastResponse {
+            txid: txid.to_string(),
+            verified_inputs: tx.input.len() - unverified_inputs.len(),
+            unverified_inputs,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        address::{Address, Network},
+        chain::tests::MockChain,
+        error::ScriptError,
+        sweep::SweepRequest,
+        taproot,
+        transaction::{TxIn, SEQUENCE_FINAL},
+        wallet::tests::derive,
+        wif::PrivateKey,
+    };
+    use serde_json::json;
+
+    const PAYEE: &str = "1BgGZ9tcN4rm9KBzDn7KprQz87SZ26SAMH";
+
+    /// Transaction paying `amount` to `address` in its only output
+    fn funding(address: &Address, amount: u64) -> Transaction {
+        Transaction {
+            version: 2,
+            input: vec![TxIn::new(
+                OutPoint::new(Txid([0xff; 32]), 0),
+                SEQUENCE_FINAL,
+            )],
+            output: vec![TxOut {
+                value: amount,
+                script_pubkey: address.script_pubkey(),
+            }],
+            lock_time: 0,
+        }
+    }
+
+    /// Sweep of P2WPKH, P2SH-P2WPKH and P2TR outputs of one key, with the spent outputs
+    async fn sweep() -> (String, Vec<(Txid, Transaction)>) {
+        let key = PrivateKey::new(derive("m/84'/0'/0'/0/0").secret_key, Network::Bitcoin);
+        let public_key = key.public_key();
+        let internal_key = taproot::x_only_key(&public_key).unwrap();
+        let (output_key, _) = taproot::output_key(&internal_key, None).unwrap();
+        let spent = [
+            (0x11, 60_000, Address::p2wpkh(&public_key, Network::Bitcoin)),
+            (
+                0x22,
+                30_000,
+                Address::p2shwpkh(&public_key, Network::Bitcoin),
+            ),
+            (
+                0x33,
+                10_000,
+                Address::p2tr_tweaked(output_key, Network::Bitcoin),
+            ),
+        ];
+        let utxos: Vec<_> = spent
+            .iter()
+            .map(|(byte, amount, address)| {
+                json!({"txid": hex::encode([*byte; 32]), "vout": 0, "amount": amount, "address": address.to_string()})
+            })
+            .collect();
+        let request: SweepRequest = serde_json::from_value(json!({
+            "wif": key.to_string(),
+            "utxos": utxos,
+            "destination": PAYEE,
+            "fee_rate": 2.0,
+        }))
+        .unwrap();
+        let transactions = spent
+            .iter()
+            .map(|(byte, amount, address)| (Txid([*byte; 32]), funding(address, *amount)))
+            .collect();
+        (request.sweep(None).await.unwrap().tx, transactions)
+    }
+
+    fn prevout(
+        transactions: &[(Txid, Transaction)],
+        index: usize,
+        amount: u64,
+    ) -> serde_json::Value {
+        let (txid, transaction) = &transactions[index];
+        json!({
+            "txid": txid.to_string(),
+            "vout": 0,
+            "amount": amount,
+            "script_pubkey": hex::encode(&transaction.output[0].script_pubkey),
+        })
+    }
+
+    #[actix_rt::test]
+    async fn test_verify_inputs() {
+        let (tx, transactions) = sweep().await;
+        let txid = Transaction::from_str(&tx).unwrap().txid().to_string();
+        // the node only finds the segwit v0 funding transactions
+        let chain = MockChain {
+            transactions: transactions[..2].iter().cloned().collect(),
+            ..MockChain::default()
+        };
+        let request: BroadcastRequest = serde_json::from_value(json!({ "tx": tx })).unwrap();
+        assert_eq!(
+            request.broadcast(&chain).await,
+            Ok(BroadcastResponse {
+                txid: txid.clone(),
+                verified_inputs: 2,
+                unverified_inputs: vec![2],
+            })
+        );
+
+        let request: BroadcastRequest = serde_json::from_value(json!({
+            "tx": tx,
+            "prevouts": [prevout(&transactions, 2, 10_000)],
+        }))
+        .unwrap();
+        assert_eq!(
+            request.broadcast(&chain).await,
+            Ok(BroadcastResponse {
+                txid,
+                verified_inputs: 3,
+                unverified_inputs: Vec::new(),
+            })
+        );
+
+        // signatures commit to the amounts spent by segwit inputs
+        let taproot_error = BroadcastError::Script {
+            index: 2,
+            error: ScriptError::SchnorrSig,
+        };
+        for (index, error) in [(0, None), (2, Some(taproot_error))] {
+            let prevouts: Vec<_> = [60_000, 30_000, 10_000]
+                .iter()
+                .enumerate()
+                .map(|(i, amount)| prevout(&transactions, i, amount + (i == index) as u64))
+                .collect();
+            let request: BroadcastRequest =
+                serde_json::from_value(json!({ "tx": tx, "prevouts": prevouts })).unwrap();
+            match (request.broadcast(&chain).await.unwrap_err(), error) {
+                (BroadcastError::Script { index: 0, .. }, None) => {}
+                (result, Some(error)) => assert_eq!(result, error),
+                (result, None) => panic!("unexpected {:?}", result),
+            }
+        }
+
+        let prevouts: Vec<_> = (0..3).map(|i| prevout(&transactions, i, 1)).collect();
+        let request: BroadcastRequest =
+            serde_json::from_value(json!({ "tx": tx, "prevouts": prevouts })).unwrap();
+        assert!(matches!(
+            request.broadcast(&chain).await,
+            Err(BroadcastError::InsufficientInputs { inputs: 3, .. })
+        ));
+
+        // amounts of more bitcoins than there can be, alone or added up
+        let request: BroadcastRequest = serde_json::from_value(json!({
+            "tx": tx,
+            "prevouts": [prevout(&transactions, 2, MAX_MONEY + 1)],
+        }))
+        .unwrap();
+        assert_eq!(
+            request.broadcast(&chain).await,
+            Err(BroadcastError::InvalidPrevout(0))
+        );
+        let prevouts: Vec<_> = (0..3)
+            .map(|i| prevout(&transactions, i, MAX_MONEY))
+            .collect();
+        let request: BroadcastRequest =
+            serde_json::from_value(json!({ "tx": tx, "prevouts": prevouts })).unwrap();
+        assert_eq!(
+            request.broadcast(&chain).await,
+            Err(BroadcastError::AmountOutOfRange)
+        );
+    }
+
+    #[actix_rt::test]
+    async fn test_rejection() {
+        let (tx, _) = sweep().await;
+        let chain = MockChain {
+            reject: Some(ChainError::Rpc {
+                code: -25,
+                message: "bad-txns-inputs-missingorspent".to_string(),
+            }),
+            ..MockChain::default()
+        };
+        let request: BroadcastRequest = serde_json::from_value(json!({ "tx": tx })).unwrap();
+        assert_eq!(
+            request.broadcast(&chain).await,
+            Err(BroadcastError::MissingInputs(
+                "bad-txns-inputs-missingorspent".to_string()
+            ))
+        );
+        let request: BroadcastRequest =
+            serde_json::from_value(json!({ "tx": "02000000" })).unwrap();
+        assert!(matches!(
+            request.broadcast(&chain).await,
+            Err(BroadcastError::Transaction(_))
+        ));
+
+        let rpc = |message: &str| ChainError::Rpc {
+            code: -26,
+            message: message.to_string(),
+        };
+        assert!(matches!(
+            rejection(rpc("min relay fee not met, 100 < 141")),
+            BroadcastError::InsufficientFee(_)
+        ));
+        assert!(matches!(
+            rejection(rpc("Transaction already in block chain")),
+            BroadcastError::AlreadyConfirmed(_)
+        ));
+        assert!(matches!(
+            rejection(rpc(
+                "non-mandatory-script-verify-flag (Invalid Schnorr signature)"
+            )),
+            BroadcastError::ScriptRejected(_)
+        ));
+        assert!(matches!(
+            rejection(rpc("dust")),
+            BroadcastError::NonStandard(_)
+        ));
+        assert!(matches!(
+            rejection(rpc("too-long-mempool-chain")),
+            BroadcastError::Rejected(_)
+        ));
+        assert_eq!(
+            rejection(ChainError::Connection("refused".to_string())),
+            BroadcastError::Chain(ChainError::Connection("refused".to_string()))
+        );
+    }
+}
@@ -0,0 +1,150 @@
+use crate::{
+    electrum::ElectrumClient,
+    error::ChainError,
+    rpc::BitcoinCoreRpc,
+    transaction::{OutPoint, Transaction, Txid, MAX_MONEY},
+};
+use std::{future::Future, pin::Pin};
+
+/// Confirmation target in blocks of fee estimates when none is given
+pub const DEFAULT_CONFIRMATION_TARGET: u16 = 6;
+
+/// Future returned by the methods of a `ChainBackend`, boxed so that backends can be picked at
+/// runtime and used as trait objects
+pub type ChainFuture<'a, T> = Pin<Box<dyn Future<Output = Result<T, ChainError>> + 'a>>;
+
+/// Unspent output found on chain
+#[derive(Debug, Clone, PartialEq)]
+pub struct Utxo {
+    pub outpoint: OutPoint,
+    /// Value in satoshis
+    pub value: u64,
+    pub script_pubkey: Vec<u8>,
+    /// Height of the block confirming it, `None` while it is in the mempool
+    pub height: Option<u32>,
+}
+
+/// Read only view of the blockchain and the mempool of a node, plus transaction relay
+pub trait ChainBackend: Send + Sync {
+    /// Unspent outputs paying to any of `script_pubkeys`, backends that only scan the UTXO set
+    /// of the chain miss those in the mempool and still return the ones it spends
+    fn utxos<'a>(&'a self, script_pubkeys: &'a [Vec<u8>]) -> ChainFuture<'a, Vec<Utxo>>;
+
+    /// Confirmed or mempool transaction `txid`
+    fn transaction(&self, txid: Txid) -> ChainFuture<'_, Transaction>;
+
+    /// Relay `tx` to the network, returning its txid once it is accepted to the mempool
+    fn broadcast<'a>(&'a self, tx: &'a Transaction) -> ChainFuture<'a, Txid>;
+
+    /// Fee rate in sat/vB for a confirmation within `target` blocks
+    fn estimate_fee(&self, target: u16) -> ChainFuture<'_, f64>;
+
+    /// Whether each of `script_pubkeys` ever received coins, `NoHistory` for backends without
+    /// an index of the history of scripts
+    fn used<'a>(&'a self, script_pubkeys: &'a [Vec<u8>]) -> ChainFuture<'a, Vec<bool>>;
+}
+
+/// Backend configured in the environment: the Electrum server of `ELECTRUM_URL` if set,
+/// otherwise the Bitcoin Core node of `BITCOIN_RPC_URL`
+pub fn from_env() -> Option<Box<dyn ChainBackend>> {
+    match ElectrumClient::from_env() {
+        Some(electrum) => Some(Box::new(electrum)),
+        None => BitcoinCoreRpc::from_env().map(|rpc| Box::new(rpc) as Box<dyn ChainBackend>),
+    }
+}
+
+/// Satoshis of a decimal BTC amount, as nodes return them, at most the 21 million bitcoins
+/// there can be
+pub(crate) fn btc_to_sat(amount: f64) -> Result<u64, ChainError> {
+    let sat = (amount * 100_000_000.0).round();
+    if !sat.is_finite() || sat < 0.0 || sat > MAX_MONEY as f64 {
+        return Err(ChainError::InvalidResponse(format!("amount {}", amount)));
+    }
+    Ok(sat as u64)
+}
+
+/// sat/vB of a fee rate in BTC/kvB
+pub(crate) fn btc_per_kvb_to_sat_per_vb(fee_rate: f64) -> f64 {
+    fee_rate * 100_000_000.0 / 1000.0
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    /// Chain backend holding `utxos` and the previous `transactions`, where `history` are the
+    /// scripts of spent outputs. Every broadcast is rejected with `reject` if set.
+    #[derive(Default)]
+    pub(crate) struct MockChain {
+        pub(crate) utxos: Vec<Utxo>,
+        pub(crate) history: Vec<Vec<u8>>,
+        pub(crate) transactions: HashMap<Txid, Transaction>,
+        pub(crate) reject: Option<ChainError>,
+    }
+
+    impl ChainBackend for MockChain {
+        fn utxos<'a>(&'a self, script_pubkeys: &'a [Vec<u8>]) -> ChainFuture<'a, Vec<Utxo>> {
+            Box::pin(async move {
+                Ok(self
+                    .utxos
+                    .iter()
+                    .filter(|utxo| script_pubkeys.contains(&utxo.script_pubkey))
+                    .cloned()
+                    .collect())
+            })
+        }
+
+        fn transaction(&self, txid: Txid) -> ChainFuture<'_, Transaction> {
+            Box::pin(async move {
+                self.transactions
+                    .get(&txid)
+                    .cloned()
+                    .ok_or(ChainError::Rpc {
+                        code: -5,
+                        message: "No such mempool or blockchain transaction".to_string(),
+                    })
+            })
+        }
+
+        fn broadcast<'a>(&'a self, tx: &'a Transaction) -> ChainFuture<'a, Txid> {
+            Box::pin(async move {
+                match &self.reject {
+                    Some(error) => Err(error.clone()),
+                    None => Ok(tx.txid()),
+                }
+            })
+        }
+
+        fn estimate_fee(&self, _: u16) -> ChainFuture<'_, f64> {
+            Box::pin(async { Err(ChainError::NotConfigured) })
+        }
+
+        fn used<'a>(&'a self, script_pubkeys: &'a [Vec<u8>]) -> ChainFuture<'a, Vec<bool>> {
+            Box::pin(async move {
+                Ok(script_pubkeys
+                    .iter()
+                    .map(|script_pubkey| {
+                        self.history.contains(script_pubkey)
+                            || self
+                                .utxos
+                                .iter()
+                                .any(|utxo| utxo.script_pubkey == *script_pubkey)
+                    })
+                    .collect())
+            })
+        }
+    }
+
+    #[test]
+    fn test_btc_to_sat() {
+        assert_eq!(btc_to_sat(0.0005), Ok(50_000));
+        assert_eq!(btc_to_sat(21_000_000.0), Ok(MAX_MONEY));
+        for amount in [-0.1, 21_000_000.000_000_01, 1e20, f64::NAN, f64::INFINITY] {
+            assert!(matches!(
+                btc_to_sat(amount),
+                Err(ChainError::InvalidResponse(_))
+            ));
+        }
+    }
+}
@@ -0,0 +1,1024 @@
+use crate::{
+    address::{Address, Network},
+    balance,
+    bip32::{DerivationPath, ExtendedPrivKey, ExtendedPubKey},
+    chain::ChainBackend,
+    error::{PsbtError, SelectionError},
+    estimator::{fee, input_weight, output_weight, AddressType},
+    interpreter::{is_p2sh, witness_program},
+    opreturn::OpReturnRequest,
+    policy::{self, dust_threshold},
+    psbt::{script_from_hex, KeySource, Psbt, PsbtOutputRequest, TapKeySource},
+    taproot,
+    transaction::{
+        sum_amounts, OutPoint, Transaction, TxIn, TxOut, Txid, MAX_MONEY, SEQUENCE_MAX_NON_RBF,
+    },
+    wallet::Seed,
+};
+use serde::{Deserialize, Serialize};
+use std::str::FromStr;
+
+/// BIP84 path of the first change address of the first account
+pub const DEFAULT_CHANGE_PATH: &str = "m/84'/0'/0'/1/0";
+/// BIP84 path of the first account, whose change chain gets the change when no path is given
+pub const CHANGE_ACCOUNT_PATH: &str = "m/84'/0'/0'";
+/// Fee rate in sat/vB the change output is expected to be spent at later on
+pub const DEFAULT_LONG_TERM_FEE_RATE: f64 = 10.0;
+/// Weight of an input without its scriptSig and witness: outpoint, sequence and scriptSig length
+pub(crate) const INPUT_BASE_WEIGHT: usize = (36 + 4 + 1) * 4;
+/// Weight of version, input and output counts and locktime, with the segwit marker and flag
+pub(crate) const TX_OVERHEAD_WEIGHT: usize = (4 + 1 + 1 + 4) * 4 + 2;
+/// Depth-first search steps of branch and bound before it gives up
+const BNB_MAX_TRIES: usize = 100_000;
+/// Random subsets knapsack tries
+const KNAPSACK_ITERATIONS: usize = 1000;
+
+/// How inputs are picked
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum Algorithm {
+    /// Search for a changeless input set, spending at most the cost of a change output extra
+    BranchAndBound,
+    /// Randomized subset sum getting as close to the target as possible, as Bitcoin Core did
+    Knapsack,
+    /// Largest UTXOs first until the target is met
+    LargestFirst,
+}
+
+/// UTXO the selection may spend
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Candidate {
+    pub value: u64,
+    /// Weight of the input spending it, scriptSig and witness included
+    pub weight: usize,
+}
+
+/// What the selected inputs must pay for, all fees in satoshis
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SelectionParams {
+    /// Fee rate in sat/vB
+    pub fee_rate: f64,
+    pub long_term_fee_rate: f64,
+    /// Amount of the payments plus the fee of the transaction without inputs
+    pub target: u64,
+    /// Fee of a change output now and of spending it later, the waste of creating one
+    pub cost_of_change: u64,
+    /// Fee of a change output now
+    pub change_fee: u64,
+    /// Smallest change value worth an output
+    pub change_dust: u64,
+}
+
+/// Inputs picked by one of the algorithms
+#[derive(Debug, Clone, PartialEq)]
+pub struct Selection {
+    pub algorithm: Algorithm,
+    /// Indices of the selected candidates
+    pub inputs: Vec<usize>,
+    /// Value of a change output, `None` when the excess goes to fees
+    pub change: Option<u64>,
+    /// Fees paid compared to spending the same inputs at the long term fee rate, plus the
+    /// cost of change or the excess dropped to fees, in satoshis
+    pub waste: i64,
+}
+
+/// Amount in satoshis as a signed value, capped at `MAX_MONEY` so sums of a few cannot overflow
+fn signed(amount: u64) -> i64 {
+    amount.min(MAX_MONEY) as i64
+}
+
+impl Candidate {
+    /// Value left once the fee of spending it is paid, negative for uneconomic UTXOs
+    fn effective_value(&self, params: &SelectionParams) -> i64 {
+        signed(self.value) - signed(fee(self.weight, params.fee_rate))
+    }
+
+    /// Fee now minus fee at the long term fee rate
+    fn waste(&self, params: &SelectionParams) -> i64 {
+        signed(fee(self.weight, params.fee_rate))
+            - signed(fee(self.weight, params.long_term_fee_rate))
+    }
+}
+
+/// Indices of the economic candidates, largest effective value first
+fn sorted_pool(candidates: &[Candidate], params: &SelectionParams) -> Vec<usize> {
+    let mut pool: Vec<usize> = (0..candidates.len())
+        .filter(|&i| candidates[i].effective_value(params) > 0)
+        .collect();
+    pool.sort_by_key(|&i| std::cmp::Reverse(candidates[i].effective_value(params)));
+    pool
+}
+
+fn effective_sum(candidates: &[Candidate], inputs: &[usize], params: &SelectionParams) -> i64 {
+    inputs
+        .iter()
+        .map(|&i| candidates[i].effective_value(params))
+        .sum()
+}
+
+/// Change output value for `excess` effective value, if worth more than dust
+fn change_value(excess: u64, params: &SelectionParams) -> Option<u64> {
+    excess
+        .checked_sub(params.change_fee)
+        .filter(|&change| change >= params.change_dust)
+}
+
+/// Depth-first search for the changeless input set with the least waste, whose effective value
+/// is between the target and the target plus the cost of change (Bitcoin Core's algorithm)
+pub fn branch_and_bound(candidates: &[Candidate], params: &SelectionParams) -> Option<Vec<usize>> {
+    let pool = sorted_pool(candidates, params);
+    let target = signed(params.target);
+    let upper_bound = target + signed(params.cost_of_change);
+    let mut available = effective_sum(candidates, &pool, params);
+    if available < target {
+        return None;
+    }
+
+    let mut selection: Vec<bool> = Vec::new();
+    let (mut value, mut waste) = (0i64, 0i64);
+    let mut best: Option<(Vec<bool>, i64)> = None;
+    for _ in 0..BNB_MAX_TRIES {
+        let worse = matches!(&best, Some((_, best_waste)) if waste > *best_waste)
+            && params.fee_rate > params.long_term_fee_rate;
+        let backtrack = if value + available < target || value > upper_bound || worse {
+            true
+        } else if value >= target {
+            let total_waste = waste + value - target;
+            if best
+                .as_ref()
+                .is_none_or(|(_, best_waste)| total_waste <= *best_waste)
+            {
+                best = Some((selection.clone(), total_waste));
+            }
+            true
+        } else {
+            false
+        };
+
+        if backtrack {
+            // drop the trailing omitted candidates, then omit the last included one
+            while selection.last() == Some(&false) {
+                selection.pop();
+                available += candidates[pool[selection.len()]].effective_value(params);
+            }
+            let last = match selection.len() {
+                0 => break,
+                len => len - 1,
+            };
+            selection[last] = false;
+            let candidate = &candidates[pool[last]];
+            value -= candidate.effective_value(params);
+            waste -= candidate.waste(params);
+        } else {
+            let candidate = &candidates[pool[selection.len()]];
+            available -= candidate.effective_value(params);
+            value += candidate.effective_value(params);
+            waste += candidate.waste(params);
+            selection.push(true);
+        }
+    }
+
+    best.map(|(selection, _)| {
+        selection
+            .iter()
+            .zip(&pool)
+            .filter(|(included, _)| **included)
+            .map(|(_, &i)| i)
+            .collect()
+    })
+}
+
+/// xorshift64, so the same request always selects the same inputs
+struct Rng(u64);
+
+impl Rng {
+    fn next_bool(&mut self) -> bool {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0 & 1 == 1
+    }
+}
+
+/// Subset of `values` (sorted descending) closest to `target` from above, by random inclusion
+/// passes each completed by the values left out
+fn approximate_best_subset(values: &[i64], target: i64) -> (Vec<bool>, i64) {
+    let mut rng = Rng(0x2545_f491_4f6c_dd1d);
+    let mut best = vec![true; values.len()];
+    let mut best_value: i64 = values.iter().sum();
+    for _ in 0..KNAPSACK_ITERATIONS {
+        if best_value == target {
+            break;
+        }
+        let mut included = vec![false; values.len()];
+        let mut total = 0;
+        let mut reached = false;
+        for pass in 0..2 {
+            if reached {
+                break;
+            }
+            for (i, value) in values.iter().enumerate() {
+                let include = match pass {
+                    0 => rng.next_bool(),
+                    _ => !included[i],
+                };
+                if !include {
+                    continue;
+                }
+                total += value;
+                included[i] = true;
+                if total >= target {
+                    reached = true;
+                    if total < best_value {
+                        best_value = total;
+                        best = included.clone();
+                    }
+                    total -= value;
+                    included[i] = false;
+                }
+            }
+        }
+    }
+    (best, best_value)
+}
+
+fn knapsack_target(
+    candidates: &[Candidate],
+    params: &SelectionParams,
+    target: i64,
+) -> Option<Vec<usize>> {
+    let pool = sorted_pool(candidates, params);
+    let value = |i: usize| candidates[i].effective_value(params);
+    if let Some(&exact) = pool.iter().find(|&&i| value(i) == target) {
+        return Some(vec![exact]);
+    }
+    let lowest_larger = pool.iter().rev().find(|&&i| value(i) > target).copied();
+    let smaller: Vec<usize> = pool.into_iter().filter(|&i| value(i) < target).collect();
+    let smaller_total = effective_sum(candidates, &smaller, params);
+    if smaller_total == target {
+        return Some(smaller);
+    }
+    if smaller_total < target {
+        return lowest_larger.map(|i| vec![i]);
+    }
+
+    let values: Vec<i64> = smaller.iter().map(|&i| value(i)).collect();
+    let (best, best_value) = approximate_best_subset(&values, target);
+    match lowest_larger {
+        Some(larger) if best_value != target && value(larger) <= best_value => Some(vec![larger]),
+        _ => Some(
+            best.iter()
+                .zip(&smaller)
+                .filter(|(included, _)| **included)
+                .map(|(_, &i)| i)
+                .collect(),
+        ),
+    }
+}
+
+/// Knapsack solver aiming for the target plus a change output, then for the target alone
+pub fn knapsack(candidates: &[Candidate], params: &SelectionParams) -> Option<Vec<usize>> {
+    let target = signed(params.target);
+    knapsack_target(
+        candidates,
+        params,
+        target + signed(params.change_fee) + signed(params.change_dust),
+    )
+    .or_else(|| knapsack_target(candidates, params, target))
+}
+
+/// Largest candidates until the target plus a change output is met, or all of them if they
+/// only cover the target
+pub fn largest_first(candidates: &[Candidate], params: &SelectionParams) -> Option<Vec<usize>> {
+    let target = signed(params.target);
+    let with_change = target + signed(params.change_fee) + signed(params.change_dust);
+    let mut selected = Vec::new();
+    let mut value = 0;
+    for i in sorted_pool(candidates, params) {
+        selected.push(i);
+        value += candidates[i].effective_value(params);
+        if value >= with_change {
+            return Some(selected);
+        }
+    }
+    Some(selected).filter(|_| value >= target)
+}
+
+/// Waste of spending `inputs`, with the change output they leave if any
+pub fn waste(
+    candidates: &[Candidate],
+    inputs: &[usize],
+    params: &SelectionParams,
+) -> (i64, Option<u64>) {
+    let excess = (effective_sum(candidates, inputs, params) - signed(params.target)) as u64;
+    let inputs_waste: i64 = inputs.iter().map(|&i| candidates[i].waste(params)).sum();
+    match change_value(excess, params) {
+        Some(change) => (inputs_waste + signed(params.cost_of_change), Some(change)),
+        None => (inputs_waste + signed(excess), None),
+    }
+}
+
+/// Run `algorithm`, or every algorithm keeping the selection with the least waste
+pub fn select(
+    candidates: &[Candidate],
+    params: &SelectionParams,
+    algorithm: Option<Algorithm>,
+) -> Result<Selection, SelectionError> {
+    if sum_amounts(candidates.iter().map(|candidate| candidate.value)).is_none()
+        || sum_amounts([params.target, params.cost_of_change]).is_none()
+        || sum_amounts([params.target, params.change_fee, params.change_dust]).is_none()
+    {
+        return Err(SelectionError::AmountOutOfRange);
+    }
+    let algorithms = match algorithm {
+        Some(algorithm) => vec![algorithm],
+        None => vec![
+            Algorithm::BranchAndBound,
+            Algorithm::Knapsack,
+            Algorithm::LargestFirst,
+        ],
+    };
+    let mut best: Option<Selection> = None;
+    for algorithm in algorithms {
+        let inputs = match algorithm {
+            Algorithm::BranchAndBound => branch_and_bound(candidates, params),
+            Algorithm::Knapsack => knapsack(candidates, params),
+            Algorithm::LargestFirst => largest_first(candidates, params),
+        };
+        if let Some(inputs) = inputs {
+            let (waste, change) = waste(candidates, &inputs, params);
+            if best.as_ref().is_none_or(|best| waste < best.waste) {
+                best = Some(Selection {
+                    algorithm,
+                    inputs,
+                    change,
+                    waste,
+                });
+            }
+        }
+    }
+    best.ok_or_else(|| SelectionError::InsufficientFunds {
+        needed: params.target,
+        available: effective_sum(candidates, &sorted_pool(candidates, params), params) as u64,
+    })
+}
+
+fn default_long_term_fee_rate() -> f64 {
+    DEFAULT_LONG_TERM_FEE_RATE
+}
+
+#[derive(Deserialize, Debug)]
+// Get the UTXOs to spend from, the payments and the fee rate from user
+pub struct CoinSelectRequest {
+    #[serde(flatten)]
+    pub seed: Seed,
+    pub utxos: Vec<SpendableUtxo>,
+    pub payments: Vec<PsbtOutputRequest>,
+    /// Fee rate in sat/vB
+    pub fee_rate: f64,
+    #[serde(default = "default_long_term_fee_rate")]
+    pub long_term_fee_rate: f64,
+    /// Every algorithm is tried when left out
+    #[serde(default)]
+    pub algorithm: Option<Algorithm>,
+    /// Path of the P2WPKH change address, derived from the seed. Left out, the first unused
+    /// change address of the account of `CHANGE_ACCOUNT_PATH` is looked up on chain.
+    #[serde(default)]
+    pub change_path: Option<String>,
+    /// Zero value data output added after the payments, e.g. a document hash to timestamp
+    #[serde(default)]
+    pub op_return: Option<OpReturnRequest>,
+    #[serde(default)]
+    pub locktime: u32,
+    #[serde(default)]
+    pub network: Network,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct SpendableUtxo {
+    pub txid: String,
+    pub vout: u32,
+    pub amount: u64,
+    pub address: String,
+    /// Derivation path of the key of a seed address, added to the PSBT for the signer
+    pub path: Option<String>,
+    /// Hex encoded transaction of the output, for legacy inputs
+    pub non_witness_utxo: Option<String>,
+    /// Hex encoded redeem script of a P2SH address, derived from the key at `path` when left out
+    pub redeem_script: Option<String>,
+    /// Hex encoded witness script of a P2WSH or P2SH-P2WSH address
+    pub witness_script: Option<String>,
+    /// Weight of the scriptSig and witness, for scripts the server cannot size
+    pub satisfaction_weight: Option<usize>,
+}
+
+impl SpendableUtxo {
+    /// Public key at the path of the UTXO, if it has one
+    pub(crate) fn public_key(
+        &self,
+        master: &ExtendedPrivKey,
+    ) -> Result<Option<Vec<u8>>, SelectionError> {
+        match &self.path {
+            Some(path) => Ok(Some(key_source(master, parse_path(path)?)?.0)),
+            None => Ok(None),
+        }
+    }
+
+    /// Weight of the input spending this output, with the satisfaction weight if given.
+    /// Without a redeem script, a P2SH address is P2SH-P2WPKH of the single key `public_key`.
+    pub(crate) fn weight(&self, public_key: Option<&[u8]>) -> Result<usize, SelectionError> {
+        let script_pubkey =
+            parse_address(&self.address, SelectionError::InvalidAddress)?.script_pubkey();
+        if let Some(weight) = self.satisfaction_weight {
+            return Ok(INPUT_BASE_WEIGHT + weight);
+        }
+        let redeem_script = match (script_from_hex(&self.redeem_script)?, public_key) {
+            (None, Some(public_key)) if is_p2sh(&script_pubkey) => {
+                Some(Address::p2wpkh(public_key, Network::Bitcoin).script_pubkey())
+            }
+            (redeem_script, _) => redeem_script,
+        };
+        let witness_script = script_from_hex(&self.witness_script)?;
+        input_weight(
+            &script_pubkey,
+            redeem_script.as_deref(),
+            witness_script.as_deref(),
+        )
+        .ok_or_else(|| SelectionError::UnsupportedAddress(self.address.clone()))
+    }
+
+    /// UTXO of input `index` spending this output, returns its scriptPubKey
+    pub(crate) fn fill_utxo(
+        &self,
+        psbt: &mut Psbt,
+        index: usize,
+    ) -> Result<Vec<u8>, SelectionError> {
+        let script_pubkey =
+            parse_address(&self.address, SelectionError::InvalidAddress)?.script_pubkey();
+        let is_segwit = witness_program(&script_pubkey).is_some() || is_p2sh(&script_pubkey);
+        let input = &mut psbt.inputs[index];
+        if let Some(tx) = &self.non_witness_utxo {
+            let tx = Transaction::from_str(tx).map_err(PsbtError::from)?;
+            if tx.txid() != psbt.unsigned_tx.input[index].previous_output.txid {
+                return Err(PsbtError::MismatchedUtxo(index).into());
+            }
+            input.non_witness_utxo = Some(tx);
+        }
+        input.redeem_script = script_from_hex(&self.redeem_script)?;
+        input.witness_script = script_from_hex(&self.witness_script)?;
+        if is_segwit {
+            input.witness_utxo = Some(TxOut {
+                value: self.amount,
+                script_pubkey: script_pubkey.clone(),
+            });
+        }
+        Ok(script_pubkey)
+    }
+
+    /// UTXO of input `index` spending this output, and the key of its path for the signer.
+    /// `utxo` is the position of the UTXO in the request, reported on a key mismatch.
+    pub(crate) fn fill_input(
+        &self,
+        psbt: &mut Psbt,
+        index: usize,
+        utxo: usize,
+        master: &ExtendedPrivKey,
+        network: Network,
+    ) -> Result<(), SelectionError> {
+        let script_pubkey = self.fill_utxo(psbt, index)?;
+        let input = &mut psbt.inputs[index];
+        let path = match &self.path {
+            Some(path) => parse_path(path)?,
+            None => return Ok(()),
+        };
+        let (public_key, source) = key_source(master, path)?;
+        if let Some((1, _)) = witness_program(&script_pubkey) {
+            let internal_key =
+                taproot::x_only_key(&public_key).map_err(|_| SelectionError::KeyDerivation)?;
+            let (output_key, _) = taproot::output_key(&internal_key, None)
+                .map_err(|_| SelectionError::KeyDerivation)?;
+            if Address::p2tr_tweaked(output_key, network).script_pubkey() != script_pubkey {
+                return Err(SelectionError::KeyMismatch(utxo));
+            }
+            input.tap_internal_key = Some(internal_key);
+            input.tap_bip32_derivation.insert(
+                internal_key,
+                TapKeySource {
+                    leaf_hashes: Vec::new(),
+                    source,
+                },
+            );
+            return Ok(());
+        }
+        let expected = if is_p2sh(&script_pubkey) {
+            let redeem_script = Address::p2wpkh(&public_key, network).script_pubkey();
+            input.redeem_script = Some(redeem_script);
+            Address::p2shwpkh(&public_key, network)
+        } else if witness_program(&script_pubkey).is_some() {
+            Address::p2wpkh(&public_key, network)
+        } else {
+            Address::p2pkh(&public_key, network)
+        };
+        if expected.script_pubkey() != script_pubkey {
+            return Err(SelectionError::KeyMismatch(utxo));
+        }
+        input.bip32_derivation.insert(public_key, source);
+        Ok(())
+    }
+}
+
+#[derive(Serialize, Debug)]
+/// SelectionResponse is the unsigned PSBT spending the selected UTXOs presented to user
+pub struct SelectionResponse {
+    pub psbt: String,
+    pub algorithm: Algorithm,
+    /// Outpoints of the selected UTXOs
+    pub inputs: Vec<String>,
+    pub fee: u64,
+    pub waste: i64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub change: Option<ChangeOutput>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct ChangeOutput {
+    pub address: String,
+    pub amount: u64,
+    pub path: String,
+}
+
+/// Parse `address`, reported through the `invalid` variant of the caller's error
+pub(crate) fn parse_address<E>(address: &str, invalid: fn(String) -> E) -> Result<Address, E> {
+    Address::from_str(address).map_err(|_| invalid(address.to_string()))
+}
+
+pub(crate) fn parse_path(path: &str) -> Result<DerivationPath, SelectionError> {
+    DerivationPath::from_str(path).map_err(|_| SelectionError::InvalidDerivationPath)
+}
+
+pub(crate) fn key_source(
+    master: &ExtendedPrivKey,
+    path: DerivationPath,
+) -> Result<(Vec<u8>, KeySource), SelectionError> {
+    let public_key = master
+        .derive_priv(&path)
+        .map_err(|_| SelectionError::KeyDerivation)?
+        .public_key()
+        .serialize()
+        .to_vec();
+    let source = KeySource {
+        fingerprint: master.fingerprint(),
+        path,
+    };
+    Ok((public_key, source))
+}
+
+/// Path of the first P2WPKH change address of the account of `CHANGE_ACCOUNT_PATH` after the
+/// last one `backend` has seen used
+pub async fn next_change_path(
+    backend: Option<&dyn ChainBackend>,
+    master: &ExtendedPrivKey,
+    network: Network,
+) -> Result<String, SelectionError> {
+    let backend = backend.ok_or(SelectionError::NoChangePath)?;
+    let account = master
+        .derive_priv(&parse_path(CHANGE_ACCOUNT_PATH)?)
+        .map_err(|_| SelectionError::KeyDerivation)?;
+    let xpub = ExtendedPubKey::from_priv(&account);
+    let index = balance::next_index(backend, &xpub, 1, AddressType::P2wpkh, network).await?;
+    Ok(format!("{}/1/{}", CHANGE_ACCOUNT_PATH, index))
+}
+
+impl CoinSelectRequest {
+    /// Path of the change address: the one given, or the next unused one of `backend`
+    pub async fn change_path(
+        &self,
+        backend: Option<&dyn ChainBackend>,
+    ) -> Result<String, SelectionError> {
+        match &self.change_path {
+            Some(change_path) => Ok(change_path.clone()),
+            None => next_change_path(backend, &self.seed.master_key(), self.network).await,
+        }
+    }
+
+    /// Select the inputs of the payments, paying the change to the address at `change_path`
+    pub fn select(&self, change_path: &str) -> Result<SelectionResponse, SelectionError> {
+        let valid = |fee_rate: f64| fee_rate >= 0.0 && fee_rate.is_finite();
+        if !valid(self.fee_rate) || self.fee_rate == 0.0 || !valid(self.long_term_fee_rate) {
+            return Err(SelectionError::InvalidFeeRate);
+        }
+        if self.payments.is_empty() && self.op_return.is_none() {
+            return Err(SelectionError::NoPayment);
+        }
+        let mut output = Vec::new();
+        for (i, payment) in self.payments.iter().enumerate() {
+            let script_pubkey =
+                parse_address(&payment.address, SelectionError::InvalidAddress)?.script_pubkey();
+            if payment.amount < dust_threshold(&script_pubkey) {
+                return Err(SelectionError::DustOutput(i));
+            }
+            output.push(TxOut {
+                value: payment.amount,
+                script_pubkey,
+            });
+        }
+        if let Some(op_return) = &self.op_return {
+            output.push(op_return.build()?.output()?);
+        }
+
+        let master = self.seed.master_key();
+        let (change_key, change_source) = key_source(&master, parse_path(change_path)?)?;
+        let change_address = Address::p2wpkh(&change_key, self.network);
+        let change_script = change_address.script_pubkey();
+
+        let mut candidates = Vec::new();
+        for utxo in &self.utxos {
+            candidates.push(Candidate {
+                value: utxo.amount,
+                weight: utxo.weight(utxo.public_key(&master)?.as_deref())?,
+            });
+        }
+        let payments = sum_amounts(output.iter().map(|output| output.value))
+            .ok_or(SelectionError::AmountOutOfRange)?;
+        let outputs_weight: usize = output
+            .iter()
+            .map(|output| output_weight(output.script_pubkey.len()))
+            .sum();
+        let change_fee = fee(output_weight(change_script.len()), self.fee_rate);
+        let change_spend_weight = input_weight(&change_script, None, None).expect("P2WPKH");
+        let target = sum_amounts([
+            payments,
+            fee(TX_OVERHEAD_WEIGHT + outputs_weight, self.fee_rate),
+        ]);
+        let cost_of_change = sum_amounts([
+            change_fee,
+            fee(change_spend_weight, self.long_term_fee_rate),
+        ]);
+        let params = SelectionParams {
+            fee_rate: self.fee_rate,
+            long_term_fee_rate: self.long_term_fee_rate,
+            target: target.ok_or(SelectionError::AmountOutOfRange)?,
+            cost_of_change: cost_of_change.ok_or(SelectionError::AmountOutOfRange)?,
+            change_fee,
+            change_dust: dust_threshold(&change_script),
+        };
+        let selection = select(&candidates, &params, self.algorithm)?;
+
+        if let Some(value) = selection.change {
+            output.push(TxOut {
+                value,
+                script_pubkey: change_script,
+            });
+        }
+        let input = selection
+            .inputs
+            .iter()
+            .map(|&i| {
+                let txid = Txid::from_str(&self.utxos[i].txid).map_err(PsbtError::from)?;
+                Ok(TxIn::new(
+                    OutPoint::new(txid, self.utxos[i].vout),
+                    SEQUENCE_MAX_NON_RBF,
+                ))
+            })
+            .collect::<Result<Vec<_>, SelectionError>>()?;
+        let mut psbt = Psbt::from_unsigned_tx(Transaction {
+            version: 2,
+            input,
+            output,
+            lock_time: self.locktime,
+        })?;
+        for (index, &i) in selection.inputs.iter().enumerate() {
+            self.utxos[i].fill_input(&mut psbt, index, i, &master, self.network)?;
+        }
+        if selection.change.is_some() {
+            let change_index = psbt.outputs.len() - 1;
+            psbt.outputs[change_index]
+                .bip32_derivation
+                .insert(change_key, change_source);
+        }
+
+        // select bounds the sum of every candidate value, so of the selected ones too
+        let spent = sum_amounts(selection.inputs.iter().map(|&i| self.utxos[i].amount))
+            .ok_or(SelectionError::AmountOutOfRange)?;
+        let paid = sum_amounts(psbt.unsigned_tx.output.iter().map(|output| output.value))
+            .ok_or(SelectionError::AmountOutOfRange)?;
+        policy::check_transaction(&psbt.unsigned_tx)?;
+        Ok(SelectionResponse {
+            algorithm: selection.algorithm,
+            inputs: psbt
+                .unsigned_tx
+                .input
+                .iter()
+                .map(|input| input.previous_output.to_string())
+                .collect(),
+            fee: spent - paid,
+            waste: selection.waste,
+            change: selection.change.map(|amount| ChangeOutput {
+                address: change_address.to_string(),
+                amount,
+                path: change_path.to_string(),
+            }),
+            psbt: psbt.to_string(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        chain::{tests::MockChain, Utxo},
+        error::OpReturnError,
+        opcodes::OP_CHECKMULTISIG,
+        script::Builder,
+        signer,
+        wallet::tests::{derive, master, SEED},
+    };
+
+    /// P2WPKH candidates with an effective value of `values` at 1 sat/vB
+    fn candidates(values: &[u64]) -> Vec<Candidate> {
+        values
+            .iter()
+            .map(|value| Candidate {
+                value: value + 68,
+                weight: 272,
+            })
+            .collect()
+    }
+
+    fn selection_params(target: u64) -> SelectionParams {
+        SelectionParams {
+            fee_rate: 1.0,
+            long_term_fee_rate: 1.0,
+            target,
+            cost_of_change: 100,
+            change_fee: 31,
+            change_dust: 294,
+        }
+    }
+
+    #[test]
+    fn test_weights() {
+        let key = [0x02; 33];
+        let p2wpkh = Address::p2wpkh(&key, Network::Bitcoin).script_pubkey();
+        let p2pkh = Address::p2pkh(&key, Network::Bitcoin).script_pubkey();
+        assert_eq!(input_weight(&p2wpkh, None, None), Some(273));
+        assert_eq!(input_weight(&p2pkh, None, None), Some(596));
+        let p2shwpkh = Address::p2shwpkh(&key, Network::Bitcoin).script_pubkey();
+        assert_eq!(input_weight(&p2shwpkh, Some(&p2wpkh), None), Some(365));
+        // a script hash cannot be sized without its script
+        assert_eq!(input_weight(&p2shwpkh, None, None), None);
+        assert_eq!(input_weight(&p2shwpkh, Some(&p2pkh), None), None);
+        assert_eq!(
+            input_weight(
+                &Address::p2tr_tweaked([0x02; 32], Network::Bitcoin).script_pubkey(),
+                None,
+                None
+            ),
+            Some(231)
+        );
+
+        let multisig = Builder::new()
+            .push_int(2)
+            .push_slice(&key)
+            .push_slice(&key)
+            .push_slice(&key)
+            .push_int(3)
+            .push_opcode(OP_CHECKMULTISIG)
+            .into_script();
+        let p2sh = Address::p2sh(&multisig, Network::Bitcoin).script_pubkey();
+        let p2wsh = Address::p2wsh(&multisig, Network::Bitcoin).script_pubkey();
+        let nested = Address::p2shwsh(&multisig, Network::Bitcoin).script_pubkey();
+        assert_eq!(input_weight(&p2sh, Some(&multisig), None), Some(1196));
+        assert_eq!(input_weight(&p2wsh, None, Some(&multisig)), Some(420));
+        assert_eq!(
+            input_weight(&nested, Some(&p2wsh), Some(&multisig)),
+            Some(560)
+        );
+        assert_eq!(input_weight(&p2wsh, None, None), None);
+        assert_eq!(input_weight(&p2wsh, None, Some(&p2wpkh)), None);
+        let p2wsh_true = Address::p2wsh(&[0x51], Network::Bitcoin).script_pubkey();
+        assert_eq!(input_weight(&p2wsh_true, None, Some(&[0x51])), None);
+
+        let utxo: SpendableUtxo = serde_json::from_value(serde_json::json!({
+            "txid": "11".repeat(32),
+            "vout": 0,
+            "amount": 60_000,
+            "address": Address::p2wsh(&multisig, Network::Bitcoin).to_string(),
+            "witness_script": hex::encode(&multisig),
+        }))
+        .unwrap();
+        assert_eq!(utxo.weight(None), Ok(420));
+        let utxo = SpendableUtxo {
+            witness_script: None,
+            ..utxo
+        };
+        assert!(matches!(
+            utxo.weight(None),
+            Err(SelectionError::UnsupportedAddress(_))
+        ));
+        let utxo = SpendableUtxo {
+            satisfaction_weight: Some(400),
+            ..utxo
+        };
+        assert_eq!(utxo.weight(None), Ok(INPUT_BASE_WEIGHT + 400));
+        assert_eq!(dust_threshold(&p2wpkh), 294);
+        assert_eq!(dust_threshold(&p2pkh), 546);
+        assert_eq!(fee(output_weight(p2wpkh.len()), 1.5), 47);
+    }
+
+    #[test]
+    fn test_algorithms() {
+        let params = selection_params(80_000);
+        assert_eq!(
+            select(&candidates(&[MAX_MONEY, 1]), &params, None),
+            Err(SelectionError::AmountOutOfRange)
+        );
+
+        let candidates = candidates(&[100_000, 50_000, 30_000, 20_000]);
+        assert_eq!(branch_and_bound(&candidates, &params), Some(vec![1, 2]));
+        assert_eq!(largest_first(&candidates, &params), Some(vec![0]));
+        assert_eq!(knapsack(&candidates, &params), Some(vec![0]));
+        assert_eq!(
+            branch_and_bound(&candidates, &selection_params(80_101)),
+            None
+        );
+
+        let selection = select(&candidates, &params, None).unwrap();
+        assert_eq!(selection.algorithm, Algorithm::BranchAndBound);
+        assert_eq!((selection.change, selection.waste), (None, 0));
+        let selection = select(&candidates, &params, Some(Algorithm::LargestFirst)).unwrap();
+        assert_eq!(selection.change, Some(100_000 - 80_000 - 31));
+        assert_eq!(selection.waste, 100);
+
+        // every candidate is needed, the excess is too small for a change output
+        let selection = select(
+            &candidates,
+            &selection_params(199_900),
+            Some(Algorithm::Knapsack),
+        )
+        .unwrap();
+        assert_eq!(selection.inputs.len(), 4);
+        assert_eq!((selection.change, selection.waste), (None, 100));
+
+        assert_eq!(
+            select(&candidates, &selection_params(200_001), None),
+            Err(SelectionError::InsufficientFunds {
+                needed: 200_001,
+                available: 200_000
+            })
+        );
+    }
+
+    #[test]
+    fn test_knapsack_subset() {
+        let candidates = candidates(&[7_000, 6_000, 5_000, 4_000, 3_000]);
+        let mut params = selection_params(12_000);
+        params.change_dust = 0;
+        params.change_fee = 0;
+        let inputs = knapsack(&candidates, &params).unwrap();
+        assert_eq!(effective_sum(&candidates, &inputs, &params), 12_000);
+    }
+
+    #[test]
+    fn test_select_request() {
+        let master = master();
+        let address = |path: &str| {
+            let key = derive(path).public_key().serialize();
+            Address::p2wpkh(&key, Network::Bitcoin).to_string()
+        };
+        let request = serde_json::json!({
+            "seed": SEED,
+            "utxos": [
+                {"txid": "11".repeat(32), "vout": 0, "amount": 60_000, "address": address("m/84'/0'/0'/0/0"), "path": "m/84'/0'/0'/0/0"},
+                {"txid": "22".repeat(32), "vout": 1, "amount": 250_000, "address": address("m/84'/0'/0'/0/1"), "path": "m/84'/0'/0'/0/1"},
+                {"txid": "33".repeat(32), "vout": 2, "amount": 40_000, "address": address("m/84'/0'/0'/0/2")},
+            ],
+            "payments": [{"address": "1BgGZ9tcN4rm9KBzDn7KprQz87SZ26SAMH", "amount": 120_000}],
+            "fee_rate": 2.0,
+            "algorithm": "largest_first",
+        });
+        let request: CoinSelectRequest = serde_json::from_value(request).unwrap();
+        let response = request.select(DEFAULT_CHANGE_PATH).unwrap();
+        assert_eq!(response.algorithm, Algorithm::LargestFirst);
+        assert_eq!(response.inputs, [format!("{}:1", "22".repeat(32))]);
+        let change = response.change.as_ref().unwrap();
+        assert_eq!(change.address, address(DEFAULT_CHANGE_PATH));
+        assert_eq!(change.amount + 120_000 + response.fee, 250_000);
+        // one P2WPKH input, a P2PKH and a P2WPKH output
+        assert_eq!(response.fee, fee(TX_OVERHEAD_WEIGHT + 273 + 136 + 124, 2.0));
+
+        let mut psbt = Psbt::from_str(&response.psbt).unwrap();
+        assert_eq!(psbt.outputs[1].bip32_derivation.len(), 1);
+        assert_eq!(
+            psbt.decode(Network::Bitcoin).unwrap().tx.fee,
+            Some(response.fee)
+        );
+        assert_eq!(signer::sign(&mut psbt, &master), Ok(1));
+
+        let mismatch = serde_json::json!({
+            "seed": SEED,
+            "utxos": [{"txid": "11".repeat(32), "vout": 0, "amount": 60_000, "address": address("m/84'/0'/0'/0/0"), "path": "m/84'/0'/0'/0/1"}],
+            "payments": [{"address": "1BgGZ9tcN4rm9KBzDn7KprQz87SZ26SAMH", "amount": 50_000}],
+            "fee_rate": 1.0,
+        });
+        let request: CoinSelectRequest = serde_json::from_value(mismatch).unwrap();
+        assert_eq!(
+            request.select(DEFAULT_CHANGE_PATH).unwrap_err(),
+            SelectionError::KeyMismatch(0)
+        );
+
+        // one payment worth more than all the bitcoins there can be
+        let request = serde_json::json!({
+            "seed": SEED,
+            "utxos": [{"txid": "11".repeat(32), "vout": 0, "amount": MAX_MONEY, "address": address("m/84'/0'/0'/0/0")}],
+            "payments": [
+                {"address": "1BgGZ9tcN4rm9KBzDn7KprQz87SZ26SAMH", "amount": MAX_MONEY},
+                {"address": "1BgGZ9tcN4rm9KBzDn7KprQz87SZ26SAMH", "amount": u64::MAX - MAX_MONEY},
+            ],
+            "fee_rate": 1.0,
+        });
+        let request: CoinSelectRequest = serde_json::from_value(request).unwrap();
+        assert_eq!(
+            request.select(DEFAULT_CHANGE_PATH).unwrap_err(),
+            SelectionError::AmountOutOfRange
+        );
+    }
+
+    #[actix_rt::test]
+    async fn test_change_path() {
+        let json = |change_path: Option<&str>| {
+            serde_json::from_value::<CoinSelectRequest>(serde_json::json!({
+                "seed": SEED,
+                "utxos": [],
+                "payments": [],
+                "fee_rate": 1.0,
+                "change_path": change_path,
+            }))
+            .unwrap()
+        };
+        let change = |index: u32| {
+            let key = derive(&format!("{}/1/{}", CHANGE_ACCOUNT_PATH, index)).public_key();
+            Address::p2wpkh(&key.serialize(), Network::Bitcoin).script_pubkey()
+        };
+        // the first two change addresses received coins, the second one still holds them
+        let chain = MockChain {
+            utxos: vec![Utxo {
+                outpoint: OutPoint::new(Txid([1; 32]), 0),
+                value: 10_000,
+                script_pubkey: change(1),
+                height: Some(700_000),
+            }],
+            history: vec![change(0)],
+            ..MockChain::default()
+        };
+        let request = json(None);
+        assert_eq!(
+            request.change_path(Some(&chain)).await,
+            Ok("m/84'/0'/0'/1/2".to_string())
+        );
+        assert_eq!(
+            request.change_path(None).await,
+            Err(SelectionError::NoChangePath)
+        );
+        assert_eq!(
+            json(Some("m/84'/0'/0'/1/7")).change_path(None).await,
+            Ok("m/84'/0'/0'/1/7".to_string())
+        );
+    }
+
+    #[test]
+    fn test_select_op_return() {
+        let key = derive("m/84'/0'/0'/0/0").public_key().serialize();
+        let address = Address::p2wpkh(&key, Network::Bitcoin).to_string();
+        let json = |data: String| {
+            serde_json::json!({
+                "seed": SEED,
+                "utxos": [{"txid": "11".repeat(32), "vout": 0, "amount": 60_000, "address": address, "path": "m/84'/0'/0'/0/0"}],
+                "payments": [],
+                "op_return": {"data": [data]},
+                "fee_rate": 2.0,
+            })
+        };
+
+        // timestamp a document hash, the rest of the input goes back to change
+        let request: CoinSelectRequest = serde_json::from_value(json("ab".repeat(32))).unwrap();
+        let response = request.select(DEFAULT_CHANGE_PATH).unwrap();
+        // one P2WPKH input, a 34 byte OP_RETURN and a P2WPKH output
+        assert_eq!(response.fee, fee(TX_OVERHEAD_WEIGHT + 273 + 172 + 124, 2.0));
+        assert_eq!(response.change.unwrap().amount + response.fee, 60_000);
+        let psbt = Psbt::from_str(&response.psbt).unwrap();
+        let op_return = &psbt.unsigned_tx.output[0];
+        assert_eq!(op_return.value, 0);
+        assert_eq!(
+            hex::encode(&op_return.script_pubkey),
+            format!("6a20{}", "ab".repeat(32))
+        );
+
+        let request: CoinSelectRequest = serde_json::from_value(json("ab".repeat(81))).unwrap();
+        assert_eq!(
+            request.select(DEFAULT_CHANGE_PATH).unwrap_err(),
+            SelectionError::OpReturn(OpReturnError::DataSize(81))
+        );
+    }
+}
@@ -0,0 +1,262 @@
+use crate::{
+    address::{Address, Network, Payload},
+    error::TransactionError,
+    opcodes,
+    script::to_asm,
+    transaction::{Transaction, TxIn, TxOut},
+};
+use serde::{Deserialize, Serialize};
+use std::str::FromStr;
+
+#[derive(Deserialize, Debug)]
+// Get a raw transaction from user, with the amounts of the outputs it spends to compute the fee
+pub struct DecodeRequest {
+    pub tx: String,
+    #[serde(default)]
+    pub prevout_amounts: Option<Vec<u64>>,
+    #[serde(default)]
+    pub network: Network,
+}
+
+#[derive(Serialize, Debug, PartialEq)]
+/// DecodedTransaction is the readable form of a raw transaction presented to user
+pub struct DecodedTransaction {
+    pub txid: String,
+    pub wtxid: String,
+    pub version: i32,
+    pub size: usize,
+    pub vsize: usize,
+    pub weight: usize,
+    pub locktime: u32,
+    pub rbf: bool,
+    pub fee: Option<u64>,
+    pub inputs: Vec<DecodedInput>,
+    pub outputs: Vec<DecodedOutput>,
+}
+
+#[derive(Serialize, Debug, PartialEq)]
+pub struct DecodedInput {
+    pub outpoint: String,
+    pub script_sig: DecodedScript,
+    pub sequence: u32,
+    pub witness: Vec<String>,
+    pub amount: Option<u64>,
+}
+
+#[derive(Serialize, Debug, PartialEq)]
+pub struct DecodedOutput {
+    pub value: u64,
+    pub script_pubkey: DecodedScript,
+    #[serde(rename = "type")]
+    pub output_type: &'static str,
+    pub address: Option<String>,
+}
+
+#[derive(Serialize, Debug, PartialEq)]
+pub struct DecodedScript {
+    pub hex: String,
+    pub asm: String,
+}
+
+impl DecodedScript {
+    pub fn new(script: &[u8]) -> Self {
+        Self {
+            hex: hex::encode(script),
+            asm: to_asm(script),
+        }
+    }
+}
+
+/// Standard template of `script_pubkey`, `nonstandard` if none matches
+pub fn output_type(script_pubkey: &[u8]) -> &'static str {
+    match Address::from_script_pubkey(script_pubkey, Network::Bitcoin).map(|a| a.payload) {
+        Some(Payload::PubkeyHash(_)) => "p2pkh",
+        Some(Payload::ScriptHash(_)) => "p2sh",
+        Some(Payload::WitnessProgram {
+            version: 0,
+            program,
+        }) if program.len() == 20 => "p2wpkh",
+        Some(Payload::WitnessProgram {
+            version: 0,
+            program,
+        }) if program.len() == 32 => "p2wsh",
+        Some(Payload::WitnessProgram {
+            version: 1,
+            program,
+        }) if program.len() == 32 => "p2tr",
+        Some(Payload::WitnessProgram { .. }) => "witness_unknown",
+        None => match script_pubkey {
+            [opcodes::OP_RETURN, ..] => "op_return",
+            [opcodes::OP_PUSHBYTES_33, key @ .., opcodes::OP_CHECKSIG] if key.len() == 33 => "p2pk",
+            [opcodes::OP_PUSHBYTES_65, key @ .., opcodes::OP_CHECKSIG] if key.len() == 65 => "p2pk",
+            _ => "nonstandard",
+        },
+    }
+}
+
+impl DecodeRequest {
+    pub fn decode(&self) -> Result<DecodedTransaction, TransactionError> {
+        let tx = Transaction::from_str(&self.tx)?;
+        decode(&tx, self.prevout_amounts.as_deref(), self.network)
+    }
+}
+
+/// Decode `tx`, the fee is only known when the amounts of all spent outputs are given
+pub fn decode(
+    tx: &Transaction,
+    prevout_amounts: Option<&[u64]>,
+    network: Network,
+) -> Result<DecodedTransaction, TransactionError> {
+    if let Some(amounts) = prevout_amounts {
+        if amounts.len() != tx.input.len() {
+            return Err(TransactionError::PrevoutCount(
+                amounts.len(),
+                tx.input.len(),
+            ));
+        }
+    }
+    let fee = match prevout_amounts {
+        Some(amounts) => {
+            let input_value = amounts
+                .iter()
+                .try_fold(0u64, |acc, amount| acc.checked_add(*amount))
+                .ok_or(TransactionError::AmountOutOfRange)?;
+            let output_value = tx
+                .output
+                .iter()
+                .try_fold(0u64, |acc, output| acc.checked_add(output.value))
+                .ok_or(TransactionError::AmountOutOfRange)?;
+            Some(
+                input_value
+                    .checked_sub(output_value)
+                    .ok_or(TransactionError::NegativeFee)?,
+            )
+        }
+        None => None,
+    };
+    Ok(DecodedTransaction {
+        txid: tx.txid().to_string(),
+        wtxid: tx.wtxid().to_string(),
+        version: tx.version,
+        size: tx.serialize().len(),
+        vsize: tx.vsize(),
+        weight: tx.weight(),
+        locktime: tx.lock_time,
+        rbf: tx.signals_rbf(),
+        fee,
+        inputs: tx
+            .input
+            .iter()
+            .enumerate()
+            .map(|(i, input)| decode_input(input, prevout_amounts.map(|amounts| amounts[i])))
+            .collect(),
+        outputs: tx
+            .output
+            .iter()
+            .map(|output| decode_output(output, network))
+            .collect(),
+    })
+}
+
+fn decode_input(input: &TxIn, amount: Option<u64>) -> DecodedInput {
+    DecodedInput {
+        outpoint: input.previous_output.to_string(),
+        script_sig: DecodedScript::new(&input.script_sig),
+        sequence: input.sequence,
+        witness: input.witness.0.iter().map(hex::encode).collect(),
+        amount,
+    }
+}
+
+fn decode_output(output: &TxOut, network: Network) -> DecodedOutput {
+    DecodedOutput {
+        value: output.value,
+        script_pubkey: DecodedScript::new(&output.script_pubkey),
+        output_type: output_type(&output.script_pubkey),
+        address: Address::from_script_pubkey(&output.script_pubkey, network)
+            .map(|address| address.to_string()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Native P2WPKH example of BIP143, spending a P2PK and a P2WPKH output
+    const BIP143_P2WPKH_TX: &str = "01000000000102fff7f7881a8099afa6940d42d1e7f6362bec38171ea3edf433541db4e4ad969f00000000494830450221008b9d1dc26ba6a9cb62127b02742fa9d754cd3bebf337f7a55d114c8e5cdd30be022040529b194ba3f9281a99f2b1c0a19c0489bc22ede944ccf4ecbab4cc618ef3ed01eeffffffef51e1b804cc89d182d279655c3aa89e815b1b309fe287d9b2b55d57b90ec68a0100000000ffffffff02202cb206000000001976a9148280b37df378db99f66f85c95a783a76ac7a6d5988ac9093510d000000001976a9143bde42dbee7e4dbe6a21b2d50ce2f0167faa815988ac000247304402203609e17b84f6a7d30c80bfa610b5b4542f32a8a0d5447a12fb1366d7f01cc44a0220573a954c4518331561406f90300e8f3358f51928d43c212a8caed02de67eebee0121025476c2e83188368da1ff3e292e7acafcdb3566bb0ad253f62fc70f07aeee635711000000";
+
+    #[test]
+    fn test_decode() {
+        let request = DecodeRequest {
+            tx: BIP143_P2WPKH_TX.to_string(),
+            prevout_amounts: Some(vec![625_000_000, 600_000_000]),
+            network: Network::Bitcoin,
+        };
+        let decoded = request.decode().unwrap();
+        assert_eq!(decoded.locktime, 17);
+        assert!(decoded.rbf);
+        assert_eq!(decoded.fee, Some(1_225_000_000 - 112_340_000 - 223_450_000));
+        assert_eq!(decoded.inputs[0].sequence, 0xffff_ffee);
+        assert!(decoded.inputs[0].witness.is_empty());
+        assert_eq!(decoded.inputs[1].witness.len(), 2);
+        assert_eq!(decoded.inputs[1].script_sig.asm, "");
+        assert_eq!(decoded.outputs[0].value, 112_340_000);
+        assert_eq!(decoded.outputs[0].output_type, "p2pkh");
+        assert_eq!(
+            decoded.outputs[0].script_pubkey.asm,
+            "OP_DUP OP_HASH160 8280b37df378db99f66f85c95a783a76ac7a6d59 OP_EQUALVERIFY OP_CHECKSIG"
+        );
+        assert!(decoded.outputs[0]
+            .address
+            .as_ref()
+            .unwrap()
+            .starts_with('1'));
+        assert!(decoded.weight < decoded.size * 4);
+
+        let request = DecodeRequest {
+            prevout_amounts: None,
+            ..request
+        };
+        assert_eq!(request.decode().unwrap().fee, None);
+    }
+
+    #[test]
+    fn test_invalid_amounts() {
+        let tx = Transaction::from_str(BIP143_P2WPKH_TX).unwrap();
+        assert_eq!(
+            decode(&tx, Some(&[625_000_000]), Network::Bitcoin),
+            Err(TransactionError::PrevoutCount(1, 2))
+        );
+        assert_eq!(
+            decode(&tx, Some(&[1, 2]), Network::Bitcoin),
+            Err(TransactionError::NegativeFee)
+        );
+        assert_eq!(
+            decode(&tx, Some(&[u64::MAX, 1]), Network::Bitcoin),
+            Err(TransactionError::AmountOutOfRange)
+        );
+    }
+
+    #[test]
+    fn test_output_type() {
+        let key = [0x02; 33];
+        assert_eq!(
+            output_type(&Address::p2wpkh(&key, Network::Bitcoin).script_pubkey()),
+            "p2wpkh"
+        );
+        assert_eq!(
+            output_type(&Address::p2shwpkh(&key, Network::Bitcoin).script_pubkey()),
+            "p2sh"
+        );
+        assert_eq!(
+            output_type(&Address::p2tr_tweaked([0x02; 32], Network::Bitcoin).script_pubkey()),
+            "p2tr"
+        );
+        let mut p2pk = vec![opcodes::OP_PUSHBYTES_33];
+        p2pk.extend(key);
+        p2pk.push(opcodes::OP_CHECKSIG);
+        assert_eq!(output_type(&p2pk), "p2pk");
+        assert_eq!(output_type(&[opcodes::OP_RETURN, 0x01, 0xff]), "op_return");
+        assert_eq!(output_type(&[opcodes::OP_CHECKSIG]), "nonstandard");
+    }
+}
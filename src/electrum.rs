@@ -0,0 +1,479 @@
+use crate::{
+    chain::{btc_per_kvb_to_sat_per_vb, ChainBackend, ChainFuture, Utxo},
+    error::ChainError,
+    hash,
+    transaction::{OutPoint, Transaction, Txid, MAX_MONEY},
+};
+use serde::Deserialize;
+use serde_json::{json, Value};
+use std::{
+    io::{self, BufRead, BufReader, Write},
+    net::{TcpStream, ToSocketAddrs},
+    str::FromStr,
+    time::Duration,
+};
+
+/// Environment variable of the `host:port` of a plain TCP Electrum server, e.g. electrs on
+/// `127.0.0.1:50001`
+pub const ELECTRUM_URL_VAR: &str = "ELECTRUM_URL";
+/// Seconds to wait for the server before giving up on a request
+const TIMEOUT_SECS: u64 = 30;
+
+/// Electrum protocol client, opening a connection per batch of requests
+#[derive(Debug, Clone)]
+pub struct ElectrumClient {
+    address: String,
+}
+
+/// Transaction touching a script, as listed by `blockchain.scripthash.get_history`
+#[derive(Debug, Clone, PartialEq)]
+pub struct HistoryItem {
+    pub txid: Txid,
+    /// Height of the block confirming it, `None` while it is in the mempool
+    pub height: Option<u32>,
+}
+
+#[derive(Deserialize, Debug)]
+struct ElectrumHistory {
+    tx_hash: String,
+    height: i64,
+}
+
+#[derive(Deserialize, Debug)]
+struct ElectrumUnspent {
+    tx_hash: String,
+    tx_pos: u32,
+    height: i64,
+    value: u64,
+}
+
+/// Electrum script hash of `script_pubkey`: its SHA-256, hex encoded in reverse byte order
+pub fn script_hash(script_pubkey: &[u8]) -> String {
+    let mut hash = hash::sha256(script_pubkey);
+    hash.reverse();
+    hex::encode(hash)
+}
+
+/// Mempool transactions have height 0, or -1 when they spend unconfirmed outputs
+fn confirmed_height(height: i64) -> Option<u32> {
+    match height {
+        height if height > 0 => Some(height as u32),
+        _ => None,
+    }
+}
+
+fn parse<T: serde::de::DeserializeOwned>(method: &str, value: Value) -> Result<T, ChainError> {
+    serde_json::from_value(value)
+        .map_err(|e| ChainError::InvalidResponse(format!("{} {}", method, e)))
+}
+
+/// Connect to the first of the socket addresses of `address` that answers within `timeout`
+fn connect(address: &str, timeout: Duration) -> io::Result<TcpStream> {
+    let mut error = io::Error::new(io::ErrorKind::InvalidInput, "no socket address");
+    for socket_address in address.to_socket_addrs()? {
+        match TcpStream::connect_timeout(&socket_address, timeout) {
+            Ok(stream) => return Ok(stream),
+            Err(e) => error = e,
+        }
+    }
+    Err(error)
+}
+
+/// Write `requests` on a new connection to `address` and read their responses in order,
+/// skipping subscription notifications
+fn exchange(address: &str, requests: &[(&str, Value)]) -> Result<Vec<Value>, ChainError> {
+    let connection = |e: io::Error| ChainError::Connection(e.to_string());
+    let timeout = Duration::from_secs(TIMEOUT_SECS);
+    let mut stream = connect(address, timeout).map_err(connection)?;
+    stream.set_read_timeout(Some(timeout)).map_err(connection)?;
+    let mut lines = String::new();
+    for (id, (method, params)) in requests.iter().enumerate() {
+        let request = json!({"jsonrpc": "2.0", "id": id, "method": method, "params": params});
+        lines.push_str(&request.to_string());
+        lines.push('\n');
+    }
+    stream.write_all(lines.as_bytes()).map_err(connection)?;
+
+    let mut results = vec![None; requests.len()];
+    let mut reader = BufReader::new(stream);
+    while results.iter().any(Option::is_none) {
+        let mut line = String::new();
+        if reader.read_line(&mut line).map_err(connection)? == 0 {
+            return Err(ChainError::Connection("connection closed".to_string()));
+        }
+        let response: Value = serde_json::from_str(&line)
+            .map_err(|_| ChainError::InvalidResponse(line.trim_end().to_string()))?;
+        let id = match response["id"].as_u64() {
+            Some(id) if (id as usize) < requests.len() => id as usize,
+            _ => continue,
+        };
+        results[id] = Some(match &response["error"] {
+            Value::Null => Ok(response["result"].clone()),
+            error => Err(ChainError::Rpc {
+                code: error["code"].as_i64().unwrap_or_default(),
+                message: error["message"]
+                    .as_str()
+                    .map(str::to_string)
+                    .unwrap_or_else(|| error.to_string()),
+            }),
+        });
+    }
+    results.into_iter().map(Option::unwrap).collect()
+}
+
+impl ElectrumClient {
+    pub fn new(address: &str) -> Self {
+        Self {
+            address: address.to_string(),
+        }
+    }
+
+    /// Client of the server configured in the environment, if any
+    pub fn from_env() -> Option<Self> {
+        std::env::var(ELECTRUM_URL_VAR)
+            .ok()
+            .map(|address| Self::new(&address))
+    }
+
+    /// Send `requests` in one go and wait for every result, in request order
+    pub async fn batch(
+        &self,
+        requests: Vec<(&'static str, Value)>,
+    ) -> Result<Vec<Value>, ChainError> {
+        let address = self.address.clone();
+        actix_rt::task::spawn_blocking(move || exchange(&address, &requests))
+            .await
+            .map_err(|e| ChainError::Connection(e.to_string()))?
+    }
+
+    pub async fn call(&self, method: &'static str, params: Value) -> Result<Value, ChainError> {
+        let mut results = self.batch(vec![(method, params)]).await?;
+        Ok(results.remove(0))
+    }
+
+    /// Confirmed and mempool transactions of `script_pubkey`
+    pub async fn history(&self, script_pubkey: &[u8]) -> Result<Vec<HistoryItem>, ChainError> {
+        const METHOD: &str = "blockchain.scripthash.get_history";
+        let history: Vec<ElectrumHistory> = parse(
+            METHOD,
+            self.call(METHOD, json!([script_hash(script_pubkey)]))
+                .await?,
+        )?;
+        history
+            .into_iter()
+            .map(|item| {
+                Ok(HistoryItem {
+                    txid: Txid::from_str(&item.tx_hash)?,
+                    height: confirmed_height(item.height),
+                })
+            })
+            .collect()
+    }
+
+    /// Status of `script_pubkey` from `blockchain.scripthash.subscribe`, a hash of its history
+    /// that is `None` for scripts never used
+    pub async fn status(&self, script_pubkey: &[u8]) -> Result<Option<String>, ChainError> {
+        const METHOD: &str = "blockchain.scripthash.subscribe";
+        parse(
+            METHOD,
+            self.call(METHOD, json!([script_hash(script_pubkey)]))
+                .await?,
+        )
+    }
+
+    async fn list_unspent(&self, script_pubkeys: &[Vec<u8>]) -> Result<Vec<Utxo>, ChainError> {
+        const METHOD: &str = "blockchain.scripthash.listunspent";
+        let requests = script_pubkeys
+            .iter()
+            .map(|script_pubkey| (METHOD, json!([script_hash(script_pubkey)])))
+            .collect();
+        let mut utxos = Vec::new();
+        for (script_pubkey, result) in script_pubkeys.iter().zip(self.batch(requests).await?) {
+            for unspent in parse::<Vec<ElectrumUnspent>>(METHOD, result)? {
+                if unspent.value > MAX_MONEY {
+                    return Err(ChainError::InvalidResponse(format!(
+                        "amount {}",
+                        unspent.value
+                    )));
+                }
+                utxos.push(Utxo {
+                    outpoint: OutPoint::new(Txid::from_str(&unspent.tx_hash)?, unspent.tx_pos),
+                    value: unspent.value,
+                    script_pubkey: script_pubkey.clone(),
+                    height: confirmed_height(unspent.height),
+                });
+            }
+        }
+        Ok(utxos)
+    }
+}
+
+impl ChainBackend for ElectrumClient {
+    fn utxos<'a>(&'a self, script_pubkeys: &'a [Vec<u8>]) -> ChainFuture<'a, Vec<Utxo>> {
+        Box::pin(self.list_unspent(script_pubkeys))
+    }
+
+    fn transaction(&self, txid: Txid) -> ChainFuture<'_, Transaction> {
+        Box::pin(async move {
+            const METHOD: &str = "blockchain.transaction.get";
+            let tx: String = parse(METHOD, self.call(METHOD, json!([txid.to_string()])).await?)?;
+            Ok(Transaction::from_str(&tx)?)
+        })
+    }
+
+    fn broadcast<'a>(&'a self, tx: &'a Transaction) -> ChainFuture<'a, Txid> {
+        Box::pin(async move {
+            const METHOD: &str = "blockchain.transaction.broadcast";
+            let txid: String = parse(
+                METHOD,
+                self.call(METHOD, json!([hex::encode(tx.serialize())]))
+                    .await?,
+            )?;
+            Ok(Txid::from_str(&txid)?)
+        })
+    }
+
+    /// `blockchain.estimatefee` answers -1 when the server has no estimate
+    fn estimate_fee(&self, target: u16) -> ChainFuture<'_, f64> {
+        Box::pin(async move {
+            const METHOD: &str = "blockchain.estimatefee";
+            let fee_rate: f64 = parse(METHOD, self.call(METHOD, json!([target])).await?)?;
+            match fee_rate {
+                fee_rate if fee_rate > 0.0 => Ok(btc_per_kvb_to_sat_per_vb(fee_rate)),
+                _ => Err(ChainError::NoFeeEstimate(target)),
+            }
+        })
+    }
+
+    /// A script has a status as soon as a transaction touches it, spent or not
+    fn used<'a>(&'a self, script_pubkeys: &'a [Vec<u8>]) -> ChainFuture<'a, Vec<bool>> {
+        Box::pin(async move {
+            const METHOD: &str = "blockchain.scripthash.subscribe";
+            let requests = script_pubkeys
+                .iter()
+                .map(|script_pubkey| (METHOD, json!([script_hash(script_pubkey)])))
+                .collect();
+            Ok(self
+                .batch(requests)
+                .await?
+                .iter()
+                .map(|status| !status.is_null())
+                .collect())
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        transaction::{TxIn, TxOut, SEQUENCE_FINAL},
+        wallet::{Multisig, PubKey, Segwit},
+    };
+    use secp256k1::{PublicKey, Secp256k1, SecretKey};
+    use std::{
+        collections::HashMap,
+        net::TcpListener,
+        sync::{Arc, Mutex},
+        thread,
+    };
+
+    /// Electrum server stand-in answering each request line with `respond`, after a
+    /// notification to check that the client skips them
+    fn mock_electrum<F>(respond: F) -> String
+    where
+        F: Fn(&str, &Value) -> Result<Value, (i64, &'static str)> + Send + Sync + 'static,
+    {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        let respond = Arc::new(respond);
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                let mut stream = stream.unwrap();
+                let respond = respond.clone();
+                thread::spawn(move || {
+                    let reader = BufReader::new(stream.try_clone().unwrap());
+                    for line in reader.lines() {
+                        let request: Value = serde_json::from_str(&line.unwrap()).unwrap();
+                        let method = request["method"].as_str().unwrap();
+                        let response = match respond(method, &request["params"]) {
+                            Ok(result) => {
+                                json!({"jsonrpc": "2.0", "id": request["id"], "result": result})
+                            }
+                            Err((code, message)) => json!({
+                                "jsonrpc": "2.0",
+                                "id": request["id"],
+                                "error": {"code": code, "message": message}
+                            }),
+                        };
+                        let notification = json!({
+                            "jsonrpc": "2.0",
+                            "method": "blockchain.headers.subscribe",
+                            "params": [{"height": 700_001, "hex": ""}]
+                        });
+                        writeln!(stream, "{}\n{}", notification, response).unwrap();
+                    }
+                });
+            }
+        });
+        address
+    }
+
+    fn public_key(byte: u8) -> PubKey {
+        let secret_key = SecretKey::from_slice(&[byte; 32]).unwrap();
+        PublicKey::from_secret_key(&Secp256k1::new(), &secret_key).serialize()
+    }
+
+    fn transaction(script_pubkey: Vec<u8>) -> Transaction {
+        Transaction {
+            version: 2,
+            input: vec![TxIn::new(OutPoint::new(Txid([1; 32]), 0), SEQUENCE_FINAL)],
+            output: vec![TxOut {
+                value: 50_000,
+                script_pubkey,
+            }],
+            lock_time: 0,
+        }
+    }
+
+    #[test]
+    fn test_script_hash() {
+        // example of the Electrum protocol documentation, the P2PKH output of
+        // 1A1zP1eP5QGefi2DMPTfTL5SLmv7DivfNa
+        let script_pubkey =
+            hex::decode("76a91462e907b15cbf27d5425399ebf6f0fb50ebb88f1888ac").unwrap();
+        assert_eq!(
+            script_hash(&script_pubkey),
+            "8b01df4e368ea28f8dc0423bcf7a4923e3a12d307c875e47a0cfbf90b5c39161"
+        );
+    }
+
+    #[actix_rt::test]
+    async fn test_scripthash_methods() {
+        let segwit = Segwit::public_key(public_key(1)).script_pubkey();
+        let multisig = Multisig::m(2)
+            .n(3)
+            .public_keys((1..=3).map(public_key).collect())
+            .generate_address()
+            .unwrap()
+            .script_pubkey()
+            .unwrap();
+        let funding = transaction(segwit.clone());
+        let txid = funding.txid().to_string();
+
+        let mut unspent = HashMap::new();
+        unspent.insert(
+            script_hash(&segwit),
+            json!([
+                {"tx_hash": txid, "tx_pos": 0, "height": 700_000, "value": 50_000},
+                {"tx_hash": "22".repeat(32), "tx_pos": 3, "height": 0, "value": 1_000},
+            ]),
+        );
+        let transactions = Mutex::new(HashMap::new());
+        transactions
+            .lock()
+            .unwrap()
+            .insert(txid.clone(), hex::encode(funding.serialize()));
+        let address = mock_electrum(move |method, params| {
+            let key = params[0].as_str().unwrap_or_default().to_string();
+            match method {
+                "blockchain.scripthash.listunspent" => {
+                    Ok(unspent.get(&key).cloned().unwrap_or_else(|| json!([])))
+                }
+                "blockchain.scripthash.get_history" if unspent.contains_key(&key) => Ok(json!([
+                    {"tx_hash": txid, "height": 700_000},
+                    {"tx_hash": "22".repeat(32), "height": -1, "fee": 200},
+                ])),
+                "blockchain.scripthash.get_history" => Ok(json!([])),
+                "blockchain.scripthash.subscribe" if unspent.contains_key(&key) => {
+                    Ok(json!("ab".repeat(32)))
+                }
+                "blockchain.scripthash.subscribe" => Ok(Value::Null),
+                "blockchain.transaction.get" => match transactions.lock().unwrap().get(&key) {
+                    Some(tx) => Ok(json!(tx)),
+                    None => Err((2, "missing transaction")),
+                },
+                "blockchain.transaction.broadcast" => {
+                    let tx = Transaction::from_str(&key).unwrap();
+                    transactions
+                        .lock()
+                        .unwrap()
+                        .insert(tx.txid().to_string(), key);
+                    Ok(json!(tx.txid().to_string()))
+                }
+                "blockchain.estimatefee" if params[0] == 6 => Ok(json!(0.00012)),
+                "blockchain.estimatefee" => Ok(json!(-1)),
+                _ => Err((-32601, "unknown method")),
+            }
+        });
+        let electrum = ElectrumClient::new(&address);
+
+        let utxos = electrum
+            .utxos(&[segwit.clone(), multisig.clone()])
+            .await
+            .unwrap();
+        assert_eq!(utxos.len(), 2);
+        assert_eq!(
+            utxos[0],
+            Utxo {
+                outpoint: OutPoint::new(funding.txid(), 0),
+                value: 50_000,
+                script_pubkey: segwit.clone(),
+                height: Some(700_000),
+            }
+        );
+        assert_eq!(utxos[1].height, None);
+
+        let history = electrum.history(&segwit).await.unwrap();
+        assert_eq!(
+            history[0],
+            HistoryItem {
+                txid: funding.txid(),
+                height: Some(700_000)
+            }
+        );
+        assert_eq!(history[1].height, None);
+        assert!(electrum.history(&multisig).await.unwrap().is_empty());
+        assert_eq!(electrum.status(&segwit).await, Ok(Some("ab".repeat(32))));
+        assert_eq!(electrum.status(&multisig).await, Ok(None));
+        assert_eq!(
+            electrum.used(&[multisig.clone(), segwit.clone()]).await,
+            Ok(vec![false, true])
+        );
+
+        assert_eq!(electrum.transaction(funding.txid()).await, Ok(funding));
+        let spend = transaction(multisig);
+        assert_eq!(electrum.broadcast(&spend).await, Ok(spend.txid()));
+        assert_eq!(electrum.transaction(spend.txid()).await, Ok(spend));
+        assert_eq!(
+            electrum.transaction(Txid([3; 32])).await,
+            Err(ChainError::Rpc {
+                code: 2,
+                message: "missing transaction".to_string()
+            })
+        );
+
+        assert_eq!(electrum.estimate_fee(6).await, Ok(12.0));
+        assert_eq!(
+            electrum.estimate_fee(1).await,
+            Err(ChainError::NoFeeEstimate(1))
+        );
+    }
+
+    #[actix_rt::test]
+    async fn test_amount_out_of_range() {
+        let address = mock_electrum(|method, _| match method {
+            "blockchain.scripthash.listunspent" => Ok(json!([
+                {"tx_hash": "11".repeat(32), "tx_pos": 0, "height": 700_000, "value": MAX_MONEY + 1},
+            ])),
+            _ => Err((-32601, "unknown method")),
+        });
+        let electrum = ElectrumClient::new(&address);
+        assert!(matches!(
+            electrum
+                .utxos(&[Segwit::public_key(public_key(1)).script_pubkey()])
+                .await,
+            Err(ChainError::InvalidResponse(_))
+        ));
+    }
+}
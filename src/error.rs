@@ -13,6 +13,466 @@ pub enum WalletError {
     LargeN,
     #[error("N is less than the total number of public keys")]
     InvalidN,
+    #[error("Locktime is out of range")]
+    InvalidLockTime,
+    #[error("Public key is not a valid curve point")]
+    InvalidPublicKey,
+    #[error("Hash has the wrong length")]
+    InvalidHashLength,
+    #[error("HTLC needs a recipient, a refund key and a lock")]
+    IncompleteHtlc,
+    #[error("Leaf version must be even and must not be 0x50")]
+    InvalidLeafVersion,
+    #[error("Taproot output without an internal key needs script leaves")]
+    UnspendableTaproot,
+    #[error("Taproot script tree is deeper than 128 levels")]
+    TaprootTreeTooDeep,
+    #[error("Signature is not a valid base64 compact signature")]
+    InvalidSignature,
+    #[error("Address is not a valid base58check or segwit address")]
+    InvalidAddress,
+    #[error("Message signature format is not supported for this address")]
+    UnsupportedProof,
+    #[error("Derivation path is not valid, e.g. m/84'/0'/0'/0/0")]
+    InvalidDerivationPath,
+    #[error("Private key is not a valid WIF")]
+    InvalidWif,
+    #[error("Secret key is zero or not below the curve order")]
+    InvalidSecretKey,
+    #[error("Fee rate must be a non negative number of sat/vB")]
+    InvalidFeeRate,
+    #[error("Extended public key is not a valid base58check xpub or tpub")]
+    InvalidExtendedKey,
+    #[error("Hardened children cannot be derived from an extended public key")]
+    HardenedDerivation,
+    #[error("Give the policy of the miniscript to estimate")]
+    MissingPolicy,
+    #[error("Transaction is too large to estimate")]
+    TransactionTooLarge,
+    #[error("Invalid policy: {0}")]
+    Miniscript(#[from] MiniscriptError),
+    #[error("Not standard: {0}")]
+    Policy(#[from] PolicyError),
+}
+
+#[derive(Error, Debug, Clone, PartialEq)]
+pub enum ScriptError {
+    #[error("Script evaluated without error but finished with a false/empty top stack element")]
+    EvalFalse,
+    #[error("OP_RETURN was encountered")]
+    OpReturn,
+    #[error("Script is larger than 10000 bytes")]
+    ScriptSize,
+    #[error("Push value size limit exceeded")]
+    PushSize,
+    #[error("Operation limit exceeded")]
+    OpCount,
+    #[error("Stack size limit exceeded")]
+    StackSize,
+    #[error("Signature count negative or greater than pubkey count")]
+    SigCount,
+    #[error("Pubkey count negative or limit exceeded")]
+    PubkeyCount,
+    #[error("Script ends in the middle of a push")]
+    BadPush,
+    #[error("Script failed an OP_VERIFY operation")]
+    Verify,
+    #[error("Script failed an OP_EQUALVERIFY operation")]
+    EqualVerify,
+    #[error("Script failed an OP_CHECKMULTISIGVERIFY operation")]
+    CheckMultisigVerify,
+    #[error("Script failed an OP_CHECKSIGVERIFY operation")]
+    CheckSigVerify,
+    #[error("Script failed an OP_NUMEQUALVERIFY operation")]
+    NumEqualVerify,
+    #[error("Opcode missing or not understood")]
+    BadOpcode,
+    #[error("Attempted to use a disabled opcode")]
+    DisabledOpcode,
+    #[error("Operation not valid with the current stack size")]
+    InvalidStackOperation,
+    #[error("Operation not valid with the current altstack size")]
+    InvalidAltstackOperation,
+    #[error("Invalid OP_IF construction")]
+    UnbalancedConditional,
+    #[error("Negative locktime")]
+    NegativeLocktime,
+    #[error("Locktime requirement not satisfied")]
+    UnsatisfiedLocktime,
+    #[error("Script number overflow")]
+    NumOverflow,
+    #[error("Non-minimally encoded script number")]
+    NumNonMinimal,
+    #[error("Data push larger than necessary")]
+    MinimalData,
+    #[error("OP_IF/NOTIF argument must be minimal")]
+    MinimalIf,
+    #[error("Only push operators allowed in signatures")]
+    SigPushOnly,
+    #[error("Non-canonical DER signature")]
+    SigDer,
+    #[error("Public key is neither compressed or uncompressed")]
+    PubkeyType,
+    #[error("Dummy CHECKMULTISIG argument must be zero")]
+    NullDummy,
+    #[error("Signature must be zero for failed CHECK(MULTI)SIG operation")]
+    NullFail,
+    #[error("Stack size must be exactly one after execution")]
+    CleanStack,
+    #[error("Witness program has incorrect length")]
+    WitnessProgramWrongLength,
+    #[error("Witness program was passed an empty witness")]
+    WitnessProgramWitnessEmpty,
+    #[error("Witness program hash mismatch")]
+    WitnessProgramMismatch,
+    #[error("Witness requires empty scriptSig")]
+    WitnessMalleated,
+    #[error("Witness requires only-redeemscript scriptSig")]
+    WitnessMalleatedP2sh,
+    #[error("Witness provided for non-witness script")]
+    WitnessUnexpected,
+    #[error("Using non-compressed keys in segwit")]
+    WitnessPubkeyType,
+    #[error("Invalid Schnorr signature")]
+    SchnorrSig,
+    #[error("Invalid Schnorr signature size")]
+    SchnorrSigSize,
+    #[error("Invalid Schnorr signature hash type")]
+    SchnorrSigHashType,
+    #[error("Invalid Taproot control block size")]
+    TaprootWrongControlSize,
+    #[error("Too much signature validation relative to witness weight")]
+    TapscriptValidationWeight,
+    #[error("OP_CHECKMULTISIG(VERIFY) is not available in tapscript")]
+    TapscriptCheckMultisig,
+    #[error("OP_IF/NOTIF argument must be minimal in tapscript")]
+    TapscriptMinimalIf,
+    #[error("Empty public key in tapscript")]
+    TapscriptEmptyPubkey,
+    #[error("NOPx reserved for soft-fork upgrades")]
+    DiscourageUpgradableNops,
+    #[error("Witness version reserved for soft-fork upgrades")]
+    DiscourageUpgradableWitnessProgram,
+    #[error("Taproot version reserved for soft-fork upgrades")]
+    DiscourageUpgradableTaprootVersion,
+    #[error("OP_SUCCESSx reserved for soft-fork upgrades")]
+    DiscourageOpSuccess,
+    #[error("Public key version reserved for soft-fork upgrades")]
+    DiscourageUpgradablePubkeyType,
+}
+
+#[derive(Error, Debug, Clone, PartialEq)]
+pub enum MiniscriptError {
+    #[error("Invalid policy expression: {0}")]
+    Parse(String),
+    #[error("Invalid public key: {0}")]
+    InvalidKey(String),
+    #[error("Invalid timelock: {0}")]
+    InvalidTimelock(u32),
+    #[error("Threshold must be between 1 and the number of sub-policies")]
+    InvalidThreshold,
+    #[error("Miniscript type check failed: {0}")]
+    TypeCheck(String),
+    #[error("Policy can be satisfied without a signature")]
+    NoSignature,
+    #[error("Policy has no non-malleable satisfaction")]
+    Malleable,
+    #[error("Policy mixes height and time based timelocks in one spending path")]
+    TimelockMixing,
+    #[error("Policy contains a public key more than once")]
+    RepeatedKey,
+    #[error("Policy can never be satisfied")]
+    Unsatisfiable,
+    #[error("Compiled script exceeds the standard {0} limit")]
+    ResourceLimits(&'static str),
+    #[error("Not standard: {0}")]
+    Policy(#[from] PolicyError),
+}
+
+#[derive(Error, Debug, Clone, PartialEq)]
+pub enum TransactionError {
+    #[error("Unexpected end of data")]
+    UnexpectedEnd,
+    #[error("Data left after the end of the transaction")]
+    TrailingData,
+    #[error("Non-canonical CompactSize")]
+    NonCanonicalCompactSize,
+    #[error("CompactSize larger than the remaining data")]
+    OversizedCompactSize,
+    #[error("Unknown segwit flag {0}")]
+    UnknownSegwitFlag(u8),
+    #[error("Segwit marker and flag without any witness")]
+    SuperfluousWitness,
+    #[error("Transaction has no inputs")]
+    NoInputs,
+    #[error("Transaction is not valid hex")]
+    InvalidHex,
+    #[error("{0} prevout amounts given for {1} inputs")]
+    PrevoutCount(usize, usize),
+    #[error("Amount out of range")]
+    AmountOutOfRange,
+    #[error("Outputs spend more than the inputs")]
+    NegativeFee,
+}
+
+#[derive(Error, Debug, Clone, PartialEq)]
+pub enum PsbtError {
+    #[error("Missing the PSBT magic bytes")]
+    InvalidMagic,
+    #[error("PSBT is not valid base64")]
+    InvalidBase64,
+    #[error("Script or transaction is not valid hex")]
+    InvalidHex,
+    #[error("Address is not a valid base58check or segwit address")]
+    InvalidAddress,
+    #[error("Key {0} appears more than once in a map")]
+    DuplicateKey(String),
+    #[error("Invalid key of type {0:#04x}")]
+    InvalidKey(u8),
+    #[error("Invalid value of type {0:#04x}")]
+    InvalidValue(u8),
+    #[error("Missing the unsigned transaction")]
+    MissingUnsignedTx,
+    #[error("Unsigned transaction has a scriptSig or a witness")]
+    SignedUnsignedTx,
+    #[error("Non-witness UTXO of input {0} is not the transaction it spends")]
+    MismatchedUtxo(usize),
+    #[error("Unsupported PSBT version {0}")]
+    UnsupportedVersion(u32),
+    #[error("Missing the UTXO spent by input {0}")]
+    MissingUtxo(usize),
+    #[error("Scripts of input {0} do not match the output it spends")]
+    ScriptMismatch(usize),
+    #[error("Invalid sighash type {0:#x}")]
+    InvalidSighashType(u32),
+    #[error("Cannot derive a key of the PSBT")]
+    KeyDerivation,
+    #[error("No PSBT to combine")]
+    NoPsbt,
+    #[error("PSBTs do not share the same unsigned transaction")]
+    MismatchedTransaction,
+    #[error("Input {0} does not have enough signatures to be finalized")]
+    MissingSignatures(usize),
+    #[error("Input {0} spends a script the finalizer does not support")]
+    UnsupportedScript(usize),
+    #[error("Input {0} is not finalized")]
+    NotFinalized(usize),
+    #[error("Cannot compute the signature hash: {0}")]
+    Sighash(#[from] SighashError),
+    #[error("Invalid transaction: {0}")]
+    Transaction(#[from] TransactionError),
+    #[error("Not standard: {0}")]
+    Policy(#[from] PolicyError),
+}
+
+#[derive(Error, Debug, Clone, PartialEq)]
+pub enum SighashError {
+    #[error("Input {0} does not exist")]
+    InputIndexOutOfRange(usize),
+    #[error("Invalid sighash type {0:#04x}")]
+    InvalidHashType(u8),
+    #[error("{0} spent outputs given for {1} inputs")]
+    PrevoutCount(usize, usize),
+    #[error("SIGHASH_SINGLE of input {0} without a matching output")]
+    SingleWithoutOutput(usize),
+}
+
+#[derive(Error, Debug, Clone, PartialEq)]
+pub enum SelectionError {
+    #[error("Fee rate must be a positive number of sat/vB")]
+    InvalidFeeRate,
+    #[error("No payment to fund")]
+    NoPayment,
+    #[error("Address {0} is not a valid base58check or segwit address")]
+    InvalidAddress(String),
+    #[error("Cannot estimate the weight of a spend from {0}, give its satisfaction weight")]
+    UnsupportedAddress(String),
+    #[error("Payment {0} is below the dust threshold")]
+    DustOutput(usize),
+    #[error("Insufficient funds: {needed} sat needed, {available} sat available after fees")]
+    InsufficientFunds { needed: u64, available: u64 },
+    #[error("Derivation path is not valid, e.g. m/84'/0'/0'/1/0")]
+    InvalidDerivationPath,
+    #[error("Key derived at the path of UTXO {0} does not match its address")]
+    KeyMismatch(usize),
+    #[error("Cannot derive a key of the seed")]
+    KeyDerivation,
+    #[error("Invalid PSBT: {0}")]
+    Psbt(#[from] PsbtError),
+    #[error("Not standard: {0}")]
+    Policy(#[from] PolicyError),
+    #[error("Invalid OP_RETURN output: {0}")]
+    OpReturn(#[from] OpReturnError),
+    #[error("Give the change path, no chain backend is configured to find an unused one")]
+    NoChangePath,
+    #[error("Cannot find an unused change address: {0}")]
+    ChangeLookup(#[from] BalanceError),
+    #[error("Amounts add up to more than the 21 million bitcoins there can be")]
+    AmountOutOfRange,
+}
+
+#[derive(Error, Debug, Clone, PartialEq)]
+pub enum FeeBumpError {
+    #[error("Transaction does not signal replace-by-fee (BIP125)")]
+    NotReplaceable,
+    #[error("Prevouts must be given for every input of the transaction, in input order")]
+    MissingPrevouts,
+    #[error("Prevout {0} is not the output spent by the input at the same index")]
+    MismatchedPrevout(usize),
+    #[error("Outputs of the transaction are worth more than its prevouts")]
+    NegativeFee,
+    #[error("Transaction has no output to the change address")]
+    NoChangeOutput,
+    #[error("Transaction already pays {0} sat/vB, at least the target fee rate")]
+    FeeRateMet(f64),
+    #[error("Invalid transaction: {0}")]
+    Transaction(#[from] TransactionError),
+    #[error(transparent)]
+    Selection(#[from] SelectionError),
+}
+
+#[derive(Error, Debug, Clone, PartialEq)]
+pub enum PolicyError {
+    #[error("{keys} keys exceed the standard limit of {limit} for a multisig script")]
+    TooManyKeys { keys: usize, limit: usize },
+    #[error("Redeem script is {0} bytes, P2SH can push at most 520")]
+    RedeemScriptSize(usize),
+    #[error("Witness script is {0} bytes, more than the standard 3600")]
+    WitnessScriptSize(usize),
+    #[error("Redeem script has {0} sigops, more than the standard 15")]
+    TooManySigops(usize),
+    #[error("Transaction version {0} is not standard")]
+    Version(i32),
+    #[error("Transaction weight {0} exceeds the standard 400000")]
+    Weight(usize),
+    #[error("Transaction without witnesses is smaller than 65 bytes")]
+    TooSmall,
+    #[error("scriptSig of input {0} is larger than 1650 bytes")]
+    ScriptSigSize(usize),
+    #[error("scriptSig of input {0} is not push only")]
+    ScriptSigNotPushOnly(usize),
+    #[error("Output {0} pays to a non-standard script")]
+    NonStandardOutput(usize),
+    #[error("Output {0} is below the dust threshold")]
+    Dust(usize),
+    #[error("OP_RETURN output {0} is larger than 83 bytes or not push only")]
+    OpReturnSize(usize),
+    #[error("Transaction has more than one OP_RETURN output")]
+    MultipleOpReturn,
+    #[error("Outputs add up to more than the 21 million bitcoins there can be")]
+    AmountOutOfRange,
+    #[error("Leaf version {0:#04x} is not standard to spend, only tapscript 0xc0 is")]
+    LeafVersion(u8),
+    #[error("Tapscript has the OP_SUCCESS opcode {0:#04x} reserved for soft forks")]
+    OpSuccess(u8),
+    #[error("Tapscript does not parse")]
+    InvalidTapscript,
+    #[error("Address pays to a non-standard script")]
+    NonStandardScriptPubkey,
+}
+
+#[derive(Error, Debug, Clone, PartialEq)]
+pub enum OpReturnError {
+    #[error("Data is not valid hex")]
+    InvalidHex,
+    #[error("{0} bytes of data exceed the standard 80 bytes")]
+    DataSize(usize),
+    #[error("OP_RETURN script is {0} bytes, more than the standard 83")]
+    ScriptSize(usize),
+}
+
+#[derive(Error, Debug, Clone, PartialEq)]
+pub enum ChainError {
+    #[error("No chain backend is configured")]
+    NotConfigured,
+    #[error("Cannot reach the chain backend: {0}")]
+    Connection(String),
+    #[error("Chain backend error {code}: {message}")]
+    Rpc { code: i64, message: String },
+    #[error("Unexpected response from the chain backend: {0}")]
+    InvalidResponse(String),
+    #[error("No fee estimate for a confirmation within {0} blocks")]
+    NoFeeEstimate(u16),
+    #[error("The chain backend has no address history, configure an Electrum server")]
+    NoHistory,
+    #[error("Invalid transaction: {0}")]
+    Transaction(#[from] TransactionError),
+}
+
+#[derive(Error, Debug, Clone, PartialEq)]
+pub enum BroadcastError {
+    #[error("Invalid transaction: {0}")]
+    Transaction(#[from] TransactionError),
+    #[error("Not standard: {0}")]
+    Policy(#[from] PolicyError),
+    #[error(
+        "Prevout {0} is not valid: expected a txid, a vout, a valid amount and a hex scriptPubKey"
+    )]
+    InvalidPrevout(usize),
+    #[error("Input {0} spends an output its previous transaction does not have")]
+    MissingPrevout(usize),
+    #[error("Input {index} does not satisfy the script of its prevout: {error}")]
+    Script { index: usize, error: ScriptError },
+    #[error("Outputs spend {outputs} satoshis but inputs only hold {inputs}")]
+    InsufficientInputs { inputs: u64, outputs: u64 },
+    #[error("Amounts add up to more than the 21 million bitcoins there can be")]
+    AmountOutOfRange,
+    #[error("Inputs are missing or already spent: {0}")]
+    MissingInputs(String),
+    #[error("Transaction is already confirmed: {0}")]
+    AlreadyConfirmed(String),
+    #[error("Transaction is already in the mempool: {0}")]
+    AlreadyInMempool(String),
+    #[error("Transaction conflicts with a mempool transaction: {0}")]
+    MempoolConflict(String),
+    #[error("Fee is below the relay or mempool minimum: {0}")]
+    InsufficientFee(String),
+    #[error("Transaction is not final yet: {0}")]
+    NonFinal(String),
+    #[error("Node failed to verify the scripts: {0}")]
+    ScriptRejected(String),
+    #[error("Node sees the transaction as not standard: {0}")]
+    NonStandard(String),
+    #[error("Transaction was rejected: {0}")]
+    Rejected(String),
+    #[error(transparent)]
+    Chain(#[from] ChainError),
+}
+
+#[derive(Error, Debug, Clone, PartialEq)]
+pub enum BalanceError {
+    #[error("Address {0} is not a valid base58check or segwit address")]
+    InvalidAddress(String),
+    #[error("Extended public key is not a valid base58check xpub or tpub")]
+    InvalidExtendedKey,
+    #[error("Gap limit must be between 1 and {0}")]
+    InvalidGapLimit(u32),
+    #[error("Only single key address types can be derived from an extended public key")]
+    UnsupportedAddressType,
+    #[error("Amounts add up to more than the 21 million bitcoins there can be")]
+    AmountOutOfRange,
+    #[error(transparent)]
+    Chain(#[from] ChainError),
+}
+
+#[derive(Error, Debug, Clone, PartialEq)]
+pub enum SweepError {
+    #[error("Give either the WIF of the key or a seed")]
+    KeySource,
+    #[error("Private key is not a valid WIF")]
+    InvalidWif,
+    #[error("No UTXO to sweep")]
+    NoUtxo,
+    #[error("UTXO {0} needs the derivation path of its key in the seed")]
+    MissingPath(usize),
+    #[error("Give the UTXOs, no chain backend is configured to look them up")]
+    NoChainBackend,
+    #[error("Cannot look up the UTXOs: {0}")]
+    Lookup(#[from] BalanceError),
+    #[error(transparent)]
+    Selection(#[from] SelectionError),
+    #[error("Invalid PSBT: {0}")]
+    Psbt(#[from] PsbtError),
 }
 
 #[derive(Debug, Error)]
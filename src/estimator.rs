@@ -0,0 +1,683 @@
+use crate::{
+    address::{Address, Network, ScriptType},
+    error::{MiniscriptError, PolicyError, WalletError},
+    hash,
+    htlc::{Hashlock, Htlc, PREIMAGE_SIZE},
+    interpreter::{is_p2sh, witness_program},
+    miniscript::{self, Context},
+    policy::MAX_P2SH_MULTISIG_KEYS,
+    script::{compact_size_len, parse_multisig},
+    taproot::TapLeaf,
+    timelock::{KeySet, Lock, Timelock},
+};
+use serde::{Deserialize, Serialize};
+
+/// Worst case ECDSA signature: 72 byte DER encoding with a high R, plus the sighash type
+pub const MAX_ECDSA_SIGNATURE_SIZE: usize = 73;
+/// Schnorr signature with a sighash type other than `SIGHASH_DEFAULT`
+pub const MAX_SCHNORR_SIGNATURE_SIZE: usize = 65;
+/// Compressed public key, the only kind segwit allows
+pub const PUBLIC_KEY_SIZE: usize = 33;
+/// Weight of an input without its scriptSig and witness: outpoint and sequence
+const OUTPOINT_AND_SEQUENCE_WEIGHT: usize = (36 + 4) * 4;
+/// Weight of version and locktime
+const VERSION_AND_LOCKTIME_WEIGHT: usize = (4 + 4) * 4;
+/// Weight of the segwit marker and flag, only counted once in a transaction
+const SEGWIT_MARKER_WEIGHT: usize = 2;
+/// Lock with the largest push, used when the request leaves it out
+const WORST_CASE_LOCK: Lock = Lock::After(u32::MAX);
+/// Control block of a tapscript leaf that is the only leaf of its tree
+const CONTROL_BLOCK_SIZE: usize = 33;
+/// Stands in for the keys of the scripts, sizes do not depend on them
+const PLACEHOLDER_KEY: [u8; 33] = [0x02; 33];
+
+/// Address types the server generates
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq)]
+pub enum AddressType {
+    #[serde(rename = "p2pkh")]
+    P2pkh,
+    #[serde(rename = "p2sh-p2wpkh")]
+    P2shP2wpkh,
+    #[serde(rename = "p2wpkh")]
+    P2wpkh,
+    /// Key path spend, as single keys and MuSig2 aggregate keys are spent
+    #[serde(rename = "p2tr")]
+    P2tr,
+    /// m-of-n `OP_CHECKMULTISIG` redeem script
+    #[serde(rename = "p2sh")]
+    P2sh,
+    /// m-of-n `OP_CHECKMULTISIG` witness script
+    #[serde(rename = "p2wsh")]
+    P2wsh,
+    /// m-of-n `OP_CHECKMULTISIG` witness script nested in P2SH
+    #[serde(rename = "p2sh-p2wsh")]
+    P2shP2wsh,
+    /// Script path spend of an m-of-n `multi_a` leaf, the only leaf of its tree
+    #[serde(rename = "p2tr-multi_a")]
+    P2trMultiA,
+    /// P2WSH timelock of `/timelock`, the m-of-n recovery branch alone unless a primary branch
+    /// is given
+    #[serde(rename = "timelock")]
+    Timelock,
+    /// P2WSH hash-time-locked contract of `/htlc`
+    #[serde(rename = "htlc")]
+    Htlc,
+    /// P2WSH script or tapscript leaf compiled from a policy by `/policy`
+    #[serde(rename = "miniscript")]
+    Miniscript,
+}
+
+impl AddressType {
+    /// Length of the scriptPubKey of the address
+    pub fn script_pubkey_len(self) -> usize {
+        match self {
+            AddressType::P2pkh => 25,
+            AddressType::P2sh | AddressType::P2shP2wpkh | AddressType::P2shP2wsh => 23,
+            AddressType::P2wpkh => 22,
+            AddressType::P2wsh
+            | AddressType::P2tr
+            | AddressType::P2trMultiA
+            | AddressType::Timelock
+            | AddressType::Htlc
+            | AddressType::Miniscript => 34,
+        }
+    }
+}
+
+impl From<ScriptType> for AddressType {
+    fn from(script_type: ScriptType) -> Self {
+        match script_type {
+            ScriptType::P2sh => AddressType::P2sh,
+            ScriptType::P2wsh => AddressType::P2wsh,
+            ScriptType::P2shP2wsh => AddressType::P2shP2wsh,
+        }
+    }
+}
+
+/// scriptSig and witness of the largest valid spend of an input
+#[derive(Debug, Clone, PartialEq)]
+pub struct SpendSize {
+    pub script_sig: usize,
+    /// Size of every witness stack item
+    pub witness: Vec<usize>,
+}
+
+/// Size of a minimal push of `len` bytes
+fn push_len(len: usize) -> usize {
+    let opcode_len = match len {
+        0..=75 => 1,
+        76..=0xff => 2,
+        0x100..=0xffff => 3,
+        _ => 5,
+    };
+    opcode_len + len
+}
+
+/// `OP_m <n keys> OP_n OP_CHECKMULTISIG`
+fn multisig_script_len(n: u8) -> usize {
+    1 + n as usize * push_len(PUBLIC_KEY_SIZE) + 1 + 1
+}
+
+/// Witness stack items before the script spending `keys`: a signature, or the dummy element
+/// of `OP_CHECKMULTISIG` and m signatures
+fn key_set_stack(keys: &KeySet) -> Vec<usize> {
+    match keys.public_keys.len() {
+        1 => vec![MAX_ECDSA_SIGNATURE_SIZE],
+        _ => {
+            let mut stack = vec![0];
+            stack.extend(vec![MAX_ECDSA_SIGNATURE_SIZE; keys.m as usize]);
+            stack
+        }
+    }
+}
+
+fn placeholder_keys(m: u8, n: u8) -> KeySet {
+    KeySet::new(m, vec![PLACEHOLDER_KEY; n as usize])
+}
+
+impl SpendSize {
+    /// Worst case spend of `address_type`, `m` and `n` only matter for multisig types, the
+    /// recovery branch of timelocks and `multi_a` leaves
+    ///
+    /// Timelocks and HTLCs are sized with the largest lock and without a primary branch, see
+    /// `timelock` and `htlc` for the others. Miniscript needs the policy, see
+    /// `miniscript_witness_size`.
+    pub fn new(address_type: AddressType, m: u8, n: u8) -> Result<Self, WalletError> {
+        let signature_and_key = vec![MAX_ECDSA_SIGNATURE_SIZE, PUBLIC_KEY_SIZE];
+        let (script_sig, witness) = match address_type {
+            AddressType::P2pkh => (
+                push_len(MAX_ECDSA_SIGNATURE_SIZE) + push_len(PUBLIC_KEY_SIZE),
+                Vec::new(),
+            ),
+            AddressType::P2shP2wpkh => (push_len(22), signature_and_key),
+            AddressType::P2wpkh => (0, signature_and_key),
+            AddressType::P2tr => (0, vec![MAX_SCHNORR_SIGNATURE_SIZE]),
+            AddressType::P2sh | AddressType::P2wsh | AddressType::P2shP2wsh => {
+                match (m, n) {
+                    (_, 0) => return Err(WalletError::EmptyN),
+                    (m, n) if m == 0 || m > n => return Err(WalletError::InvalidM),
+                    (_, n) if n > 16 => return Err(WalletError::NumberOfKeysExceeds),
+                    _ => {}
+                }
+                if address_type == AddressType::P2sh && n as usize > MAX_P2SH_MULTISIG_KEYS {
+                    return Err(PolicyError::TooManyKeys {
+                        keys: n as usize,
+                        limit: MAX_P2SH_MULTISIG_KEYS,
+                    }
+                    .into());
+                }
+                return Ok(Self::multisig(
+                    address_type,
+                    m as usize,
+                    multisig_script_len(n),
+                ));
+            }
+            AddressType::P2trMultiA => return Self::multi_a(m as usize, n as usize),
+            AddressType::Timelock => {
+                return Self::timelock(WORST_CASE_LOCK, None, (m, n), ScriptType::P2wsh)
+            }
+            AddressType::Htlc => return Self::htlc(WORST_CASE_LOCK),
+            AddressType::Miniscript => return Err(WalletError::MissingPolicy),
+        };
+        Ok(Self {
+            script_sig,
+            witness,
+        })
+    }
+
+    /// Spend of an `m`-of-n `OP_CHECKMULTISIG` script of `script_len` bytes behind
+    /// `address_type`
+    fn multisig(address_type: AddressType, m: usize, script_len: usize) -> Self {
+        // the dummy element OP_CHECKMULTISIG pops, then m signatures
+        let mut stack = vec![0];
+        stack.extend(vec![MAX_ECDSA_SIGNATURE_SIZE; m]);
+        Self::script_hash(address_type, stack, script_len)
+    }
+
+    /// Script path spend of a k-of-n `multi_a` leaf: k signatures and empty elements for the
+    /// other keys, the script and the control block
+    pub fn multi_a(k: usize, n: usize) -> Result<Self, WalletError> {
+        let leaf = TapLeaf::multi_a(k, &vec![[0x02; 32]; n])?;
+        let mut witness = vec![MAX_SCHNORR_SIGNATURE_SIZE; k];
+        witness.extend(vec![0; n - k]);
+        witness.push(leaf.script.len());
+        witness.push(CONTROL_BLOCK_SIZE);
+        Ok(Self {
+            script_sig: 0,
+            witness,
+        })
+    }
+
+    /// Spend of the larger branch of a `/timelock` script wrapped as `script_type`, with the
+    /// `(m, n)` key sets of its branches
+    pub fn timelock(
+        lock: Lock,
+        primary: Option<(u8, u8)>,
+        recovery: (u8, u8),
+        script_type: ScriptType,
+    ) -> Result<Self, WalletError> {
+        let recovery = placeholder_keys(recovery.0, recovery.1);
+        recovery.script()?;
+        let mut timelock = Timelock::lock(lock.check()?).recovery(recovery.clone());
+        let mut stacks = vec![key_set_stack(&recovery)];
+        if let Some((m, n)) = primary {
+            let primary = placeholder_keys(m, n);
+            primary.script()?;
+            timelock = timelock.primary(primary.clone());
+            // branch selectors: empty for the recovery branch, 0x01 for the primary one
+            stacks[0].push(0);
+            let mut stack = key_set_stack(&primary);
+            stack.push(1);
+            stacks.push(stack);
+        }
+        let timelock = timelock
+            .generate_script()
+            .expect("checked lock and key sets");
+        let script_len = timelock.witness_script().len();
+        Ok(Self::largest(stacks.into_iter().map(|stack| {
+            Self::script_hash(script_type.into(), stack, script_len)
+        })))
+    }
+
+    /// Spend of the larger branch of a `/htlc` script, with a SHA-256 hashlock as it is larger than
+    /// a HASH160 one
+    pub fn htlc(lock: Lock) -> Result<Self, WalletError> {
+        let htlc = Htlc::hashlock(Hashlock::Sha256([0; 32]))
+            .recipient(PLACEHOLDER_KEY)
+            .refund(PLACEHOLDER_KEY)
+            .lock(lock.check()?)
+            .generate_script()
+            .expect("checked lock");
+        let script_len = htlc.witness_script().len();
+        // claim with a signature and the preimage, or refund with a signature
+        let claim = vec![MAX_ECDSA_SIGNATURE_SIZE, PREIMAGE_SIZE, 1];
+        let refund = vec![MAX_ECDSA_SIGNATURE_SIZE, 0];
+        Ok(Self::largest(vec![claim, refund].into_iter().map(
+            |stack| Self::script_hash(AddressType::P2wsh, stack, script_len),
+        )))
+    }
+
+    fn largest<I: IntoIterator<Item = Self>>(sizes: I) -> Self {
+        sizes
+            .into_iter()
+            .max_by_key(|size| size.input_weight())
+            .expect("at least one branch")
+    }
+
+    /// Spend of a script of `script_len` bytes behind `address_type`, with `stack` before it
+    fn script_hash(address_type: AddressType, mut stack: Vec<usize>, script_len: usize) -> Self {
+        stack.push(script_len);
+        match address_type {
+            AddressType::P2sh => Self {
+                script_sig: stack.into_iter().map(push_len).sum(),
+                witness: Vec::new(),
+            },
+            AddressType::P2wsh => Self {
+                script_sig: 0,
+                witness: stack,
+            },
+            _ => Self {
+                script_sig: push_len(34),
+                witness: stack,
+            },
+        }
+    }
+
+    /// Serialized witness, empty for inputs without one
+    pub fn witness_size(&self) -> usize {
+        if self.witness.is_empty() {
+            return 0;
+        }
+        compact_size_len(self.witness.len())
+            + self
+                .witness
+                .iter()
+                .map(|item| compact_size_len(*item) + item)
+                .sum::<usize>()
+    }
+
+    pub fn input_weight(&self) -> usize {
+        spend_weight(self.script_sig, self.witness_size())
+    }
+}
+
+/// Weight of an input with a scriptSig of `script_sig` bytes and a serialized witness of
+/// `witness_size` bytes
+fn spend_weight(script_sig: usize, witness_size: usize) -> usize {
+    OUTPOINT_AND_SEQUENCE_WEIGHT + (compact_size_len(script_sig) + script_sig) * 4 + witness_size
+}
+
+/// Witness size of spending the script compiled from `policy`, as `/policy` pays to it in
+/// `context`
+pub fn miniscript_witness_size(policy: &str, context: Context) -> Result<usize, WalletError> {
+    Ok(miniscript::compile(&policy.parse()?, context)?
+        .max_satisfaction_weight()
+        .ok_or(MiniscriptError::Unsatisfiable)?)
+}
+
+/// Fee in satoshis of `weight` at `fee_rate` sat/vB, rounded up
+pub fn fee(weight: usize, fee_rate: f64) -> u64 {
+    (weight as f64 * fee_rate / 4.0).ceil() as u64
+}
+
+/// Virtual size of `weight`, rounded up
+pub fn vsize(weight: usize) -> usize {
+    weight.div_ceil(4)
+}
+
+/// Weight of an output paying to a scriptPubKey of `len` bytes
+pub fn output_weight(len: usize) -> usize {
+    (8 + compact_size_len(len) + len) * 4
+}
+
+/// Worst case weight of an input spending `script_pubkey`, `None` for scripts whose
+/// satisfaction is unknown
+///
+/// Script hashes are sized from their `redeem_script` and `witness_script`, which must hash to
+/// the address: P2SH-P2WPKH, and `OP_CHECKMULTISIG` scripts behind P2SH, P2WSH and P2SH-P2WSH.
+pub fn input_weight(
+    script_pubkey: &[u8],
+    redeem_script: Option<&[u8]>,
+    witness_script: Option<&[u8]>,
+) -> Option<usize> {
+    let redeem_script = if is_p2sh(script_pubkey) {
+        Some(redeem_script.filter(|script| hash::hash160(script)[..] == script_pubkey[2..22])?)
+    } else {
+        None
+    };
+    let multisig = |script: &[u8], address_type| {
+        parse_multisig(script).map(|(m, _)| SpendSize::multisig(address_type, m, script.len()))
+    };
+    let witness_multisig = |program: &[u8], address_type| {
+        witness_script
+            .filter(|script| hash::sha256(script)[..] == program[..])
+            .and_then(|script| multisig(script, address_type))
+    };
+    let size = match (
+        redeem_script,
+        witness_program(redeem_script.unwrap_or(script_pubkey)),
+    ) {
+        (None, Some((0, program))) if program.len() == 20 => {
+            SpendSize::new(AddressType::P2wpkh, 0, 0).ok()?
+        }
+        (None, Some((0, program))) if program.len() == 32 => {
+            witness_multisig(program, AddressType::P2wsh)?
+        }
+        (None, Some((1, program))) if program.len() == 32 => {
+            SpendSize::new(AddressType::P2tr, 0, 0).ok()?
+        }
+        (Some(_), Some((0, program))) if program.len() == 20 => {
+            SpendSize::new(AddressType::P2shP2wpkh, 0, 0).ok()?
+        }
+        (Some(_), Some((0, program))) if program.len() == 32 => {
+            witness_multisig(program, AddressType::P2shP2wsh)?
+        }
+        (Some(redeem_script), None) => multisig(redeem_script, AddressType::P2sh)?,
+        (None, None) => {
+            Address::from_script_pubkey(script_pubkey, Network::Bitcoin)?;
+            SpendSize::new(AddressType::P2pkh, 0, 0).ok()?
+        }
+        _ => return None,
+    };
+    Some(size.input_weight())
+}
+
+fn one() -> usize {
+    1
+}
+
+#[derive(Deserialize, Debug)]
+// Get the address type, with m and n for multisig, and the fee rate from user
+pub struct FeeEstimateRequest {
+    pub address_type: AddressType,
+    #[serde(default)]
+    pub m: u8,
+    #[serde(default)]
+    pub n: u8,
+    /// Fee rate in sat/vB
+    pub fee_rate: f64,
+    /// Inputs of the address type in the example transaction
+    #[serde(default = "one")]
+    pub inputs: usize,
+    /// Outputs to the address type in the example transaction
+    #[serde(default = "one")]
+    pub outputs: usize,
+    /// Lock of a timelock or HTLC, the largest absolute locktime when left out
+    #[serde(default)]
+    pub lock: Option<Lock>,
+    /// m and n of the primary branch of a timelock, none when `primary_n` is left out
+    #[serde(default)]
+    pub primary_m: u8,
+    #[serde(default)]
+    pub primary_n: u8,
+    /// How a timelock is wrapped into an output
+    #[serde(default)]
+    pub script_type: ScriptType,
+    /// Policy of a miniscript, compiled for `context`
+    #[serde(default)]
+    pub policy: Option<String>,
+    #[serde(default)]
+    pub context: Context,
+}
+
+#[derive(Serialize, Debug, PartialEq)]
+/// FeeEstimate is the worst case size and fee of spending from and paying to an address type
+pub struct FeeEstimate {
+    pub address_type: AddressType,
+    pub input_weight: usize,
+    pub input_vsize: usize,
+    pub input_fee: u64,
+    pub script_sig_size: usize,
+    pub witness_size: usize,
+    pub output_weight: usize,
+    pub output_vsize: usize,
+    pub output_fee: u64,
+    /// Transaction with `inputs` inputs and `outputs` outputs of the address type
+    pub tx_vsize: usize,
+    pub tx_fee: u64,
+}
+
+/// Worst case sizes and fees at `fee_rate` sat/vB of spending from and paying to `address_type`
+pub fn estimate(
+    address_type: AddressType,
+    m: u8,
+    n: u8,
+    fee_rate: f64,
+    inputs: usize,
+    outputs: usize,
+) -> Result<FeeEstimate, WalletError> {
+    let size = SpendSize::new(address_type, m, n)?;
+    estimate_spend(
+        address_type,
+        (size.script_sig, size.witness_size()),
+        address_type.script_pubkey_len(),
+        fee_rate,
+        inputs,
+        outputs,
+    )
+}
+
+/// Worst case sizes and fees of spending inputs with a scriptSig and a witness of the given
+/// sizes, and of paying to scriptPubKeys of `script_pubkey_len` bytes
+fn estimate_spend(
+    address_type: AddressType,
+    (script_sig_size, witness_size): (usize, usize),
+    script_pubkey_len: usize,
+    fee_rate: f64,
+    inputs: usize,
+    outputs: usize,
+) -> Result<FeeEstimate, WalletError> {
+    if !(fee_rate >= 0.0 && fee_rate.is_finite()) {
+        return Err(WalletError::InvalidFeeRate);
+    }
+    let input_weight = spend_weight(script_sig_size, witness_size);
+    let output_weight = output_weight(script_pubkey_len);
+    let mut overhead_weight =
+        VERSION_AND_LOCKTIME_WEIGHT + (compact_size_len(inputs) + compact_size_len(outputs)) * 4;
+    if inputs > 0 && witness_size > 0 {
+        overhead_weight += SEGWIT_MARKER_WEIGHT;
+    }
+    let tx_weight = inputs
+        .checked_mul(input_weight)
+        .and_then(|weight| weight.checked_add(outputs.checked_mul(output_weight)?))
+        .and_then(|weight| weight.checked_add(overhead_weight))
+        .ok_or(WalletError::TransactionTooLarge)?;
+    Ok(FeeEstimate {
+        address_type,
+        input_weight,
+        input_vsize: vsize(input_weight),
+        input_fee: fee(input_weight, fee_rate),
+        script_sig_size,
+        witness_size,
+        output_weight,
+        output_vsize: vsize(output_weight),
+        output_fee: fee(output_weight, fee_rate),
+        tx_vsize: vsize(tx_weight),
+        tx_fee: fee(tx_weight, fee_rate),
+    })
+}
+
+impl FeeEstimateRequest {
+    pub fn estimate(&self) -> Result<FeeEstimate, WalletError> {
+        let lock = self.lock.unwrap_or(WORST_CASE_LOCK);
+        let (spend_size, script_pubkey_len) = match self.address_type {
+            AddressType::Timelock => {
+                let primary = Some((self.primary_m, self.primary_n)).filter(|(_, n)| *n > 0);
+                let size = SpendSize::timelock(lock, primary, (self.m, self.n), self.script_type)?;
+                (
+                    (size.script_sig, size.witness_size()),
+                    AddressType::from(self.script_type).script_pubkey_len(),
+                )
+            }
+            AddressType::Htlc => {
+                let size = SpendSize::htlc(lock)?;
+                (
+                    (size.script_sig, size.witness_size()),
+                    self.address_type.script_pubkey_len(),
+                )
+            }
+            AddressType::Miniscript => {
+                let policy = self.policy.as_ref().ok_or(WalletError::MissingPolicy)?;
+                (
+                    (0, miniscript_witness_size(policy, self.context)?),
+                    self.address_type.script_pubkey_len(),
+                )
+            }
+            _ => {
+                return estimate(
+                    self.address_type,
+                    self.m,
+                    self.n,
+                    self.fee_rate,
+                    self.inputs,
+                    self.outputs,
+                )
+            }
+        };
+        estimate_spend(
+            self.address_type,
+            spend_size,
+            script_pubkey_len,
+            self.fee_rate,
+            self.inputs,
+            self.outputs,
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::interpreter::tests::keypair;
+
+    #[test]
+    fn test_single_key() {
+        let p2wpkh = estimate(AddressType::P2wpkh, 0, 0, 1.0, 1, 1).unwrap();
+        assert_eq!(p2wpkh.input_weight, 273);
+        assert_eq!(p2wpkh.input_vsize, 69);
+        assert_eq!(p2wpkh.witness_size, 109);
+        assert_eq!(p2wpkh.output_weight, 124);
+        assert_eq!(p2wpkh.tx_vsize, 110);
+        assert_eq!(p2wpkh.tx_fee, 110);
+
+        let p2pkh = SpendSize::new(AddressType::P2pkh, 0, 0).unwrap();
+        assert_eq!(p2pkh.script_sig, 108);
+        assert_eq!(p2pkh.witness_size(), 0);
+        assert_eq!(p2pkh.input_weight(), 596);
+        assert_eq!(
+            SpendSize::new(AddressType::P2shP2wpkh, 0, 0)
+                .unwrap()
+                .input_weight(),
+            365
+        );
+        let p2tr = estimate(AddressType::P2tr, 0, 0, 2.5, 2, 1).unwrap();
+        assert_eq!(p2tr.input_weight, 231);
+        assert_eq!(p2tr.input_fee, 145);
+        assert_eq!(p2tr.output_weight, 172);
+    }
+
+    #[test]
+    fn test_multisig() {
+        let p2wsh = SpendSize::new(AddressType::P2wsh, 2, 3).unwrap();
+        assert_eq!(p2wsh.witness, vec![0, 73, 73, 105]);
+        assert_eq!(p2wsh.input_weight(), 420);
+        let p2sh = SpendSize::new(AddressType::P2sh, 2, 3).unwrap();
+        assert_eq!(p2sh.script_sig, 256);
+        assert_eq!(p2sh.input_weight(), 1196);
+        let nested = SpendSize::new(AddressType::P2shP2wsh, 2, 3).unwrap();
+        assert_eq!(nested.script_sig, 35);
+        assert_eq!(nested.input_weight(), 560);
+
+        assert!(SpendSize::new(AddressType::P2sh, 15, 15).is_ok());
+        assert!(matches!(
+            SpendSize::new(AddressType::P2sh, 1, 16),
+            Err(WalletError::Policy(PolicyError::TooManyKeys {
+                keys: 16,
+                limit: 15
+            }))
+        ));
+        assert!(SpendSize::new(AddressType::P2wsh, 16, 16).is_ok());
+        assert!(matches!(
+            SpendSize::new(AddressType::P2wsh, 3, 2),
+            Err(WalletError::InvalidM)
+        ));
+        assert!(matches!(
+            SpendSize::new(AddressType::P2shP2wsh, 0, 0),
+            Err(WalletError::EmptyN)
+        ));
+        assert!(matches!(
+            estimate(AddressType::P2wpkh, 0, 0, -1.0, 1, 1),
+            Err(WalletError::InvalidFeeRate)
+        ));
+        assert!(matches!(
+            estimate(AddressType::P2wpkh, 0, 0, 1.0, usize::MAX / 100, 1),
+            Err(WalletError::TransactionTooLarge)
+        ));
+    }
+
+    #[test]
+    fn test_script_types() {
+        let multi_a = SpendSize::new(AddressType::P2trMultiA, 2, 3).unwrap();
+        assert_eq!(multi_a.witness, vec![65, 65, 0, 104, 33]);
+        assert_eq!(multi_a.input_weight(), 437);
+        assert!(matches!(
+            SpendSize::new(AddressType::P2trMultiA, 4, 3),
+            Err(WalletError::InvalidM)
+        ));
+
+        // claiming with the preimage: signature, preimage, branch selector and script
+        let htlc = SpendSize::new(AddressType::Htlc, 0, 0).unwrap();
+        assert_eq!(htlc.witness, vec![73, 32, 1, 119]);
+        assert_eq!(htlc.input_weight(), 394);
+
+        let timelock = SpendSize::new(AddressType::Timelock, 1, 1).unwrap();
+        assert_eq!(timelock.witness, vec![73, 43]);
+        // the 2-of-3 primary branch is larger than the single key recovery branch
+        let with_primary =
+            SpendSize::timelock(Lock::Older(144), Some((2, 3)), (1, 1), ScriptType::P2wsh).unwrap();
+        assert_eq!(with_primary.witness, vec![0, 73, 73, 1, 148]);
+        let nested = SpendSize::timelock(
+            Lock::Older(144),
+            Some((2, 3)),
+            (1, 1),
+            ScriptType::P2shP2wsh,
+        )
+        .unwrap();
+        assert_eq!(nested.script_sig, 35);
+        assert!(matches!(
+            SpendSize::timelock(Lock::After(0), None, (1, 1), ScriptType::P2wsh),
+            Err(WalletError::InvalidLockTime)
+        ));
+
+        let (_, key) = keypair(1);
+        let request = |policy: &str| FeeEstimateRequest {
+            address_type: AddressType::Miniscript,
+            m: 0,
+            n: 0,
+            fee_rate: 1.0,
+            inputs: 1,
+            outputs: 1,
+            lock: None,
+            primary_m: 0,
+            primary_n: 0,
+            script_type: ScriptType::P2wsh,
+            policy: Some(policy.to_string()),
+            context: Context::Segwitv0,
+        };
+        // a signature and the 35 byte <key> OP_CHECKSIG script, sized by the compiler which
+        // counts 72 byte signatures
+        let pk = request(&format!("pk({})", hex::encode(key)))
+            .estimate()
+            .unwrap();
+        assert_eq!(pk.witness_size, 1 + 73 + 36);
+        assert_eq!(pk.output_weight, 172);
+        assert!(matches!(
+            request("pk(00)").estimate(),
+            Err(WalletError::Miniscript(_))
+        ));
+        assert!(matches!(
+            SpendSize::new(AddressType::Miniscript, 0, 0),
+            Err(WalletError::MissingPolicy)
+        ));
+    }
+}
@@ -0,0 +1,679 @@
+use crate::{
+    address::{Address, Network},
+    bip32::{ChildNumber, ExtendedPubKey},
+    chain::ChainBackend,
+    coinselect::{
+        key_source, next_change_path, parse_path, ChangeOutput, SpendableUtxo, CHANGE_ACCOUNT_PATH,
+        TX_OVERHEAD_WEIGHT,
+    },
+    error::{FeeBumpError, PsbtError, SelectionError},
+    estimator::{fee, input_weight, output_weight},
+    policy::{self, dust_threshold},
+    psbt::Psbt,
+    transaction::{sum_amounts, OutPoint, Transaction, TxIn, TxOut, Txid, SEQUENCE_ENABLE_RBF},
+    wallet::Seed,
+};
+use serde::{Deserialize, Serialize};
+use std::str::FromStr;
+
+/// Fee rate in sat/vB a replacement pays for its own size on top of the fees it replaces, as the
+/// default `-incrementalrelayfee` of Bitcoin Core (BIP125 rule 4)
+pub const INCREMENTAL_RELAY_FEE_RATE: f64 = 1.0;
+/// Change addresses of the account of `CHANGE_ACCOUNT_PATH` matched against the outputs when
+/// no change path is given, change further down the chain needs its path
+pub const CHANGE_SEARCH_LIMIT: u32 = 100;
+
+/// How a stuck transaction gets its fee bumped
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum BumpMethod {
+    /// Replace the transaction with one paying more (BIP125)
+    #[default]
+    Rbf,
+    /// Spend the change output with a child paying for both (child pays for parent)
+    Cpfp,
+}
+
+#[derive(Deserialize, Debug)]
+// Get the stuck transaction, the outputs it spends and the target fee rate from user
+pub struct FeeBumpRequest {
+    #[serde(flatten)]
+    pub seed: Seed,
+    /// Hex encoded transaction to bump
+    pub tx: String,
+    /// Outputs spent by the transaction, in input order
+    pub prevouts: Vec<SpendableUtxo>,
+    /// Fee rate in sat/vB of the replacement, or of the parent and child package
+    pub fee_rate: f64,
+    #[serde(default)]
+    pub method: BumpMethod,
+    /// Confirmed UTXOs a replacement may add, largest first, when the change does not cover
+    /// the fee
+    #[serde(default)]
+    pub utxos: Vec<SpendableUtxo>,
+    /// Path of the P2WPKH change address the transaction pays its change to. Left out, the
+    /// outputs are matched against the change addresses of the account of `CHANGE_ACCOUNT_PATH`.
+    #[serde(default)]
+    pub change_path: Option<String>,
+    /// Path of the P2WPKH address the CPFP child pays to. Left out, the first unused change
+    /// address of the account of `CHANGE_ACCOUNT_PATH` is looked up on chain.
+    #[serde(default)]
+    pub child_path: Option<String>,
+    #[serde(default)]
+    pub network: Network,
+}
+
+#[derive(Serialize, Debug)]
+/// FeeBumpResponse is the unsigned replacement or child PSBT presented to user
+pub struct FeeBumpResponse {
+    pub psbt: String,
+    pub method: BumpMethod,
+    /// Outpoints spent by the new transaction
+    pub inputs: Vec<String>,
+    pub original_fee: u64,
+    /// Fee of the replacement, or of the child
+    pub fee: u64,
+    /// Fee rate in sat/vB of the replacement, or of the parent and child package
+    pub fee_rate: f64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub change: Option<ChangeOutput>,
+}
+
+/// Unsigned replacement or child, before the key derivations of its inputs are added
+struct Bump<'a> {
+    psbt: Psbt,
+    fee: u64,
+    /// Estimated weight of the replacement, or of the parent and child package
+    weight: usize,
+    /// UTXOs the inputs spend, in input order, empty for a child
+    spent: Vec<&'a SpendableUtxo>,
+    /// Index and path of the change output
+    change: Option<(usize, String)>,
+}
+
+/// Fee rate in sat/vB of `fee` for `weight`, rounded to two decimals
+fn fee_rate(fee: u64, weight: usize) -> f64 {
+    (fee as f64 * 4.0 / weight as f64 * 100.0).round() / 100.0
+}
+
+fn parse_txid(txid: &str) -> Result<Txid, SelectionError> {
+    Ok(Txid::from_str(txid).map_err(PsbtError::from)?)
+}
+
+/// Sum of `amounts`, which must not exceed `MAX_MONEY`
+fn checked_sum<I: IntoIterator<Item = u64>>(amounts: I) -> Result<u64, SelectionError> {
+    sum_amounts(amounts).ok_or(SelectionError::AmountOutOfRange)
+}
+
+fn is_signed(tx: &Transaction) -> bool {
+    tx.input
+        .iter()
+        .all(|input| !input.script_sig.is_empty() || !input.witness.is_empty())
+}
+
+impl FeeBumpRequest {
+    /// Replace the transaction, or spend its change with a child paying to the given
+    /// `child_path` or to the next unused change address of `backend`
+    pub async fn bump(
+        &self,
+        backend: Option<&dyn ChainBackend>,
+    ) -> Result<FeeBumpResponse, FeeBumpError> {
+        if !(self.fee_rate > 0.0 && self.fee_rate.is_finite()) {
+            return Err(SelectionError::InvalidFeeRate.into());
+        }
+        let tx = Transaction::from_str(&self.tx)?;
+        if self.prevouts.len() != tx.input.len() {
+            return Err(FeeBumpError::MissingPrevouts);
+        }
+        for (index, (input, prevout)) in tx.input.iter().zip(&self.prevouts).enumerate() {
+            let txid = parse_txid(&prevout.txid)?;
+            if input.previous_output != OutPoint::new(txid, prevout.vout) {
+                return Err(FeeBumpError::MismatchedPrevout(index));
+            }
+        }
+        let spent = checked_sum(self.prevouts.iter().map(|prevout| prevout.amount))?;
+        let paid = checked_sum(tx.output.iter().map(|output| output.value))?;
+        let original_fee = spent.checked_sub(paid).ok_or(FeeBumpError::NegativeFee)?;
+
+        let master = self.seed.master_key();
+        let change = self.find_change(&tx)?;
+
+        let mut bump = match self.method {
+            BumpMethod::Rbf => {
+                // without a change output, the replacement may still need one
+                let (change_index, change_path) = match (change, &self.change_path) {
+                    (Some((index, path)), _) => (Some(index), path),
+                    (None, Some(path)) => (None, path.clone()),
+                    (None, None) => return Err(FeeBumpError::NoChangeOutput),
+                };
+                self.replace(&tx, original_fee, change_index, change_path)?
+            }
+            BumpMethod::Cpfp => {
+                let (change_index, change_path) = change.ok_or(FeeBumpError::NoChangeOutput)?;
+                let (change_key, change_source) = key_source(&master, parse_path(&change_path)?)?;
+                let child_path = match &self.child_path {
+                    Some(child_path) => child_path.clone(),
+                    None => next_change_path(backend, &master, self.network).await?,
+                };
+                let mut bump = self.child(&tx, original_fee, change_index, child_path)?;
+                // the child spends the change output of the parent
+                bump.psbt.inputs[0].witness_utxo = Some(tx.output[change_index].clone());
+                bump.psbt.inputs[0]
+                    .bip32_derivation
+                    .insert(change_key, change_source);
+                bump
+            }
+        };
+        for (index, utxo) in bump.spent.iter().enumerate() {
+            utxo.fill_input(&mut bump.psbt, index, index, &master, self.network)?;
+        }
+
+        let mut psbt = bump.psbt;
+        policy::check_transaction(&psbt.unsigned_tx).map_err(SelectionError::from)?;
+        let change = match bump.change {
+            Some((index, path)) => {
+                let (key, source) = key_source(&master, parse_path(&path)?)?;
+                psbt.outputs[index]
+                    .bip32_derivation
+                    .insert(key.clone(), source);
+                Some(ChangeOutput {
+                    address: Address::p2wpkh(&key, self.network).to_string(),
+                    amount: psbt.unsigned_tx.output[index].value,
+                    path,
+                })
+            }
+            None => None,
+        };
+        Ok(FeeBumpResponse {
+            method: self.method,
+            inputs: psbt
+                .unsigned_tx
+                .input
+                .iter()
+                .map(|input| input.previous_output.to_string())
+                .collect(),
+            original_fee,
+            fee: bump.fee,
+            fee_rate: match self.method {
+                BumpMethod::Rbf => fee_rate(bump.fee, bump.weight),
+                BumpMethod::Cpfp => fee_rate(original_fee + bump.fee, bump.weight),
+            },
+            change,
+            psbt: psbt.to_string(),
+        })
+    }
+
+    /// Index and path of the output of `tx` paying to the change address at `change_path`, or
+    /// to one of the first `CHANGE_SEARCH_LIMIT` change addresses of the account
+    fn find_change(&self, tx: &Transaction) -> Result<Option<(usize, String)>, FeeBumpError> {
+        let master = self.seed.master_key();
+        let position = |script_pubkey: &[u8]| {
+            tx.output
+                .iter()
+                .position(|output| output.script_pubkey == script_pubkey)
+        };
+        if let Some(change_path) = &self.change_path {
+            let (key, _) = key_source(&master, parse_path(change_path)?)?;
+            let script_pubkey = Address::p2wpkh(&key, self.network).script_pubkey();
+            return Ok(position(&script_pubkey).map(|index| (index, change_path.clone())));
+        }
+        let account = master
+            .derive_priv(&parse_path(CHANGE_ACCOUNT_PATH)?)
+            .map_err(|_| SelectionError::KeyDerivation)?;
+        let chain = ExtendedPubKey::from_priv(&account)
+            .ckd_pub(ChildNumber::Normal(1))
+            .map_err(|_| SelectionError::KeyDerivation)?;
+        for index in 0..CHANGE_SEARCH_LIMIT {
+            let key = chain
+                .ckd_pub(ChildNumber::Normal(index))
+                .map_err(|_| SelectionError::KeyDerivation)?;
+            let address = Address::p2wpkh(&key.public_key.serialize(), self.network);
+            if let Some(position) = position(&address.script_pubkey()) {
+                let path = format!("{}/1/{}", CHANGE_ACCOUNT_PATH, index);
+                return Ok(Some((position, path)));
+            }
+        }
+        Ok(None)
+    }
+
+    /// Weight of the transaction, estimated from its prevouts when it is not signed yet
+    fn parent_weight(&self, tx: &Transaction) -> Result<usize, FeeBumpError> {
+        if is_signed(tx) {
+            return Ok(tx.weight());
+        }
+        let master = self.seed.master_key();
+        let mut weight = TX_OVERHEAD_WEIGHT;
+        for prevout in &self.prevouts {
+            weight += prevout.weight(prevout.public_key(&master)?.as_deref())?;
+        }
+        for output in &tx.output {
+            weight += output_weight(output.script_pubkey.len());
+        }
+        Ok(weight)
+    }
+
+    /// BIP125 replacement paying the same outputs, taking the fee from the change at
+    /// `change_path` and adding UTXOs when the change does not cover it
+    fn replace(
+        &self,
+        tx: &Transaction,
+        original_fee: u64,
+        change_index: Option<usize>,
+        change_path: String,
+    ) -> Result<Bump<'_>, FeeBumpError> {
+        if !tx.signals_rbf() {
+            return Err(FeeBumpError::NotReplaceable);
+        }
+        let master = self.seed.master_key();
+        let (change_key, _) = key_source(&master, parse_path(&change_path)?)?;
+        let change_script = Address::p2wpkh(&change_key, self.network).script_pubkey();
+        let payments: Vec<&TxOut> = tx
+            .output
+            .iter()
+            .enumerate()
+            .filter(|(index, _)| Some(*index) != change_index)
+            .map(|(_, output)| output)
+            .collect();
+        let payments_value = checked_sum(payments.iter().map(|output| output.value))?;
+        let payments_weight: usize = payments
+            .iter()
+            .map(|output| output_weight(output.script_pubkey.len()))
+            .sum();
+        let change_weight = output_weight(change_script.len());
+        let change_dust = dust_threshold(&change_script);
+        let required = |weight: usize| {
+            fee(weight, self.fee_rate).max(original_fee + fee(weight, INCREMENTAL_RELAY_FEE_RATE))
+        };
+
+        let mut extra: Vec<&SpendableUtxo> = self.utxos.iter().collect();
+        extra.sort_by_key(|utxo| std::cmp::Reverse(utxo.amount));
+        let mut extra = extra.into_iter();
+        let mut spent: Vec<&SpendableUtxo> = self.prevouts.iter().collect();
+        let (change, weight) = loop {
+            let mut inputs_weight = 0;
+            for utxo in &spent {
+                inputs_weight += utxo.weight(utxo.public_key(&master)?.as_deref())?;
+            }
+            let available = checked_sum(spent.iter().map(|utxo| utxo.amount))?;
+            let without_change = TX_OVERHEAD_WEIGHT + inputs_weight + payments_weight;
+            let with_change = without_change + change_weight;
+            let needed = checked_sum([payments_value, required(without_change)])?;
+            let needed_with_change =
+                checked_sum([payments_value, required(with_change), change_dust])?;
+            if available >= needed_with_change {
+                let change = available - payments_value - required(with_change);
+                break (Some(change), with_change);
+            }
+            if available >= needed {
+                break (None, without_change);
+            }
+            match extra.next() {
+                Some(utxo) => spent.push(utxo),
+                None => return Err(SelectionError::InsufficientFunds { needed, available }.into()),
+            }
+        };
+
+        let mut input: Vec<TxIn> = tx
+            .input
+            .iter()
+            .map(|input| TxIn::new(input.previous_output, input.sequence))
+            .collect();
+        for utxo in &spent[tx.input.len()..] {
+            let txid = parse_txid(&utxo.txid)?;
+            input.push(TxIn::new(
+                OutPoint::new(txid, utxo.vout),
+                SEQUENCE_ENABLE_RBF,
+            ));
+        }
+        let mut output = Vec::new();
+        let mut new_change_index = None;
+        for (index, original) in tx.output.iter().enumerate() {
+            if Some(index) != change_index {
+                output.push(original.clone());
+            } else if let Some(value) = change {
+                new_change_index = Some(output.len());
+                output.push(TxOut {
+                    value,
+                    script_pubkey: change_script.clone(),
+                });
+            }
+        }
+        if let (Some(value), None) = (change, new_change_index) {
+            new_change_index = Some(output.len());
+            output.push(TxOut {
+                value,
+                script_pubkey: change_script,
+            });
+        }
+        let psbt = Psbt::from_unsigned_tx(Transaction {
+            version: tx.version,
+            input,
+            output,
+            lock_time: tx.lock_time,
+        })
+        .map_err(SelectionError::from)?;
+        let spent_value = checked_sum(spent.iter().map(|utxo| utxo.amount))?;
+        let paid = checked_sum(psbt.unsigned_tx.output.iter().map(|output| output.value))?;
+        Ok(Bump {
+            psbt,
+            fee: spent_value - paid,
+            weight,
+            spent,
+            change: new_change_index.map(|index| (index, change_path)),
+        })
+    }
+
+    /// Child spending the change output of `tx` to the address at `child_path`, with a fee that
+    /// brings the package of both to the fee rate
+    fn child(
+        &self,
+        tx: &Transaction,
+        original_fee: u64,
+        change_index: usize,
+        child_path: String,
+    ) -> Result<Bump<'_>, FeeBumpError> {
+        let parent_weight = self.parent_weight(tx)?;
+        if original_fee >= fee(parent_weight, self.fee_rate) {
+            return Err(FeeBumpError::FeeRateMet(fee_rate(
+                original_fee,
+                parent_weight,
+            )));
+        }
+        let change = &tx.output[change_index];
+        let (child_key, _) = key_source(&self.seed.master_key(), parse_path(&child_path)?)?;
+        let child_script = Address::p2wpkh(&child_key, self.network).script_pubkey();
+        let child_weight = TX_OVERHEAD_WEIGHT
+            + input_weight(&change.script_pubkey, None, None).expect("P2WPKH")
+            + output_weight(child_script.len());
+        let package_weight = parent_weight + child_weight;
+        let child_fee = (fee(package_weight, self.fee_rate) - original_fee)
+            .max(fee(child_weight, INCREMENTAL_RELAY_FEE_RATE));
+        let needed = checked_sum([child_fee, dust_threshold(&child_script)])?;
+        if change.value < needed {
+            return Err(SelectionError::InsufficientFunds {
+                needed,
+                available: change.value,
+            }
+            .into());
+        }
+        let psbt = Psbt::from_unsigned_tx(Transaction {
+            version: 2,
+            input: vec![TxIn::new(
+                OutPoint::new(tx.txid(), change_index as u32),
+                SEQUENCE_ENABLE_RBF,
+            )],
+            output: vec![TxOut {
+                value: change.value - child_fee,
+                script_pubkey: child_script,
+            }],
+            lock_time: 0,
+        })
+        .map_err(SelectionError::from)?;
+        Ok(Bump {
+            psbt,
+            fee: child_fee,
+            weight: package_weight,
+            spent: Vec::new(),
+            change: Some((0, child_path)),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::coinselect::DEFAULT_CHANGE_PATH;
+    use crate::{
+        chain::{tests::MockChain, Utxo},
+        finalizer, signer,
+        transaction::SEQUENCE_MAX_NON_RBF,
+        wallet::tests::{derive, master, SEED},
+    };
+
+    const PAYEE: &str = "1BgGZ9tcN4rm9KBzDn7KprQz87SZ26SAMH";
+
+    fn address(path: &str) -> String {
+        let key = derive(path).public_key();
+        Address::p2wpkh(&key.serialize(), Network::Bitcoin).to_string()
+    }
+
+    fn utxo(txid: &str, vout: u32, amount: u64, path: &str) -> serde_json::Value {
+        serde_json::json!({"txid": txid, "vout": vout, "amount": amount, "address": address(path), "path": path})
+    }
+
+    /// Signed transaction spending 60000 sat at m/84'/0'/0'/0/0 to `payment` sat and change
+    fn parent(payment: u64, change: u64, sequence: u32) -> Transaction {
+        parent_paying(payment, change, DEFAULT_CHANGE_PATH, sequence)
+    }
+
+    /// `parent` with the change paid to the address at `change_path`
+    fn parent_paying(payment: u64, change: u64, change_path: &str, sequence: u32) -> Transaction {
+        let prevout: SpendableUtxo =
+            serde_json::from_value(utxo(&"11".repeat(32), 0, 60_000, "m/84'/0'/0'/0/0")).unwrap();
+        let tx = Transaction {
+            version: 2,
+            input: vec![TxIn::new(
+                OutPoint::new(parse_txid(&prevout.txid).unwrap(), 0),
+                sequence,
+            )],
+            output: vec![
+                TxOut {
+                    value: payment,
+                    script_pubkey: Address::from_str(PAYEE).unwrap().script_pubkey(),
+                },
+                TxOut {
+                    value: change,
+                    script_pubkey: Address::from_str(&address(change_path))
+                        .unwrap()
+                        .script_pubkey(),
+                },
+            ],
+            lock_time: 0,
+        };
+        let mut psbt = Psbt::from_unsigned_tx(tx).unwrap();
+        prevout
+            .fill_input(&mut psbt, 0, 0, &master(), Network::Bitcoin)
+            .unwrap();
+        assert_eq!(signer::sign(&mut psbt, &master()), Ok(1));
+        finalizer::finalize(&mut psbt).unwrap();
+        finalizer::extract(&psbt).unwrap()
+    }
+
+    fn request(tx: &Transaction, fee_rate: f64, extra: serde_json::Value) -> FeeBumpRequest {
+        let mut request = serde_json::json!({
+            "seed": SEED,
+            "tx": hex::encode(tx.serialize()),
+            "prevouts": [utxo(&"11".repeat(32), 0, 60_000, "m/84'/0'/0'/0/0")],
+            "fee_rate": fee_rate,
+        });
+        for (key, value) in extra.as_object().unwrap() {
+            request[key] = value.clone();
+        }
+        serde_json::from_value(request).unwrap()
+    }
+
+    fn sign(response: &FeeBumpResponse) -> Result<usize, PsbtError> {
+        signer::sign(&mut Psbt::from_str(&response.psbt).unwrap(), &master())
+    }
+
+    #[actix_rt::test]
+    async fn test_rbf() {
+        // one P2WPKH input, a P2PKH payment and P2WPKH change
+        let weight = TX_OVERHEAD_WEIGHT + 273 + 136 + 124;
+        let tx = parent(20_000, 39_000, SEQUENCE_ENABLE_RBF);
+        let response = request(&tx, 10.0, serde_json::json!({}))
+            .bump(None)
+            .await
+            .unwrap();
+        assert_eq!(response.original_fee, 1000);
+        assert_eq!(response.fee, fee(weight, 10.0));
+        assert_eq!(
+            response.change.as_ref().unwrap().amount,
+            40_000 - response.fee
+        );
+        assert_eq!(sign(&response), Ok(1));
+        let psbt = Psbt::from_str(&response.psbt).unwrap();
+        assert_eq!(psbt.unsigned_tx.input[0].sequence, SEQUENCE_ENABLE_RBF);
+        assert_eq!(psbt.unsigned_tx.output[0], tx.output[0]);
+
+        // the change pays for the bump, then is dropped, then another UTXO is added
+        let tx = parent(55_000, 4_000, SEQUENCE_ENABLE_RBF);
+        let response = request(&tx, 20.0, serde_json::json!({}))
+            .bump(None)
+            .await
+            .unwrap();
+        assert_eq!(response.fee, fee(weight, 20.0));
+        assert_eq!(response.change.unwrap().amount, 5_000 - fee(weight, 20.0));
+        let response = request(&tx, 40.0, serde_json::json!({}))
+            .bump(None)
+            .await
+            .unwrap();
+        assert_eq!(response.fee, 5_000);
+        assert!(response.change.is_none());
+        assert_eq!(
+            request(&tx, 60.0, serde_json::json!({}))
+                .bump(None)
+                .await
+                .unwrap_err(),
+            FeeBumpError::Selection(SelectionError::InsufficientFunds {
+                needed: 55_000 + fee(weight - 124, 60.0),
+                available: 60_000
+            })
+        );
+        let utxos = serde_json::json!({
+            "utxos": [utxo(&"22".repeat(32), 1, 30_000, "m/84'/0'/0'/0/1")]
+        });
+        let response = request(&tx, 60.0, utxos).bump(None).await.unwrap();
+        assert_eq!(response.inputs.len(), 2);
+        assert_eq!(response.fee, fee(weight + 273, 60.0));
+        assert_eq!(
+            response.change.as_ref().unwrap().amount,
+            35_000 - response.fee
+        );
+        assert_eq!(sign(&response), Ok(2));
+
+        let tx = parent(20_000, 39_000, SEQUENCE_MAX_NON_RBF);
+        assert_eq!(
+            request(&tx, 10.0, serde_json::json!({}))
+                .bump(None)
+                .await
+                .unwrap_err(),
+            FeeBumpError::NotReplaceable
+        );
+        let prevouts = serde_json::json!({
+            "prevouts": [utxo(&"11".repeat(32), 1, 60_000, "m/84'/0'/0'/0/0")]
+        });
+        assert_eq!(
+            request(&tx, 10.0, prevouts).bump(None).await.unwrap_err(),
+            FeeBumpError::MismatchedPrevout(0)
+        );
+        let prevouts = serde_json::json!({
+            "prevouts": [utxo(&"11".repeat(32), 0, u64::MAX, "m/84'/0'/0'/0/0")]
+        });
+        assert_eq!(
+            request(&tx, 10.0, prevouts).bump(None).await.unwrap_err(),
+            FeeBumpError::Selection(SelectionError::AmountOutOfRange)
+        );
+    }
+
+    #[actix_rt::test]
+    async fn test_cpfp() {
+        let tx = parent(20_000, 39_000, SEQUENCE_MAX_NON_RBF);
+        // the parent is in the mempool, so the child pays to the change address after its own
+        let chain = MockChain {
+            utxos: vec![Utxo {
+                outpoint: OutPoint::new(tx.txid(), 1),
+                value: 39_000,
+                script_pubkey: tx.output[1].script_pubkey.clone(),
+                height: None,
+            }],
+            ..MockChain::default()
+        };
+        let cpfp = serde_json::json!({"method": "cpfp"});
+        let response = request(&tx, 10.0, cpfp.clone())
+            .bump(Some(&chain))
+            .await
+            .unwrap();
+        // one P2WPKH input and one P2WPKH output
+        let package_weight = tx.weight() + TX_OVERHEAD_WEIGHT + 273 + 124;
+        assert_eq!(response.fee, fee(package_weight, 10.0) - 1000);
+        assert_eq!(response.inputs, [format!("{}:1", tx.txid())]);
+        let change = response.change.as_ref().unwrap();
+        assert_eq!(change.path, "m/84'/0'/0'/1/1");
+        assert_eq!(change.address, address("m/84'/0'/0'/1/1"));
+        assert_eq!(change.amount, 39_000 - response.fee);
+        assert!(response.fee_rate >= 10.0);
+        assert_eq!(sign(&response), Ok(1));
+
+        assert_eq!(
+            request(&tx, 10.0, cpfp.clone())
+                .bump(None)
+                .await
+                .unwrap_err(),
+            FeeBumpError::Selection(SelectionError::NoChangePath)
+        );
+        let child_path = serde_json::json!({"method": "cpfp", "child_path": "m/84'/0'/0'/1/9"});
+        let response = request(&tx, 10.0, child_path).bump(None).await.unwrap();
+        assert_eq!(response.change.unwrap().address, address("m/84'/0'/0'/1/9"));
+
+        assert!(matches!(
+            request(&tx, 1.0, cpfp)
+                .bump(Some(&chain))
+                .await
+                .unwrap_err(),
+            FeeBumpError::FeeRateMet(_)
+        ));
+        let other_change = serde_json::json!({"method": "cpfp", "change_path": "m/84'/0'/0'/1/5"});
+        assert_eq!(
+            request(&tx, 10.0, other_change)
+                .bump(None)
+                .await
+                .unwrap_err(),
+            FeeBumpError::NoChangeOutput
+        );
+    }
+
+    #[actix_rt::test]
+    async fn test_change_lookup() {
+        // the change went to the fourth change address, as coin selection picks the next unused
+        let weight = TX_OVERHEAD_WEIGHT + 273 + 136 + 124;
+        let tx = parent_paying(20_000, 39_000, "m/84'/0'/0'/1/3", SEQUENCE_ENABLE_RBF);
+        let response = request(&tx, 10.0, serde_json::json!({}))
+            .bump(None)
+            .await
+            .unwrap();
+        assert_eq!(response.fee, fee(weight, 10.0));
+        let change = response.change.as_ref().unwrap();
+        assert_eq!(change.path, "m/84'/0'/0'/1/3");
+        assert_eq!(change.amount, 40_000 - response.fee);
+        let psbt = Psbt::from_str(&response.psbt).unwrap();
+        assert_eq!(psbt.unsigned_tx.output.len(), 2);
+        assert_eq!(sign(&response), Ok(1));
+
+        let cpfp = serde_json::json!({"method": "cpfp", "child_path": "m/84'/0'/0'/1/4"});
+        let response = request(&tx, 10.0, cpfp).bump(None).await.unwrap();
+        assert_eq!(response.inputs, [format!("{}:1", tx.txid())]);
+        assert_eq!(sign(&response), Ok(1));
+
+        // change to a receive address is a payment like any other
+        let tx = parent_paying(20_000, 39_000, "m/84'/0'/0'/0/3", SEQUENCE_ENABLE_RBF);
+        assert_eq!(
+            request(&tx, 10.0, serde_json::json!({}))
+                .bump(None)
+                .await
+                .unwrap_err(),
+            FeeBumpError::NoChangeOutput
+        );
+        let change_path = serde_json::json!({"change_path": "m/84'/0'/0'/1/3"});
+        assert!(matches!(
+            request(&tx, 10.0, change_path)
+                .bump(None)
+                .await
+                .unwrap_err(),
+            FeeBumpError::Selection(SelectionError::InsufficientFunds {
+                available: 60_000,
+                ..
+            })
+        ));
+    }
+}
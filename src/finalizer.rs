@@ -0,0 +1,474 @@
+use crate::{
+    address::{Address, Network, Payload},
+    error::PsbtError,
+    hash,
+    interpreter::{is_p2sh, verify_ecdsa, witness_program, SigVersion},
+    policy,
+    psbt::{Psbt, PsbtInput, PsbtOutput},
+    script::{parse_multisig, Builder},
+    sighash,
+    transaction::{Transaction, Witness},
+};
+use serde::{Deserialize, Serialize};
+
+#[derive(Deserialize, Debug)]
+// Get the base64 encoded PSBTs of every cosigner to merge
+pub struct PsbtCombineRequest {
+    pub psbts: Vec<String>,
+}
+
+#[derive(Serialize, Debug)]
+/// ExtractResponse is the network serialized transaction of a finalized PSBT presented to user
+pub struct ExtractResponse {
+    pub tx: String,
+    pub txid: String,
+}
+
+/// Keep the value of `into` and fill it from `from` when unset
+fn merge_option<T>(into: &mut Option<T>, from: Option<T>) {
+    if into.is_none() {
+        *into = from;
+    }
+}
+
+fn merge_input(into: &mut PsbtInput, from: PsbtInput) {
+    merge_option(&mut into.non_witness_utxo, from.non_witness_utxo);
+    merge_option(&mut into.witness_utxo, from.witness_utxo);
+    into.partial_sigs.extend(from.partial_sigs);
+    merge_option(&mut into.sighash_type, from.sighash_type);
+    merge_option(&mut into.redeem_script, from.redeem_script);
+    merge_option(&mut into.witness_script, from.witness_script);
+    into.bip32_derivation.extend(from.bip32_derivation);
+    merge_option(&mut into.final_script_sig, from.final_script_sig);
+    merge_option(&mut into.final_script_witness, from.final_script_witness);
+    merge_option(&mut into.tap_key_sig, from.tap_key_sig);
+    into.tap_script_sigs.extend(from.tap_script_sigs);
+    into.tap_bip32_derivation.extend(from.tap_bip32_derivation);
+    merge_option(&mut into.tap_internal_key, from.tap_internal_key);
+    merge_option(&mut into.tap_merkle_root, from.tap_merkle_root);
+    into.unknown.extend(from.unknown);
+}
+
+fn merge_output(into: &mut PsbtOutput, from: PsbtOutput) {
+    merge_option(&mut into.redeem_script, from.redeem_script);
+    merge_option(&mut into.witness_script, from.witness_script);
+    into.bip32_derivation.extend(from.bip32_derivation);
+    into.unknown.extend(from.unknown);
+}
+
+/// Merge PSBTs of the same unsigned transaction signed by different cosigners (BIP174 combiner)
+///
+/// Maps are united, a field set in several PSBTs keeps the value of the first one.
+pub fn combine(psbts: Vec<Psbt>) -> Result<Psbt, PsbtError> {
+    let mut psbts = psbts.into_iter();
+    let mut combined = psbts.next().ok_or(PsbtError::NoPsbt)?;
+    for psbt in psbts {
+        if psbt.unsigned_tx != combined.unsigned_tx {
+            return Err(PsbtError::MismatchedTransaction);
+        }
+        combined.xpubs.extend(psbt.xpubs);
+        merge_option(&mut combined.version, psbt.version);
+        combined.unknown.extend(psbt.unknown);
+        for (into, from) in combined.inputs.iter_mut().zip(psbt.inputs) {
+            merge_input(into, from);
+        }
+        for (into, from) in combined.outputs.iter_mut().zip(psbt.outputs) {
+            merge_output(into, from);
+        }
+    }
+    Ok(combined)
+}
+
+/// Whether `signature` by `public_key` signs input `index` of `psbt` spending `script_code`
+fn signs_input(
+    psbt: &Psbt,
+    index: usize,
+    script_code: &[u8],
+    sig_version: SigVersion,
+    public_key: &[u8],
+    signature: &[u8],
+) -> bool {
+    let hash_type = match signature.last() {
+        Some(&hash_type) => hash_type,
+        None => return false,
+    };
+    let sighash = match (sig_version, psbt.spent_output(index)) {
+        (SigVersion::Base, _) => {
+            sighash::legacy_sighash(&psbt.unsigned_tx, index, script_code, hash_type.into())
+        }
+        (SigVersion::WitnessV0, Some(spent_output)) => sighash::segwit_v0_sighash(
+            &psbt.unsigned_tx,
+            index,
+            script_code,
+            spent_output.value,
+            hash_type,
+        ),
+        _ => return false,
+    };
+    matches!(sighash, Ok(sighash) if verify_ecdsa(signature, public_key, &sighash))
+}
+
+/// Stack satisfying the multisig `script`: the `OP_0` dummy `OP_CHECKMULTISIG` pops by mistake,
+/// then `m` signatures in the order of their keys in the script
+///
+/// Partial signatures not signing the input are skipped, a single one would fail the whole
+/// `OP_CHECKMULTISIG`.
+fn multisig_stack(
+    psbt: &Psbt,
+    index: usize,
+    script: &[u8],
+    sig_version: SigVersion,
+) -> Result<Vec<Vec<u8>>, PsbtError> {
+    let (m, keys) = parse_multisig(script).ok_or(PsbtError::UnsupportedScript(index))?;
+    let input = &psbt.inputs[index];
+    let mut stack = vec![Vec::new()];
+    stack.extend(
+        keys.iter()
+            .filter_map(|key| {
+                input.partial_sigs.get(*key).filter(|signature| {
+                    signs_input(psbt, index, script, sig_version, key, signature)
+                })
+            })
+            .take(m)
+            .cloned(),
+    );
+    if stack.len() != m + 1 {
+        return Err(PsbtError::MissingSignatures(index));
+    }
+    Ok(stack)
+}
+
+/// Signature and public key of the key hashing to `public_key_hash`
+fn key_hash_stack(
+    public_key_hash: &[u8],
+    input: &PsbtInput,
+    index: usize,
+) -> Result<Vec<Vec<u8>>, PsbtError> {
+    input
+        .partial_sigs
+        .iter()
+        .find(|(public_key, _)| hash::hash160(public_key)[..] == public_key_hash[..])
+        .map(|(public_key, signature)| vec![signature.clone(), public_key.clone()])
+        .ok_or(PsbtError::MissingSignatures(index))
+}
+
+fn push_only(stack: &[Vec<u8>]) -> Vec<u8> {
+    stack
+        .iter()
+        .fold(Builder::new(), |builder, item| builder.push_slice(item))
+        .into_script()
+}
+
+/// Final scriptSig and witness of input `index`
+fn finalize_input(psbt: &Psbt, index: usize) -> Result<(Vec<u8>, Witness), PsbtError> {
+    let input = &psbt.inputs[index];
+    let script_pubkey = &psbt
+        .spent_output(index)
+        .ok_or(PsbtError::MissingUtxo(index))?
+        .script_pubkey;
+    if let Some(signature) = &input.tap_key_sig {
+        return match witness_program(script_pubkey) {
+            Some((1, program)) if program.len() == 32 => {
+                Ok((Vec::new(), Witness(vec![signature.clone()])))
+            }
+            _ => Err(PsbtError::ScriptMismatch(index)),
+        };
+    }
+
+    let redeem_script = if is_p2sh(script_pubkey) {
+        match &input.redeem_script {
+            Some(redeem_script) if hash::hash160(redeem_script)[..] == script_pubkey[2..22] => {
+                Some(redeem_script)
+            }
+            _ => return Err(PsbtError::ScriptMismatch(index)),
+        }
+    } else {
+        None
+    };
+    // a nested segwit scriptSig only pushes the redeem script
+    let nested_script_sig = match redeem_script {
+        Some(redeem_script) => Builder::new().push_slice(redeem_script).into_script(),
+        None => Vec::new(),
+    };
+    match witness_program(redeem_script.unwrap_or(script_pubkey)) {
+        Some((0, program)) if program.len() == 20 => Ok((
+            nested_script_sig,
+            Witness(key_hash_stack(program, input, index)?),
+        )),
+        Some((0, program)) if program.len() == 32 => match &input.witness_script {
+            Some(witness_script) if hash::sha256(witness_script)[..] == program[..] => {
+                let mut stack = multisig_stack(psbt, index, witness_script, SigVersion::WitnessV0)?;
+                stack.push(witness_script.clone());
+                Ok((nested_script_sig, Witness(stack)))
+            }
+            _ => Err(PsbtError::ScriptMismatch(index)),
+        },
+        Some(_) => Err(PsbtError::UnsupportedScript(index)),
+        None => {
+            let stack = match redeem_script {
+                Some(redeem_script) => {
+                    let mut stack = multisig_stack(psbt, index, redeem_script, SigVersion::Base)?;
+                    stack.push(redeem_script.clone());
+                    stack
+                }
+                None => match Address::from_script_pubkey(script_pubkey, Network::Bitcoin) {
+                    Some(Address {
+                        payload: Payload::PubkeyHash(public_key_hash),
+                        ..
+                    }) => key_hash_stack(&public_key_hash, input, index)?,
+                    _ => return Err(PsbtError::UnsupportedScript(index)),
+                },
+            };
+            Ok((push_only(&stack), Witness::default()))
+        }
+    }
+}
+
+/// Build the final scriptSig and witness of every input not finalized yet (BIP174 finalizer)
+///
+/// Supports single key P2PKH, P2WPKH and P2SH-P2WPKH inputs, `OP_CHECKMULTISIG` scripts behind
+/// P2SH, P2WSH and P2SH-P2WSH, and taproot key path spends. Everything but the UTXOs and the
+/// unknown pairs is cleared from finalized inputs, as the BIP requires.
+pub fn finalize(psbt: &mut Psbt) -> Result<(), PsbtError> {
+    for index in 0..psbt.inputs.len() {
+        let input = &psbt.inputs[index];
+        if input.final_script_sig.is_some() || input.final_script_witness.is_some() {
+            continue;
+        }
+        let (script_sig, witness) = finalize_input(psbt, index)?;
+        let input = &mut psbt.inputs[index];
+        *input = PsbtInput {
+            non_witness_utxo: input.non_witness_utxo.take(),
+            witness_utxo: input.witness_utxo.take(),
+            final_script_sig: Some(script_sig).filter(|script_sig| !script_sig.is_empty()),
+            final_script_witness: Some(witness).filter(|witness| !witness.is_empty()),
+            unknown: std::mem::take(&mut input.unknown),
+            ..PsbtInput::default()
+        };
+    }
+    Ok(())
+}
+
+/// The network transaction of a finalized PSBT (BIP174 extractor)
+pub fn extract(psbt: &Psbt) -> Result<Transaction, PsbtError> {
+    let mut tx = psbt.unsigned_tx.clone();
+    for (index, (tx_in, input)) in tx.input.iter_mut().zip(&psbt.inputs).enumerate() {
+        if input.final_script_sig.is_none() && input.final_script_witness.is_none() {
+            return Err(PsbtError::NotFinalized(index));
+        }
+        tx_in.script_sig = input.final_script_sig.clone().unwrap_or_default();
+        tx_in.witness = input.final_script_witness.clone().unwrap_or_default();
+    }
+    policy::check_transaction(&tx)?;
+    Ok(tx)
+}
+
+impl PsbtCombineRequest {
+    pub fn combine(&self) -> Result<Psbt, PsbtError> {
+        combine(
+            self.psbts
+                .iter()
+                .map(|psbt| psbt.parse())
+                .collect::<Result<Vec<_>, _>>()?,
+        )
+    }
+}
+
+impl From<&Transaction> for ExtractResponse {
+    fn from(tx: &Transaction) -> Self {
+        Self {
+            tx: hex::encode(tx.serialize()),
+            txid: tx.txid().to_string(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        bip32::{DerivationPath, ExtendedPrivKey},
+        interpreter::verify_with_sighash,
+        opcodes::OP_CHECKMULTISIG,
+        psbt::KeySource,
+        sighash::SIGHASH_ALL,
+        signer,
+        transaction::{OutPoint, TxIn, TxOut, Txid},
+    };
+    use std::str::FromStr;
+
+    const PATH: &str = "m/48'/0'/0'/2'/0/0";
+
+    fn cosigners() -> Vec<ExtendedPrivKey> {
+        (1..=3)
+            .map(|byte| ExtendedPrivKey::new_master(&[byte; 32]).unwrap())
+            .collect()
+    }
+
+    fn public_key(master: &ExtendedPrivKey) -> Vec<u8> {
+        let path = DerivationPath::from_str(PATH).unwrap();
+        let child = master.derive_priv(&path).unwrap();
+        child.public_key().serialize().to_vec()
+    }
+
+    /// 2-of-3 script of the cosigners, the third one first so signatures need reordering
+    fn multisig_script(cosigners: &[ExtendedPrivKey]) -> Vec<u8> {
+        Builder::new()
+            .push_int(2)
+            .push_slice(&public_key(&cosigners[2]))
+            .push_slice(&public_key(&cosigners[0]))
+            .push_slice(&public_key(&cosigners[1]))
+            .push_int(3)
+            .push_opcode(OP_CHECKMULTISIG)
+            .into_script()
+    }
+
+    fn unsigned_tx(previous_output: OutPoint) -> Transaction {
+        Transaction {
+            version: 2,
+            input: vec![TxIn::new(previous_output, 0xffff_fffd)],
+            output: vec![TxOut {
+                value: 90_000,
+                script_pubkey: Address::p2wpkh(&[0x02; 33], Network::Bitcoin).script_pubkey(),
+            }],
+            lock_time: 0,
+        }
+    }
+
+    /// Sign a copy of `psbt` by each of `cosigners` and combine the copies
+    fn sign_and_combine(psbt: &Psbt, cosigners: &[ExtendedPrivKey]) -> Psbt {
+        let mut psbt = psbt.clone();
+        for master in cosigners {
+            psbt.inputs[0].bip32_derivation.insert(
+                public_key(master),
+                KeySource {
+                    fingerprint: master.fingerprint(),
+                    path: DerivationPath::from_str(PATH).unwrap(),
+                },
+            );
+        }
+        let signed = cosigners
+            .iter()
+            .map(|master| {
+                let mut psbt = psbt.clone();
+                assert_eq!(signer::sign(&mut psbt, master), Ok(1));
+                psbt
+            })
+            .collect();
+        combine(signed).unwrap()
+    }
+
+    fn verify(tx: &Transaction, script_pubkey: &[u8], sighash: [u8; 32]) {
+        let input = &tx.input[0];
+        verify_with_sighash(&input.script_sig, &input.witness.0, script_pubkey, sighash).unwrap();
+    }
+
+    #[test]
+    fn test_segwit_multisig() {
+        let cosigners = cosigners();
+        let witness_script = multisig_script(&cosigners);
+        let txid =
+            Txid::from_str("f4184fc596403b9d638783cf57adfe4c75c605f6356fbc91338530e9831e9e16")
+                .unwrap();
+        for nested in [false, true] {
+            let address = match nested {
+                false => Address::p2wsh(&witness_script, Network::Bitcoin),
+                true => Address::p2shwsh(&witness_script, Network::Bitcoin),
+            };
+            let mut psbt = Psbt::from_unsigned_tx(unsigned_tx(OutPoint::new(txid, 0))).unwrap();
+            let input = &mut psbt.inputs[0];
+            input.witness_utxo = Some(TxOut {
+                value: 100_000,
+                script_pubkey: address.script_pubkey(),
+            });
+            if nested {
+                input.redeem_script =
+                    Some(Address::p2wsh(&witness_script, Network::Bitcoin).script_pubkey());
+            }
+            input.witness_script = Some(witness_script.clone());
+
+            let mut partial = sign_and_combine(&psbt, &cosigners[..1]);
+            assert_eq!(finalize(&mut partial), Err(PsbtError::MissingSignatures(0)));
+            assert_eq!(extract(&partial), Err(PsbtError::NotFinalized(0)));
+
+            let mut psbt = sign_and_combine(&psbt, &cosigners[..2]);
+            assert_eq!(psbt.inputs[0].partial_sigs.len(), 2);
+            finalize(&mut psbt).unwrap();
+            let input = &psbt.inputs[0];
+            assert!(input.partial_sigs.is_empty() && input.witness_script.is_none());
+            assert_eq!(input.final_script_witness.as_ref().unwrap().0.len(), 4);
+            assert_eq!(input.final_script_sig.is_some(), nested);
+            assert_eq!(Psbt::from_str(&psbt.to_string()).unwrap(), psbt);
+
+            let tx = extract(&psbt).unwrap();
+            let sighash = sighash::segwit_v0_sighash(
+                &psbt.unsigned_tx,
+                0,
+                &witness_script,
+                100_000,
+                SIGHASH_ALL,
+            )
+            .unwrap();
+            verify(&tx, &address.script_pubkey(), sighash);
+        }
+    }
+
+    #[test]
+    fn test_legacy_multisig() {
+        let cosigners = cosigners();
+        let redeem_script = multisig_script(&cosigners);
+        let address = Address::p2sh(&redeem_script, Network::Bitcoin);
+        let mut previous = unsigned_tx(OutPoint::default());
+        previous.output[0].script_pubkey = address.script_pubkey();
+        let mut psbt =
+            Psbt::from_unsigned_tx(unsigned_tx(OutPoint::new(previous.txid(), 0))).unwrap();
+        psbt.inputs[0].non_witness_utxo = Some(previous);
+        psbt.inputs[0].redeem_script = Some(redeem_script.clone());
+
+        let mut psbt = sign_and_combine(&psbt, &cosigners[1..]);
+        // a signature of the second key filed under the first one, which the script lists before it
+        let partial_sigs = &mut psbt.inputs[0].partial_sigs;
+        let signature = partial_sigs[&public_key(&cosigners[1])].clone();
+        partial_sigs.insert(public_key(&cosigners[0]), signature);
+        let mut missing = psbt.clone();
+        missing.inputs[0]
+            .partial_sigs
+            .remove(&public_key(&cosigners[2]));
+        assert_eq!(finalize(&mut missing), Err(PsbtError::MissingSignatures(0)));
+        finalize(&mut psbt).unwrap();
+        assert!(psbt.inputs[0].final_script_witness.is_none());
+        let tx = extract(&psbt).unwrap();
+        assert!(!tx.has_witness());
+        let sighash =
+            sighash::legacy_sighash(&psbt.unsigned_tx, 0, &redeem_script, SIGHASH_ALL.into())
+                .unwrap();
+        verify(&tx, &address.script_pubkey(), sighash);
+    }
+
+    #[test]
+    fn test_combine() {
+        assert_eq!(combine(Vec::new()), Err(PsbtError::NoPsbt));
+        let txid = Txid::from_str(&"11".repeat(32)).unwrap();
+        let psbt = Psbt::from_unsigned_tx(unsigned_tx(OutPoint::new(txid, 0))).unwrap();
+        let mut other = psbt.clone();
+        other.unsigned_tx.lock_time = 1;
+        assert_eq!(
+            combine(vec![psbt.clone(), other]),
+            Err(PsbtError::MismatchedTransaction)
+        );
+
+        let mut first = psbt.clone();
+        first.inputs[0].sighash_type = Some(1);
+        first.inputs[0]
+            .partial_sigs
+            .insert(vec![0x02; 33], vec![0x01]);
+        let mut second = psbt;
+        second.inputs[0].sighash_type = Some(3);
+        second.inputs[0]
+            .partial_sigs
+            .insert(vec![0x03; 33], vec![0x02]);
+        let combined = combine(vec![first, second]).unwrap();
+        assert_eq!(combined.inputs[0].sighash_type, Some(1));
+        assert_eq!(combined.inputs[0].partial_sigs.len(), 2);
+    }
+}
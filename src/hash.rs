@@ -0,0 +1,74 @@
+use ring::{digest, hmac};
+use ripemd160::{Digest, Ripemd160};
+
+/// SHA-256 of `data`
+pub fn sha256(data: &[u8]) -> [u8; 32] {
+    let mut hash = [0u8; 32];
+    hash.copy_from_slice(digest::digest(&digest::SHA256, data).as_ref());
+    hash
+}
+
+/// Double SHA-256 of `data`, as used by txids, checksums and signature hashes
+pub fn sha256d(data: &[u8]) -> [u8; 32] {
+    sha256(&sha256(data))
+}
+
+/// BIP340 tagged hash: SHA-256 of `SHA-256(tag) || SHA-256(tag) || data`
+pub fn tagged_hash(tag: &str, data: &[u8]) -> [u8; 32] {
+    let tag = sha256(tag.as_bytes());
+    let mut preimage = Vec::with_capacity(64 + data.len());
+    preimage.extend(tag);
+    preimage.extend(tag);
+    preimage.extend(data);
+    sha256(&preimage)
+}
+
+/// RIPEMD-160 of `data`
+pub fn ripemd160(data: &[u8]) -> [u8; 20] {
+    let mut ripemd160 = Ripemd160::new();
+    ripemd160.update(data);
+    ripemd160.finalize().into()
+}
+
+/// RIPEMD-160 of the SHA-256 of `data`, as used by P2PKH, P2SH and P2WPKH
+pub fn hash160(data: &[u8]) -> [u8; 20] {
+    ripemd160(&sha256(data))
+}
+
+/// SHA-1 of `data`, only needed by `OP_SHA1`
+pub fn sha1(data: &[u8]) -> [u8; 20] {
+    let mut hash = [0u8; 20];
+    hash.copy_from_slice(digest::digest(&digest::SHA1_FOR_LEGACY_USE_ONLY, data).as_ref());
+    hash
+}
+
+/// HMAC-SHA512 of `data` under `key`, as used by BIP32 key derivation
+pub fn hmac_sha512(key: &[u8], data: &[u8]) -> [u8; 64] {
+    let mut mac = [0u8; 64];
+    mac.copy_from_slice(hmac::sign(&hmac::Key::new(hmac::HMAC_SHA512, key), data).as_ref());
+    mac
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_hash160() {
+        assert_eq!(
+            hex::encode(hash160(
+                &hex::decode("0279be667ef9dcbbac55a06295ce870b07029bfcdb2dce28d959f2815b16f81798")
+                    .unwrap()
+            )),
+            "751e76e8199196d454941c45d1b3a323f1433bd6"
+        );
+    }
+
+    #[test]
+    fn test_sha256d() {
+        assert_eq!(
+            hex::encode(sha256d(b"hello")),
+            "9595c9df90075148eb06860365df33584b75bff782a510c6cd4883a419833d50"
+        );
+    }
+}
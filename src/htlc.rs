@@ -0,0 +1,320 @@
+use crate::{
+    address::{Address, Network},
+    error::WalletError,
+    opcodes, policy,
+    script::Builder,
+    timelock::Lock,
+    wallet::PubKey,
+};
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use std::convert::TryInto;
+
+/// Size of the preimage, checked by the script so that both chains of a swap agree on it
+pub const PREIMAGE_SIZE: usize = 32;
+
+#[derive(Deserialize, Debug)]
+// Get a hash-time-locked contract from user
+pub struct HtlcRequest {
+    pub hashlock: HashlockRequest,
+    pub recipient: String,
+    pub refund: String,
+    pub lock: Lock,
+    #[serde(default)]
+    pub network: Network,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "lowercase")]
+// Get the hex encoded payment hash from user
+pub enum HashlockRequest {
+    Sha256(String),
+    Hash160(String),
+}
+
+impl HashlockRequest {
+    pub fn decode(&self) -> Result<Hashlock, WalletError> {
+        let decode = |hash: &str| hex::decode(hash).map_err(|_| WalletError::InvalidHashLength);
+        match self {
+            HashlockRequest::Sha256(hash) => Ok(Hashlock::Sha256(
+                decode(hash)?
+                    .try_into()
+                    .map_err(|_| WalletError::InvalidHashLength)?,
+            )),
+            HashlockRequest::Hash160(hash) => Ok(Hashlock::Hash160(
+                decode(hash)?
+                    .try_into()
+                    .map_err(|_| WalletError::InvalidHashLength)?,
+            )),
+        }
+    }
+}
+
+/// Hash the claim preimage has to match
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Hashlock {
+    /// `OP_SHA256`, as used by Lightning payment hashes
+    Sha256([u8; 32]),
+    /// `OP_HASH160`, smaller and common in older swap protocols
+    Hash160([u8; 20]),
+}
+
+impl Hashlock {
+    /// `OP_SIZE 32 OP_EQUALVERIFY OP_SHA256 <hash> OP_EQUALVERIFY` or the `OP_HASH160` variant
+    pub fn script(self) -> Vec<u8> {
+        let builder = Builder::new()
+            .push_opcode(opcodes::OP_SIZE)
+            .push_int(PREIMAGE_SIZE as i64)
+            .push_opcode(opcodes::OP_EQUALVERIFY);
+        match self {
+            Hashlock::Sha256(hash) => builder.push_opcode(opcodes::OP_SHA256).push_slice(&hash),
+            Hashlock::Hash160(hash) => builder.push_opcode(opcodes::OP_HASH160).push_slice(&hash),
+        }
+        .push_opcode(opcodes::OP_EQUALVERIFY)
+        .into_script()
+    }
+}
+
+#[derive(Serialize, Debug)]
+/// HtlcWallet is the HTLC script, its address and how to spend it, presented to user
+pub struct HtlcWallet {
+    pub script: String,
+    pub address: String,
+    pub claim_witness: Vec<String>,
+    pub refund_witness: Vec<String>,
+}
+
+/// Hash-time-locked contract: `recipient` can claim with the preimage of the hash, `refund` can
+/// take the coins back once `lock` has passed
+pub struct Htlc {
+    hashlock: Hashlock,
+    recipient: Option<PubKey>,
+    refund: Option<PubKey>,
+    lock: Option<Lock>,
+    witness_script: Vec<u8>,
+}
+
+impl Htlc {
+    pub fn hashlock(hashlock: Hashlock) -> Self {
+        Self {
+            hashlock,
+            recipient: None,
+            refund: None,
+            lock: None,
+            witness_script: Vec::new(),
+        }
+    }
+
+    pub fn recipient(mut self, recipient: PubKey) -> Self {
+        self.recipient = Some(recipient);
+        self
+    }
+
+    pub fn refund(mut self, refund: PubKey) -> Self {
+        self.refund = Some(refund);
+        self
+    }
+
+    pub fn lock(mut self, lock: Lock) -> Self {
+        self.lock = Some(lock);
+        self
+    }
+
+    /// Generate the witness script
+    ///
+    /// `OP_IF <hashlock> <recipient> OP_ELSE <lock> <refund> OP_ENDIF OP_CHECKSIG`, where the
+    /// claim branch is selected with a `0x01` and the refund branch with an empty element on
+    /// top of the stack. Fails unless the recipient, the refund key and the lock are all set.
+    pub fn generate_script(mut self) -> Result<Self> {
+        let (recipient, refund, lock) = match (self.recipient, self.refund, self.lock) {
+            (Some(recipient), Some(refund), Some(lock)) => (recipient, refund, lock),
+            _ => return Err(WalletError::IncompleteHtlc.into()),
+        };
+        let lock = lock.check()?.script();
+        self.witness_script = Builder::new()
+            .push_opcode(opcodes::OP_IF)
+            .append(&self.hashlock.script())
+            .push_slice(&recipient)
+            .push_opcode(opcodes::OP_ELSE)
+            .append(&lock)
+            .push_slice(&refund)
+            .push_opcode(opcodes::OP_ENDIF)
+            .push_opcode(opcodes::OP_CHECKSIG)
+            .into_script();
+        policy::check_witness_script(&self.witness_script).map_err(WalletError::from)?;
+        log::debug!("Witness script: {}", hex::encode(&self.witness_script));
+        Ok(self)
+    }
+
+    /// The witness script, empty until `generate_script` succeeds
+    pub fn witness_script(&self) -> &[u8] {
+        &self.witness_script
+    }
+
+    /// P2WSH address of the witness script
+    pub fn address(&self, network: Network) -> Address {
+        Address::p2wsh(&self.witness_script, network)
+    }
+
+    /// Witness stack claiming with the recipient's `signature` and the `preimage`
+    pub fn claim_witness(&self, signature: &[u8], preimage: &[u8]) -> Vec<Vec<u8>> {
+        vec![
+            signature.to_vec(),
+            preimage.to_vec(),
+            vec![1],
+            self.witness_script.clone(),
+        ]
+    }
+
+    /// Witness stack refunding with the refund key's `signature`, the spending transaction
+    /// needs a locktime (`after`) or input sequence (`older`) satisfying the lock
+    pub fn refund_witness(&self, signature: &[u8]) -> Vec<Vec<u8>> {
+        vec![signature.to_vec(), vec![], self.witness_script.clone()]
+    }
+
+    /// Finalize Htlc and return as HtlcWallet, with placeholders for the signatures and the
+    /// preimage in the witness stacks
+    pub fn finalize(self, network: Network) -> HtlcWallet {
+        let script = hex::encode(&self.witness_script);
+        HtlcWallet {
+            address: self.address(network).to_string(),
+            claim_witness: vec![
+                "<recipient signature>".to_string(),
+                format!("<{} byte preimage>", PREIMAGE_SIZE),
+                "01".to_string(),
+                script.clone(),
+            ],
+            refund_witness: vec![
+                "<refund signature>".to_string(),
+                "".to_string(),
+                script.clone(),
+            ],
+            script,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        error::ScriptError,
+        hash,
+        interpreter::{
+            tests::{keypair, sign, SIGHASH},
+            verify_script, ScriptFlags, SighashChecker,
+        },
+    };
+
+    const PREIMAGE: [u8; 32] = [0x5a; 32];
+
+    fn htlc(hashlock: Hashlock, lock: Lock) -> Htlc {
+        Htlc::hashlock(hashlock)
+            .recipient(keypair(1).1)
+            .refund(keypair(2).1)
+            .lock(lock)
+            .generate_script()
+            .unwrap()
+    }
+
+    #[test]
+    fn test_sha256_claim_and_cltv_refund() {
+        let htlc = htlc(
+            Hashlock::Sha256(hash::sha256(&PREIMAGE)),
+            Lock::After(750_000),
+        );
+        let script_pubkey = htlc.address(Network::Bitcoin).script_pubkey();
+        let flags = ScriptFlags::default();
+        let checker = SighashChecker::new(SIGHASH);
+
+        let witness = htlc.claim_witness(&sign(&keypair(1).0), &PREIMAGE);
+        assert_eq!(
+            verify_script(&[], &witness, &script_pubkey, &flags, &checker),
+            Ok(())
+        );
+        let witness = htlc.claim_witness(&sign(&keypair(1).0), &[0x5b; 32]);
+        assert_eq!(
+            verify_script(&[], &witness, &script_pubkey, &flags, &checker),
+            Err(ScriptError::EqualVerify)
+        );
+
+        let witness = htlc.refund_witness(&sign(&keypair(2).0));
+        assert_eq!(
+            verify_script(&[], &witness, &script_pubkey, &flags, &checker),
+            Err(ScriptError::UnsatisfiedLocktime)
+        );
+        let checker = SighashChecker::new(SIGHASH)
+            .lock_time(750_000)
+            .sequence(0xffff_fffe);
+        assert_eq!(
+            verify_script(&[], &witness, &script_pubkey, &flags, &checker),
+            Ok(())
+        );
+    }
+
+    #[test]
+    fn test_hash160_claim_and_csv_refund() {
+        let htlc = htlc(
+            Hashlock::Hash160(hash::hash160(&PREIMAGE)),
+            Lock::Older(144),
+        );
+        let script_pubkey = htlc.address(Network::Bitcoin).script_pubkey();
+        let flags = ScriptFlags::default();
+
+        let checker = SighashChecker::new(SIGHASH);
+        let witness = htlc.claim_witness(&sign(&keypair(1).0), &PREIMAGE);
+        assert_eq!(
+            verify_script(&[], &witness, &script_pubkey, &flags, &checker),
+            Ok(())
+        );
+        // Only the recipient can claim, even with the preimage
+        let witness = htlc.claim_witness(&sign(&keypair(2).0), &PREIMAGE);
+        assert_eq!(
+            verify_script(&[], &witness, &script_pubkey, &flags, &checker),
+            Err(ScriptError::NullFail)
+        );
+
+        let witness = htlc.refund_witness(&sign(&keypair(2).0));
+        let checker = SighashChecker::new(SIGHASH).version(2).sequence(144);
+        assert_eq!(
+            verify_script(&[], &witness, &script_pubkey, &flags, &checker),
+            Ok(())
+        );
+    }
+
+    #[test]
+    fn test_finalize() {
+        let contract = || htlc(Hashlock::Sha256([0; 32]), Lock::After(750_000));
+        let testnet = contract().finalize(Network::Testnet);
+        assert!(testnet.address.starts_with("tb1q"));
+        let wallet = contract().finalize(Network::Bitcoin);
+        assert!(wallet.address.starts_with("bc1q"));
+        assert_eq!(wallet.claim_witness.len(), 4);
+        assert_eq!(wallet.refund_witness[1], "");
+        assert_eq!(wallet.refund_witness[2], wallet.script);
+        assert!(HashlockRequest::Hash160("00".repeat(32)).decode().is_err());
+    }
+
+    #[test]
+    fn test_incomplete() {
+        let hashlock = Hashlock::Sha256([0; 32]);
+        let incomplete = [
+            Htlc::hashlock(hashlock)
+                .refund(keypair(2).1)
+                .lock(Lock::After(750_000)),
+            Htlc::hashlock(hashlock)
+                .recipient(keypair(1).1)
+                .lock(Lock::After(750_000)),
+            Htlc::hashlock(hashlock)
+                .recipient(keypair(1).1)
+                .refund(keypair(2).1),
+        ];
+        for htlc in incomplete {
+            let error = htlc.generate_script().err().unwrap();
+            assert!(matches!(
+                error.downcast_ref::<WalletError>(),
+                Some(WalletError::IncompleteHtlc)
+            ));
+        }
+    }
+}
@@ -1,4 +1,6 @@
-use actix_web::{error, get, post, web, App, HttpResponse, HttpServer, Responder, Result};
+use actix_web::{
+    error, get, post, web, App, HttpRequest, HttpResponse, HttpServer, Responder, Result,
+};
 
 #[cfg(feature = "postgres")]
 use crate::{handlers, models};
@@ -9,7 +11,31 @@ use diesel::{
 };
 
 use crate::auth;
-use crate::wallet::{MofN, Multisig, PubKey, Seed, Segwit};
+use crate::balance::{self, XpubQuery};
+use crate::bip322::{self, Bip322SignRequest};
+use crate::broadcast::BroadcastRequest;
+use crate::chain::{self, ChainBackend};
+use crate::coinselect::CoinSelectRequest;
+use crate::decode::DecodeRequest;
+use crate::error::{
+    BalanceError, BroadcastError, ChainError, FeeBumpError, SelectionError, SweepError,
+};
+use crate::estimator::FeeEstimateRequest;
+use crate::feebump::FeeBumpRequest;
+use crate::finalizer::{self, ExtractResponse, PsbtCombineRequest};
+use crate::htlc::{Htlc, HtlcRequest};
+use crate::message::{
+    sign_with_seed, verify_message, MessageVerification, SignMessageRequest, VerifyMessageRequest,
+};
+use crate::miniscript::{CompiledPolicy, PolicyRequest};
+use crate::opreturn::OpReturnRequest;
+use crate::psbt::{Psbt, PsbtCreateRequest, PsbtRequest, PsbtResponse};
+use crate::signer::PsbtSignRequest;
+use crate::sweep::SweepRequest;
+use crate::taproot::{x_only_key, LeafScriptRequest, TapLeaf, Taproot, TaprootRequest};
+use crate::timelock::{KeySet, Timelock, TimelockRequest};
+use crate::wallet::{MofN, Multisig, MultisigMode, PubKey, Seed, Segwit};
+use crate::wif::{WifRequest, EXPORT_TOKEN_HEADER, EXPORT_TOKEN_VAR};
 
 use std::convert::TryInto;
 
@@ -43,6 +69,31 @@ pub async fn start_http_server() -> Result<(), std::io::Error> {
                 .service(index)
                 .service(post_seed)
                 .service(post_mofn)
+                .service(post_timelock)
+                .service(post_htlc)
+                .service(post_policy)
+                .service(post_taproot)
+                .service(post_sign_message)
+                .service(post_verify_message)
+                .service(post_bip322_sign)
+                .service(post_bip322_verify)
+                .service(post_wif)
+                .service(post_tx_decode)
+                .service(post_psbt_create)
+                .service(post_psbt_decode)
+                .service(post_psbt_sign)
+                .service(post_psbt_combine)
+                .service(post_psbt_finalize)
+                .service(post_psbt_extract)
+                .service(post_psbt_select)
+                .service(post_fee_estimate)
+                .service(post_fee_bump)
+                .service(post_sweep)
+                .service(post_op_return)
+                .service(get_address_utxos)
+                .service(get_address_balance)
+                .service(get_xpub_balance)
+                .service(post_tx_broadcast)
                 .service(handlers::get_user_by_id)
                 .service(handlers::add_user)
                 .service(handlers::delete_user)
@@ -60,6 +111,31 @@ pub async fn start_http_server() -> Result<(), std::io::Error> {
                 .service(index)
                 .service(post_seed)
                 .service(post_mofn)
+                .service(post_timelock)
+                .service(post_htlc)
+                .service(post_policy)
+                .service(post_taproot)
+                .service(post_sign_message)
+                .service(post_verify_message)
+                .service(post_bip322_sign)
+                .service(post_bip322_verify)
+                .service(post_wif)
+                .service(post_tx_decode)
+                .service(post_psbt_create)
+                .service(post_psbt_decode)
+                .service(post_psbt_sign)
+                .service(post_psbt_combine)
+                .service(post_psbt_finalize)
+                .service(post_psbt_extract)
+                .service(post_psbt_select)
+                .service(post_fee_estimate)
+                .service(post_fee_bump)
+                .service(post_sweep)
+                .service(post_op_return)
+                .service(get_address_utxos)
+                .service(get_address_balance)
+                .service(get_xpub_balance)
+                .service(post_tx_broadcast)
         })
         .bind(addr)?
         .run()
@@ -69,7 +145,7 @@ pub async fn start_http_server() -> Result<(), std::io::Error> {
 
 #[get("/")]
 async fn index() -> impl Responder {
-    format!("Welcome to bitcoin address server")
+    "Welcome to bitcoin address server".to_string()
 }
 
 #[post("/seed")]
@@ -78,24 +154,523 @@ async fn post_seed(seed: web::Json<Seed>) -> Result<HttpResponse> {
     Ok(HttpResponse::Ok().json(wallet))
 }
 
-#[post("/mofn")]
-async fn post_mofn(mofn: web::Json<MofN>) -> Result<HttpResponse> {
-    let keys = mofn
-        .public_keys
+/// Decode hex encoded compressed public keys
+fn decode_public_keys(public_keys: &[String]) -> Result<Vec<PubKey>> {
+    public_keys
         .iter()
         .map(|key| {
             let key: PubKey = hex::decode(key)
-                .map_err(|e| error::ErrorBadRequest(e))?
+                .map_err(error::ErrorBadRequest)?
                 .try_into()
                 .map_err(|e| error::ErrorBadRequest(format!("{:?}", e)))?;
+            // a 33 byte blob off the curve would give an unspendable script
+            secp256k1::PublicKey::from_slice(&key).map_err(error::ErrorBadRequest)?;
             Ok(key)
         })
-        .collect::<Result<Vec<PubKey>>>()?;
-    let wallet = Multisig::m(mofn.m)
+        .collect()
+}
+
+#[post("/mofn")]
+async fn post_mofn(mofn: web::Json<MofN>) -> Result<HttpResponse> {
+    let keys = decode_public_keys(&mofn.public_keys)?;
+    let multisig = Multisig::m(mofn.m)
         .n(mofn.n)
         .public_keys(keys)
-        .generate_address()
+        .network(mofn.network);
+    let wallet = match mofn.mode {
+        MultisigMode::P2sh => multisig.generate_address(),
+        MultisigMode::Musig2 => multisig.generate_musig_address(),
+    }
+    .map_err(|e| error::ErrorBadRequest(format!("{:?}", e)))?
+    .finalize();
+    Ok(HttpResponse::Ok().json(wallet))
+}
+
+#[post("/timelock")]
+async fn post_timelock(request: web::Json<TimelockRequest>) -> Result<HttpResponse> {
+    let mut timelock = Timelock::lock(request.lock).recovery(KeySet::new(
+        request.recovery.m,
+        decode_public_keys(&request.recovery.public_keys)?,
+    ));
+    if let Some(primary) = &request.primary {
+        timelock = timelock.primary(KeySet::new(
+            primary.m,
+            decode_public_keys(&primary.public_keys)?,
+        ));
+    }
+    let wallet = timelock
+        .generate_script()
+        .map_err(|e| error::ErrorBadRequest(format!("{:?}", e)))?
+        .check_policy(request.script_type)
         .map_err(|e| error::ErrorBadRequest(format!("{:?}", e)))?
-        .finalize();
+        .finalize(request.script_type, request.network);
     Ok(HttpResponse::Ok().json(wallet))
 }
+
+#[post("/htlc")]
+async fn post_htlc(request: web::Json<HtlcRequest>) -> Result<HttpResponse> {
+    let hashlock = request
+        .hashlock
+        .decode()
+        .map_err(|e| error::ErrorBadRequest(format!("{:?}", e)))?;
+    let recipient = decode_public_keys(std::slice::from_ref(&request.recipient))?[0];
+    let refund = decode_public_keys(std::slice::from_ref(&request.refund))?[0];
+    let wallet = Htlc::hashlock(hashlock)
+        .recipient(recipient)
+        .refund(refund)
+        .lock(request.lock)
+        .generate_script()
+        .map_err(|e| error::ErrorBadRequest(format!("{:?}", e)))?
+        .finalize(request.network);
+    Ok(HttpResponse::Ok().json(wallet))
+}
+
+#[post("/policy")]
+async fn post_policy(request: web::Json<PolicyRequest>) -> Result<HttpResponse> {
+    let compiled = CompiledPolicy::new(&request.policy, request.context, request.network)
+        .map_err(|e| error::ErrorBadRequest(e.to_string()))?;
+    Ok(HttpResponse::Ok().json(compiled))
+}
+
+#[post("/taproot")]
+async fn post_taproot(request: web::Json<TaprootRequest>) -> Result<HttpResponse> {
+    let bad_request = |e| error::ErrorBadRequest(format!("{:?}", e));
+    let internal_key = match &request.internal_key {
+        Some(key) => Some(
+            x_only_key(&hex::decode(key).map_err(error::ErrorBadRequest)?).map_err(bad_request)?,
+        ),
+        None => None,
+    };
+    let mut taproot = Taproot::internal_key(internal_key);
+    for leaf in &request.leaves {
+        let leaf_script = match &leaf.script {
+            LeafScriptRequest::Multisig { m, public_keys } => {
+                let keys = public_keys
+                    .iter()
+                    .map(|key| {
+                        x_only_key(&hex::decode(key).map_err(error::ErrorBadRequest)?)
+                            .map_err(bad_request)
+                    })
+                    .collect::<Result<Vec<_>>>()?;
+                TapLeaf::multi_a(*m, &keys).map_err(bad_request)?
+            }
+            LeafScriptRequest::Script { script } => TapLeaf::new(
+                hex::decode(script).map_err(error::ErrorBadRequest)?,
+                leaf.leaf_version,
+            )
+            .map_err(bad_request)?,
+        };
+        taproot = taproot.leaf(leaf.weight, leaf_script);
+    }
+    let wallet = taproot
+        .generate_tree()
+        .map_err(|e| error::ErrorBadRequest(format!("{:?}", e)))?
+        .check_policy()
+        .map_err(|e| error::ErrorBadRequest(format!("{:?}", e)))?
+        .finalize(request.network);
+    Ok(HttpResponse::Ok().json(wallet))
+}
+
+#[post("/message/sign")]
+async fn post_sign_message(request: web::Json<SignMessageRequest>) -> Result<HttpResponse> {
+    let signed = sign_with_seed(&request.seed, &request.message, request.address_type);
+    Ok(HttpResponse::Ok().json(signed))
+}
+
+#[post("/message/verify")]
+async fn post_verify_message(request: web::Json<VerifyMessageRequest>) -> Result<HttpResponse> {
+    let valid = verify_message(&request.address, &request.message, &request.signature)
+        .map_err(|e| error::ErrorBadRequest(format!("{:?}", e)))?;
+    Ok(HttpResponse::Ok().json(MessageVerification { valid }))
+}
+
+#[post("/message/bip322/sign")]
+async fn post_bip322_sign(request: web::Json<Bip322SignRequest>) -> Result<HttpResponse> {
+    let signed = bip322::sign_with_seed(&request.seed, &request.message, request.address_type)
+        .map_err(|e| error::ErrorBadRequest(format!("{:?}", e)))?;
+    Ok(HttpResponse::Ok().json(signed))
+}
+
+#[post("/message/bip322/verify")]
+async fn post_bip322_verify(request: web::Json<VerifyMessageRequest>) -> Result<HttpResponse> {
+    let valid = bip322::verify_message(&request.address, &request.message, &request.signature)
+        .map_err(|e| error::ErrorBadRequest(format!("{:?}", e)))?;
+    Ok(HttpResponse::Ok().json(MessageVerification { valid }))
+}
+
+/// WIF export needs its own token on top of the bearer token, and is disabled unless the
+/// operator configured one
+fn authorize_export(req: &HttpRequest) -> Result<()> {
+    let expected = std::env::var(EXPORT_TOKEN_VAR)
+        .ok()
+        .filter(|token| !token.is_empty())
+        .ok_or_else(|| error::ErrorForbidden("WIF export is disabled"))?;
+    let token = req
+        .headers()
+        .get(EXPORT_TOKEN_HEADER)
+        .map(|token| token.as_bytes())
+        .unwrap_or_default();
+    ring::constant_time::verify_slices_are_equal(token, expected.as_bytes())
+        .map_err(|_| error::ErrorForbidden("Invalid export token"))
+}
+
+#[post("/wif")]
+async fn post_wif(req: HttpRequest, body: web::Bytes) -> Result<HttpResponse> {
+    let peer = req
+        .peer_addr()
+        .map(|addr| addr.to_string())
+        .unwrap_or_default();
+    // parsed here instead of by the Json extractor so that malformed requests are audited too
+    let request: WifRequest = match serde_json::from_slice(&body) {
+        Ok(request) => request,
+        Err(e) => {
+            log::warn!(target: "audit", "WIF export request from {} rejected: {:?}", peer, e);
+            return Err(error::ErrorBadRequest(format!("{:?}", e)));
+        }
+    };
+    if let Err(e) = authorize_export(&req) {
+        log::warn!(target: "audit", "WIF export of {:?} denied to {}: {}", request.path, peer, e);
+        return Err(e);
+    }
+    let export = match request.export() {
+        Ok(export) => export,
+        Err(e) => {
+            log::warn!(target: "audit", "WIF export of {:?} to {} failed: {:?}", request.path, peer, e);
+            return Err(error::ErrorBadRequest(format!("{:?}", e)));
+        }
+    };
+    log::warn!(
+        target: "audit",
+        "WIF export of {:?} ({}) granted to {}",
+        export.path,
+        export.address,
+        peer
+    );
+    Ok(HttpResponse::Ok().json(export))
+}
+
+#[post("/tx/decode")]
+async fn post_tx_decode(request: web::Json<DecodeRequest>) -> Result<HttpResponse> {
+    let decoded = request
+        .decode()
+        .map_err(|e| error::ErrorBadRequest(format!("{:?}", e)))?;
+    Ok(HttpResponse::Ok().json(decoded))
+}
+
+#[post("/psbt/create")]
+async fn post_psbt_create(request: web::Json<PsbtCreateRequest>) -> Result<HttpResponse> {
+    let psbt = request
+        .create()
+        .map_err(|e| error::ErrorBadRequest(format!("{:?}", e)))?;
+    Ok(HttpResponse::Ok().json(PsbtResponse {
+        psbt: psbt.to_string(),
+    }))
+}
+
+#[post("/psbt/decode")]
+async fn post_psbt_decode(request: web::Json<PsbtRequest>) -> Result<HttpResponse> {
+    let decoded = request
+        .psbt
+        .parse::<Psbt>()
+        .and_then(|psbt| psbt.decode(request.network))
+        .map_err(|e| error::ErrorBadRequest(format!("{:?}", e)))?;
+    Ok(HttpResponse::Ok().json(decoded))
+}
+
+#[post("/psbt/sign")]
+async fn post_psbt_sign(request: web::Json<PsbtSignRequest>) -> Result<HttpResponse> {
+    let signed = request
+        .sign()
+        .map_err(|e| error::ErrorBadRequest(format!("{:?}", e)))?;
+    Ok(HttpResponse::Ok().json(signed))
+}
+
+#[post("/psbt/combine")]
+async fn post_psbt_combine(request: web::Json<PsbtCombineRequest>) -> Result<HttpResponse> {
+    let psbt = request
+        .combine()
+        .map_err(|e| error::ErrorBadRequest(format!("{:?}", e)))?;
+    Ok(HttpResponse::Ok().json(PsbtResponse {
+        psbt: psbt.to_string(),
+    }))
+}
+
+#[post("/psbt/finalize")]
+async fn post_psbt_finalize(request: web::Json<PsbtRequest>) -> Result<HttpResponse> {
+    let psbt = request
+        .psbt
+        .parse::<Psbt>()
+        .and_then(|mut psbt| finalizer::finalize(&mut psbt).map(|_| psbt))
+        .map_err(|e| error::ErrorBadRequest(format!("{:?}", e)))?;
+    Ok(HttpResponse::Ok().json(PsbtResponse {
+        psbt: psbt.to_string(),
+    }))
+}
+
+#[post("/psbt/extract")]
+async fn post_psbt_extract(request: web::Json<PsbtRequest>) -> Result<HttpResponse> {
+    let tx = request
+        .psbt
+        .parse::<Psbt>()
+        .and_then(|psbt| finalizer::extract(&psbt))
+        .map_err(|e| error::ErrorBadRequest(format!("{:?}", e)))?;
+    Ok(HttpResponse::Ok().json(ExtractResponse::from(&tx)))
+}
+
+#[post("/psbt/select")]
+async fn post_psbt_select(request: web::Json<CoinSelectRequest>) -> Result<HttpResponse> {
+    let backend = chain::from_env();
+    let change_path = request
+        .change_path(backend.as_deref())
+        .await
+        .map_err(selection_error)?;
+    let selection = request.select(&change_path).map_err(selection_error)?;
+    Ok(HttpResponse::Ok().json(selection))
+}
+
+#[post("/fee/estimate")]
+async fn post_fee_estimate(request: web::Json<FeeEstimateRequest>) -> Result<HttpResponse> {
+    let estimate = request
+        .estimate()
+        .map_err(|e| error::ErrorBadRequest(format!("{:?}", e)))?;
+    Ok(HttpResponse::Ok().json(estimate))
+}
+
+#[post("/fee/bump")]
+async fn post_fee_bump(request: web::Json<FeeBumpRequest>) -> Result<HttpResponse> {
+    let backend = chain::from_env();
+    let bump = request
+        .bump(backend.as_deref())
+        .await
+        .map_err(|e| match e {
+            FeeBumpError::Selection(e) => selection_error(e),
+            e => error::ErrorBadRequest(format!("{:?}", e)),
+        })?;
+    Ok(HttpResponse::Ok().json(bump))
+}
+
+#[post("/sweep")]
+async fn post_sweep(request: web::Json<SweepRequest>) -> Result<HttpResponse> {
+    let backend = chain::from_env();
+    let sweep = request
+        .sweep(backend.as_deref())
+        .await
+        .map_err(|e| match e {
+            SweepError::Lookup(BalanceError::Chain(e)) => chain_error(e),
+            e => error::ErrorBadRequest(format!("{:?}", e)),
+        })?;
+    Ok(HttpResponse::Ok().json(sweep))
+}
+
+#[post("/opreturn")]
+async fn post_op_return(request: web::Json<OpReturnRequest>) -> Result<HttpResponse> {
+    let op_return = request
+        .build()
+        .and_then(|op_return| op_return.finalize())
+        .map_err(|e| error::ErrorBadRequest(format!("{:?}", e)))?;
+    Ok(HttpResponse::Ok().json(op_return))
+}
+
+/// Chain backend configured in the environment, unavailable when there is none
+fn chain_backend() -> Result<Box<dyn ChainBackend>> {
+    chain::from_env()
+        .ok_or_else(|| error::ErrorServiceUnavailable(format!("{:?}", ChainError::NotConfigured)))
+}
+
+/// Failures of the chain backend are the upstream's, unless it lacks the address history asked
+fn chain_error(e: ChainError) -> error::Error {
+    match e {
+        ChainError::NoHistory => error::ErrorServiceUnavailable(format!("{:?}", e)),
+        e => error::ErrorBadGateway(format!("{:?}", e)),
+    }
+}
+
+/// Failures of the chain backend are the upstream's, any other is the request's
+fn balance_error(e: BalanceError) -> error::Error {
+    match e {
+        BalanceError::Chain(e) => chain_error(e),
+        e => error::ErrorBadRequest(format!("{:?}", e)),
+    }
+}
+
+/// Failures of the chain backend looking up the change address are the upstream's
+fn selection_error(e: SelectionError) -> error::Error {
+    match e {
+        SelectionError::ChangeLookup(BalanceError::Chain(e)) => chain_error(e),
+        e => error::ErrorBadRequest(format!("{:?}", e)),
+    }
+}
+
+#[get("/address/{address}/utxos")]
+async fn get_address_utxos(address: web::Path<String>) -> Result<HttpResponse> {
+    let backend = chain_backend()?;
+    let utxos = balance::address_utxos(backend.as_ref(), &address)
+        .await
+        .map_err(balance_error)?;
+    Ok(HttpResponse::Ok().json(utxos))
+}
+
+#[get("/address/{address}/balance")]
+async fn get_address_balance(address: web::Path<String>) -> Result<HttpResponse> {
+    let backend = chain_backend()?;
+    let balance = balance::address_balance(backend.as_ref(), &address)
+        .await
+        .map_err(balance_error)?;
+    Ok(HttpResponse::Ok().json(balance))
+}
+
+#[get("/xpub/{xpub}/balance")]
+async fn get_xpub_balance(
+    xpub: web::Path<String>,
+    query: web::Query<XpubQuery>,
+) -> Result<HttpResponse> {
+    let backend = chain_backend()?;
+    let balance = balance::xpub_balance(backend.as_ref(), &xpub, &query)
+        .await
+        .map_err(balance_error)?;
+    Ok(HttpResponse::Ok().json(balance))
+}
+
+#[post("/tx/broadcast")]
+async fn post_tx_broadcast(request: web::Json<BroadcastRequest>) -> Result<HttpResponse> {
+    let backend = chain_backend()?;
+    let broadcast = request
+        .broadcast(backend.as_ref())
+        .await
+        .map_err(|e| match e {
+            BroadcastError::Chain(e) => error::ErrorBadGateway(format!("{:?}", e)),
+            e => error::ErrorBadRequest(format!("{:?}", e)),
+        })?;
+    Ok(HttpResponse::Ok().json(broadcast))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::wallet::tests::SEED;
+    use actix_web::{http::StatusCode, test};
+    use log::{Log, Metadata, Record};
+    use std::sync::{Mutex, Once};
+
+    const KEYS: [&str; 3] = [
+        "03d728ad6757d4784effea04d47baafa216cf474866c2d4dc99b1e8e3eb936e730",
+        "03aeb681df5ac19e449a872b9e9347f1db5a0394d2ec5caf2a9c143f86e232b0d9",
+        "02d83bba35a8022c247b645eed6f81ac41b7c1580de550e7e82c75ad63ee9ac2fd",
+    ];
+
+    /// Records the messages logged to the audit target
+    struct AuditLog;
+
+    static AUDIT: Mutex<Vec<String>> = Mutex::new(Vec::new());
+    /// The export token is process wide, tests setting it must not overlap. Each test runs on its
+    /// own single threaded runtime, so holding the guard across awaits cannot deadlock.
+    static EXPORT_TOKEN: Mutex<()> = Mutex::new(());
+
+    impl Log for AuditLog {
+        fn enabled(&self, metadata: &Metadata) -> bool {
+            metadata.target() == "audit"
+        }
+
+        fn log(&self, record: &Record) {
+            if self.enabled(record.metadata()) {
+                AUDIT.lock().unwrap().push(record.args().to_string());
+            }
+        }
+
+        fn flush(&self) {}
+    }
+
+    fn audit_log() {
+        static INIT: Once = Once::new();
+        INIT.call_once(|| {
+            log::set_logger(&AuditLog).unwrap();
+            log::set_max_level(log::LevelFilter::Warn);
+        });
+    }
+
+    async fn export(token: Option<&str>) -> (StatusCode, Option<serde_json::Value>) {
+        let app = test::init_service(App::new().service(post_wif)).await;
+        let mut request = test::TestRequest::post()
+            .uri("/wif")
+            .peer_addr("127.0.0.1:8080".parse().unwrap())
+            .set_json(&serde_json::json!({"seed": SEED, "path": "m/84'/0'/0'/0/0"}));
+        if let Some(token) = token {
+            request = request.insert_header((EXPORT_TOKEN_HEADER, token));
+        }
+        let response = test::call_service(&app, request.to_request()).await;
+        let status = response.status();
+        let export = match status {
+            StatusCode::OK => Some(test::read_body_json(response).await),
+            _ => None,
+        };
+        (status, export)
+    }
+
+    #[actix_rt::test]
+    async fn test_mofn_off_curve_key() {
+        let app = test::init_service(App::new().service(post_mofn)).await;
+        let mofn = |key: &str| {
+            test::TestRequest::post()
+                .uri("/mofn")
+                .set_json(
+                    &serde_json::json!({"m": 2, "n": 3, "public_keys": [KEYS[0], KEYS[1], key]}),
+                )
+                .to_request()
+        };
+        let response = test::call_service(&app, mofn(KEYS[2])).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        // 33 bytes each, but an uncompressed prefix and an x coordinate above the field size
+        for key in [
+            format!("04{}", &KEYS[2][2..]),
+            format!("02{}", "ff".repeat(32)),
+        ]
+        .iter()
+        {
+            let response = test::call_service(&app, mofn(key)).await;
+            assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        }
+    }
+
+    #[actix_rt::test]
+    #[allow(clippy::await_holding_lock)]
+    async fn test_wif_export_disabled() {
+        let _guard = EXPORT_TOKEN.lock().unwrap_or_else(|e| e.into_inner());
+        std::env::remove_var(EXPORT_TOKEN_VAR);
+        assert_eq!(export(Some("secret")).await.0, StatusCode::FORBIDDEN);
+    }
+
+    #[actix_rt::test]
+    #[allow(clippy::await_holding_lock)]
+    async fn test_wif_export_authorized() {
+        let _guard = EXPORT_TOKEN.lock().unwrap_or_else(|e| e.into_inner());
+        std::env::set_var(EXPORT_TOKEN_VAR, "secret");
+        assert_eq!(export(None).await.0, StatusCode::FORBIDDEN);
+        assert_eq!(export(Some("wrong")).await.0, StatusCode::FORBIDDEN);
+        let (status, export) = export(Some("secret")).await;
+        std::env::remove_var(EXPORT_TOKEN_VAR);
+        assert_eq!(status, StatusCode::OK);
+        let request: WifRequest =
+            serde_json::from_value(serde_json::json!({"seed": SEED, "path": "m/84'/0'/0'/0/0"}))
+                .unwrap();
+        assert_eq!(export, serde_json::to_value(request.export().unwrap()).ok());
+    }
+
+    #[actix_rt::test]
+    #[allow(clippy::await_holding_lock)]
+    async fn test_wif_export_audited() {
+        let _guard = EXPORT_TOKEN.lock().unwrap_or_else(|e| e.into_inner());
+        audit_log();
+        std::env::set_var(EXPORT_TOKEN_VAR, "secret");
+        let denied = export(Some("wrong")).await.0;
+        let granted = export(Some("secret")).await.0;
+        std::env::remove_var(EXPORT_TOKEN_VAR);
+        assert_eq!((denied, granted), (StatusCode::FORBIDDEN, StatusCode::OK));
+        let audit = AUDIT.lock().unwrap();
+        assert!(audit.iter().any(|entry| entry
+            == "WIF export of \"m/84'/0'/0'/0/0\" denied to 127.0.0.1:8080: Invalid export token"));
+        assert!(audit.iter().any(
+            |entry| entry.starts_with("WIF export of \"m/84'/0'/0'/0/0\" (")
+                && entry.ends_with(") granted to 127.0.0.1:8080")
+        ));
+    }
+}
@@ -0,0 +1,1671 @@
+use crate::{
+    error::ScriptError,
+    hash,
+    opcodes::*,
+    script::{
+        self, cast_to_bool, instructions, is_minimal_push, is_push_only, push_compact_size,
+        push_data, read_scriptint, scriptint_bytes, Instruction,
+    },
+    sighash::{ScriptPath, SIGHASH_DEFAULT},
+    taproot::{self, TAPROOT_LEAF_TAPSCRIPT},
+};
+use secp256k1::{schnorrsig, Message, PublicKey, Secp256k1, Signature};
+
+/// Maximum combined size of the stack and the altstack
+pub(crate) const MAX_STACK_SIZE: usize = 1_000;
+/// Locktimes below this are block heights, above it unix timestamps
+pub(crate) const LOCKTIME_THRESHOLD: u32 = 500_000_000;
+/// Sequence bit that disables the relative locktime (BIP68)
+const SEQUENCE_LOCKTIME_DISABLE_FLAG: i64 = 1 << 31;
+/// Sequence bit that marks the relative locktime as time based (BIP68)
+pub const SEQUENCE_LOCKTIME_TYPE_FLAG: i64 = 1 << 22;
+/// Sequence bits that carry the relative locktime value (BIP68)
+pub const SEQUENCE_LOCKTIME_MASK: i64 = 0x0000_ffff;
+/// First byte of the annex, the optional last element of a taproot witness (BIP341)
+pub const ANNEX_TAG: u8 = 0x50;
+/// Validation weight every tapscript starts with on top of its witness size (BIP342)
+const VALIDATION_WEIGHT_OFFSET: i64 = 50;
+/// Validation weight used by each signature check with a non-empty signature (BIP342)
+const VALIDATION_WEIGHT_PER_SIGOP_PASSED: i64 = 50;
+
+/// Which set of rules a script is evaluated under
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SigVersion {
+    /// Bare scripts, scriptSigs and P2SH redeem scripts
+    Base,
+    /// P2WPKH and P2WSH witness scripts (BIP143)
+    WitnessV0,
+    /// Leaf scripts of taproot script path spends (BIP342)
+    Tapscript,
+}
+
+/// Script verification flags. `Default` gives the standardness rules Bitcoin Core applies to
+/// mempool transactions, `consensus()` only the soft forks every block has to follow.
+#[derive(Debug, Clone, Copy)]
+pub struct ScriptFlags {
+    /// Evaluate P2SH redeem scripts (BIP16)
+    pub p2sh: bool,
+    /// Evaluate witness programs (BIP141)
+    pub witness: bool,
+    /// Evaluate segwit v1 witness programs as taproot outputs (BIP341, BIP342)
+    pub taproot: bool,
+    /// Enforce OP_CHECKLOCKTIMEVERIFY (BIP65)
+    pub check_lock_time: bool,
+    /// Enforce OP_CHECKSEQUENCEVERIFY (BIP112)
+    pub check_sequence: bool,
+    /// The OP_CHECKMULTISIG dummy element must be empty (BIP147)
+    pub null_dummy: bool,
+    /// Signatures must be strict DER (BIP66)
+    pub strict_der: bool,
+    /// Pushes and script numbers must use their smallest encoding
+    pub minimal_data: bool,
+    /// OP_IF arguments in witness scripts must be empty or exactly `0x01`
+    pub minimal_if: bool,
+    /// Failing signature checks must be given an empty signature
+    pub null_fail: bool,
+    /// scriptSigs may only contain pushes
+    pub sig_push_only: bool,
+    /// Exactly one element must remain on the stack
+    pub clean_stack: bool,
+    /// Segwit v0 scripts must use compressed public keys
+    pub witness_pubkey_type: bool,
+    /// Reject the NOPs, witness versions, leaf versions, OP_SUCCESS opcodes and public key
+    /// types reserved for future soft forks instead of treating them as anyone-can-spend
+    pub discourage_upgradable: bool,
+}
+
+impl Default for ScriptFlags {
+    fn default() -> Self {
+        Self {
+            p2sh: true,
+            witness: true,
+            taproot: true,
+            check_lock_time: true,
+            check_sequence: true,
+            null_dummy: true,
+            strict_der: true,
+            minimal_data: true,
+            minimal_if: true,
+            null_fail: true,
+            sig_push_only: true,
+            clean_stack: true,
+            witness_pubkey_type: true,
+            discourage_upgradable: true,
+        }
+    }
+}
+
+impl ScriptFlags {
+    /// Only the rules enforced by consensus
+    pub fn consensus() -> Self {
+        Self {
+            p2sh: true,
+            witness: true,
+            taproot: true,
+            check_lock_time: true,
+            check_sequence: true,
+            null_dummy: true,
+            strict_der: true,
+            minimal_data: false,
+            minimal_if: false,
+            null_fail: false,
+            sig_push_only: false,
+            clean_stack: false,
+            witness_pubkey_type: false,
+            discourage_upgradable: false,
+        }
+    }
+}
+
+/// Everything the interpreter needs to know about the spending transaction
+pub trait SignatureChecker {
+    /// Check an ECDSA `signature` (DER followed by the sighash type byte) by `pubkey` over `script_code`
+    fn check_ecdsa_signature(
+        &self,
+        signature: &[u8],
+        pubkey: &[u8],
+        script_code: &[u8],
+        sig_version: SigVersion,
+    ) -> bool;
+
+    /// Check a BIP340 `signature` (64 bytes, or 65 with the sighash type byte) by the x-only
+    /// `pubkey` of a taproot spend, `script_path` is `None` for key path spends. Checkers that
+    /// cannot compute taproot signature hashes reject every signature.
+    fn check_schnorr_signature(
+        &self,
+        _signature: &[u8],
+        _pubkey: &[u8],
+        _annex: Option<&[u8]>,
+        _script_path: Option<ScriptPath>,
+    ) -> bool {
+        false
+    }
+
+    /// Check an OP_CHECKLOCKTIMEVERIFY argument against the transaction locktime
+    fn check_lock_time(&self, lock_time: i64) -> bool;
+
+    /// Check an OP_CHECKSEQUENCEVERIFY argument against the input sequence
+    fn check_sequence(&self, sequence: i64) -> bool;
+}
+
+/// Test harness checker: every signature is verified against one fixed `sighash`, whatever its
+/// sighash type byte says, and timelocks against the given transaction fields
+pub struct SighashChecker {
+    sighash: [u8; 32],
+    version: i32,
+    lock_time: u32,
+    sequence: u32,
+}
+
+impl SighashChecker {
+    pub fn new(sighash: [u8; 32]) -> Self {
+        Self {
+            sighash,
+            version: 2,
+            lock_time: 0,
+            sequence: 0xffff_ffff,
+        }
+    }
+
+    pub fn version(mut self, version: i32) -> Self {
+        self.version = version;
+        self
+    }
+
+    pub fn lock_time(mut self, lock_time: u32) -> Self {
+        self.lock_time = lock_time;
+        self
+    }
+
+    pub fn sequence(mut self, sequence: u32) -> Self {
+        self.sequence = sequence;
+        self
+    }
+}
+
+impl SignatureChecker for SighashChecker {
+    fn check_ecdsa_signature(
+        &self,
+        signature: &[u8],
+        pubkey: &[u8],
+        _script_code: &[u8],
+        _sig_version: SigVersion,
+    ) -> bool {
+        verify_ecdsa(signature, pubkey, &self.sighash)
+    }
+
+    fn check_schnorr_signature(
+        &self,
+        signature: &[u8],
+        pubkey: &[u8],
+        _annex: Option<&[u8]>,
+        _script_path: Option<ScriptPath>,
+    ) -> bool {
+        verify_schnorr(signature, pubkey, &self.sighash)
+    }
+
+    fn check_lock_time(&self, lock_time: i64) -> bool {
+        lock_time_satisfied(self.lock_time, self.sequence, lock_time)
+    }
+
+    fn check_sequence(&self, sequence: i64) -> bool {
+        sequence_satisfied(self.version, self.sequence, sequence)
+    }
+}
+
+/// Verify a DER signature with trailing sighash type byte against a precomputed `sighash`
+pub(crate) fn verify_ecdsa(signature: &[u8], pubkey: &[u8], sighash: &[u8; 32]) -> bool {
+    if signature.is_empty() {
+        return false;
+    }
+    let der = &signature[..signature.len() - 1];
+    let (mut signature, pubkey, message) = match (
+        Signature::from_der(der),
+        PublicKey::from_slice(pubkey),
+        Message::from_slice(sighash),
+    ) {
+        (Ok(signature), Ok(pubkey), Ok(message)) => (signature, pubkey, message),
+        _ => return false,
+    };
+    // consensus accepts high-S signatures, libsecp256k1 only verifies low-S ones
+    signature.normalize_s();
+    Secp256k1::verification_only()
+        .verify(&message, &signature, &pubkey)
+        .is_ok()
+}
+
+/// Verify a BIP340 signature with optional trailing sighash type byte against a precomputed
+/// `sighash`
+pub(crate) fn verify_schnorr(signature: &[u8], pubkey: &[u8], sighash: &[u8; 32]) -> bool {
+    if signature.len() != 64 && signature.len() != 65 {
+        return false;
+    }
+    let (signature, pubkey, message) = match (
+        schnorrsig::Signature::from_slice(&signature[..64]),
+        schnorrsig::PublicKey::from_slice(pubkey),
+        Message::from_slice(sighash),
+    ) {
+        (Ok(signature), Ok(pubkey), Ok(message)) => (signature, pubkey, message),
+        _ => return false,
+    };
+    // the schnorrsig methods of this secp256k1 version need a signing capable context
+    Secp256k1::new()
+        .schnorrsig_verify(&signature, &message, &pubkey)
+        .is_ok()
+}
+
+/// BIP65 rule: the transaction locktime must be of the same kind and at least `lock_time`
+pub(crate) fn lock_time_satisfied(tx_lock_time: u32, tx_sequence: u32, lock_time: i64) -> bool {
+    let tx_lock_time = tx_lock_time as i64;
+    let threshold = LOCKTIME_THRESHOLD as i64;
+    if (tx_lock_time < threshold) != (lock_time < threshold) {
+        return false;
+    }
+    if lock_time > tx_lock_time {
+        return false;
+    }
+    // a final input disables the transaction locktime altogether
+    tx_sequence != 0xffff_ffff
+}
+
+/// BIP112 rule: the input sequence must be a relative locktime of the same kind and at least `sequence`
+pub(crate) fn sequence_satisfied(tx_version: i32, tx_sequence: u32, sequence: i64) -> bool {
+    let tx_sequence = tx_sequence as i64;
+    if tx_version < 2 || tx_sequence & SEQUENCE_LOCKTIME_DISABLE_FLAG != 0 {
+        return false;
+    }
+    let mask = SEQUENCE_LOCKTIME_TYPE_FLAG | SEQUENCE_LOCKTIME_MASK;
+    let tx_sequence = tx_sequence & mask;
+    let sequence = sequence & mask;
+    if (tx_sequence < SEQUENCE_LOCKTIME_TYPE_FLAG) != (sequence < SEQUENCE_LOCKTIME_TYPE_FLAG) {
+        return false;
+    }
+    sequence <= tx_sequence
+}
+
+/// BIP66 strict DER check of a signature with trailing sighash type byte
+fn is_valid_signature_encoding(sig: &[u8]) -> bool {
+    if sig.len() < 9 || sig.len() > 73 || sig[0] != 0x30 || sig[1] as usize != sig.len() - 3 {
+        return false;
+    }
+    let len_r = sig[3] as usize;
+    if 5 + len_r >= sig.len() {
+        return false;
+    }
+    let len_s = sig[5 + len_r] as usize;
+    if len_r + len_s + 7 != sig.len() {
+        return false;
+    }
+    if sig[2] != 0x02 || len_r == 0 || sig[4] & 0x80 != 0 {
+        return false;
+    }
+    if len_r > 1 && sig[4] == 0x00 && sig[5] & 0x80 == 0 {
+        return false;
+    }
+    if sig[len_r + 4] != 0x02 || len_s == 0 || sig[len_r + 6] & 0x80 != 0 {
+        return false;
+    }
+    !(len_s > 1 && sig[len_r + 6] == 0x00 && sig[len_r + 7] & 0x80 == 0)
+}
+
+fn is_compressed_pubkey(pubkey: &[u8]) -> bool {
+    pubkey.len() == 33 && (pubkey[0] == 0x02 || pubkey[0] == 0x03)
+}
+
+fn check_signature_encoding(sig: &[u8], flags: &ScriptFlags) -> Result<(), ScriptError> {
+    if !sig.is_empty() && flags.strict_der && !is_valid_signature_encoding(sig) {
+        return Err(ScriptError::SigDer);
+    }
+    Ok(())
+}
+
+fn check_pubkey_encoding(
+    pubkey: &[u8],
+    flags: &ScriptFlags,
+    sig_version: SigVersion,
+) -> Result<(), ScriptError> {
+    if flags.witness_pubkey_type
+        && sig_version == SigVersion::WitnessV0
+        && !is_compressed_pubkey(pubkey)
+    {
+        return Err(ScriptError::WitnessPubkeyType);
+    }
+    Ok(())
+}
+
+/// Remove every push of `sig` from a legacy `script_code`, a signature cannot sign itself
+fn find_and_delete(script_code: &[u8], sig: &[u8]) -> Vec<u8> {
+    let mut pattern = Vec::new();
+    push_data(&mut pattern, sig);
+    let mut result = Vec::new();
+    let mut iter = instructions(script_code);
+    let mut start = 0;
+    while let Some(Ok(_)) = iter.next() {
+        let end = iter.position();
+        if script_code[start..end] != pattern[..] {
+            result.extend(&script_code[start..end]);
+        }
+        start = end;
+    }
+    result.extend(&script_code[start..]);
+    result
+}
+
+fn is_disabled(op: u8) -> bool {
+    matches!(
+        op,
+        OP_CAT
+            | OP_SUBSTR
+            | OP_LEFT
+            | OP_RIGHT
+            | OP_INVERT
+            | OP_AND
+            | OP_OR
+            | OP_XOR
+            | OP_2MUL
+            | OP_2DIV
+            | OP_MUL
+            | OP_DIV
+            | OP_MOD
+            | OP_LSHIFT
+            | OP_RSHIFT
+    )
+}
+
+fn pop(stack: &mut Vec<Vec<u8>>) -> Result<Vec<u8>, ScriptError> {
+    stack.pop().ok_or(ScriptError::InvalidStackOperation)
+}
+
+fn top(stack: &[Vec<u8>], depth: usize) -> Result<&Vec<u8>, ScriptError> {
+    if depth == 0 || stack.len() < depth {
+        return Err(ScriptError::InvalidStackOperation);
+    }
+    Ok(&stack[stack.len() - depth])
+}
+
+fn pop_num(stack: &mut Vec<Vec<u8>>, flags: &ScriptFlags) -> Result<i64, ScriptError> {
+    read_scriptint(&pop(stack)?, flags.minimal_data, 4)
+}
+
+fn push_bool(stack: &mut Vec<Vec<u8>>, value: bool) {
+    stack.push(if value { vec![1] } else { vec![] });
+}
+
+/// What tapscript signatures commit to and the signature checks they may still do (BIP342)
+struct ExecutionData<'a> {
+    annex: Option<&'a [u8]>,
+    leaf_hash: [u8; 32],
+    /// Opcode position of the last executed `OP_CODESEPARATOR`, `0xffffffff` if none
+    code_separator_position: u32,
+    validation_weight_left: i64,
+}
+
+impl<'a> Default for ExecutionData<'a> {
+    fn default() -> Self {
+        Self {
+            annex: None,
+            leaf_hash: [0; 32],
+            code_separator_position: u32::MAX,
+            validation_weight_left: i64::MAX,
+        }
+    }
+}
+
+/// Opcodes that make a tapscript succeed unconditionally, reserved for future soft forks
+pub fn is_op_success(op: u8) -> bool {
+    matches!(
+        op,
+        80 | 98 | 126..=129 | 131..=134 | 137..=138 | 141..=142 | 149..=153 | 187..=254
+    )
+}
+
+/// BIP340 signature size and sighash type rules, then the signature itself
+fn check_schnorr_signature<C: SignatureChecker + ?Sized>(
+    sig: &[u8],
+    pubkey: &[u8],
+    checker: &C,
+    annex: Option<&[u8]>,
+    script_path: Option<ScriptPath>,
+) -> Result<(), ScriptError> {
+    match sig.len() {
+        64 => {}
+        65 if sig[64] != SIGHASH_DEFAULT => {}
+        65 => return Err(ScriptError::SchnorrSigHashType),
+        _ => return Err(ScriptError::SchnorrSigSize),
+    }
+    if !checker.check_schnorr_signature(sig, pubkey, annex, script_path) {
+        return Err(ScriptError::SchnorrSig);
+    }
+    Ok(())
+}
+
+/// OP_CHECKSIG family signature check of a tapscript, whether `sig` was non-empty
+fn check_tapscript_signature<C: SignatureChecker + ?Sized>(
+    sig: &[u8],
+    pubkey: &[u8],
+    flags: &ScriptFlags,
+    checker: &C,
+    execution: &mut ExecutionData,
+) -> Result<bool, ScriptError> {
+    if !sig.is_empty() {
+        execution.validation_weight_left -= VALIDATION_WEIGHT_PER_SIGOP_PASSED;
+        if execution.validation_weight_left < 0 {
+            return Err(ScriptError::TapscriptValidationWeight);
+        }
+    }
+    if pubkey.is_empty() {
+        return Err(ScriptError::TapscriptEmptyPubkey);
+    }
+    // other public key sizes are unknown key types, reserved for future soft forks
+    if pubkey.len() != 32 && flags.discourage_upgradable {
+        return Err(ScriptError::DiscourageUpgradablePubkeyType);
+    }
+    if pubkey.len() == 32 && !sig.is_empty() {
+        let script_path = ScriptPath {
+            leaf_hash: execution.leaf_hash,
+            code_separator_position: execution.code_separator_position,
+        };
+        check_schnorr_signature(sig, pubkey, checker, execution.annex, Some(script_path))?;
+    }
+    Ok(!sig.is_empty())
+}
+
+/// Evaluate `script` on top of `stack`
+pub fn eval_script<C: SignatureChecker + ?Sized>(
+    stack: &mut Vec<Vec<u8>>,
+    script: &[u8],
+    flags: &ScriptFlags,
+    checker: &C,
+    sig_version: SigVersion,
+) -> Result<(), ScriptError> {
+    eval(
+        stack,
+        script,
+        flags,
+        checker,
+        sig_version,
+        &mut ExecutionData::default(),
+    )
+}
+
+fn eval<C: SignatureChecker + ?Sized>(
+    stack: &mut Vec<Vec<u8>>,
+    script: &[u8],
+    flags: &ScriptFlags,
+    checker: &C,
+    sig_version: SigVersion,
+    execution: &mut ExecutionData,
+) -> Result<(), ScriptError> {
+    let tapscript = sig_version == SigVersion::Tapscript;
+    if !tapscript && script.len() > script::MAX_SCRIPT_SIZE {
+        return Err(ScriptError::ScriptSize);
+    }
+    let mut altstack: Vec<Vec<u8>> = Vec::new();
+    let mut exec: Vec<bool> = Vec::new();
+    let mut op_count = 0;
+    let mut code_separator = 0;
+    let mut iter = instructions(script);
+    let mut opcode_position = 0u32;
+
+    loop {
+        let start = iter.position();
+        let instruction = match iter.next() {
+            Some(instruction) => instruction?,
+            None => break,
+        };
+        let executing = exec.iter().all(|branch| *branch);
+
+        match instruction {
+            Instruction::PushBytes(data) => {
+                if data.len() > script::MAX_SCRIPT_ELEMENT_SIZE {
+                    return Err(ScriptError::PushSize);
+                }
+                if executing {
+                    if flags.minimal_data && !is_minimal_push(script[start], data) {
+                        return Err(ScriptError::MinimalData);
+                    }
+                    stack.push(data.to_vec());
+                }
+            }
+            Instruction::Op(op) => {
+                // tapscripts are bounded by their validation weight instead
+                if op > OP_PUSHNUM_16 && !tapscript {
+                    op_count += 1;
+                    if op_count > script::MAX_OPS_PER_SCRIPT {
+                        return Err(ScriptError::OpCount);
+                    }
+                }
+                if is_disabled(op) {
+                    return Err(ScriptError::DisabledOpcode);
+                }
+                if op == OP_VERIF || op == OP_VERNOTIF {
+                    return Err(ScriptError::BadOpcode);
+                }
+                if executing || (OP_IF..=OP_ENDIF).contains(&op) {
+                    execute_opcode(
+                        op,
+                        stack,
+                        &mut altstack,
+                        &mut exec,
+                        &mut op_count,
+                        &mut code_separator,
+                        iter.position(),
+                        opcode_position,
+                        script,
+                        flags,
+                        checker,
+                        sig_version,
+                        execution,
+                    )?;
+                }
+            }
+        }
+
+        if stack.len() + altstack.len() > MAX_STACK_SIZE {
+            return Err(ScriptError::StackSize);
+        }
+        opcode_position += 1;
+    }
+
+    if !exec.is_empty() {
+        return Err(ScriptError::UnbalancedConditional);
+    }
+    Ok(())
+}
+
+#[allow(clippy::too_many_arguments)]
+fn execute_opcode<C: SignatureChecker + ?Sized>(
+    op: u8,
+    stack: &mut Vec<Vec<u8>>,
+    altstack: &mut Vec<Vec<u8>>,
+    exec: &mut Vec<bool>,
+    op_count: &mut usize,
+    code_separator: &mut usize,
+    position: usize,
+    opcode_position: u32,
+    script: &[u8],
+    flags: &ScriptFlags,
+    checker: &C,
+    sig_version: SigVersion,
+    execution: &mut ExecutionData,
+) -> Result<(), ScriptError> {
+    let executing = exec.iter().all(|branch| *branch);
+    match op {
+        OP_PUSHNUM_NEG1 => stack.push(scriptint_bytes(-1)),
+        OP_PUSHNUM_1..=OP_PUSHNUM_16 => stack.push(scriptint_bytes((op - OP_PUSHNUM_1 + 1) as i64)),
+
+        // flow control
+        OP_NOP => {}
+        OP_NOP1 | OP_NOP4..=OP_NOP10 => {
+            if flags.discourage_upgradable {
+                return Err(ScriptError::DiscourageUpgradableNops);
+            }
+        }
+        OP_IF | OP_NOTIF => {
+            let mut value = false;
+            if executing {
+                let condition = pop(stack)?;
+                let minimal = condition.is_empty() || condition == [1];
+                // consensus for tapscript, policy for witness v0 scripts
+                if sig_version == SigVersion::Tapscript && !minimal {
+                    return Err(ScriptError::TapscriptMinimalIf);
+                }
+                if sig_version == SigVersion::WitnessV0 && flags.minimal_if && !minimal {
+                    return Err(ScriptError::MinimalIf);
+                }
+                value = cast_to_bool(&condition);
+                if op == OP_NOTIF {
+                    value = !value;
+                }
+            }
+            exec.push(value);
+        }
+        OP_ELSE => {
+            let branch = exec.last_mut().ok_or(ScriptError::UnbalancedConditional)?;
+            *branch = !*branch;
+        }
+        OP_ENDIF => {
+            exec.pop().ok_or(ScriptError::UnbalancedConditional)?;
+        }
+        OP_VERIFY => {
+            if !cast_to_bool(&pop(stack)?) {
+                return Err(ScriptError::Verify);
+            }
+        }
+        OP_RETURN => return Err(ScriptError::OpReturn),
+        OP_CLTV => {
+            if flags.check_lock_time {
+                let lock_time = read_scriptint(top(stack, 1)?, flags.minimal_data, 5)?;
+                if lock_time < 0 {
+                    return Err(ScriptError::NegativeLocktime);
+                }
+                if !checker.check_lock_time(lock_time) {
+                    return Err(ScriptError::UnsatisfiedLocktime);
+                }
+            }
+        }
+        OP_CSV => {
+            if flags.check_sequence {
+                let sequence = read_scriptint(top(stack, 1)?, flags.minimal_data, 5)?;
+                if sequence < 0 {
+                    return Err(ScriptError::NegativeLocktime);
+                }
+                if sequence & SEQUENCE_LOCKTIME_DISABLE_FLAG == 0
+                    && !checker.check_sequence(sequence)
+                {
+                    return Err(ScriptError::UnsatisfiedLocktime);
+                }
+            }
+        }
+
+        // stack operations
+        OP_TOALTSTACK => altstack.push(pop(stack)?),
+        OP_FROMALTSTACK => stack.push(
+            altstack
+                .pop()
+                .ok_or(ScriptError::InvalidAltstackOperation)?,
+        ),
+        OP_2DROP => {
+            top(stack, 2)?;
+            stack.truncate(stack.len() - 2);
+        }
+        OP_2DUP => {
+            let (a, b) = (top(stack, 2)?.clone(), top(stack, 1)?.clone());
+            stack.extend([a, b]);
+        }
+        OP_3DUP => {
+            let (a, b, c) = (
+                top(stack, 3)?.clone(),
+                top(stack, 2)?.clone(),
+                top(stack, 1)?.clone(),
+            );
+            stack.extend([a, b, c]);
+        }
+        OP_2OVER => {
+            let (a, b) = (top(stack, 4)?.clone(), top(stack, 3)?.clone());
+            stack.extend([a, b]);
+        }
+        OP_2ROT => {
+            top(stack, 6)?;
+            let index = stack.len() - 6;
+            let items: Vec<Vec<u8>> = stack.drain(index..index + 2).collect();
+            stack.extend(items);
+        }
+        OP_2SWAP => {
+            top(stack, 4)?;
+            let len = stack.len();
+            stack.swap(len - 4, len - 2);
+            stack.swap(len - 3, len - 1);
+        }
+        OP_IFDUP => {
+            let value = top(stack, 1)?.clone();
+            if cast_to_bool(&value) {
+                stack.push(value);
+            }
+        }
+        OP_DEPTH => stack.push(scriptint_bytes(stack.len() as i64)),
+        OP_DROP => {
+            pop(stack)?;
+        }
+        OP_DUP => {
+            let value = top(stack, 1)?.clone();
+            stack.push(value);
+        }
+        OP_NIP => {
+            top(stack, 2)?;
+            let index = stack.len() - 2;
+            stack.remove(index);
+        }
+        OP_OVER => {
+            let value = top(stack, 2)?.clone();
+            stack.push(value);
+        }
+        OP_PICK | OP_ROLL => {
+            let n = pop_num(stack, flags)?;
+            if n < 0 || n as usize >= stack.len() {
+                return Err(ScriptError::InvalidStackOperation);
+            }
+            let index = stack.len() - 1 - n as usize;
+            let value = if op == OP_ROLL {
+                stack.remove(index)
+            } else {
+                stack[index].clone()
+            };
+            stack.push(value);
+        }
+        OP_ROT => {
+            top(stack, 3)?;
+            let index = stack.len() - 3;
+            let value = stack.remove(index);
+            stack.push(value);
+        }
+        OP_SWAP => {
+            top(stack, 2)?;
+            let len = stack.len();
+            stack.swap(len - 2, len - 1);
+        }
+        OP_TUCK => {
+            let value = top(stack, 1)?.clone();
+            top(stack, 2)?;
+            let index = stack.len() - 2;
+            stack.insert(index, value);
+        }
+        OP_SIZE => {
+            let size = top(stack, 1)?.len();
+            stack.push(scriptint_bytes(size as i64));
+        }
+
+        // bitwise logic
+        OP_EQUAL | OP_EQUALVERIFY => {
+            let b = pop(stack)?;
+            let a = pop(stack)?;
+            let equal = a == b;
+            if op == OP_EQUALVERIFY {
+                if !equal {
+                    return Err(ScriptError::EqualVerify);
+                }
+            } else {
+                push_bool(stack, equal);
+            }
+        }
+
+        // arithmetic
+        OP_1ADD | OP_1SUB | OP_NEGATE | OP_ABS | OP_NOT | OP_0NOTEQUAL => {
+            let a = pop_num(stack, flags)?;
+            let result = match op {
+                OP_1ADD => a + 1,
+                OP_1SUB => a - 1,
+                OP_NEGATE => -a,
+                OP_ABS => a.abs(),
+                OP_NOT => (a == 0) as i64,
+                _ => (a != 0) as i64,
+            };
+            stack.push(scriptint_bytes(result));
+        }
+        OP_ADD
+        | OP_SUB
+        | OP_BOOLAND
+        | OP_BOOLOR
+        | OP_NUMEQUAL
+        | OP_NUMEQUALVERIFY
+        | OP_NUMNOTEQUAL
+        | OP_LESSTHAN
+        | OP_GREATERTHAN
+        | OP_LESSTHANOREQUAL
+        | OP_GREATERTHANOREQUAL
+        | OP_MIN
+        | OP_MAX => {
+            let b = pop_num(stack, flags)?;
+            let a = pop_num(stack, flags)?;
+            let result = match op {
+                OP_ADD => a + b,
+                OP_SUB => a - b,
+                OP_BOOLAND => (a != 0 && b != 0) as i64,
+                OP_BOOLOR => (a != 0 || b != 0) as i64,
+                OP_NUMEQUAL | OP_NUMEQUALVERIFY => (a == b) as i64,
+                OP_NUMNOTEQUAL => (a != b) as i64,
+                OP_LESSTHAN => (a < b) as i64,
+                OP_GREATERTHAN => (a > b) as i64,
+                OP_LESSTHANOREQUAL => (a <= b) as i64,
+                OP_GREATERTHANOREQUAL => (a >= b) as i64,
+                OP_MIN => a.min(b),
+                _ => a.max(b),
+            };
+            if op == OP_NUMEQUALVERIFY {
+                if result == 0 {
+                    return Err(ScriptError::NumEqualVerify);
+                }
+            } else {
+                stack.push(scriptint_bytes(result));
+            }
+        }
+        OP_WITHIN => {
+            let max = pop_num(stack, flags)?;
+            let min = pop_num(stack, flags)?;
+            let x = pop_num(stack, flags)?;
+            push_bool(stack, min <= x && x < max);
+        }
+
+        // crypto
+        OP_RIPEMD160 => {
+            let value = pop(stack)?;
+            stack.push(hash::ripemd160(&value).to_vec());
+        }
+        OP_SHA1 => {
+            let value = pop(stack)?;
+            stack.push(hash::sha1(&value).to_vec());
+        }
+        OP_SHA256 => {
+            let value = pop(stack)?;
+            stack.push(hash::sha256(&value).to_vec());
+        }
+        OP_HASH160 => {
+            let value = pop(stack)?;
+            stack.push(hash::hash160(&value).to_vec());
+        }
+        OP_HASH256 => {
+            let value = pop(stack)?;
+            stack.push(hash::sha256d(&value).to_vec());
+        }
+        OP_CODESEPARATOR => {
+            *code_separator = position;
+            execution.code_separator_position = opcode_position;
+        }
+        OP_CHECKSIG | OP_CHECKSIGVERIFY if sig_version == SigVersion::Tapscript => {
+            let pubkey = pop(stack)?;
+            let sig = pop(stack)?;
+            let success = check_tapscript_signature(&sig, &pubkey, flags, checker, execution)?;
+            if op == OP_CHECKSIGVERIFY {
+                if !success {
+                    return Err(ScriptError::CheckSigVerify);
+                }
+            } else {
+                push_bool(stack, success);
+            }
+        }
+        OP_CHECKSIGADD if sig_version == SigVersion::Tapscript => {
+            top(stack, 3)?;
+            let pubkey = pop(stack)?;
+            let n = pop_num(stack, flags)?;
+            let sig = pop(stack)?;
+            let success = check_tapscript_signature(&sig, &pubkey, flags, checker, execution)?;
+            stack.push(scriptint_bytes(n + success as i64));
+        }
+        OP_CHECKMULTISIG | OP_CHECKMULTISIGVERIFY if sig_version == SigVersion::Tapscript => {
+            return Err(ScriptError::TapscriptCheckMultisig)
+        }
+        OP_CHECKSIG | OP_CHECKSIGVERIFY => {
+            let pubkey = pop(stack)?;
+            let sig = pop(stack)?;
+            check_signature_encoding(&sig, flags)?;
+            check_pubkey_encoding(&pubkey, flags, sig_version)?;
+            let mut script_code = script[*code_separator..].to_vec();
+            if sig_version == SigVersion::Base {
+                script_code = find_and_delete(&script_code, &sig);
+            }
+            let success = checker.check_ecdsa_signature(&sig, &pubkey, &script_code, sig_version);
+            if !success && flags.null_fail && !sig.is_empty() {
+                return Err(ScriptError::NullFail);
+            }
+            if op == OP_CHECKSIGVERIFY {
+                if !success {
+                    return Err(ScriptError::CheckSigVerify);
+                }
+            } else {
+                push_bool(stack, success);
+            }
+        }
+        OP_CHECKMULTISIG | OP_CHECKMULTISIGVERIFY => {
+            let mut i = 1;
+            let key_count = read_scriptint(top(stack, i)?, flags.minimal_data, 4)?;
+            if key_count < 0 || key_count as usize > script::MAX_PUBKEYS_PER_MULTISIG {
+                return Err(ScriptError::PubkeyCount);
+            }
+            let mut key_count = key_count as usize;
+            *op_count += key_count;
+            if *op_count > script::MAX_OPS_PER_SCRIPT {
+                return Err(ScriptError::OpCount);
+            }
+            i += 1;
+            let mut ikey = i;
+            i += key_count;
+            let sig_count = read_scriptint(top(stack, i)?, flags.minimal_data, 4)?;
+            if sig_count < 0 || sig_count as usize > key_count {
+                return Err(ScriptError::SigCount);
+            }
+            let mut sig_count = sig_count as usize;
+            i += 1;
+            let mut isig = i;
+            let (first_sig, total_sigs) = (isig, sig_count);
+            i += sig_count;
+            // the dummy element consumed by the off-by-one bug
+            top(stack, i)?;
+
+            let mut script_code = script[*code_separator..].to_vec();
+            if sig_version == SigVersion::Base {
+                for k in 0..total_sigs {
+                    script_code = find_and_delete(&script_code, top(stack, first_sig + k)?);
+                }
+            }
+
+            let mut success = true;
+            while success && sig_count > 0 {
+                let sig = top(stack, isig)?;
+                let pubkey = top(stack, ikey)?;
+                check_signature_encoding(sig, flags)?;
+                check_pubkey_encoding(pubkey, flags, sig_version)?;
+                if checker.check_ecdsa_signature(sig, pubkey, &script_code, sig_version) {
+                    isig += 1;
+                    sig_count -= 1;
+                }
+                ikey += 1;
+                key_count -= 1;
+                if sig_count > key_count {
+                    success = false;
+                }
+            }
+
+            if !success && flags.null_fail {
+                for k in 0..total_sigs {
+                    if !top(stack, first_sig + k)?.is_empty() {
+                        return Err(ScriptError::NullFail);
+                    }
+                }
+            }
+            stack.truncate(stack.len() + 1 - i);
+            let dummy = pop(stack)?;
+            if flags.null_dummy && !dummy.is_empty() {
+                return Err(ScriptError::NullDummy);
+            }
+            if op == OP_CHECKMULTISIGVERIFY {
+                if !success {
+                    return Err(ScriptError::CheckMultisigVerify);
+                }
+            } else {
+                push_bool(stack, success);
+            }
+        }
+
+        _ => return Err(ScriptError::BadOpcode),
+    }
+    Ok(())
+}
+
+/// Split a witness program scriptPubKey into its version and program
+pub fn witness_program(script_pubkey: &[u8]) -> Option<(u8, &[u8])> {
+    if script_pubkey.len() < 4 || script_pubkey.len() > 42 {
+        return None;
+    }
+    let version = match script_pubkey[0] {
+        OP_PUSHBYTES_0 => 0,
+        op @ OP_PUSHNUM_1..=OP_PUSHNUM_16 => op - OP_PUSHNUM_1 + 1,
+        _ => return None,
+    };
+    if script_pubkey[1] as usize + 2 != script_pubkey.len() {
+        return None;
+    }
+    Some((version, &script_pubkey[2..]))
+}
+
+/// Whether `script_pubkey` is `OP_HASH160 <20 bytes> OP_EQUAL`
+pub fn is_p2sh(script_pubkey: &[u8]) -> bool {
+    script_pubkey.len() == 23
+        && script_pubkey[0] == OP_HASH160
+        && script_pubkey[1] == OP_PUSHBYTES_20
+        && script_pubkey[22] == OP_EQUAL
+}
+
+fn execute_witness_script<C: SignatureChecker + ?Sized>(
+    mut stack: Vec<Vec<u8>>,
+    script: &[u8],
+    flags: &ScriptFlags,
+    checker: &C,
+    sig_version: SigVersion,
+    execution: &mut ExecutionData,
+) -> Result<(), ScriptError> {
+    if sig_version == SigVersion::Tapscript {
+        // an OP_SUCCESS anywhere in the script, even unexecuted, succeeds before evaluation
+        for instruction in instructions(script) {
+            if let Instruction::Op(op) = instruction? {
+                if is_op_success(op) {
+                    if flags.discourage_upgradable {
+                        return Err(ScriptError::DiscourageOpSuccess);
+                    }
+                    return Ok(());
+                }
+            }
+        }
+        if stack.len() > MAX_STACK_SIZE {
+            return Err(ScriptError::StackSize);
+        }
+    }
+    if stack
+        .iter()
+        .any(|item| item.len() > script::MAX_SCRIPT_ELEMENT_SIZE)
+    {
+        return Err(ScriptError::PushSize);
+    }
+    eval(&mut stack, script, flags, checker, sig_version, execution)?;
+    // segwit scripts are always clean stack
+    if stack.len() != 1 {
+        return Err(ScriptError::CleanStack);
+    }
+    if !cast_to_bool(&stack[0]) {
+        return Err(ScriptError::EvalFalse);
+    }
+    Ok(())
+}
+
+/// Serialized size of a witness, the validation weight budget of its tapscript
+fn witness_size(witness: &[Vec<u8>]) -> usize {
+    let mut data = Vec::new();
+    push_compact_size(&mut data, witness.len());
+    for item in witness {
+        push_compact_size(&mut data, item.len());
+    }
+    data.len() + witness.iter().map(Vec::len).sum::<usize>()
+}
+
+/// Verify a taproot key path or script path spend of `output_key` (BIP341)
+fn verify_taproot<C: SignatureChecker + ?Sized>(
+    witness: &[Vec<u8>],
+    output_key: &[u8],
+    flags: &ScriptFlags,
+    checker: &C,
+) -> Result<(), ScriptError> {
+    let (stack, annex) = match witness.split_last() {
+        None => return Err(ScriptError::WitnessProgramWitnessEmpty),
+        Some((last, rest)) if !rest.is_empty() && last.first() == Some(&ANNEX_TAG) => {
+            (rest, Some(last.as_slice()))
+        }
+        _ => (witness, None),
+    };
+    if let [signature] = stack {
+        return check_schnorr_signature(signature, output_key, checker, annex, None);
+    }
+    let (control_block, stack) = stack.split_last().expect("at least two elements");
+    let (script, stack) = stack.split_last().expect("at least one element");
+    if !taproot::is_valid_control_block_size(control_block) {
+        return Err(ScriptError::TaprootWrongControlSize);
+    }
+    let mut key = [0u8; 32];
+    key.copy_from_slice(output_key);
+    if !taproot::verify_control_block(&key, script, control_block) {
+        return Err(ScriptError::WitnessProgramMismatch);
+    }
+    let leaf_version = control_block[0] & 0xfe;
+    if leaf_version != TAPROOT_LEAF_TAPSCRIPT {
+        // unknown leaf versions are anyone-can-spend, reserved for future soft forks
+        if flags.discourage_upgradable {
+            return Err(ScriptError::DiscourageUpgradableTaprootVersion);
+        }
+        return Ok(());
+    }
+    let mut execution = ExecutionData {
+        annex,
+        leaf_hash: taproot::tap_leaf_hash(script, leaf_version),
+        code_separator_position: u32::MAX,
+        validation_weight_left: VALIDATION_WEIGHT_OFFSET + witness_size(witness) as i64,
+    };
+    execute_witness_script(
+        stack.to_vec(),
+        script,
+        flags,
+        checker,
+        SigVersion::Tapscript,
+        &mut execution,
+    )
+}
+
+fn verify_witness_program<C: SignatureChecker + ?Sized>(
+    witness: &[Vec<u8>],
+    version: u8,
+    program: &[u8],
+    flags: &ScriptFlags,
+    checker: &C,
+    is_p2sh: bool,
+) -> Result<(), ScriptError> {
+    if version == 1 && program.len() == 32 && !is_p2sh && flags.taproot {
+        return verify_taproot(witness, program, flags, checker);
+    }
+    if version != 0 {
+        // unknown witness versions are anyone-can-spend, reserved for future soft forks
+        if flags.discourage_upgradable {
+            return Err(ScriptError::DiscourageUpgradableWitnessProgram);
+        }
+        return Ok(());
+    }
+    match program.len() {
+        32 => {
+            let (script, stack) = witness
+                .split_last()
+                .ok_or(ScriptError::WitnessProgramWitnessEmpty)?;
+            if hash::sha256(script)[..] != program[..] {
+                return Err(ScriptError::WitnessProgramMismatch);
+            }
+            execute_witness_script(
+                stack.to_vec(),
+                script,
+                flags,
+                checker,
+                SigVersion::WitnessV0,
+                &mut ExecutionData::default(),
+            )
+        }
+        20 => {
+            if witness.len() != 2 {
+                return Err(ScriptError::WitnessProgramMismatch);
+            }
+            let mut script = vec![OP_DUP, OP_HASH160, OP_PUSHBYTES_20];
+            script.extend(program);
+            script.extend([OP_EQUALVERIFY, OP_CHECKSIG]);
+            execute_witness_script(
+                witness.to_vec(),
+                &script,
+                flags,
+                checker,
+                SigVersion::WitnessV0,
+                &mut ExecutionData::default(),
+            )
+        }
+        _ => Err(ScriptError::WitnessProgramWrongLength),
+    }
+}
+
+/// Verify that `script_sig` and `witness` satisfy `script_pubkey`, including P2SH, segwit v0 and
+/// taproot
+pub fn verify_script<C: SignatureChecker + ?Sized>(
+    script_sig: &[u8],
+    witness: &[Vec<u8>],
+    script_pubkey: &[u8],
+    flags: &ScriptFlags,
+    checker: &C,
+) -> Result<(), ScriptError> {
+    if flags.sig_push_only && !is_push_only(script_sig) {
+        return Err(ScriptError::SigPushOnly);
+    }
+    let mut stack = Vec::new();
+    eval_script(&mut stack, script_sig, flags, checker, SigVersion::Base)?;
+    let p2sh_stack = stack.clone();
+    eval_script(&mut stack, script_pubkey, flags, checker, SigVersion::Base)?;
+    match stack.last() {
+        Some(value) if cast_to_bool(value) => {}
+        _ => return Err(ScriptError::EvalFalse),
+    }
+
+    let mut had_witness = false;
+    if flags.witness {
+        if let Some((version, program)) = witness_program(script_pubkey) {
+            had_witness = true;
+            if !script_sig.is_empty() {
+                return Err(ScriptError::WitnessMalleated);
+            }
+            verify_witness_program(witness, version, program, flags, checker, false)?;
+            stack.truncate(1);
+        }
+    }
+
+    if flags.p2sh && is_p2sh(script_pubkey) {
+        if !is_push_only(script_sig) {
+            return Err(ScriptError::SigPushOnly);
+        }
+        stack = p2sh_stack;
+        let redeem_script = pop(&mut stack)?;
+        eval_script(&mut stack, &redeem_script, flags, checker, SigVersion::Base)?;
+        match stack.last() {
+            Some(value) if cast_to_bool(value) => {}
+            _ => return Err(ScriptError::EvalFalse),
+        }
+        if flags.witness {
+            if let Some((version, program)) = witness_program(&redeem_script) {
+                had_witness = true;
+                let mut expected = Vec::new();
+                push_data(&mut expected, &redeem_script);
+                if script_sig != &expected[..] {
+                    return Err(ScriptError::WitnessMalleatedP2sh);
+                }
+                verify_witness_program(witness, version, program, flags, checker, true)?;
+                stack.truncate(1);
+            }
+        }
+    }
+
+    if flags.clean_stack && stack.len() != 1 {
+        return Err(ScriptError::CleanStack);
+    }
+    if flags.witness && !had_witness && !witness.is_empty() {
+        return Err(ScriptError::WitnessUnexpected);
+    }
+    Ok(())
+}
+
+/// Test harness: verify a spend of `script_pubkey` under standardness rules, checking every
+/// signature against `sighash`
+pub fn verify_with_sighash(
+    script_sig: &[u8],
+    witness: &[Vec<u8>],
+    script_pubkey: &[u8],
+    sighash: [u8; 32],
+) -> Result<(), ScriptError> {
+    verify_script(
+        script_sig,
+        witness,
+        script_pubkey,
+        &ScriptFlags::default(),
+        &SighashChecker::new(sighash),
+    )
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::{
+        taproot::{TapLeaf, Taproot},
+        wallet::{Multisig, PubKey},
+    };
+    use secp256k1::SecretKey;
+
+    /// Sighash every test signature commits to, for a `SighashChecker`
+    pub(crate) const SIGHASH: [u8; 32] = [0x42; 32];
+
+    /// Key pair of the secret key made of `byte` repeated
+    pub(crate) fn keypair(byte: u8) -> (SecretKey, PubKey) {
+        let secret_key = SecretKey::from_slice(&[byte; 32]).unwrap();
+        let public_key = PublicKey::from_secret_key(&Secp256k1::new(), &secret_key).serialize();
+        (secret_key, public_key)
+    }
+
+    /// SIGHASH_ALL signature of `SIGHASH`
+    pub(crate) fn sign(secret_key: &SecretKey) -> Vec<u8> {
+        let message = Message::from_slice(&SIGHASH).unwrap();
+        let mut sig = Secp256k1::new()
+            .sign(&message, secret_key)
+            .serialize_der()
+            .to_vec();
+        sig.push(0x01);
+        sig
+    }
+
+    fn schnorr_keypair(byte: u8) -> (schnorrsig::KeyPair, [u8; 32]) {
+        let secp = Secp256k1::new();
+        let keypair = schnorrsig::KeyPair::from_seckey_slice(&secp, &[byte; 32]).unwrap();
+        let x_only_key = schnorrsig::PublicKey::from_keypair(&secp, &keypair).serialize();
+        (keypair, x_only_key)
+    }
+
+    fn schnorr_sign(keypair: &schnorrsig::KeyPair) -> Vec<u8> {
+        let message = Message::from_slice(&SIGHASH).unwrap();
+        Secp256k1::new()
+            .schnorrsig_sign_no_aux_rand(&message, keypair)
+            .as_ref()
+            .to_vec()
+    }
+
+    fn p2tr(output_key: &[u8; 32]) -> Vec<u8> {
+        let mut script_pubkey = vec![OP_PUSHNUM_1];
+        push_data(&mut script_pubkey, output_key);
+        script_pubkey
+    }
+
+    /// Spend the only leaf of a tree with the NUMS internal key
+    fn spend_leaf_with_flags(
+        leaf: TapLeaf,
+        mut witness: Vec<Vec<u8>>,
+        flags: &ScriptFlags,
+    ) -> Result<(), ScriptError> {
+        let taproot = Taproot::internal_key(None)
+            .leaf(1, leaf.clone())
+            .generate_tree()
+            .unwrap();
+        witness.extend([leaf.script, taproot.control_block(0)]);
+        let script_pubkey = p2tr(&taproot.output_key());
+        let checker = SighashChecker::new(SIGHASH);
+        verify_script(&[], &witness, &script_pubkey, flags, &checker)
+    }
+
+    fn spend_leaf(leaf: TapLeaf, witness: Vec<Vec<u8>>) -> Result<(), ScriptError> {
+        spend_leaf_with_flags(leaf, witness, &ScriptFlags::default())
+    }
+
+    #[test]
+    fn test_arithmetic_and_flow_control() {
+        // 2 3 OP_ADD 5 OP_EQUAL OP_IF 1 OP_ELSE 0 OP_ENDIF
+        let script = [
+            OP_PUSHNUM_2,
+            OP_PUSHNUM_3,
+            OP_ADD,
+            OP_PUSHNUM_5,
+            OP_EQUAL,
+            OP_IF,
+            OP_PUSHNUM_1,
+            OP_ELSE,
+            OP_PUSHBYTES_0,
+            OP_ENDIF,
+        ];
+        assert_eq!(verify_with_sighash(&[], &[], &script, SIGHASH), Ok(()));
+        assert_eq!(
+            verify_with_sighash(&[], &[], &[OP_PUSHNUM_1, OP_IF], SIGHASH),
+            Err(ScriptError::UnbalancedConditional)
+        );
+        assert_eq!(
+            verify_with_sighash(&[], &[], &[OP_PUSHNUM_1, OP_PUSHNUM_2, OP_MUL], SIGHASH),
+            Err(ScriptError::DisabledOpcode)
+        );
+        assert_eq!(
+            verify_with_sighash(&[], &[], &[OP_PUSHNUM_1, OP_RETURN], SIGHASH),
+            Err(ScriptError::OpReturn)
+        );
+    }
+
+    #[test]
+    fn test_hashlock() {
+        let preimage = b"secret";
+        let mut script = vec![OP_SHA256];
+        push_data(&mut script, &hash::sha256(preimage));
+        script.push(OP_EQUAL);
+        let mut script_sig = Vec::new();
+        push_data(&mut script_sig, preimage);
+        assert_eq!(
+            verify_with_sighash(&script_sig, &[], &script, SIGHASH),
+            Ok(())
+        );
+        let mut script_sig = Vec::new();
+        push_data(&mut script_sig, b"guess");
+        assert_eq!(
+            verify_with_sighash(&script_sig, &[], &script, SIGHASH),
+            Err(ScriptError::EvalFalse)
+        );
+    }
+
+    #[test]
+    fn test_p2pkh() {
+        let (secret_key, public_key) = keypair(1);
+        let mut script_pubkey = vec![OP_DUP, OP_HASH160];
+        push_data(&mut script_pubkey, &hash::hash160(&public_key));
+        script_pubkey.extend([OP_EQUALVERIFY, OP_CHECKSIG]);
+        let mut script_sig = Vec::new();
+        push_data(&mut script_sig, &sign(&secret_key));
+        push_data(&mut script_sig, &public_key);
+        assert_eq!(
+            verify_with_sighash(&script_sig, &[], &script_pubkey, SIGHASH),
+            Ok(())
+        );
+        assert_eq!(
+            verify_with_sighash(&script_sig, &[], &script_pubkey, [0x43; 32]),
+            Err(ScriptError::NullFail)
+        );
+    }
+
+    #[test]
+    fn test_p2wpkh() {
+        let (secret_key, public_key) = keypair(2);
+        let mut script_pubkey = vec![OP_PUSHBYTES_0];
+        push_data(&mut script_pubkey, &hash::hash160(&public_key));
+        let witness = vec![sign(&secret_key), public_key.to_vec()];
+        assert_eq!(
+            verify_with_sighash(&[], &witness, &script_pubkey, SIGHASH),
+            Ok(())
+        );
+        assert_eq!(
+            verify_with_sighash(&[], &witness[..1], &script_pubkey, SIGHASH),
+            Err(ScriptError::WitnessProgramMismatch)
+        );
+    }
+
+    #[test]
+    fn test_multisig_p2sh_and_p2wsh() {
+        let keys: Vec<(SecretKey, PubKey)> = (1..=3).map(keypair).collect();
+        let multisig = Multisig::m(2)
+            .n(3)
+            .public_keys(keys.iter().map(|(_, public_key)| *public_key).collect())
+            .generate_address()
+            .unwrap();
+        let redeem_script = multisig.redeem_script().to_vec();
+
+        let mut script_pubkey = vec![OP_HASH160];
+        push_data(&mut script_pubkey, &hash::hash160(&redeem_script));
+        script_pubkey.push(OP_EQUAL);
+        let mut script_sig = vec![OP_PUSHBYTES_0];
+        push_data(&mut script_sig, &sign(&keys[0].0));
+        push_data(&mut script_sig, &sign(&keys[2].0));
+        push_data(&mut script_sig, &redeem_script);
+        assert_eq!(
+            verify_with_sighash(&script_sig, &[], &script_pubkey, SIGHASH),
+            Ok(())
+        );
+
+        // signatures out of key order cannot be matched
+        let mut script_sig = vec![OP_PUSHBYTES_0];
+        push_data(&mut script_sig, &sign(&keys[2].0));
+        push_data(&mut script_sig, &sign(&keys[0].0));
+        push_data(&mut script_sig, &redeem_script);
+        assert_eq!(
+            verify_with_sighash(&script_sig, &[], &script_pubkey, SIGHASH),
+            Err(ScriptError::NullFail)
+        );
+
+        let mut script_pubkey = vec![OP_PUSHBYTES_0];
+        push_data(&mut script_pubkey, &hash::sha256(&redeem_script));
+        let witness = vec![
+            vec![],
+            sign(&keys[1].0),
+            sign(&keys[2].0),
+            redeem_script.clone(),
+        ];
+        assert_eq!(
+            verify_with_sighash(&[], &witness, &script_pubkey, SIGHASH),
+            Ok(())
+        );
+        let witness = vec![vec![1], sign(&keys[1].0), sign(&keys[2].0), redeem_script];
+        assert_eq!(
+            verify_with_sighash(&[], &witness, &script_pubkey, SIGHASH),
+            Err(ScriptError::NullDummy)
+        );
+    }
+
+    #[test]
+    fn test_check_lock_time_verify() {
+        // <500000> OP_CHECKLOCKTIMEVERIFY OP_DROP OP_1
+        let mut script = Vec::new();
+        push_data(&mut script, &scriptint_bytes(500_000));
+        script.extend([OP_CLTV, OP_DROP, OP_PUSHNUM_1]);
+        let flags = ScriptFlags::default();
+        let checker = SighashChecker::new(SIGHASH)
+            .lock_time(500_000)
+            .sequence(0xffff_fffe);
+        assert_eq!(verify_script(&[], &[], &script, &flags, &checker), Ok(()));
+        let checker = SighashChecker::new(SIGHASH)
+            .lock_time(499_999)
+            .sequence(0xffff_fffe);
+        assert_eq!(
+            verify_script(&[], &[], &script, &flags, &checker),
+            Err(ScriptError::UnsatisfiedLocktime)
+        );
+        let checker = SighashChecker::new(SIGHASH).lock_time(500_000);
+        assert_eq!(
+            verify_script(&[], &[], &script, &flags, &checker),
+            Err(ScriptError::UnsatisfiedLocktime)
+        );
+    }
+
+    #[test]
+    fn test_check_sequence_verify() {
+        // <144> OP_CHECKSEQUENCEVERIFY OP_DROP OP_1
+        let mut script = Vec::new();
+        push_data(&mut script, &scriptint_bytes(144));
+        script.extend([OP_CSV, OP_DROP, OP_PUSHNUM_1]);
+        let flags = ScriptFlags::default();
+        let checker = SighashChecker::new(SIGHASH).sequence(144);
+        assert_eq!(verify_script(&[], &[], &script, &flags, &checker), Ok(()));
+        let checker = SighashChecker::new(SIGHASH).sequence(143);
+        assert_eq!(
+            verify_script(&[], &[], &script, &flags, &checker),
+            Err(ScriptError::UnsatisfiedLocktime)
+        );
+        let checker = SighashChecker::new(SIGHASH).version(1).sequence(144);
+        assert_eq!(
+            verify_script(&[], &[], &script, &flags, &checker),
+            Err(ScriptError::UnsatisfiedLocktime)
+        );
+    }
+
+    #[test]
+    fn test_taproot_key_path() {
+        let secp = Secp256k1::new();
+        let (mut keypair, internal_key) = schnorr_keypair(1);
+        let (output_key, _) = taproot::output_key(&internal_key, None).unwrap();
+        let untweaked = schnorr_sign(&keypair);
+        keypair
+            .tweak_add_assign(&secp, &hash::tagged_hash("TapTweak", &internal_key))
+            .unwrap();
+        let sig = schnorr_sign(&keypair);
+        let script_pubkey = p2tr(&output_key);
+
+        let verify =
+            |witness: Vec<Vec<u8>>| verify_with_sighash(&[], &witness, &script_pubkey, SIGHASH);
+        assert_eq!(verify(vec![sig.clone()]), Ok(()));
+        assert_eq!(verify(vec![[&sig[..], &[0x01]].concat()]), Ok(()));
+        assert_eq!(verify(vec![sig.clone(), vec![ANNEX_TAG, 0x01]]), Ok(()));
+        assert_eq!(
+            verify(vec![[&sig[..], &[SIGHASH_DEFAULT]].concat()]),
+            Err(ScriptError::SchnorrSigHashType)
+        );
+        assert_eq!(
+            verify(vec![sig[..63].to_vec()]),
+            Err(ScriptError::SchnorrSigSize)
+        );
+        assert_eq!(verify(vec![untweaked]), Err(ScriptError::SchnorrSig));
+        assert_eq!(verify(vec![]), Err(ScriptError::WitnessProgramWitnessEmpty));
+    }
+
+    #[test]
+    fn test_taproot_script_path_multi_a() {
+        let keys: Vec<_> = (1..=3).map(schnorr_keypair).collect();
+        let public_keys: Vec<[u8; 32]> = keys.iter().map(|(_, key)| *key).collect();
+        let leaf = TapLeaf::multi_a(2, &public_keys).unwrap();
+        let sigs: Vec<Vec<u8>> = keys
+            .iter()
+            .map(|(keypair, _)| schnorr_sign(keypair))
+            .collect();
+
+        // the signature of the first key ends up on top of the stack
+        let witness = vec![sigs[2].clone(), vec![], sigs[0].clone()];
+        assert_eq!(spend_leaf(leaf.clone(), witness), Ok(()));
+        let witness = vec![vec![], vec![], sigs[0].clone()];
+        assert_eq!(
+            spend_leaf(leaf.clone(), witness),
+            Err(ScriptError::EvalFalse)
+        );
+        let witness = vec![sigs[2].clone(), vec![], sigs[1].clone()];
+        assert_eq!(
+            spend_leaf(leaf.clone(), witness),
+            Err(ScriptError::SchnorrSig)
+        );
+
+        // a control block of another output key or of the wrong size
+        let taproot = Taproot::internal_key(None)
+            .leaf(1, leaf.clone())
+            .generate_tree()
+            .unwrap();
+        let mut control_block = taproot.control_block(0);
+        control_block[0] ^= 1;
+        let script_pubkey = p2tr(&taproot.output_key());
+        let witness = vec![sigs[0].clone(), leaf.script.clone(), control_block.clone()];
+        assert_eq!(
+            verify_with_sighash(&[], &witness, &script_pubkey, SIGHASH),
+            Err(ScriptError::WitnessProgramMismatch)
+        );
+        control_block.push(0);
+        let witness = vec![sigs[0].clone(), leaf.script, control_block];
+        assert_eq!(
+            verify_with_sighash(&[], &witness, &script_pubkey, SIGHASH),
+            Err(ScriptError::TaprootWrongControlSize)
+        );
+    }
+
+    #[test]
+    fn test_tapscript_rules() {
+        let tapscript = |script: Vec<u8>| TapLeaf::new(script, TAPROOT_LEAF_TAPSCRIPT).unwrap();
+        // OP_CHECKMULTISIG is disabled
+        let leaf = tapscript(vec![
+            OP_PUSHBYTES_0,
+            OP_PUSHBYTES_0,
+            OP_PUSHBYTES_0,
+            OP_CHECKMULTISIG,
+        ]);
+        assert_eq!(
+            spend_leaf(leaf, vec![]),
+            Err(ScriptError::TapscriptCheckMultisig)
+        );
+        // OP_IF arguments must be minimal whatever the flags
+        let leaf = tapscript(vec![OP_IF, OP_PUSHNUM_1, OP_ELSE, OP_PUSHNUM_1, OP_ENDIF]);
+        assert_eq!(spend_leaf(leaf.clone(), vec![vec![1]]), Ok(()));
+        assert_eq!(
+            spend_leaf(leaf, vec![vec![2]]),
+            Err(ScriptError::TapscriptMinimalIf)
+        );
+        // empty public keys are invalid
+        let leaf = tapscript(vec![OP_PUSHBYTES_0, OP_CHECKSIG]);
+        assert_eq!(
+            spend_leaf(leaf, vec![vec![]]),
+            Err(ScriptError::TapscriptEmptyPubkey)
+        );
+    }
+
+    #[test]
+    fn test_discourage_upgradable() {
+        let consensus = ScriptFlags::consensus();
+        // an OP_SUCCESS succeeds even after an OP_RETURN
+        let leaf = TapLeaf::new(vec![OP_RETURN, 0x50], TAPROOT_LEAF_TAPSCRIPT).unwrap();
+        assert_eq!(
+            spend_leaf_with_flags(leaf.clone(), vec![], &consensus),
+            Ok(())
+        );
+        assert_eq!(
+            spend_leaf(leaf, vec![]),
+            Err(ScriptError::DiscourageOpSuccess)
+        );
+        // as do leaves of unknown versions
+        let leaf = TapLeaf::new(vec![OP_RETURN], 0xc2).unwrap();
+        assert_eq!(
+            spend_leaf_with_flags(leaf.clone(), vec![], &consensus),
+            Ok(())
+        );
+        assert_eq!(
+            spend_leaf(leaf, vec![]),
+            Err(ScriptError::DiscourageUpgradableTaprootVersion)
+        );
+        // and unknown public key types
+        let leaf = TapLeaf::new(vec![OP_PUSHNUM_1, OP_CHECKSIG], TAPROOT_LEAF_TAPSCRIPT).unwrap();
+        assert_eq!(
+            spend_leaf_with_flags(leaf.clone(), vec![vec![1]], &consensus),
+            Ok(())
+        );
+        assert_eq!(
+            spend_leaf(leaf, vec![vec![1]]),
+            Err(ScriptError::DiscourageUpgradablePubkeyType)
+        );
+
+        let checker = SighashChecker::new(SIGHASH);
+        let flags = ScriptFlags::default();
+        let witness_v2 = [OP_PUSHNUM_2, OP_PUSHBYTES_2, 0xab, 0xcd];
+        assert_eq!(
+            verify_script(&[], &[], &witness_v2, &consensus, &checker),
+            Ok(())
+        );
+        assert_eq!(
+            verify_script(&[], &[], &witness_v2, &flags, &checker),
+            Err(ScriptError::DiscourageUpgradableWitnessProgram)
+        );
+        let nop = [OP_NOP4, OP_PUSHNUM_1];
+        assert_eq!(verify_script(&[], &[], &nop, &consensus, &checker), Ok(()));
+        assert_eq!(
+            verify_script(&[], &[], &nop, &flags, &checker),
+            Err(ScriptError::DiscourageUpgradableNops)
+        );
+    }
+
+    #[test]
+    fn test_tapscript_validation_weight() {
+        // (OP_DUP <key> OP_CHECKSIGVERIFY) repeated: each check uses 50 weight units of a
+        // budget of 50 plus the witness size, which grows by only 35 bytes per check
+        let (keypair, key) = schnorr_keypair(1);
+        let sig = schnorr_sign(&keypair);
+        let leaf = |checks: usize| {
+            let mut script = Vec::new();
+            for _ in 0..checks {
+                script.push(OP_DUP);
+                push_data(&mut script, &key);
+                script.push(OP_CHECKSIGVERIFY);
+            }
+            TapLeaf::new(script, TAPROOT_LEAF_TAPSCRIPT).unwrap()
+        };
+        // budget 50 + 453 for 500 units of checks
+        assert_eq!(spend_leaf(leaf(10), vec![sig.clone()]), Ok(()));
+        // budget 50 + 488 for 550 units of checks
+        assert_eq!(
+            spend_leaf(leaf(11), vec![sig]),
+            Err(ScriptError::TapscriptValidationWeight)
+        );
+    }
+}
@@ -1,9 +1,39 @@
+pub mod address;
 pub mod bech32;
+pub mod balance;
+pub mod bip32;
+pub mod bip322;
+pub mod broadcast;
+pub mod chain;
+pub mod coinselect;
+pub mod decode;
+pub mod electrum;
 pub mod error;
+pub mod estimator;
+pub mod feebump;
+pub mod finalizer;
+pub mod hash;
+pub mod htlc;
 pub mod http;
+pub mod interpreter;
 pub mod log;
+pub mod message;
+pub mod miniscript;
+pub mod musig;
 pub mod opcodes;
+pub mod opreturn;
+pub mod policy;
+pub mod psbt;
+pub mod rpc;
+pub mod script;
+pub mod sighash;
+pub mod signer;
+pub mod sweep;
+pub mod taproot;
+pub mod timelock;
+pub mod transaction;
 pub mod wallet;
+pub mod wif;
 
 #[cfg(feature = "postgres")]
 #[macro_use]
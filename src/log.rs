@@ -5,7 +5,7 @@ use std::io::prelude::*;
 
 /// Setup the log
 pub fn setup() {
-    let _ = Builder::from_default_env()
+    Builder::from_default_env()
         .format(|buf, record| {
             writeln!(
                 buf,
@@ -7,7 +7,5 @@ async fn main() -> std::io::Result<()> {
     log::info!("Hello, world!");
 
     // http
-    http::start_http_server().await?;
-
-    loop {}
+    http::start_http_server().await
 }
@@ -0,0 +1,211 @@
+use crate::{
+    address::{Address, Network},
+    error::WalletError,
+    hash,
+    script::push_compact_size,
+    wallet::Seed,
+};
+use secp256k1::{
+    recovery::{RecoverableSignature, RecoveryId},
+    Message, PublicKey, Secp256k1, SecretKey,
+};
+use serde::{Deserialize, Serialize};
+
+/// Prefix committed to by every signed message, so that a signature can never be a transaction
+/// signature
+pub const MESSAGE_PREFIX: &str = "Bitcoin Signed Message:\n";
+
+#[derive(Deserialize, Debug)]
+// Get the seed and the message to sign from user
+pub struct SignMessageRequest {
+    #[serde(flatten)]
+    pub seed: Seed,
+    pub message: String,
+    #[serde(default)]
+    pub address_type: MessageAddressType,
+}
+
+#[derive(Deserialize, Debug)]
+// Get the address, the message and its signature from user
+pub struct VerifyMessageRequest {
+    pub address: String,
+    pub message: String,
+    pub signature: String,
+}
+
+/// Address type a signature claims, encoded in its BIP137 header byte
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Default)]
+pub enum MessageAddressType {
+    #[default]
+    #[serde(rename = "p2pkh")]
+    P2pkh,
+    #[serde(rename = "p2sh-p2wpkh")]
+    P2shP2wpkh,
+    #[serde(rename = "p2wpkh")]
+    P2wpkh,
+}
+
+impl MessageAddressType {
+    /// Header byte of recovery id 0, the recovery ids 1 to 3 follow
+    fn header(self) -> u8 {
+        match self {
+            MessageAddressType::P2pkh => 31,
+            MessageAddressType::P2shP2wpkh => 35,
+            MessageAddressType::P2wpkh => 39,
+        }
+    }
+
+    /// Address of `public_key`, which is compressed unless P2PKH
+    fn address(self, public_key: &[u8], network: Network) -> Address {
+        match self {
+            MessageAddressType::P2pkh => Address::p2pkh(public_key, network),
+            MessageAddressType::P2shP2wpkh => Address::p2shwpkh(public_key, network),
+            MessageAddressType::P2wpkh => Address::p2wpkh(public_key, network),
+        }
+    }
+}
+
+#[derive(Serialize, Debug)]
+/// SignedMessage is the signing address and the base64 signature presented to user
+pub struct SignedMessage {
+    pub address: String,
+    pub message: String,
+    pub signature: String,
+}
+
+#[derive(Serialize, Debug)]
+/// MessageVerification is the result of a verification presented to user
+pub struct MessageVerification {
+    pub valid: bool,
+}
+
+/// Double SHA-256 of the prefixed message, what is actually signed
+pub fn message_hash(message: &str) -> [u8; 32] {
+    let mut data = Vec::new();
+    push_compact_size(&mut data, MESSAGE_PREFIX.len());
+    data.extend(MESSAGE_PREFIX.as_bytes());
+    push_compact_size(&mut data, message.len());
+    data.extend(message.as_bytes());
+    hash::sha256d(&data)
+}
+
+/// Sign `message` with `secret_key`, returning the 65-byte base64 encoded compact signature
+pub fn sign_message(
+    secret_key: &SecretKey,
+    message: &str,
+    address_type: MessageAddressType,
+) -> String {
+    let secp = Secp256k1::signing_only();
+    let digest = Message::from_slice(&message_hash(message)).expect("32 bytes");
+    let (recovery_id, signature) = secp
+        .sign_recoverable(&digest, secret_key)
+        .serialize_compact();
+    let mut data = vec![address_type.header() + recovery_id.to_i32() as u8];
+    data.extend(signature);
+    base64::encode(data)
+}
+
+/// Recover the public key from `signature`, with the address type claimed by its header
+///
+/// Headers 27 to 30 are uncompressed P2PKH keys, returned uncompressed.
+pub fn recover_public_key(
+    message: &str,
+    signature: &str,
+) -> Result<(Vec<u8>, MessageAddressType), WalletError> {
+    let data = base64::decode(signature).map_err(|_| WalletError::InvalidSignature)?;
+    if data.len() != 65 {
+        return Err(WalletError::InvalidSignature);
+    }
+    let (address_type, compressed, recovery_id) = match data[0] {
+        27..=30 => (MessageAddressType::P2pkh, false, data[0] - 27),
+        31..=34 => (MessageAddressType::P2pkh, true, data[0] - 31),
+        35..=38 => (MessageAddressType::P2shP2wpkh, true, data[0] - 35),
+        39..=42 => (MessageAddressType::P2wpkh, true, data[0] - 39),
+        _ => return Err(WalletError::InvalidSignature),
+    };
+    let recovery_id =
+        RecoveryId::from_i32(recovery_id as i32).map_err(|_| WalletError::InvalidSignature)?;
+    let signature = RecoverableSignature::from_compact(&data[1..], recovery_id)
+        .map_err(|_| WalletError::InvalidSignature)?;
+    let digest = Message::from_slice(&message_hash(message)).expect("32 bytes");
+    let public_key: PublicKey = Secp256k1::verification_only()
+        .recover(&digest, &signature)
+        .map_err(|_| WalletError::InvalidSignature)?;
+    let public_key = if compressed {
+        public_key.serialize().to_vec()
+    } else {
+        public_key.serialize_uncompressed().to_vec()
+    };
+    Ok((public_key, address_type))
+}
+
+/// Check that `signature` over `message` was made by the key behind `address`
+///
+/// Only the address type claimed by the signature header is accepted, on either network.
+pub fn verify_message(address: &str, message: &str, signature: &str) -> Result<bool, WalletError> {
+    let (public_key, address_type) = recover_public_key(message, signature)?;
+    Ok([Network::Bitcoin, Network::Testnet]
+        .iter()
+        .any(|network| address_type.address(&public_key, *network).to_string() == address))
+}
+
+/// Sign `message` with the master key of `seed`, along with the address it proves control of
+pub fn sign_with_seed(
+    seed: &Seed,
+    message: &str,
+    address_type: MessageAddressType,
+) -> SignedMessage {
+    let secret_key = seed.secret_key();
+    let public_key = PublicKey::from_secret_key(&Secp256k1::signing_only(), &secret_key);
+    SignedMessage {
+        address: address_type
+            .address(&public_key.serialize(), Network::Bitcoin)
+            .to_string(),
+        message: message.to_string(),
+        signature: sign_message(&secret_key, message, address_type),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sign_and_verify() {
+        let secret_key = SecretKey::from_slice(&[0x11; 32]).unwrap();
+        let public_key =
+            PublicKey::from_secret_key(&Secp256k1::signing_only(), &secret_key).serialize();
+        for address_type in [
+            MessageAddressType::P2pkh,
+            MessageAddressType::P2shP2wpkh,
+            MessageAddressType::P2wpkh,
+        ]
+        .iter()
+        {
+            let signature = sign_message(&secret_key, "hello", *address_type);
+            let address = address_type
+                .address(&public_key, Network::Bitcoin)
+                .to_string();
+            assert!(verify_message(&address, "hello", &signature).unwrap());
+            assert!(!verify_message(&address, "hello!", &signature).unwrap());
+        }
+        // A P2PKH signature does not prove control of the P2WPKH address
+        let signature = sign_message(&secret_key, "hello", MessageAddressType::P2pkh);
+        let address = Address::p2wpkh(&public_key, Network::Bitcoin).to_string();
+        assert!(!verify_message(&address, "hello", &signature).unwrap());
+        assert!(matches!(
+            verify_message(&address, "hello", "AAAA"),
+            Err(WalletError::InvalidSignature)
+        ));
+    }
+
+    #[test]
+    fn test_verify_known_signature() {
+        // python-bitcoinlib test vector, the message is the address itself
+        let address = "1F26pNMrywyZJdr22jErtKcjF8R3Ttt55G";
+        let signature =
+            "H85WKpqtNZDrajOnYDgUY+abh0KCAcOsAIOQwx2PftAbLEPRA7mzXA/CjXRxzz0MC225pR/hx02Vf2Ag2x33kU4=";
+        assert!(verify_message(address, address, signature).unwrap());
+        assert!(!verify_message("1BgGZ9tcN4rm9KBzDn7KprQz87SZ26SAMH", address, signature).unwrap());
+    }
+}
@@ -0,0 +1,1750 @@
+use crate::{
+    address::{Address, Network, ScriptType},
+    error::MiniscriptError,
+    hash,
+    interpreter::{LOCKTIME_THRESHOLD, MAX_STACK_SIZE, SEQUENCE_LOCKTIME_TYPE_FLAG},
+    opcodes::*,
+    policy::{self, MAX_STANDARD_P2WSH_SCRIPT_SIZE, MAX_STANDARD_P2WSH_STACK_ITEMS},
+    script::{self, Builder, Instruction, MAX_OPS_PER_SCRIPT, MAX_PUBKEYS_PER_MULTISIG},
+    taproot::{self, MAX_PUBKEYS_PER_MULTI_A},
+    timelock::Lock,
+    wallet::PubKey,
+};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{BTreeMap, HashMap},
+    convert::TryInto,
+    fmt,
+    str::FromStr,
+    sync::Arc,
+};
+
+#[derive(Deserialize, Debug)]
+// Get a spending policy from user
+pub struct PolicyRequest {
+    pub policy: String,
+    #[serde(default)]
+    pub context: Context,
+    #[serde(default)]
+    pub network: Network,
+}
+
+/// Script context a miniscript is compiled for
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default, Deserialize)]
+pub enum Context {
+    /// Witness script of a P2WSH output
+    #[default]
+    #[serde(rename = "p2wsh")]
+    Segwitv0,
+    /// BIP342 tapscript leaf of a P2TR output
+    #[serde(rename = "tapscript")]
+    Tap,
+}
+
+impl Context {
+    /// Size of a signature push including the sighash byte
+    fn signature_size(self) -> usize {
+        match self {
+            Context::Segwitv0 => 73,
+            Context::Tap => 66,
+        }
+    }
+
+    /// Size of a public key push
+    fn key_size(self) -> usize {
+        match self {
+            Context::Segwitv0 => 34,
+            Context::Tap => 33,
+        }
+    }
+
+    /// Public key as serialized in scripts, x-only in tapscript
+    fn key_bytes(self, key: &PubKey) -> &[u8] {
+        match self {
+            Context::Segwitv0 => key,
+            Context::Tap => &key[1..],
+        }
+    }
+}
+
+/// A spending policy, e.g. `or(99@pk(A),1@and(pk(B),older(1000)))`
+#[derive(Debug, Clone, PartialEq)]
+pub enum Policy {
+    Unsatisfiable,
+    Trivial,
+    Key(PubKey),
+    After(u32),
+    Older(u32),
+    Sha256([u8; 32]),
+    Hash256([u8; 32]),
+    Ripemd160([u8; 20]),
+    Hash160([u8; 20]),
+    /// Both sub-policies, always two of them
+    And(Vec<Policy>),
+    /// Either sub-policy with its relative likelihood, always two of them
+    Or(Vec<(u32, Policy)>),
+    /// `k` of the sub-policies
+    Thresh(usize, Vec<Policy>),
+}
+
+impl FromStr for Policy {
+    type Err = MiniscriptError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s: String = s.chars().filter(|c| !c.is_whitespace()).collect();
+        parse_policy(&s)
+    }
+}
+
+/// Split `args` at the commas outside of parentheses
+fn split_args(args: &str) -> Result<Vec<&str>, MiniscriptError> {
+    let mut parts = Vec::new();
+    let mut depth = 0usize;
+    let mut start = 0;
+    for (i, c) in args.char_indices() {
+        match c {
+            '(' => depth += 1,
+            ')' => {
+                depth = depth
+                    .checked_sub(1)
+                    .ok_or_else(|| MiniscriptError::Parse(args.to_string()))?
+            }
+            ',' if depth == 0 => {
+                parts.push(&args[start..i]);
+                start = i + 1;
+            }
+            _ => {}
+        }
+    }
+    if depth != 0 {
+        return Err(MiniscriptError::Parse(args.to_string()));
+    }
+    parts.push(&args[start..]);
+    Ok(parts)
+}
+
+fn parse_hex<const N: usize>(s: &str) -> Result<[u8; N], MiniscriptError> {
+    hex::decode(s)
+        .ok()
+        .and_then(|bytes| bytes.try_into().ok())
+        .ok_or_else(|| MiniscriptError::Parse(s.to_string()))
+}
+
+fn parse_number(s: &str) -> Result<u32, MiniscriptError> {
+    s.parse().map_err(|_| MiniscriptError::Parse(s.to_string()))
+}
+
+fn parse_policy(s: &str) -> Result<Policy, MiniscriptError> {
+    let (name, args) = match s.find('(') {
+        Some(open) if s.ends_with(')') => (&s[..open], split_args(&s[open + 1..s.len() - 1])?),
+        _ => (s, Vec::new()),
+    };
+    let policy = match (name, args.len()) {
+        ("UNSATISFIABLE", 0) => Policy::Unsatisfiable,
+        ("TRIVIAL", 0) => Policy::Trivial,
+        ("pk", 1) => {
+            let key =
+                parse_hex(args[0]).map_err(|_| MiniscriptError::InvalidKey(args[0].into()))?;
+            secp256k1::PublicKey::from_slice(&key)
+                .map_err(|_| MiniscriptError::InvalidKey(args[0].into()))?;
+            Policy::Key(key)
+        }
+        ("after", 1) => match parse_number(args[0])? {
+            n if n == 0 || n >= 1 << 31 => return Err(MiniscriptError::InvalidTimelock(n)),
+            n => Policy::After(n),
+        },
+        ("older", 1) => {
+            let n = parse_number(args[0])?;
+            Lock::Older(n)
+                .check()
+                .map_err(|_| MiniscriptError::InvalidTimelock(n))?;
+            Policy::Older(n)
+        }
+        ("sha256", 1) => Policy::Sha256(parse_hex(args[0])?),
+        ("hash256", 1) => Policy::Hash256(parse_hex(args[0])?),
+        ("ripemd160", 1) => Policy::Ripemd160(parse_hex(args[0])?),
+        ("hash160", 1) => Policy::Hash160(parse_hex(args[0])?),
+        ("and", 2) => Policy::And(
+            args.iter()
+                .map(|arg| parse_policy(arg))
+                .collect::<Result<_, _>>()?,
+        ),
+        ("or", 2) => Policy::Or(
+            args.iter()
+                .map(|arg| match arg.find('@') {
+                    Some(at) if !arg[..at].contains('(') => match parse_number(&arg[..at])? {
+                        0 => Err(MiniscriptError::Parse(arg.to_string())),
+                        weight => Ok((weight, parse_policy(&arg[at + 1..])?)),
+                    },
+                    _ => Ok((1, parse_policy(arg)?)),
+                })
+                .collect::<Result<_, _>>()?,
+        ),
+        ("thresh", n) if n >= 2 => {
+            let k = parse_number(args[0])? as usize;
+            if k == 0 || k > n - 1 {
+                return Err(MiniscriptError::InvalidThreshold);
+            }
+            Policy::Thresh(
+                k,
+                args[1..]
+                    .iter()
+                    .map(|arg| parse_policy(arg))
+                    .collect::<Result<_, _>>()?,
+            )
+        }
+        _ => return Err(MiniscriptError::Parse(s.to_string())),
+    };
+    Ok(policy)
+}
+
+/// Basic type of a miniscript expression
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Base {
+    /// Consumes its inputs and pushes nonzero on satisfaction, zero on dissatisfaction
+    B,
+    /// Continues or aborts, pushes nothing
+    V,
+    /// Pushes a public key whose signature is checked by a wrapper
+    K,
+    /// Like `B`, but takes its inputs from one below the top of the stack
+    W,
+}
+
+/// Type of a miniscript expression: its base type, correctness and malleability properties
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Type {
+    pub base: Base,
+    /// Consumes exactly 0 stack elements
+    pub z: bool,
+    /// Consumes exactly 1 stack element
+    pub o: bool,
+    /// The top input is never empty when satisfying
+    pub n: bool,
+    /// Has a dissatisfaction
+    pub d: bool,
+    /// Puts exactly 1 on the stack when satisfied
+    pub u: bool,
+    /// Has a unique dissatisfaction which a third party cannot forge
+    pub e: bool,
+    /// Cannot be dissatisfied at all
+    pub f: bool,
+    /// Every satisfaction requires a signature
+    pub s: bool,
+    /// Has a non-malleable satisfaction for every witness
+    pub m: bool,
+}
+
+impl Type {
+    fn new(base: Base) -> Self {
+        Self {
+            base,
+            z: false,
+            o: false,
+            n: false,
+            d: false,
+            u: false,
+            e: false,
+            f: false,
+            s: false,
+            m: false,
+        }
+    }
+}
+
+/// Miniscript fragments, wrappers use the single letter names of the specification
+#[derive(Debug, Clone, PartialEq)]
+pub enum Terminal {
+    True,
+    False,
+    PkK(PubKey),
+    PkH(PubKey),
+    After(u32),
+    Older(u32),
+    Sha256([u8; 32]),
+    Hash256([u8; 32]),
+    Ripemd160([u8; 20]),
+    Hash160([u8; 20]),
+    /// `a:X`
+    Alt(Arc<Miniscript>),
+    /// `s:X`
+    Swap(Arc<Miniscript>),
+    /// `c:X`
+    Check(Arc<Miniscript>),
+    /// `d:X`
+    DupIf(Arc<Miniscript>),
+    /// `v:X`
+    Verify(Arc<Miniscript>),
+    /// `j:X`
+    NonZero(Arc<Miniscript>),
+    /// `n:X`
+    ZeroNotEqual(Arc<Miniscript>),
+    AndV(Arc<Miniscript>, Arc<Miniscript>),
+    AndB(Arc<Miniscript>, Arc<Miniscript>),
+    AndOr(Arc<Miniscript>, Arc<Miniscript>, Arc<Miniscript>),
+    OrB(Arc<Miniscript>, Arc<Miniscript>),
+    OrD(Arc<Miniscript>, Arc<Miniscript>),
+    OrC(Arc<Miniscript>, Arc<Miniscript>),
+    OrI(Arc<Miniscript>, Arc<Miniscript>),
+    Thresh(usize, Vec<Arc<Miniscript>>),
+    /// `OP_CHECKMULTISIG`, segwit v0 only
+    Multi(usize, Vec<PubKey>),
+    /// `OP_CHECKSIGADD`, tapscript only
+    MultiA(usize, Vec<PubKey>),
+}
+
+/// Worst case size of a witness: number of stack elements and bytes including their length
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct WitnessSize {
+    pub elements: usize,
+    pub bytes: usize,
+}
+
+fn witness_size(elements: usize, bytes: usize) -> Option<WitnessSize> {
+    Some(WitnessSize { elements, bytes })
+}
+
+fn add(a: Option<WitnessSize>, b: Option<WitnessSize>) -> Option<WitnessSize> {
+    Some(WitnessSize {
+        elements: a?.elements + b?.elements,
+        bytes: a?.bytes + b?.bytes,
+    })
+}
+
+fn max(a: Option<WitnessSize>, b: Option<WitnessSize>) -> Option<WitnessSize> {
+    match (a, b) {
+        (Some(a), Some(b)) => Some(WitnessSize {
+            elements: a.elements.max(b.elements),
+            bytes: a.bytes.max(b.bytes),
+        }),
+        (a, None) => a,
+        (None, b) => b,
+    }
+}
+
+/// Kinds of timelocks a satisfaction may need, which must not be mixed in a single spend
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+struct Timelocks {
+    csv_height: bool,
+    csv_time: bool,
+    cltv_height: bool,
+    cltv_time: bool,
+    mixed: bool,
+}
+
+impl Timelocks {
+    fn or(self, other: Self) -> Self {
+        Self {
+            csv_height: self.csv_height || other.csv_height,
+            csv_time: self.csv_time || other.csv_time,
+            cltv_height: self.cltv_height || other.cltv_height,
+            cltv_time: self.cltv_time || other.cltv_time,
+            mixed: self.mixed || other.mixed,
+        }
+    }
+
+    fn and(self, other: Self) -> Self {
+        let mixed = (self.csv_height && other.csv_time)
+            || (self.csv_time && other.csv_height)
+            || (self.cltv_height && other.cltv_time)
+            || (self.cltv_time && other.cltv_height);
+        let mut timelocks = self.or(other);
+        timelocks.mixed |= mixed;
+        timelocks
+    }
+}
+
+/// A typed miniscript expression for a given script context
+#[derive(Debug, Clone, PartialEq)]
+pub struct Miniscript {
+    pub node: Terminal,
+    pub ty: Type,
+    context: Context,
+    script_size: usize,
+    sat: Option<WitnessSize>,
+    dissat: Option<WitnessSize>,
+    timelocks: Timelocks,
+}
+
+fn type_error(fragment: &str) -> MiniscriptError {
+    MiniscriptError::TypeCheck(fragment.to_string())
+}
+
+/// Type of `node` from the types of its children, per the miniscript specification
+fn type_check(node: &Terminal, context: Context) -> Result<Type, MiniscriptError> {
+    use Base::*;
+    let ty = match node {
+        Terminal::True => Type {
+            z: true,
+            u: true,
+            f: true,
+            m: true,
+            ..Type::new(B)
+        },
+        Terminal::False => Type {
+            z: true,
+            u: true,
+            d: true,
+            e: true,
+            s: true,
+            m: true,
+            ..Type::new(B)
+        },
+        Terminal::PkK(_) => Type {
+            o: true,
+            n: true,
+            d: true,
+            u: true,
+            e: true,
+            s: true,
+            m: true,
+            ..Type::new(K)
+        },
+        Terminal::PkH(_) => Type {
+            n: true,
+            d: true,
+            u: true,
+            e: true,
+            s: true,
+            m: true,
+            ..Type::new(K)
+        },
+        Terminal::After(_) | Terminal::Older(_) => Type {
+            z: true,
+            f: true,
+            m: true,
+            ..Type::new(B)
+        },
+        Terminal::Sha256(_)
+        | Terminal::Hash256(_)
+        | Terminal::Ripemd160(_)
+        | Terminal::Hash160(_) => Type {
+            o: true,
+            n: true,
+            d: true,
+            u: true,
+            m: true,
+            ..Type::new(B)
+        },
+        Terminal::Alt(x) if x.ty.base == B => Type {
+            d: x.ty.d,
+            u: x.ty.u,
+            ..wrap(x.ty, W)
+        },
+        Terminal::Swap(x) if x.ty.base == B && x.ty.o => Type {
+            d: x.ty.d,
+            u: x.ty.u,
+            ..wrap(x.ty, W)
+        },
+        Terminal::Check(x) if x.ty.base == K => Type {
+            o: x.ty.o,
+            n: x.ty.n,
+            d: x.ty.d,
+            u: true,
+            ..wrap(x.ty, B)
+        },
+        Terminal::DupIf(x) if x.ty.base == V && x.ty.z => Type {
+            o: true,
+            n: true,
+            d: true,
+            u: context == Context::Tap,
+            e: true,
+            f: false,
+            ..wrap(x.ty, B)
+        },
+        Terminal::Verify(x) if x.ty.base == B => Type {
+            z: x.ty.z,
+            o: x.ty.o,
+            n: x.ty.n,
+            e: false,
+            f: true,
+            ..wrap(x.ty, V)
+        },
+        Terminal::NonZero(x) if x.ty.base == B && x.ty.n => Type {
+            o: x.ty.o,
+            n: true,
+            d: true,
+            u: x.ty.u,
+            e: x.ty.f,
+            f: false,
+            ..wrap(x.ty, B)
+        },
+        Terminal::ZeroNotEqual(x) if x.ty.base == B => Type {
+            z: x.ty.z,
+            o: x.ty.o,
+            n: x.ty.n,
+            d: x.ty.d,
+            u: true,
+            ..wrap(x.ty, B)
+        },
+        Terminal::AndV(x, y) if x.ty.base == V && y.ty.base != W => {
+            let (x, y) = (x.ty, y.ty);
+            Type {
+                z: x.z && y.z,
+                o: (x.z && y.o) || (x.o && y.z),
+                n: x.n || (x.z && y.n),
+                u: y.u,
+                f: y.f || x.s,
+                s: x.s || y.s,
+                m: x.m && y.m,
+                ..Type::new(y.base)
+            }
+        }
+        Terminal::AndB(x, y) if x.ty.base == B && y.ty.base == W => {
+            let (x, y) = (x.ty, y.ty);
+            let f = (x.f && (y.f || x.s)) || (y.f && y.s);
+            Type {
+                z: x.z && y.z,
+                o: (x.z && y.o) || (x.o && y.z),
+                n: x.n || (x.z && y.n),
+                d: x.d && y.d,
+                u: true,
+                e: !f && x.e && y.e && x.s && y.s,
+                f,
+                s: x.s || y.s,
+                m: x.m && y.m,
+                ..Type::new(B)
+            }
+        }
+        Terminal::AndOr(x, y, z)
+            if x.ty.base == B && x.ty.d && x.ty.u && y.ty.base == z.ty.base && y.ty.base != W =>
+        {
+            let (x, y, z) = (x.ty, y.ty, z.ty);
+            let forced = y.f || x.s;
+            Type {
+                z: x.z && y.z && z.z,
+                o: (x.z && y.o && z.o) || (x.o && y.z && z.z),
+                d: z.d,
+                u: y.u && z.u,
+                e: z.e && forced,
+                f: z.f && forced,
+                s: (x.s || y.s) && z.s,
+                m: x.m && y.m && z.m && x.e && (x.s || y.s || z.s),
+                ..Type::new(y.base)
+            }
+        }
+        Terminal::OrB(x, z) if x.ty.base == B && x.ty.d && z.ty.base == W && z.ty.d => {
+            let (x, z) = (x.ty, z.ty);
+            Type {
+                z: x.z && z.z,
+                o: (x.z && z.o) || (x.o && z.z),
+                d: true,
+                u: true,
+                e: true,
+                s: x.s && z.s,
+                m: x.m && z.m && x.e && z.e && (x.s || z.s),
+                ..Type::new(B)
+            }
+        }
+        Terminal::OrD(x, z) if x.ty.base == B && x.ty.d && x.ty.u && z.ty.base == B => {
+            let (x, z) = (x.ty, z.ty);
+            Type {
+                z: x.z && z.z,
+                o: x.o && z.z,
+                d: z.d,
+                u: z.u,
+                e: z.e,
+                f: z.f,
+                s: x.s && z.s,
+                m: x.m && z.m && x.e && (x.s || z.s),
+                ..Type::new(B)
+            }
+        }
+        Terminal::OrC(x, z) if x.ty.base == B && x.ty.d && x.ty.u && z.ty.base == V => {
+            let (x, z) = (x.ty, z.ty);
+            Type {
+                z: x.z && z.z,
+                o: x.o && z.z,
+                f: true,
+                s: x.s && z.s,
+                m: x.m && z.m && x.e && (x.s || z.s),
+                ..Type::new(V)
+            }
+        }
+        Terminal::OrI(x, z) if x.ty.base == z.ty.base && x.ty.base != W => {
+            let (x, z) = (x.ty, z.ty);
+            Type {
+                o: x.z && z.z,
+                d: x.d || z.d,
+                u: x.u && z.u,
+                e: (x.e && z.f) || (x.f && z.e),
+                f: x.f && z.f,
+                s: x.s && z.s,
+                m: x.m && z.m && (x.s || z.s),
+                ..Type::new(x.base)
+            }
+        }
+        Terminal::Thresh(k, subs)
+            if *k >= 1
+                && *k <= subs.len()
+                && subs.iter().enumerate().all(|(i, sub)| {
+                    sub.ty.base == if i == 0 { B } else { W } && sub.ty.d && sub.ty.u
+                }) =>
+        {
+            let n = subs.len();
+            let safe = subs.iter().filter(|sub| sub.ty.s).count();
+            let all_e = subs.iter().all(|sub| sub.ty.e);
+            Type {
+                z: subs.iter().all(|sub| sub.ty.z),
+                o: subs.iter().filter(|sub| sub.ty.o).count() == 1
+                    && subs.iter().filter(|sub| sub.ty.z).count() == n - 1,
+                d: true,
+                u: true,
+                e: all_e && safe == n,
+                s: safe > n - k,
+                m: all_e && subs.iter().all(|sub| sub.ty.m) && safe >= n - k,
+                ..Type::new(B)
+            }
+        }
+        Terminal::Multi(k, keys)
+            if context == Context::Segwitv0
+                && *k >= 1
+                && *k <= keys.len()
+                && keys.len() <= MAX_PUBKEYS_PER_MULTISIG =>
+        {
+            Type {
+                n: true,
+                d: true,
+                u: true,
+                e: true,
+                s: true,
+                m: true,
+                ..Type::new(B)
+            }
+        }
+        Terminal::MultiA(k, keys)
+            if context == Context::Tap
+                && *k >= 1
+                && *k <= keys.len()
+                && keys.len() <= MAX_PUBKEYS_PER_MULTI_A =>
+        {
+            Type {
+                d: true,
+                u: true,
+                e: true,
+                s: true,
+                m: true,
+                ..Type::new(B)
+            }
+        }
+        Terminal::Alt(_) => return Err(type_error("a: needs a B expression")),
+        Terminal::Swap(_) => return Err(type_error("s: needs a Bo expression")),
+        Terminal::Check(_) => return Err(type_error("c: needs a K expression")),
+        Terminal::DupIf(_) => return Err(type_error("d: needs a Vz expression")),
+        Terminal::Verify(_) => return Err(type_error("v: needs a B expression")),
+        Terminal::NonZero(_) => return Err(type_error("j: needs a Bn expression")),
+        Terminal::ZeroNotEqual(_) => return Err(type_error("n: needs a B expression")),
+        Terminal::AndV(..) => return Err(type_error("and_v(V,B/K/V)")),
+        Terminal::AndB(..) => return Err(type_error("and_b(B,W)")),
+        Terminal::AndOr(..) => return Err(type_error("andor(Bdu,B/K/V,B/K/V)")),
+        Terminal::OrB(..) => return Err(type_error("or_b(Bd,Wd)")),
+        Terminal::OrD(..) => return Err(type_error("or_d(Bdu,B)")),
+        Terminal::OrC(..) => return Err(type_error("or_c(Bdu,V)")),
+        Terminal::OrI(..) => return Err(type_error("or_i(B/K/V,B/K/V)")),
+        Terminal::Thresh(..) => return Err(type_error("thresh(k,Bdu,Wdu,...)")),
+        Terminal::Multi(..) => return Err(type_error("multi is segwit v0 only, up to 20 keys")),
+        Terminal::MultiA(..) => return Err(type_error("multi_a is tapscript only")),
+    };
+    Ok(ty)
+}
+
+/// Keep the malleability properties of a wrapped expression `x` under a new `base`
+fn wrap(x: Type, base: Base) -> Type {
+    Type {
+        e: x.e,
+        f: x.f,
+        s: x.s,
+        m: x.m,
+        ..Type::new(base)
+    }
+}
+
+impl Miniscript {
+    /// Type check `node` and compute its sizes
+    pub fn new(node: Terminal, context: Context) -> Result<Self, MiniscriptError> {
+        let ty = type_check(&node, context)?;
+        let sig = context.signature_size();
+        let (sat, dissat, timelocks) = match &node {
+            Terminal::True => (witness_size(0, 0), None, Timelocks::default()),
+            Terminal::False => (None, witness_size(0, 0), Timelocks::default()),
+            Terminal::PkK(_) => (
+                witness_size(1, sig),
+                witness_size(1, 1),
+                Timelocks::default(),
+            ),
+            Terminal::PkH(_) => (
+                witness_size(2, sig + context.key_size()),
+                witness_size(2, 1 + context.key_size()),
+                Timelocks::default(),
+            ),
+            Terminal::After(n) => (
+                witness_size(0, 0),
+                None,
+                Timelocks {
+                    cltv_height: *n < LOCKTIME_THRESHOLD,
+                    cltv_time: *n >= LOCKTIME_THRESHOLD,
+                    ..Timelocks::default()
+                },
+            ),
+            Terminal::Older(n) => (
+                witness_size(0, 0),
+                None,
+                Timelocks {
+                    csv_height: *n as i64 & SEQUENCE_LOCKTIME_TYPE_FLAG == 0,
+                    csv_time: *n as i64 & SEQUENCE_LOCKTIME_TYPE_FLAG != 0,
+                    ..Timelocks::default()
+                },
+            ),
+            Terminal::Sha256(_)
+            | Terminal::Hash256(_)
+            | Terminal::Ripemd160(_)
+            | Terminal::Hash160(_) => (
+                witness_size(1, 33),
+                witness_size(1, 33),
+                Timelocks::default(),
+            ),
+            Terminal::Alt(x)
+            | Terminal::Swap(x)
+            | Terminal::Check(x)
+            | Terminal::ZeroNotEqual(x) => (x.sat, x.dissat, x.timelocks),
+            Terminal::DupIf(x) => (
+                add(x.sat, witness_size(1, 2)),
+                witness_size(1, 1),
+                x.timelocks,
+            ),
+            Terminal::Verify(x) => (x.sat, None, x.timelocks),
+            Terminal::NonZero(x) => (x.sat, witness_size(1, 1), x.timelocks),
+            Terminal::AndV(x, y) => (
+                add(x.sat, y.sat),
+                add(x.sat, y.dissat),
+                x.timelocks.and(y.timelocks),
+            ),
+            Terminal::AndB(x, y) => (
+                add(x.sat, y.sat),
+                add(x.dissat, y.dissat),
+                x.timelocks.and(y.timelocks),
+            ),
+            Terminal::AndOr(x, y, z) => (
+                max(add(x.sat, y.sat), add(x.dissat, z.sat)),
+                add(x.dissat, z.dissat),
+                x.timelocks.and(y.timelocks).or(z.timelocks),
+            ),
+            Terminal::OrB(x, z) => (
+                max(add(x.sat, z.dissat), add(x.dissat, z.sat)),
+                add(x.dissat, z.dissat),
+                x.timelocks.or(z.timelocks),
+            ),
+            Terminal::OrD(x, z) => (
+                max(x.sat, add(x.dissat, z.sat)),
+                add(x.dissat, z.dissat),
+                x.timelocks.or(z.timelocks),
+            ),
+            Terminal::OrC(x, z) => (
+                max(x.sat, add(x.dissat, z.sat)),
+                None,
+                x.timelocks.or(z.timelocks),
+            ),
+            Terminal::OrI(x, z) => (
+                max(
+                    add(x.sat, witness_size(1, 2)),
+                    add(z.sat, witness_size(1, 1)),
+                ),
+                max(
+                    add(x.dissat, witness_size(1, 2)),
+                    add(z.dissat, witness_size(1, 1)),
+                ),
+                x.timelocks.or(z.timelocks),
+            ),
+            Terminal::Thresh(k, subs) => {
+                let dissat = subs
+                    .iter()
+                    .fold(witness_size(0, 0), |acc, sub| add(acc, sub.dissat));
+                // Satisfy the k subexpressions whose satisfaction costs the most over their
+                // dissatisfaction, the rest are dissatisfied
+                let mut extra: Vec<(usize, usize)> = subs
+                    .iter()
+                    .filter_map(|sub| {
+                        let (sat, dissat) = (sub.sat?, sub.dissat?);
+                        Some((
+                            sat.bytes.saturating_sub(dissat.bytes),
+                            sat.elements.saturating_sub(dissat.elements),
+                        ))
+                    })
+                    .collect();
+                extra.sort_unstable_by(|a, b| b.cmp(a));
+                let sat = if extra.len() < *k {
+                    None
+                } else {
+                    extra[..*k].iter().fold(dissat, |acc, (bytes, elements)| {
+                        add(acc, witness_size(*elements, *bytes))
+                    })
+                };
+                let timelocks = subs.iter().fold(Timelocks::default(), |acc, sub| {
+                    if *k > 1 {
+                        acc.and(sub.timelocks)
+                    } else {
+                        acc.or(sub.timelocks)
+                    }
+                });
+                (sat, dissat, timelocks)
+            }
+            Terminal::Multi(k, _) => (
+                witness_size(k + 1, 1 + k * sig),
+                witness_size(k + 1, k + 1),
+                Timelocks::default(),
+            ),
+            Terminal::MultiA(k, keys) => (
+                witness_size(keys.len(), k * sig + keys.len() - k),
+                witness_size(keys.len(), keys.len()),
+                Timelocks::default(),
+            ),
+        };
+        let mut miniscript = Self {
+            node,
+            ty,
+            context,
+            script_size: 0,
+            sat,
+            dissat,
+            timelocks,
+        };
+        miniscript.script_size = miniscript.encode().len();
+        Ok(miniscript)
+    }
+
+    /// Script context of this expression
+    pub fn context(&self) -> Context {
+        self.context
+    }
+
+    /// Size of the encoded script
+    pub fn script_size(&self) -> usize {
+        self.script_size
+    }
+
+    /// Worst case satisfaction witness, not counting the script itself
+    pub fn max_satisfaction_size(&self) -> Option<WitnessSize> {
+        self.sat
+    }
+
+    /// Encode as Bitcoin script
+    pub fn encode(&self) -> Vec<u8> {
+        self.encode_into(Builder::new()).into_script()
+    }
+
+    fn encode_into(&self, builder: Builder) -> Builder {
+        let context = self.context;
+        match &self.node {
+            Terminal::True => builder.push_int(1),
+            Terminal::False => builder.push_int(0),
+            Terminal::PkK(key) => builder.push_slice(context.key_bytes(key)),
+            Terminal::PkH(key) => builder
+                .push_opcode(OP_DUP)
+                .push_opcode(OP_HASH160)
+                .push_slice(&hash::hash160(context.key_bytes(key)))
+                .push_opcode(OP_EQUALVERIFY),
+            Terminal::After(n) => builder.push_int(*n as i64).push_opcode(OP_CLTV),
+            Terminal::Older(n) => builder.push_int(*n as i64).push_opcode(OP_CSV),
+            Terminal::Sha256(h) => encode_hash(builder, OP_SHA256, h),
+            Terminal::Hash256(h) => encode_hash(builder, OP_HASH256, h),
+            Terminal::Ripemd160(h) => encode_hash(builder, OP_RIPEMD160, h),
+            Terminal::Hash160(h) => encode_hash(builder, OP_HASH160, h),
+            Terminal::Alt(x) => x
+                .encode_into(builder.push_opcode(OP_TOALTSTACK))
+                .push_opcode(OP_FROMALTSTACK),
+            Terminal::Swap(x) => x.encode_into(builder.push_opcode(OP_SWAP)),
+            Terminal::Check(x) => x.encode_into(builder).push_opcode(OP_CHECKSIG),
+            Terminal::DupIf(x) => x
+                .encode_into(builder.push_opcode(OP_DUP).push_opcode(OP_IF))
+                .push_opcode(OP_ENDIF),
+            Terminal::Verify(x) => {
+                let mut script = x.encode_into(builder).into_script();
+                // Fuse with the last opcode where a VERIFY variant exists
+                match script.last().copied() {
+                    Some(OP_EQUAL) => *script.last_mut().unwrap() = OP_EQUALVERIFY,
+                    Some(OP_CHECKSIG) => *script.last_mut().unwrap() = OP_CHECKSIGVERIFY,
+                    Some(OP_CHECKMULTISIG) => *script.last_mut().unwrap() = OP_CHECKMULTISIGVERIFY,
+                    Some(OP_NUMEQUAL) => *script.last_mut().unwrap() = OP_NUMEQUALVERIFY,
+                    _ => script.push(OP_VERIFY),
+                }
+                Builder::new().append(&script)
+            }
+            Terminal::NonZero(x) => x
+                .encode_into(
+                    builder
+                        .push_opcode(OP_SIZE)
+                        .push_opcode(OP_0NOTEQUAL)
+                        .push_opcode(OP_IF),
+                )
+                .push_opcode(OP_ENDIF),
+            Terminal::ZeroNotEqual(x) => x.encode_into(builder).push_opcode(OP_0NOTEQUAL),
+            Terminal::AndV(x, y) => y.encode_into(x.encode_into(builder)),
+            Terminal::AndB(x, y) => y
+                .encode_into(x.encode_into(builder))
+                .push_opcode(OP_BOOLAND),
+            Terminal::AndOr(x, y, z) => {
+                let builder = z.encode_into(x.encode_into(builder).push_opcode(OP_NOTIF));
+                y.encode_into(builder.push_opcode(OP_ELSE))
+                    .push_opcode(OP_ENDIF)
+            }
+            Terminal::OrB(x, z) => z.encode_into(x.encode_into(builder)).push_opcode(OP_BOOLOR),
+            Terminal::OrD(x, z) => z
+                .encode_into(
+                    x.encode_into(builder)
+                        .push_opcode(OP_IFDUP)
+                        .push_opcode(OP_NOTIF),
+                )
+                .push_opcode(OP_ENDIF),
+            Terminal::OrC(x, z) => z
+                .encode_into(x.encode_into(builder).push_opcode(OP_NOTIF))
+                .push_opcode(OP_ENDIF),
+            Terminal::OrI(x, z) => z
+                .encode_into(
+                    x.encode_into(builder.push_opcode(OP_IF))
+                        .push_opcode(OP_ELSE),
+                )
+                .push_opcode(OP_ENDIF),
+            Terminal::Thresh(k, subs) => subs
+                .iter()
+                .enumerate()
+                .fold(builder, |builder, (i, sub)| {
+                    let builder = sub.encode_into(builder);
+                    if i > 0 {
+                        builder.push_opcode(OP_ADD)
+                    } else {
+                        builder
+                    }
+                })
+                .push_int(*k as i64)
+                .push_opcode(OP_EQUAL),
+            Terminal::Multi(k, keys) => keys
+                .iter()
+                .fold(builder.push_int(*k as i64), |builder, key| {
+                    builder.push_slice(key)
+                })
+                .push_int(keys.len() as i64)
+                .push_opcode(OP_CHECKMULTISIG),
+            Terminal::MultiA(k, keys) => keys
+                .iter()
+                .enumerate()
+                .fold(builder, |builder, (i, key)| {
+                    builder
+                        .push_slice(context.key_bytes(key))
+                        .push_opcode(if i == 0 { OP_CHECKSIG } else { OP_CHECKSIGADD })
+                })
+                .push_int(*k as i64)
+                .push_opcode(OP_NUMEQUAL),
+        }
+    }
+
+    /// Public keys in the order they appear in the script
+    fn keys(&self) -> Vec<PubKey> {
+        match &self.node {
+            Terminal::PkK(key) | Terminal::PkH(key) => vec![*key],
+            Terminal::Multi(_, keys) | Terminal::MultiA(_, keys) => keys.clone(),
+            Terminal::Alt(x)
+            | Terminal::Swap(x)
+            | Terminal::Check(x)
+            | Terminal::DupIf(x)
+            | Terminal::Verify(x)
+            | Terminal::NonZero(x)
+            | Terminal::ZeroNotEqual(x) => x.keys(),
+            Terminal::AndV(x, y)
+            | Terminal::AndB(x, y)
+            | Terminal::OrB(x, y)
+            | Terminal::OrD(x, y)
+            | Terminal::OrC(x, y)
+            | Terminal::OrI(x, y) => [x.keys(), y.keys()].concat(),
+            Terminal::AndOr(x, y, z) => [x.keys(), y.keys(), z.keys()].concat(),
+            Terminal::Thresh(_, subs) => subs.iter().flat_map(|sub| sub.keys()).collect(),
+            _ => Vec::new(),
+        }
+    }
+
+    /// The standard miniscript sanity checks: a top level B expression which always needs a
+    /// signature, cannot be malleated, does not mix timelock kinds, has no repeated keys and
+    /// stays within the standardness limits of its context
+    pub fn sanity_check(&self) -> Result<(), MiniscriptError> {
+        if self.ty.base != Base::B {
+            return Err(type_error("top level expression must be B"));
+        }
+        if !self.ty.s {
+            return Err(MiniscriptError::NoSignature);
+        }
+        if !self.ty.m {
+            return Err(MiniscriptError::Malleable);
+        }
+        if self.timelocks.mixed {
+            return Err(MiniscriptError::TimelockMixing);
+        }
+        let mut keys = self.keys();
+        let count = keys.len();
+        keys.sort_unstable();
+        keys.dedup();
+        if keys.len() != count {
+            return Err(MiniscriptError::RepeatedKey);
+        }
+        let sat = self.sat.ok_or(MiniscriptError::Unsatisfiable)?;
+        match self.context {
+            Context::Segwitv0 => {
+                if self.script_size > MAX_STANDARD_P2WSH_SCRIPT_SIZE {
+                    return Err(MiniscriptError::ResourceLimits("script size"));
+                }
+                if op_count(&self.encode()) > MAX_OPS_PER_SCRIPT {
+                    return Err(MiniscriptError::ResourceLimits("operation count"));
+                }
+                if sat.elements > MAX_STANDARD_P2WSH_STACK_ITEMS {
+                    return Err(MiniscriptError::ResourceLimits("witness stack items"));
+                }
+            }
+            Context::Tap => {
+                if sat.elements > MAX_STACK_SIZE {
+                    return Err(MiniscriptError::ResourceLimits("witness stack items"));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Worst case weight of the witness spending this script: satisfaction, the script and for
+    /// tapscript the control block of a single leaf tree
+    pub fn max_satisfaction_weight(&self) -> Option<usize> {
+        let sat = self.sat?;
+        let mut weight = sat.bytes + script::compact_size_len(self.script_size) + self.script_size;
+        let elements = match self.context {
+            Context::Segwitv0 => sat.elements + 1,
+            Context::Tap => {
+                weight += 1 + 33;
+                sat.elements + 2
+            }
+        };
+        Some(weight + script::compact_size_len(elements))
+    }
+}
+
+fn encode_hash(builder: Builder, opcode: u8, hash: &[u8]) -> Builder {
+    builder
+        .push_opcode(OP_SIZE)
+        .push_int(32)
+        .push_opcode(OP_EQUALVERIFY)
+        .push_opcode(opcode)
+        .push_slice(hash)
+        .push_opcode(OP_EQUAL)
+}
+
+/// Non-push operations of `script` as counted against `MAX_OPS_PER_SCRIPT`, with the keys of
+/// every `OP_CHECKMULTISIG(VERIFY)`
+fn op_count(script: &[u8]) -> usize {
+    let mut count = 0;
+    let mut last_push: Option<usize> = None;
+    for instruction in script::instructions(script).flatten() {
+        match instruction {
+            Instruction::Op(OP_CHECKMULTISIG | OP_CHECKMULTISIGVERIFY) => {
+                count += 1 + last_push.unwrap_or(MAX_PUBKEYS_PER_MULTISIG)
+            }
+            Instruction::Op(op) if op > OP_PUSHNUM_16 => count += 1,
+            _ => {}
+        }
+        last_push = match instruction {
+            Instruction::Op(op) if (OP_PUSHNUM_1..=OP_PUSHNUM_16).contains(&op) => {
+                Some((op - OP_PUSHNUM_1 + 1) as usize)
+            }
+            _ => None,
+        };
+    }
+    count
+}
+
+impl fmt::Display for Miniscript {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut wrappers = String::new();
+        let mut inner = self;
+        loop {
+            let (wrapper, x) = match &inner.node {
+                Terminal::Alt(x) => ('a', x),
+                Terminal::Swap(x) => ('s', x),
+                Terminal::Check(x) if !matches!(x.node, Terminal::PkK(_) | Terminal::PkH(_)) => {
+                    ('c', x)
+                }
+                Terminal::DupIf(x) => ('d', x),
+                Terminal::Verify(x) => ('v', x),
+                Terminal::NonZero(x) => ('j', x),
+                Terminal::ZeroNotEqual(x) => ('n', x),
+                _ => break,
+            };
+            wrappers.push(wrapper);
+            inner = x;
+        }
+        if !wrappers.is_empty() {
+            write!(f, "{}:", wrappers)?;
+        }
+        match &inner.node {
+            Terminal::True => write!(f, "1"),
+            Terminal::False => write!(f, "0"),
+            Terminal::PkK(key) => write!(f, "pk_k({})", hex::encode(key)),
+            Terminal::PkH(key) => write!(f, "pk_h({})", hex::encode(key)),
+            Terminal::Check(x) => match &x.node {
+                Terminal::PkK(key) => write!(f, "pk({})", hex::encode(key)),
+                Terminal::PkH(key) => write!(f, "pkh({})", hex::encode(key)),
+                _ => unreachable!("other c: wrappers are printed as prefixes"),
+            },
+            Terminal::After(n) => write!(f, "after({})", n),
+            Terminal::Older(n) => write!(f, "older({})", n),
+            Terminal::Sha256(h) => write!(f, "sha256({})", hex::encode(h)),
+            Terminal::Hash256(h) => write!(f, "hash256({})", hex::encode(h)),
+            Terminal::Ripemd160(h) => write!(f, "ripemd160({})", hex::encode(h)),
+            Terminal::Hash160(h) => write!(f, "hash160({})", hex::encode(h)),
+            Terminal::AndV(x, y) => write!(f, "and_v({},{})", x, y),
+            Terminal::AndB(x, y) => write!(f, "and_b({},{})", x, y),
+            Terminal::AndOr(x, y, z) => write!(f, "andor({},{},{})", x, y, z),
+            Terminal::OrB(x, z) => write!(f, "or_b({},{})", x, z),
+            Terminal::OrD(x, z) => write!(f, "or_d({},{})", x, z),
+            Terminal::OrC(x, z) => write!(f, "or_c({},{})", x, z),
+            Terminal::OrI(x, z) => write!(f, "or_i({},{})", x, z),
+            Terminal::Thresh(k, subs) => {
+                write!(f, "thresh({}", k)?;
+                for sub in subs {
+                    write!(f, ",{}", sub)?;
+                }
+                write!(f, ")")
+            }
+            Terminal::Multi(k, keys) | Terminal::MultiA(k, keys) => {
+                let name = match inner.node {
+                    Terminal::Multi(..) => "multi",
+                    _ => "multi_a",
+                };
+                write!(f, "{}({}", name, k)?;
+                for key in keys {
+                    write!(f, ",{}", hex::encode(key))?;
+                }
+                write!(f, ")")
+            }
+            _ => unreachable!("wrappers are printed as prefixes"),
+        }
+    }
+}
+
+/// A part of the policy tree being compiled: a policy or the conjunction, weighted disjunction
+/// or equally likely disjunction of a slice of them
+#[derive(Clone, Copy)]
+enum Node<'a> {
+    Policy(&'a Policy),
+    And(&'a [Policy]),
+    Or(&'a [(u32, Policy)]),
+    Any(&'a [Policy]),
+}
+
+impl<'a> Node<'a> {
+    fn and(policies: &'a [Policy]) -> Self {
+        match policies {
+            [policy] => Node::Policy(policy),
+            _ => Node::And(policies),
+        }
+    }
+
+    fn or(policies: &'a [(u32, Policy)]) -> Self {
+        match policies {
+            [(_, policy)] => Node::Policy(policy),
+            _ => Node::Or(policies),
+        }
+    }
+
+    fn any(policies: &'a [Policy]) -> Self {
+        match policies {
+            [policy] => Node::Policy(policy),
+            _ => Node::Any(policies),
+        }
+    }
+
+    /// Identity of this node for the compilation cache, the policy tree outlives the cache
+    fn key(&self) -> (u8, usize, usize) {
+        match self {
+            Node::Policy(policy) => (0, *policy as *const Policy as usize, 1),
+            Node::And(policies) => (1, policies.as_ptr() as usize, policies.len()),
+            Node::Or(policies) => (2, policies.as_ptr() as usize, policies.len()),
+            Node::Any(policies) => (3, policies.as_ptr() as usize, policies.len()),
+        }
+    }
+}
+
+/// Cheapest candidate compilation per type, for given probabilities of the expression being
+/// satisfied and dissatisfied
+struct Candidates {
+    p_sat: f64,
+    p_dissat: f64,
+    best: BTreeMap<Type, (f64, Arc<Miniscript>)>,
+}
+
+impl Candidates {
+    fn new(p_sat: f64, p_dissat: f64) -> Self {
+        Self {
+            p_sat,
+            p_dissat,
+            best: BTreeMap::new(),
+        }
+    }
+
+    /// Expected cost of spending through `miniscript`: its script size plus its satisfaction
+    /// and dissatisfaction sizes weighted by their probabilities. Infinite when it lacks a needed
+    /// satisfaction or dissatisfaction, such candidates are still kept as a base for wrappers.
+    fn cost(&self, miniscript: &Miniscript) -> f64 {
+        let weighted = |p: f64, size: Option<WitnessSize>| match size {
+            _ if p == 0.0 => 0.0,
+            Some(size) => p * size.bytes as f64,
+            None => f64::INFINITY,
+        };
+        miniscript.script_size as f64
+            + weighted(self.p_sat, miniscript.sat)
+            + weighted(self.p_dissat, miniscript.dissat)
+    }
+
+    fn insert(&mut self, miniscript: Miniscript) -> bool {
+        let cost = self.cost(&miniscript);
+        match self.best.get(&miniscript.ty) {
+            Some((best, _)) if *best <= cost => false,
+            _ => {
+                self.best
+                    .insert(miniscript.ty, (cost, Arc::new(miniscript)));
+                true
+            }
+        }
+    }
+
+    fn try_insert(&mut self, node: Terminal, context: Context) -> bool {
+        match Miniscript::new(node, context) {
+            Ok(miniscript) => self.insert(miniscript),
+            Err(_) => false,
+        }
+    }
+
+    /// Add every wrapper that type checks until no cheaper candidate turns up
+    fn wrap(&mut self, context: Context) {
+        let false_ = Arc::new(Miniscript::new(Terminal::False, context).unwrap());
+        let true_ = Arc::new(Miniscript::new(Terminal::True, context).unwrap());
+        loop {
+            let mut changed = false;
+            let current: Vec<Arc<Miniscript>> =
+                self.best.values().map(|(_, ms)| ms.clone()).collect();
+            for x in current {
+                let wrapped = [
+                    Terminal::Alt(x.clone()),
+                    Terminal::Swap(x.clone()),
+                    Terminal::Check(x.clone()),
+                    Terminal::DupIf(x.clone()),
+                    Terminal::Verify(x.clone()),
+                    Terminal::NonZero(x.clone()),
+                    Terminal::ZeroNotEqual(x.clone()),
+                    Terminal::AndV(x.clone(), true_.clone()),
+                    Terminal::OrI(x.clone(), false_.clone()),
+                    Terminal::OrI(false_.clone(), x.clone()),
+                ];
+                for node in wrapped {
+                    changed |= self.try_insert(node, context);
+                }
+            }
+            if !changed {
+                break;
+            }
+        }
+    }
+
+    fn of_base(&self, base: Base) -> impl Iterator<Item = &Arc<Miniscript>> {
+        self.best
+            .values()
+            .map(|(_, ms)| ms)
+            .filter(move |ms| ms.ty.base == base)
+    }
+
+    fn all(&self) -> impl Iterator<Item = &Arc<Miniscript>> {
+        self.best.values().map(|(_, ms)| ms)
+    }
+
+    /// Cheapest candidate of `base` with a dissatisfaction and a unit result, preferring
+    /// non-malleable ones, as needed by thresh
+    fn best_du(&self, base: Base) -> Option<Arc<Miniscript>> {
+        self.best
+            .values()
+            .filter(|(cost, ms)| cost.is_finite() && ms.ty.base == base && ms.ty.d && ms.ty.u)
+            .min_by(|(a_cost, a), (b_cost, b)| {
+                let a_malleable = !(a.ty.m && a.ty.e);
+                let b_malleable = !(b.ty.m && b.ty.e);
+                a_malleable
+                    .cmp(&b_malleable)
+                    .then(a_cost.partial_cmp(b_cost).unwrap())
+            })
+            .map(|(_, ms)| ms.clone())
+    }
+}
+
+struct Compiler {
+    context: Context,
+    cache: HashMap<(u8, usize, usize, u64, u64), Arc<Candidates>>,
+}
+
+impl Compiler {
+    fn compile(&mut self, node: Node, p_sat: f64, p_dissat: f64) -> Arc<Candidates> {
+        let (kind, ptr, len) = node.key();
+        let key = (kind, ptr, len, p_sat.to_bits(), p_dissat.to_bits());
+        if let Some(candidates) = self.cache.get(&key) {
+            return candidates.clone();
+        }
+        let context = self.context;
+        let mut candidates = Candidates::new(p_sat, p_dissat);
+        match node {
+            Node::Policy(policy) => match policy {
+                Policy::Unsatisfiable => {
+                    candidates.try_insert(Terminal::False, context);
+                }
+                Policy::Trivial => {
+                    candidates.try_insert(Terminal::True, context);
+                }
+                Policy::Key(key) => {
+                    candidates.try_insert(Terminal::PkK(*key), context);
+                    candidates.try_insert(Terminal::PkH(*key), context);
+                }
+                Policy::After(n) => {
+                    candidates.try_insert(Terminal::After(*n), context);
+                }
+                Policy::Older(n) => {
+                    candidates.try_insert(Terminal::Older(*n), context);
+                }
+                Policy::Sha256(h) => {
+                    candidates.try_insert(Terminal::Sha256(*h), context);
+                }
+                Policy::Hash256(h) => {
+                    candidates.try_insert(Terminal::Hash256(*h), context);
+                }
+                Policy::Ripemd160(h) => {
+                    candidates.try_insert(Terminal::Ripemd160(*h), context);
+                }
+                Policy::Hash160(h) => {
+                    candidates.try_insert(Terminal::Hash160(*h), context);
+                }
+                Policy::And(policies) => {
+                    self.compile_and(&mut candidates, Node::and(policies));
+                }
+                Policy::Or(policies) => {
+                    self.compile_or(&mut candidates, Node::or(policies));
+                }
+                Policy::Thresh(k, policies) => {
+                    self.compile_thresh(&mut candidates, *k, policies);
+                }
+            },
+            node => match node {
+                Node::And(_) => self.compile_and(&mut candidates, node),
+                _ => self.compile_or(&mut candidates, node),
+            },
+        }
+        candidates.wrap(context);
+        let candidates = Arc::new(candidates);
+        self.cache.insert(key, candidates.clone());
+        candidates
+    }
+
+    /// Split a conjunction into its first policy and the rest
+    fn split_and(node: Node) -> Option<(Node, Node)> {
+        match node {
+            Node::And(policies) => Some((Node::Policy(&policies[0]), Node::and(&policies[1..]))),
+            _ => None,
+        }
+    }
+
+    fn compile_and(&mut self, candidates: &mut Candidates, node: Node) {
+        let (left, right) = Self::split_and(node).expect("a conjunction");
+        let context = self.context;
+        let (p_sat, p_dissat) = (candidates.p_sat, candidates.p_dissat);
+        for (x, y) in [(left, right), (right, left)] {
+            let x_sat = self.compile(x, p_sat, 0.0);
+            let x_both = self.compile(x, p_sat, p_dissat);
+            let y_both = self.compile(y, p_sat, p_dissat);
+            let zero = Arc::new(Miniscript::new(Terminal::False, context).unwrap());
+            for x in x_sat.of_base(Base::V) {
+                for y in y_both.all() {
+                    candidates.try_insert(Terminal::AndV(x.clone(), y.clone()), context);
+                }
+            }
+            for x in x_both.of_base(Base::B) {
+                for y in y_both.of_base(Base::W) {
+                    candidates.try_insert(Terminal::AndB(x.clone(), y.clone()), context);
+                }
+                for y in y_both.all() {
+                    candidates
+                        .try_insert(Terminal::AndOr(x.clone(), y.clone(), zero.clone()), context);
+                }
+            }
+        }
+    }
+
+    fn compile_or(&mut self, candidates: &mut Candidates, node: Node) {
+        let (left, left_weight, right, right_weight) = match node {
+            Node::Or(policies) => (
+                Node::Policy(&policies[0].1),
+                policies[0].0 as f64,
+                Node::or(&policies[1..]),
+                policies[1..].iter().map(|(w, _)| *w as f64).sum::<f64>(),
+            ),
+            Node::Any(policies) => (
+                Node::Policy(&policies[0]),
+                1.0,
+                Node::any(&policies[1..]),
+                (policies.len() - 1) as f64,
+            ),
+            _ => unreachable!("a disjunction"),
+        };
+        let context = self.context;
+        let (p_sat, p_dissat) = (candidates.p_sat, candidates.p_dissat);
+        let total = left_weight + right_weight;
+        let branches = [
+            (left, left_weight / total, right, right_weight / total),
+            (right, right_weight / total, left, left_weight / total),
+        ];
+        for (x, lx, z, lz) in branches {
+            let x_or = self.compile(x, p_sat * lx, p_dissat + p_sat * lz);
+            let z_or = self.compile(z, p_sat * lz, p_dissat + p_sat * lx);
+            let z_dissat = self.compile(z, p_sat * lz, p_dissat);
+            let x_or_c = self.compile(x, p_sat * lx, p_sat * lz);
+            let z_or_c = self.compile(z, p_sat * lz, 0.0);
+            let x_or_i = self.compile(x, p_sat * lx, p_dissat);
+            for x in x_or.of_base(Base::B) {
+                for z in z_or.of_base(Base::W) {
+                    candidates.try_insert(Terminal::OrB(x.clone(), z.clone()), context);
+                }
+                for z in z_dissat.of_base(Base::B) {
+                    candidates.try_insert(Terminal::OrD(x.clone(), z.clone()), context);
+                }
+            }
+            for x in x_or_c.of_base(Base::B) {
+                for z in z_or_c.of_base(Base::V) {
+                    candidates.try_insert(Terminal::OrC(x.clone(), z.clone()), context);
+                }
+            }
+            for x in x_or_i.all() {
+                for z in z_dissat.all() {
+                    candidates.try_insert(Terminal::OrI(x.clone(), z.clone()), context);
+                }
+            }
+            // or(and(A,B),Z) as andor(A,B,Z)
+            if let Some((a, b)) = match x {
+                Node::Policy(Policy::And(policies)) => Self::split_and(Node::And(policies)),
+                _ => None,
+            } {
+                for (a, b) in [(a, b), (b, a)] {
+                    let a = self.compile(a, p_sat * lx, p_dissat + p_sat * lz);
+                    let b = self.compile(b, p_sat * lx, 0.0);
+                    for a in a.of_base(Base::B) {
+                        for b in b.all() {
+                            for z in z_dissat.all() {
+                                candidates.try_insert(
+                                    Terminal::AndOr(a.clone(), b.clone(), z.clone()),
+                                    context,
+                                );
+                            }
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    fn compile_thresh(&mut self, candidates: &mut Candidates, k: usize, policies: &[Policy]) {
+        let context = self.context;
+        let n = policies.len();
+        let (p_sat, p_dissat) = (candidates.p_sat, candidates.p_dissat);
+        if n == 1 {
+            for miniscript in self
+                .compile(Node::Policy(&policies[0]), p_sat, p_dissat)
+                .all()
+            {
+                candidates.insert(miniscript.as_ref().clone());
+            }
+            return;
+        }
+        if k == n {
+            self.compile_and(candidates, Node::and(policies));
+        } else if k == 1 {
+            self.compile_or(candidates, Node::any(policies));
+        }
+        let keys: Option<Vec<PubKey>> = policies
+            .iter()
+            .map(|policy| match policy {
+                Policy::Key(key) => Some(*key),
+                _ => None,
+            })
+            .collect();
+        if let Some(keys) = keys {
+            let node = match context {
+                Context::Segwitv0 => Terminal::Multi(k, keys),
+                Context::Tap => Terminal::MultiA(k, keys),
+            };
+            candidates.try_insert(node, context);
+        }
+        let sub_sat = p_sat * k as f64 / n as f64;
+        let sub_dissat = p_dissat + p_sat * (n - k) as f64 / n as f64;
+        let subs: Vec<Arc<Candidates>> = policies
+            .iter()
+            .map(|policy| self.compile(Node::Policy(policy), sub_sat, sub_dissat))
+            .collect();
+        for first in 0..n {
+            let mut chosen = vec![subs[first].best_du(Base::B)];
+            chosen.extend(
+                subs.iter()
+                    .enumerate()
+                    .filter(|(i, _)| *i != first)
+                    .map(|(_, sub)| sub.best_du(Base::W)),
+            );
+            if let Some(chosen) = chosen.into_iter().collect::<Option<Vec<_>>>() {
+                candidates.try_insert(Terminal::Thresh(k, chosen), context);
+            }
+        }
+    }
+}
+
+/// Compile `policy` to the cheapest miniscript for `context` that passes the sanity checks
+pub fn compile(policy: &Policy, context: Context) -> Result<Miniscript, MiniscriptError> {
+    let mut compiler = Compiler {
+        context,
+        cache: HashMap::new(),
+    };
+    let candidates = compiler.compile(Node::Policy(policy), 1.0, 0.0);
+    let mut top: Vec<(f64, &Arc<Miniscript>)> = candidates
+        .best
+        .values()
+        .filter(|(cost, ms)| cost.is_finite() && ms.ty.base == Base::B)
+        .map(|(cost, ms)| (*cost, ms))
+        .collect();
+    top.sort_by(|a, b| a.0.partial_cmp(&b.0).unwrap());
+    let mut error = MiniscriptError::Unsatisfiable;
+    for (i, (_, miniscript)) in top.iter().enumerate() {
+        match miniscript.sanity_check() {
+            Ok(()) => return Ok(miniscript.as_ref().clone()),
+            // Report why the cheapest compilation was rejected
+            Err(e) if i == 0 => error = e,
+            Err(_) => {}
+        }
+    }
+    Err(error)
+}
+
+#[derive(Serialize, Debug)]
+/// CompiledPolicy is the compilation of a policy presented to user
+pub struct CompiledPolicy {
+    pub miniscript: String,
+    pub script: String,
+    pub script_size: usize,
+    pub max_satisfaction_weight: usize,
+    pub address: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub leaf_hash: Option<String>,
+}
+
+impl CompiledPolicy {
+    /// Compile `policy` and pay to its script with a P2WSH output, or with a P2TR output whose
+    /// only leaf is the script and whose key path is disabled by the NUMS internal key
+    pub fn new(policy: &str, context: Context, network: Network) -> Result<Self, MiniscriptError> {
+        let miniscript = compile(&policy.parse()?, context)?;
+        let script = miniscript.encode();
+        log::debug!("Miniscript: {}", miniscript);
+        let (address, leaf_hash) = match context {
+            Context::Segwitv0 => {
+                policy::check_script(&script, ScriptType::P2wsh)?;
+                (Address::p2wsh(&script, network), None)
+            }
+            Context::Tap => {
+                policy::check_tap_leaf(&script, taproot::TAPROOT_LEAF_TAPSCRIPT)?;
+                let leaf_hash = taproot::tap_leaf_hash(&script, taproot::TAPROOT_LEAF_TAPSCRIPT);
+                let (output_key, _) =
+                    taproot::output_key(&taproot::NUMS_INTERNAL_KEY, Some(leaf_hash))
+                        .expect("NUMS key is a valid x-only key");
+                (
+                    Address::p2tr_tweaked(output_key, network),
+                    Some(hex::encode(leaf_hash)),
+                )
+            }
+        };
+        Ok(Self {
+            miniscript: miniscript.to_string(),
+            script_size: script.len(),
+            max_satisfaction_weight: miniscript
+                .max_satisfaction_weight()
+                .ok_or(MiniscriptError::Unsatisfiable)?,
+            script: hex::encode(script),
+            address: address.to_string(),
+            leaf_hash,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::interpreter::{
+        tests::{keypair, sign, SIGHASH},
+        verify_script, ScriptFlags, SighashChecker,
+    };
+
+    fn pk(miniscript: Terminal) -> Arc<Miniscript> {
+        let key = Arc::new(Miniscript::new(miniscript, Context::Segwitv0).unwrap());
+        Arc::new(Miniscript::new(Terminal::Check(key), Context::Segwitv0).unwrap())
+    }
+
+    #[test]
+    fn test_parse_policy() {
+        let (_, a) = keypair(1);
+        let policy: Policy = format!("or(9@pk({}), 1@older(144))", hex::encode(a))
+            .parse()
+            .unwrap();
+        assert_eq!(
+            policy,
+            Policy::Or(vec![(9, Policy::Key(a)), (1, Policy::Older(144))])
+        );
+        assert_eq!(
+            "thresh(3,after(1),after(2))".parse::<Policy>(),
+            Err(MiniscriptError::InvalidThreshold)
+        );
+        assert_eq!(
+            "older(0)".parse::<Policy>(),
+            Err(MiniscriptError::InvalidTimelock(0))
+        );
+        assert!("pk(02)".parse::<Policy>().is_err());
+        assert!("and(after(1))".parse::<Policy>().is_err());
+        assert!("or(after(1),after(2)".parse::<Policy>().is_err());
+    }
+
+    #[test]
+    fn test_compile_and_spend() {
+        let (a_secret, a) = keypair(1);
+        let (b_secret, b) = keypair(2);
+        let policy = format!(
+            "or(pk({}),and(pk({}),older(1000)))",
+            hex::encode(a),
+            hex::encode(b)
+        );
+        let miniscript = compile(&policy.parse().unwrap(), Context::Segwitv0).unwrap();
+        assert_eq!(
+            miniscript.to_string(),
+            format!(
+                "or_d(pk({}),and_v(v:pk({}),older(1000)))",
+                hex::encode(a),
+                hex::encode(b)
+            )
+        );
+        let script = miniscript.encode();
+        assert_eq!(
+            script,
+            Builder::new()
+                .push_slice(&a)
+                .push_opcode(OP_CHECKSIG)
+                .push_opcode(OP_IFDUP)
+                .push_opcode(OP_NOTIF)
+                .push_slice(&b)
+                .push_opcode(OP_CHECKSIGVERIFY)
+                .push_int(1000)
+                .push_opcode(OP_CSV)
+                .push_opcode(OP_ENDIF)
+                .into_script()
+        );
+        assert_eq!(
+            miniscript.max_satisfaction_weight(),
+            Some(1 + 73 + 1 + 1 + 77)
+        );
+
+        let script_pubkey = Address::p2wsh(&script, Network::Bitcoin).script_pubkey();
+        let flags = ScriptFlags::default();
+        let witness = vec![sign(&a_secret), script.clone()];
+        let checker = SighashChecker::new(SIGHASH);
+        assert_eq!(
+            verify_script(&[], &witness, &script_pubkey, &flags, &checker),
+            Ok(())
+        );
+        let witness = vec![sign(&b_secret), vec![], script];
+        let checker = SighashChecker::new(SIGHASH).version(2).sequence(1000);
+        assert_eq!(
+            verify_script(&[], &witness, &script_pubkey, &flags, &checker),
+            Ok(())
+        );
+        let checker = SighashChecker::new(SIGHASH).version(2).sequence(999);
+        assert!(verify_script(&[], &witness, &script_pubkey, &flags, &checker).is_err());
+    }
+
+    #[test]
+    fn test_compile_thresholds() {
+        let keys: Vec<String> = (1..=3).map(|byte| hex::encode(keypair(byte).1)).collect();
+        let policy = format!("thresh(2,pk({}),pk({}),pk({}))", keys[0], keys[1], keys[2]);
+        let compiled = CompiledPolicy::new(&policy, Context::Segwitv0, Network::Bitcoin).unwrap();
+        assert_eq!(
+            compiled.miniscript,
+            format!("multi(2,{},{},{})", keys[0], keys[1], keys[2])
+        );
+        assert!(compiled.address.starts_with("bc1q"));
+
+        let compiled = CompiledPolicy::new(&policy, Context::Tap, Network::Testnet).unwrap();
+        assert_eq!(
+            compiled.miniscript,
+            format!("multi_a(2,{},{},{})", keys[0], keys[1], keys[2])
+        );
+        assert!(compiled
+            .script
+            .ends_with(&hex::encode([OP_PUSHNUM_2, OP_NUMEQUAL])));
+        assert!(compiled.address.starts_with("tb1p"));
+        assert!(compiled.leaf_hash.is_some());
+
+        let policy = format!("thresh(2,pk({}),pk({}),older(144))", keys[0], keys[1]);
+        let miniscript = compile(&policy.parse().unwrap(), Context::Segwitv0).unwrap();
+        assert!(matches!(miniscript.node, Terminal::Thresh(2, _)));
+    }
+
+    #[test]
+    fn test_sanity_checks() {
+        let a = hex::encode(keypair(1).1);
+        let b = hex::encode(keypair(2).1);
+        let check = |policy: String| compile(&policy.parse().unwrap(), Context::Segwitv0);
+        assert_eq!(
+            check("after(100)".to_string()),
+            Err(MiniscriptError::NoSignature)
+        );
+        assert_eq!(
+            check(format!("or(pk({}),sha256({}))", a, "11".repeat(32))),
+            Err(MiniscriptError::NoSignature)
+        );
+        assert_eq!(
+            check(format!("and(pk({}),pk({}))", a, a)),
+            Err(MiniscriptError::RepeatedKey)
+        );
+        assert_eq!(
+            check(format!("and(pk({}),and(after(100),after(500000001)))", a)),
+            Err(MiniscriptError::TimelockMixing)
+        );
+        assert!(check(format!(
+            "or(and(pk({}),after(100)),and(pk({}),after(500000001)))",
+            a, b
+        ))
+        .is_ok());
+
+        // A third party can swap the dissatisfaction of the hash for any other 32 bytes
+        let hash =
+            Arc::new(Miniscript::new(Terminal::Sha256([0x11; 32]), Context::Segwitv0).unwrap());
+        let subs = vec![
+            pk(Terminal::PkK(keypair(1).1)),
+            Arc::new(
+                Miniscript::new(
+                    Terminal::Swap(pk(Terminal::PkK(keypair(2).1))),
+                    Context::Segwitv0,
+                )
+                .unwrap(),
+            ),
+            Arc::new(Miniscript::new(Terminal::Alt(hash), Context::Segwitv0).unwrap()),
+        ];
+        let miniscript = Miniscript::new(Terminal::Thresh(2, subs), Context::Segwitv0).unwrap();
+        assert!(miniscript.ty.s);
+        assert_eq!(miniscript.sanity_check(), Err(MiniscriptError::Malleable));
+
+        assert!(matches!(
+            Miniscript::new(
+                Terminal::Verify(pk(Terminal::PkK(keypair(1).1))),
+                Context::Segwitv0
+            )
+            .and_then(|v| Miniscript::new(Terminal::Alt(Arc::new(v)), Context::Segwitv0)),
+            Err(MiniscriptError::TypeCheck(_))
+        ));
+    }
+}
@@ -0,0 +1,126 @@
+use crate::{error::WalletError, hash, wallet::PubKey};
+use secp256k1::{PublicKey, Secp256k1};
+
+/// Coefficient of the second key, saving a point multiplication
+const ONE: [u8; 32] = {
+    let mut one = [0u8; 32];
+    one[31] = 1;
+    one
+};
+
+/// BIP327 KeySort: the public keys in lexicographical order
+pub fn key_sort(public_keys: &[PubKey]) -> Vec<PubKey> {
+    let mut public_keys = public_keys.to_vec();
+    public_keys.sort_unstable();
+    public_keys
+}
+
+/// BIP327 HashKeys: commitment to the whole, ordered list of public keys
+fn hash_keys(public_keys: &[PubKey]) -> [u8; 32] {
+    hash::tagged_hash("KeyAgg list", &public_keys.concat())
+}
+
+/// BIP327 GetSecondKey: the first key differing from the first one, whose coefficient is 1
+fn second_key(public_keys: &[PubKey]) -> Option<&PubKey> {
+    public_keys.iter().find(|key| **key != public_keys[0])
+}
+
+/// BIP327 KeyAggCoeff of `public_key` in `public_keys`, a big endian scalar
+pub fn key_agg_coefficient(public_keys: &[PubKey], public_key: &PubKey) -> [u8; 32] {
+    if second_key(public_keys) == Some(public_key) {
+        return ONE;
+    }
+    let mut data = hash_keys(public_keys).to_vec();
+    data.extend(public_key);
+    hash::tagged_hash("KeyAgg coefficient", &data)
+}
+
+/// BIP327 KeyAgg: the sum of the public keys, each multiplied by its coefficient
+///
+/// The order of `public_keys` matters, sort them with `key_sort` first unless the signers
+/// agreed on another order.
+pub fn key_agg(public_keys: &[PubKey]) -> Result<PublicKey, WalletError> {
+    if public_keys.is_empty() {
+        return Err(WalletError::EmptyN);
+    }
+    let secp = Secp256k1::verification_only();
+    let points = public_keys
+        .iter()
+        .map(|public_key| {
+            let mut point =
+                PublicKey::from_slice(public_key).map_err(|_| WalletError::InvalidPublicKey)?;
+            let coefficient = key_agg_coefficient(public_keys, public_key);
+            if coefficient != ONE {
+                point
+                    .mul_assign(&secp, &coefficient)
+                    .map_err(|_| WalletError::InvalidPublicKey)?;
+            }
+            Ok(point)
+        })
+        .collect::<Result<Vec<_>, WalletError>>()?;
+    PublicKey::combine_keys(&points.iter().collect::<Vec<_>>())
+        .map_err(|_| WalletError::InvalidPublicKey)
+}
+
+/// x-only serialization of the aggregate key, the taproot internal key of the signers
+pub fn aggregate_key(public_keys: &[PubKey]) -> Result<[u8; 32], WalletError> {
+    let mut x_only = [0u8; 32];
+    x_only.copy_from_slice(&key_agg(public_keys)?.serialize()[1..]);
+    Ok(x_only)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::convert::TryInto;
+
+    fn keys() -> Vec<PubKey> {
+        [
+            "02F9308A019258C31049344F85F89D5229B531C845836F99B08601F113BCE036F9",
+            "03DFF1D77F2A671C5F36183726DB2341BE58FEAE1DA2DECED843240F7B502BA659",
+            "023590A94E768F8E1815C2F24B4D80A8E3149316C3518CE7B7AD338368D038CA66",
+        ]
+        .iter()
+        .map(|key| hex::decode(key).unwrap().try_into().unwrap())
+        .collect()
+    }
+
+    #[test]
+    fn test_key_agg_vectors() {
+        // BIP327 key_agg_vectors.json
+        let keys = keys();
+        let vectors: [(&[usize], &str); 4] = [
+            (
+                &[0, 1, 2],
+                "90539eede565f5d054f32cc0c220126889ed1e5d193baf15aef344fe59d4610c",
+            ),
+            (
+                &[2, 1, 0],
+                "6204de8b083426dc6eaf9502d27024d53fc826bf7d2012148a0575435df54b2b",
+            ),
+            (
+                &[0, 0, 0],
+                "b436e3bad62b8cd409969a224731c193d051162d8c5ae8b109306127da3aa935",
+            ),
+            (
+                &[0, 0, 1, 1],
+                "69bc22bfa5d106306e48a20679de1d7389386124d07571d0d872686028c26a3e",
+            ),
+        ];
+        for (indices, expected) in vectors.iter() {
+            let public_keys: Vec<PubKey> = indices.iter().map(|i| keys[*i]).collect();
+            assert_eq!(hex::encode(aggregate_key(&public_keys).unwrap()), *expected);
+        }
+    }
+
+    #[test]
+    fn test_key_sort() {
+        let keys = keys();
+        let sorted = key_sort(&keys);
+        assert_eq!(sorted, vec![keys[2], keys[0], keys[1]]);
+        assert_eq!(
+            aggregate_key(&key_sort(&[keys[1], keys[2], keys[0]])).unwrap(),
+            aggregate_key(&sorted).unwrap()
+        );
+    }
+}
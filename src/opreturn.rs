@@ -0,0 +1,160 @@
+use crate::{
+    decode::DecodedScript, error::OpReturnError, opcodes, policy::MAX_OP_RETURN_RELAY,
+    script::Builder, transaction::TxOut,
+};
+use serde::{Deserialize, Serialize};
+
+/// Maximum standard data of an `OP_RETURN` output: the 83 byte scriptPubKey relayed by default,
+/// less `OP_RETURN` and an `OP_PUSHDATA1` header
+pub const MAX_OP_RETURN_DATA: usize = 80;
+
+#[derive(Deserialize, Debug)]
+// Get the hex encoded data pushes from user, e.g. a protocol tag then a document hash
+pub struct OpReturnRequest {
+    pub data: Vec<String>,
+}
+
+#[derive(Serialize, Debug)]
+/// OpReturnResponse is the scriptPubKey of a zero value data output presented to user
+pub struct OpReturnResponse {
+    #[serde(flatten)]
+    pub script: DecodedScript,
+    /// Bytes of data pushed
+    pub data_size: usize,
+}
+
+/// Provably unspendable `OP_RETURN <data>...` output carrying data, such as a commitment to
+/// timestamp
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct OpReturn {
+    pushes: Vec<Vec<u8>>,
+}
+
+impl OpReturnRequest {
+    pub fn build(&self) -> Result<OpReturn, OpReturnError> {
+        self.data
+            .iter()
+            .try_fold(OpReturn::new(), |op_return, data| {
+                Ok(op_return.push(&hex::decode(data).map_err(|_| OpReturnError::InvalidHex)?))
+            })
+    }
+}
+
+impl OpReturn {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Push `data` after the previous pushes
+    pub fn push(mut self, data: &[u8]) -> Self {
+        self.pushes.push(data.to_vec());
+        self
+    }
+
+    /// Bytes of data pushed, without the push opcodes
+    pub fn data_size(&self) -> usize {
+        self.pushes.iter().map(Vec::len).sum()
+    }
+
+    /// `OP_RETURN` followed by a minimal push of every data element, at most 80 bytes of data in
+    /// a scriptPubKey of at most 83 bytes to be relayed
+    pub fn script(&self) -> Result<Vec<u8>, OpReturnError> {
+        let data_size = self.data_size();
+        if data_size > MAX_OP_RETURN_DATA {
+            return Err(OpReturnError::DataSize(data_size));
+        }
+        let script = self
+            .pushes
+            .iter()
+            .fold(
+                Builder::new().push_opcode(opcodes::OP_RETURN),
+                |builder, data| match data.as_slice() {
+                    [byte @ 1..=16] => builder.push_opcode(opcodes::OP_PUSHNUM_1 + byte - 1),
+                    [0x81] => builder.push_opcode(opcodes::OP_PUSHNUM_NEG1),
+                    data => builder.push_slice(data),
+                },
+            )
+            .into_script();
+        if script.len() > MAX_OP_RETURN_RELAY {
+            return Err(OpReturnError::ScriptSize(script.len()));
+        }
+        Ok(script)
+    }
+
+    /// The zero value output, burning no coins
+    pub fn output(&self) -> Result<TxOut, OpReturnError> {
+        Ok(TxOut {
+            value: 0,
+            script_pubkey: self.script()?,
+        })
+    }
+
+    /// Finalize OpReturn and return as OpReturnResponse
+    pub fn finalize(self) -> Result<OpReturnResponse, OpReturnError> {
+        Ok(OpReturnResponse {
+            script: DecodedScript::new(&self.script()?),
+            data_size: self.data_size(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{decode::output_type, policy};
+
+    #[test]
+    fn test_push_encoding() {
+        let commitment = [0xab; 32];
+        let script = OpReturn::new().push(&commitment).script().unwrap();
+        assert_eq!(script[..2], [opcodes::OP_RETURN, opcodes::OP_PUSHBYTES_32]);
+        assert_eq!(script[2..], commitment);
+        assert_eq!(output_type(&script), "op_return");
+
+        let script = OpReturn::new().push(&[0x80; 80]).script().unwrap();
+        assert_eq!(script.len(), MAX_OP_RETURN_RELAY);
+        assert_eq!(script[..3], [opcodes::OP_RETURN, opcodes::OP_PUSHDATA1, 80]);
+
+        let script = OpReturn::new()
+            .push(&[])
+            .push(&[7])
+            .push(&[0x81])
+            .push(b"ts")
+            .script()
+            .unwrap();
+        assert_eq!(
+            script,
+            [
+                opcodes::OP_RETURN,
+                opcodes::OP_PUSHBYTES_0,
+                opcodes::OP_PUSHNUM_7,
+                opcodes::OP_PUSHNUM_NEG1,
+                opcodes::OP_PUSHBYTES_2,
+                b't',
+                b's'
+            ]
+        );
+        assert!(policy::is_standard_script_pubkey(&script));
+    }
+
+    #[test]
+    fn test_size_limits() {
+        assert_eq!(
+            OpReturn::new().push(&[0; 81]).script(),
+            Err(OpReturnError::DataSize(81))
+        );
+        // 80 bytes of data in 4 pushes of 20 need 4 push opcodes instead of one OP_PUSHDATA1
+        let op_return = (0..4).fold(OpReturn::new(), |op_return, _| op_return.push(&[0; 20]));
+        assert_eq!(op_return.data_size(), 80);
+        assert_eq!(op_return.script(), Err(OpReturnError::ScriptSize(85)));
+
+        let request: OpReturnRequest =
+            serde_json::from_str(r#"{"data": ["6f7473", "zz"]}"#).unwrap();
+        assert_eq!(request.build(), Err(OpReturnError::InvalidHex));
+        let request: OpReturnRequest =
+            serde_json::from_str(r#"{"data": ["6f7473", "0102"]}"#).unwrap();
+        let response = request.build().unwrap().finalize().unwrap();
+        assert_eq!(response.script.hex, "6a036f7473020102");
+        assert_eq!(response.data_size, 5);
+    }
+}
@@ -0,0 +1,379 @@
+use crate::{
+    address::ScriptType,
+    error::PolicyError,
+    estimator::output_weight,
+    interpreter::{is_op_success, is_p2sh, witness_program},
+    opcodes::*,
+    script::{
+        instructions, is_push_only, parse_multisig, Instruction, MAX_PUBKEYS_PER_MULTISIG,
+        MAX_SCRIPT_ELEMENT_SIZE,
+    },
+    taproot::TAPROOT_LEAF_TAPSCRIPT,
+    transaction::{sum_amounts, Transaction, TxOut},
+};
+
+/// Maximum standard size of a P2WSH witness script
+pub const MAX_STANDARD_P2WSH_SCRIPT_SIZE: usize = 3600;
+/// Maximum number of standard P2WSH witness stack items, not counting the witness script
+pub const MAX_STANDARD_P2WSH_STACK_ITEMS: usize = 100;
+/// Maximum number of sigops of a standard P2SH redeem script
+pub const MAX_P2SH_SIGOPS: usize = 15;
+/// Maximum number of keys of a P2SH multisig, the most a 520 byte redeem script fits
+pub const MAX_P2SH_MULTISIG_KEYS: usize = 15;
+/// Maximum number of keys of a standard bare multisig output
+pub const MAX_BARE_MULTISIG_KEYS: usize = 3;
+/// Maximum standard size of a scriptSig, enough for a 15-of-15 P2SH multisig
+pub const MAX_STANDARD_SCRIPTSIG_SIZE: usize = 1650;
+/// Maximum standard weight of a transaction
+pub const MAX_STANDARD_TX_WEIGHT: usize = 400_000;
+/// Maximum standard size of an `OP_RETURN` scriptPubKey, as the default `-datacarriersize`
+pub const MAX_OP_RETURN_RELAY: usize = 83;
+/// Minimum standard size of a transaction without its witnesses, so it cannot be a 64 byte node
+pub const MIN_STANDARD_TX_NONWITNESS_SIZE: usize = 65;
+/// Highest standard transaction version
+pub const TX_MAX_STANDARD_VERSION: i32 = 3;
+/// Fee rate in sat/vB of the dust threshold, as the default `-dustrelayfee` of Bitcoin Core
+pub const DUST_RELAY_FEE_RATE: u64 = 3;
+
+/// Smallest value of an output to `script_pubkey` worth more than the fee of spending it
+pub fn dust_threshold(script_pubkey: &[u8]) -> u64 {
+    if script_pubkey.first() == Some(&OP_RETURN) {
+        return 0;
+    }
+    let spend_size = match witness_program(script_pubkey) {
+        Some(_) => 32 + 4 + 1 + 107 / 4 + 4,
+        None => 32 + 4 + 1 + 107 + 4,
+    };
+    (output_weight(script_pubkey.len()) as u64 / 4 + spend_size) * DUST_RELAY_FEE_RATE
+}
+
+/// Signature operations of `script`, counting `OP_CHECKMULTISIG` as its number of keys when
+/// `accurate`, as for redeem and witness scripts, or as 20 otherwise
+pub fn sigop_count(script: &[u8], accurate: bool) -> usize {
+    let mut count = 0;
+    let mut last = None;
+    for instruction in instructions(script) {
+        let instruction = match instruction {
+            Ok(instruction) => instruction,
+            Err(_) => break,
+        };
+        if let Instruction::Op(op) = instruction {
+            match op {
+                OP_CHECKSIG | OP_CHECKSIGVERIFY => count += 1,
+                OP_CHECKMULTISIG | OP_CHECKMULTISIGVERIFY => {
+                    count += match last {
+                        Some(n @ OP_PUSHNUM_1..=OP_PUSHNUM_16) if accurate => {
+                            (n - OP_PUSHNUM_1 + 1) as usize
+                        }
+                        _ => MAX_PUBKEYS_PER_MULTISIG,
+                    }
+                }
+                _ => {}
+            }
+        }
+        last = match instruction {
+            Instruction::Op(op) => Some(op),
+            Instruction::PushBytes(_) => None,
+        };
+    }
+    count
+}
+
+/// Check that the P2SH `redeem_script` can be pushed by a standard scriptSig and relayed
+pub fn check_redeem_script(redeem_script: &[u8]) -> Result<(), PolicyError> {
+    if let Some((_, keys)) = parse_multisig(redeem_script) {
+        if keys.len() > MAX_P2SH_MULTISIG_KEYS {
+            return Err(PolicyError::TooManyKeys {
+                keys: keys.len(),
+                limit: MAX_P2SH_MULTISIG_KEYS,
+            });
+        }
+    }
+    if redeem_script.len() > MAX_SCRIPT_ELEMENT_SIZE {
+        return Err(PolicyError::RedeemScriptSize(redeem_script.len()));
+    }
+    let sigops = sigop_count(redeem_script, true);
+    if sigops > MAX_P2SH_SIGOPS {
+        return Err(PolicyError::TooManySigops(sigops));
+    }
+    Ok(())
+}
+
+/// Check that the P2WSH `witness_script` is within the standard size and multisig limits
+pub fn check_witness_script(witness_script: &[u8]) -> Result<(), PolicyError> {
+    if let Some((_, keys)) = parse_multisig(witness_script) {
+        if keys.len() > MAX_PUBKEYS_PER_MULTISIG {
+            return Err(PolicyError::TooManyKeys {
+                keys: keys.len(),
+                limit: MAX_PUBKEYS_PER_MULTISIG,
+            });
+        }
+    }
+    if witness_script.len() > MAX_STANDARD_P2WSH_SCRIPT_SIZE {
+        return Err(PolicyError::WitnessScriptSize(witness_script.len()));
+    }
+    Ok(())
+}
+
+/// Check `script` wrapped as `script_type`; P2SH-P2WSH only pushes the witness program
+pub fn check_script(script: &[u8], script_type: ScriptType) -> Result<(), PolicyError> {
+    match script_type {
+        ScriptType::P2sh => check_redeem_script(script),
+        ScriptType::P2wsh | ScriptType::P2shP2wsh => check_witness_script(script),
+    }
+}
+
+/// Check the taproot leaf `script` is a BIP342 tapscript nodes relay spends of, without the
+/// leaf versions and `OP_SUCCESS` opcodes reserved for soft forks
+pub fn check_tap_leaf(script: &[u8], leaf_version: u8) -> Result<(), PolicyError> {
+    if leaf_version != TAPROOT_LEAF_TAPSCRIPT {
+        return Err(PolicyError::LeafVersion(leaf_version));
+    }
+    for instruction in instructions(script) {
+        match instruction.map_err(|_| PolicyError::InvalidTapscript)? {
+            Instruction::Op(op) if is_op_success(op) => return Err(PolicyError::OpSuccess(op)),
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+/// Check an address pays to `script_pubkey`, one of the output templates relayed by default
+pub fn check_script_pubkey(script_pubkey: &[u8]) -> Result<(), PolicyError> {
+    if !is_standard_script_pubkey(script_pubkey) {
+        return Err(PolicyError::NonStandardScriptPubkey);
+    }
+    Ok(())
+}
+
+/// Whether `script_pubkey` is one of the output templates relayed by default
+pub fn is_standard_script_pubkey(script_pubkey: &[u8]) -> bool {
+    if let Some((version, program)) = witness_program(script_pubkey) {
+        return version != 0 || program.len() == 20 || program.len() == 32;
+    }
+    if is_p2sh(script_pubkey) || is_p2pkh(script_pubkey) {
+        return true;
+    }
+    if script_pubkey.first() == Some(&OP_RETURN) {
+        return script_pubkey.len() <= MAX_OP_RETURN_RELAY && is_push_only(&script_pubkey[1..]);
+    }
+    match parse_multisig(script_pubkey) {
+        Some((m, keys)) => {
+            m > 0
+                && keys.len() <= MAX_BARE_MULTISIG_KEYS
+                && keys.iter().all(|key| key.len() == 33 || key.len() == 65)
+        }
+        None => false,
+    }
+}
+
+/// Check the `index`th output pays to a standard script and is not dust
+pub fn check_output(index: usize, output: &TxOut) -> Result<(), PolicyError> {
+    let script_pubkey = &output.script_pubkey;
+    if script_pubkey.first() == Some(&OP_RETURN) {
+        if !is_standard_script_pubkey(script_pubkey) {
+            return Err(PolicyError::OpReturnSize(index));
+        }
+        return Ok(());
+    }
+    if !is_standard_script_pubkey(script_pubkey) {
+        return Err(PolicyError::NonStandardOutput(index));
+    }
+    if output.value < dust_threshold(script_pubkey) {
+        return Err(PolicyError::Dust(index));
+    }
+    Ok(())
+}
+
+/// Check `tx` against the standardness rules nodes apply before relaying it
+pub fn check_transaction(tx: &Transaction) -> Result<(), PolicyError> {
+    if tx.version < 1 || tx.version > TX_MAX_STANDARD_VERSION {
+        return Err(PolicyError::Version(tx.version));
+    }
+    let weight = tx.weight();
+    if weight > MAX_STANDARD_TX_WEIGHT {
+        return Err(PolicyError::Weight(weight));
+    }
+    if tx.serialize_without_witness().len() < MIN_STANDARD_TX_NONWITNESS_SIZE {
+        return Err(PolicyError::TooSmall);
+    }
+    for (index, input) in tx.input.iter().enumerate() {
+        if input.script_sig.len() > MAX_STANDARD_SCRIPTSIG_SIZE {
+            return Err(PolicyError::ScriptSigSize(index));
+        }
+        if !is_push_only(&input.script_sig) {
+            return Err(PolicyError::ScriptSigNotPushOnly(index));
+        }
+    }
+    sum_amounts(tx.output.iter().map(|output| output.value))
+        .ok_or(PolicyError::AmountOutOfRange)?;
+    let mut op_returns = 0;
+    for (index, output) in tx.output.iter().enumerate() {
+        check_output(index, output)?;
+        if output.script_pubkey.first() == Some(&OP_RETURN) {
+            op_returns += 1;
+        }
+    }
+    if op_returns > 1 {
+        return Err(PolicyError::MultipleOpReturn);
+    }
+    Ok(())
+}
+
+fn is_p2pkh(script_pubkey: &[u8]) -> bool {
+    script_pubkey.len() == 25
+        && script_pubkey[..3] == [OP_DUP, OP_HASH160, OP_PUSHBYTES_20]
+        && script_pubkey[23..] == [OP_EQUALVERIFY, OP_CHECKSIG]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        script::Builder,
+        transaction::{OutPoint, TxIn, Txid, MAX_MONEY},
+    };
+
+    fn multisig(m: usize, n: usize) -> Vec<u8> {
+        let mut builder = Builder::new().push_int(m as i64);
+        for i in 0..n {
+            builder = builder.push_slice(&[0x02 + (i % 2) as u8; 33]);
+        }
+        builder
+            .push_int(n as i64)
+            .push_opcode(OP_CHECKMULTISIG)
+            .into_script()
+    }
+
+    fn transaction(output: Vec<TxOut>) -> Transaction {
+        Transaction {
+            version: 2,
+            input: vec![TxIn::new(OutPoint::new(Txid([1; 32]), 0), 0xffff_fffd)],
+            output,
+            lock_time: 0,
+        }
+    }
+
+    #[test]
+    fn test_multisig_limits() {
+        assert_eq!(sigop_count(&multisig(2, 3), true), 3);
+        assert_eq!(sigop_count(&multisig(2, 3), false), 20);
+        assert!(check_redeem_script(&multisig(15, 15)).is_ok());
+        assert_eq!(
+            check_redeem_script(&multisig(1, 16)),
+            Err(PolicyError::TooManyKeys {
+                keys: 16,
+                limit: 15
+            })
+        );
+        assert!(check_script(&multisig(1, 16), ScriptType::P2wsh).is_ok());
+        assert!(check_script(&multisig(1, 20), ScriptType::P2shP2wsh).is_ok());
+
+        // a 15 key multisig and a checksig fit 520 bytes but need 16 signature checks
+        let mut script = multisig(1, 15);
+        script.extend([OP_VERIFY, OP_CHECKSIG]);
+        assert_eq!(
+            check_redeem_script(&script),
+            Err(PolicyError::TooManySigops(16))
+        );
+
+        let bare = TxOut {
+            value: 1000,
+            script_pubkey: multisig(1, 3),
+        };
+        assert!(check_output(0, &bare).is_ok());
+        let bare = TxOut {
+            value: 1000,
+            script_pubkey: multisig(1, 4),
+        };
+        assert_eq!(
+            check_output(0, &bare),
+            Err(PolicyError::NonStandardOutput(0))
+        );
+    }
+
+    #[test]
+    fn test_tap_leaf() {
+        let script = Builder::new()
+            .push_slice(&[0x02; 32])
+            .push_opcode(OP_CHECKSIG)
+            .into_script();
+        assert!(check_tap_leaf(&script, TAPROOT_LEAF_TAPSCRIPT).is_ok());
+        assert_eq!(
+            check_tap_leaf(&script, 0xc2),
+            Err(PolicyError::LeafVersion(0xc2))
+        );
+        // OP_CAT is OP_SUCCESS126 in tapscript, even after an OP_RETURN
+        assert_eq!(
+            check_tap_leaf(&[OP_RETURN, 0x7e], TAPROOT_LEAF_TAPSCRIPT),
+            Err(PolicyError::OpSuccess(0x7e))
+        );
+        assert_eq!(
+            check_tap_leaf(&[OP_PUSHDATA1], TAPROOT_LEAF_TAPSCRIPT),
+            Err(PolicyError::InvalidTapscript)
+        );
+        assert!(check_script_pubkey(&script).is_err());
+    }
+
+    #[test]
+    fn test_transaction() {
+        let p2wpkh = [&[OP_PUSHBYTES_0, OP_PUSHBYTES_20][..], &[7; 20]].concat();
+        let payment = TxOut {
+            value: 294,
+            script_pubkey: p2wpkh.clone(),
+        };
+        let op_return = |len: usize| TxOut {
+            value: 0,
+            script_pubkey: Builder::new()
+                .push_opcode(OP_RETURN)
+                .push_slice(&vec![0xab; len])
+                .into_script(),
+        };
+        assert_eq!(dust_threshold(&p2wpkh), 294);
+        assert!(check_transaction(&transaction(vec![payment.clone(), op_return(80)])).is_ok());
+        assert_eq!(
+            check_transaction(&transaction(vec![payment.clone(), op_return(81)])),
+            Err(PolicyError::OpReturnSize(1))
+        );
+        assert_eq!(
+            check_transaction(&transaction(vec![
+                payment.clone(),
+                op_return(4),
+                op_return(4)
+            ])),
+            Err(PolicyError::MultipleOpReturn)
+        );
+        let dust = TxOut {
+            value: 293,
+            ..payment.clone()
+        };
+        assert_eq!(
+            check_transaction(&transaction(vec![dust])),
+            Err(PolicyError::Dust(0))
+        );
+
+        let all_coins = TxOut {
+            value: MAX_MONEY,
+            ..payment.clone()
+        };
+        assert!(check_transaction(&transaction(vec![all_coins.clone()])).is_ok());
+        assert_eq!(
+            check_transaction(&transaction(vec![all_coins, payment.clone()])),
+            Err(PolicyError::AmountOutOfRange)
+        );
+
+        let mut tx = transaction(vec![payment.clone()]);
+        tx.version = 4;
+        assert_eq!(check_transaction(&tx), Err(PolicyError::Version(4)));
+        let mut tx = transaction(vec![payment]);
+        tx.input[0].script_sig = vec![OP_NOP];
+        assert_eq!(
+            check_transaction(&tx),
+            Err(PolicyError::ScriptSigNotPushOnly(0))
+        );
+        assert_eq!(
+            check_transaction(&transaction(vec![op_return(0)])),
+            Err(PolicyError::TooSmall)
+        );
+    }
+}
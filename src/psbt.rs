@@ -0,0 +1,973 @@
+use crate::{
+    address::{Address, Network},
+    bip32::{self, ChildNumber, DerivationPath},
+    decode::{self, DecodedScript, DecodedTransaction},
+    error::{PsbtError, TransactionError},
+    policy,
+    script::push_compact_size,
+    transaction::{
+        OutPoint, Reader, Transaction, TxIn, TxOut, Txid, Witness, SEQUENCE_MAX_NON_RBF,
+    },
+};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fmt::Display;
+use std::str::FromStr;
+
+/// Bytes every PSBT starts with, "psbt" and a separator
+pub const PSBT_MAGIC: [u8; 5] = *b"psbt\xff";
+
+const PSBT_GLOBAL_UNSIGNED_TX: u8 = 0x00;
+const PSBT_GLOBAL_XPUB: u8 = 0x01;
+const PSBT_GLOBAL_VERSION: u8 = 0xfb;
+const PSBT_IN_NON_WITNESS_UTXO: u8 = 0x00;
+const PSBT_IN_WITNESS_UTXO: u8 = 0x01;
+const PSBT_IN_PARTIAL_SIG: u8 = 0x02;
+const PSBT_IN_SIGHASH_TYPE: u8 = 0x03;
+const PSBT_IN_REDEEM_SCRIPT: u8 = 0x04;
+const PSBT_IN_WITNESS_SCRIPT: u8 = 0x05;
+const PSBT_IN_BIP32_DERIVATION: u8 = 0x06;
+const PSBT_IN_FINAL_SCRIPTSIG: u8 = 0x07;
+const PSBT_IN_FINAL_SCRIPTWITNESS: u8 = 0x08;
+const PSBT_IN_TAP_KEY_SIG: u8 = 0x13;
+const PSBT_IN_TAP_SCRIPT_SIG: u8 = 0x14;
+const PSBT_IN_TAP_BIP32_DERIVATION: u8 = 0x16;
+const PSBT_IN_TAP_INTERNAL_KEY: u8 = 0x17;
+const PSBT_IN_TAP_MERKLE_ROOT: u8 = 0x18;
+const PSBT_OUT_REDEEM_SCRIPT: u8 = 0x00;
+const PSBT_OUT_WITNESS_SCRIPT: u8 = 0x01;
+const PSBT_OUT_BIP32_DERIVATION: u8 = 0x02;
+
+/// Length of a serialized extended public key, the key data of global xpubs
+const XPUB_LEN: usize = 78;
+
+/// Raw key-value pairs of a map, keys include their type byte
+type Map = BTreeMap<Vec<u8>, Vec<u8>>;
+
+/// Fingerprint of the master key and derivation path of a key
+#[derive(Debug, Clone, PartialEq)]
+pub struct KeySource {
+    pub fingerprint: [u8; 4],
+    pub path: DerivationPath,
+}
+
+impl KeySource {
+    fn serialize(&self) -> Vec<u8> {
+        let mut data = self.fingerprint.to_vec();
+        for child in &self.path.0 {
+            data.extend(child.index().to_le_bytes());
+        }
+        data
+    }
+
+    fn deserialize(value: &[u8], key_type: u8) -> Result<Self, PsbtError> {
+        if value.len() < 4 || !value.len().is_multiple_of(4) {
+            return Err(PsbtError::InvalidValue(key_type));
+        }
+        let mut fingerprint = [0u8; 4];
+        fingerprint.copy_from_slice(&value[..4]);
+        let path = value[4..]
+            .chunks(4)
+            .map(|index| {
+                ChildNumber::from(u32::from_le_bytes([index[0], index[1], index[2], index[3]]))
+            })
+            .collect();
+        Ok(Self {
+            fingerprint,
+            path: DerivationPath(path),
+        })
+    }
+}
+
+/// Source of a taproot key and the hashes of the leaves it appears in (BIP371)
+#[derive(Debug, Clone, PartialEq)]
+pub struct TapKeySource {
+    pub leaf_hashes: Vec<[u8; 32]>,
+    pub source: KeySource,
+}
+
+impl TapKeySource {
+    fn serialize(&self) -> Vec<u8> {
+        let mut data = Vec::new();
+        push_compact_size(&mut data, self.leaf_hashes.len());
+        for leaf_hash in &self.leaf_hashes {
+            data.extend(leaf_hash);
+        }
+        data.extend(self.source.serialize());
+        data
+    }
+
+    fn deserialize(value: &[u8], key_type: u8) -> Result<Self, PsbtError> {
+        let mut reader = Reader::new(value);
+        let leaf_hashes = (0..reader.compact_size()?)
+            .map(|_| reader.array())
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(Self {
+            leaf_hashes,
+            source: KeySource::deserialize(reader.bytes(reader.len())?, key_type)?,
+        })
+    }
+}
+
+/// Per input map
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct PsbtInput {
+    /// Transaction of the spent output, needed to sign legacy inputs
+    pub non_witness_utxo: Option<Transaction>,
+    /// Spent output, enough to sign segwit inputs
+    pub witness_utxo: Option<TxOut>,
+    /// Signatures by public key
+    pub partial_sigs: BTreeMap<Vec<u8>, Vec<u8>>,
+    pub sighash_type: Option<u32>,
+    pub redeem_script: Option<Vec<u8>>,
+    pub witness_script: Option<Vec<u8>>,
+    pub bip32_derivation: BTreeMap<Vec<u8>, KeySource>,
+    pub final_script_sig: Option<Vec<u8>>,
+    pub final_script_witness: Option<Witness>,
+    /// Taproot key path signature
+    pub tap_key_sig: Option<Vec<u8>>,
+    /// Tapscript signatures by x-only public key and leaf hash
+    pub tap_script_sigs: BTreeMap<([u8; 32], [u8; 32]), Vec<u8>>,
+    pub tap_bip32_derivation: BTreeMap<[u8; 32], TapKeySource>,
+    pub tap_internal_key: Option<[u8; 32]>,
+    pub tap_merkle_root: Option<[u8; 32]>,
+    pub unknown: Map,
+}
+
+/// Per output map
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct PsbtOutput {
+    pub redeem_script: Option<Vec<u8>>,
+    pub witness_script: Option<Vec<u8>>,
+    pub bip32_derivation: BTreeMap<Vec<u8>, KeySource>,
+    pub unknown: Map,
+}
+
+/// Partially signed bitcoin transaction, version 0 (BIP174)
+#[derive(Debug, Clone, PartialEq)]
+pub struct Psbt {
+    pub unsigned_tx: Transaction,
+    /// Serialized extended public keys and their source
+    pub xpubs: BTreeMap<Vec<u8>, KeySource>,
+    pub version: Option<u32>,
+    pub unknown: Map,
+    pub inputs: Vec<PsbtInput>,
+    pub outputs: Vec<PsbtOutput>,
+}
+
+fn push_pair(data: &mut Vec<u8>, key_type: u8, key_data: &[u8], value: &[u8]) {
+    push_compact_size(data, key_data.len() + 1);
+    data.push(key_type);
+    data.extend(key_data);
+    push_compact_size(data, value.len());
+    data.extend(value);
+}
+
+fn push_unknown(data: &mut Vec<u8>, unknown: &Map) {
+    for (key, value) in unknown {
+        push_compact_size(data, key.len());
+        data.extend(key);
+        push_compact_size(data, value.len());
+        data.extend(value);
+    }
+}
+
+/// Read key-value pairs up to the separator, rejecting repeated keys
+fn read_map(reader: &mut Reader) -> Result<Map, PsbtError> {
+    let mut map = Map::new();
+    loop {
+        let key = reader.var_bytes()?;
+        if key.is_empty() {
+            return Ok(map);
+        }
+        let value = reader.var_bytes()?;
+        if map.insert(key.clone(), value).is_some() {
+            return Err(PsbtError::DuplicateKey(hex::encode(key)));
+        }
+    }
+}
+
+/// Keys of fields which are not per public key have no key data
+fn check_no_key_data(key_type: u8, key_data: &[u8]) -> Result<(), PsbtError> {
+    if key_data.is_empty() {
+        Ok(())
+    } else {
+        Err(PsbtError::InvalidKey(key_type))
+    }
+}
+
+fn check_public_key(key_type: u8, key_data: &[u8]) -> Result<(), PsbtError> {
+    match key_data.len() {
+        33 | 65 => Ok(()),
+        _ => Err(PsbtError::InvalidKey(key_type)),
+    }
+}
+
+fn read_hash(value: &[u8], key_type: u8) -> Result<[u8; 32], PsbtError> {
+    let mut hash = [0u8; 32];
+    if value.len() != hash.len() {
+        return Err(PsbtError::InvalidValue(key_type));
+    }
+    hash.copy_from_slice(value);
+    Ok(hash)
+}
+
+fn read_witness(value: &[u8], key_type: u8) -> Result<Witness, PsbtError> {
+    let mut reader = Reader::new(value);
+    let witness = reader.witness()?;
+    if !reader.is_empty() {
+        return Err(PsbtError::InvalidValue(key_type));
+    }
+    Ok(witness)
+}
+
+impl PsbtInput {
+    fn serialize(&self, data: &mut Vec<u8>) {
+        if let Some(tx) = &self.non_witness_utxo {
+            push_pair(data, PSBT_IN_NON_WITNESS_UTXO, &[], &tx.serialize());
+        }
+        if let Some(output) = &self.witness_utxo {
+            push_pair(data, PSBT_IN_WITNESS_UTXO, &[], &output.serialize());
+        }
+        for (public_key, signature) in &self.partial_sigs {
+            push_pair(data, PSBT_IN_PARTIAL_SIG, public_key, signature);
+        }
+        if let Some(sighash_type) = self.sighash_type {
+            push_pair(data, PSBT_IN_SIGHASH_TYPE, &[], &sighash_type.to_le_bytes());
+        }
+        if let Some(script) = &self.redeem_script {
+            push_pair(data, PSBT_IN_REDEEM_SCRIPT, &[], script);
+        }
+        if let Some(script) = &self.witness_script {
+            push_pair(data, PSBT_IN_WITNESS_SCRIPT, &[], script);
+        }
+        for (public_key, source) in &self.bip32_derivation {
+            push_pair(
+                data,
+                PSBT_IN_BIP32_DERIVATION,
+                public_key,
+                &source.serialize(),
+            );
+        }
+        if let Some(script) = &self.final_script_sig {
+            push_pair(data, PSBT_IN_FINAL_SCRIPTSIG, &[], script);
+        }
+        if let Some(witness) = &self.final_script_witness {
+            push_pair(data, PSBT_IN_FINAL_SCRIPTWITNESS, &[], &witness.serialize());
+        }
+        if let Some(signature) = &self.tap_key_sig {
+            push_pair(data, PSBT_IN_TAP_KEY_SIG, &[], signature);
+        }
+        for ((public_key, leaf_hash), signature) in &self.tap_script_sigs {
+            let key_data = [&public_key[..], &leaf_hash[..]].concat();
+            push_pair(data, PSBT_IN_TAP_SCRIPT_SIG, &key_data, signature);
+        }
+        for (public_key, source) in &self.tap_bip32_derivation {
+            push_pair(
+                data,
+                PSBT_IN_TAP_BIP32_DERIVATION,
+                public_key,
+                &source.serialize(),
+            );
+        }
+        if let Some(internal_key) = &self.tap_internal_key {
+            push_pair(data, PSBT_IN_TAP_INTERNAL_KEY, &[], internal_key);
+        }
+        if let Some(merkle_root) = &self.tap_merkle_root {
+            push_pair(data, PSBT_IN_TAP_MERKLE_ROOT, &[], merkle_root);
+        }
+        push_unknown(data, &self.unknown);
+        data.push(0x00);
+    }
+
+    fn deserialize(reader: &mut Reader) -> Result<Self, PsbtError> {
+        let mut input = Self::default();
+        for (key, value) in read_map(reader)? {
+            let (key_type, key_data) = (key[0], &key[1..]);
+            match key_type {
+                PSBT_IN_NON_WITNESS_UTXO => {
+                    check_no_key_data(key_type, key_data)?;
+                    input.non_witness_utxo = Some(Transaction::deserialize(&value)?);
+                }
+                PSBT_IN_WITNESS_UTXO => {
+                    check_no_key_data(key_type, key_data)?;
+                    let mut reader = Reader::new(&value);
+                    input.witness_utxo = Some(reader.tx_out()?);
+                    if !reader.is_empty() {
+                        return Err(PsbtError::InvalidValue(key_type));
+                    }
+                }
+                PSBT_IN_PARTIAL_SIG => {
+                    check_public_key(key_type, key_data)?;
+                    input.partial_sigs.insert(key_data.to_vec(), value);
+                }
+                PSBT_IN_SIGHASH_TYPE => {
+                    check_no_key_data(key_type, key_data)?;
+                    let mut reader = Reader::new(&value);
+                    input.sighash_type = Some(reader.u32()?);
+                    if !reader.is_empty() {
+                        return Err(PsbtError::InvalidValue(key_type));
+                    }
+                }
+                PSBT_IN_REDEEM_SCRIPT => {
+                    check_no_key_data(key_type, key_data)?;
+                    input.redeem_script = Some(value);
+                }
+                PSBT_IN_WITNESS_SCRIPT => {
+                    check_no_key_data(key_type, key_data)?;
+                    input.witness_script = Some(value);
+                }
+                PSBT_IN_BIP32_DERIVATION => {
+                    check_public_key(key_type, key_data)?;
+                    input
+                        .bip32_derivation
+                        .insert(key_data.to_vec(), KeySource::deserialize(&value, key_type)?);
+                }
+                PSBT_IN_FINAL_SCRIPTSIG => {
+                    check_no_key_data(key_type, key_data)?;
+                    input.final_script_sig = Some(value);
+                }
+                PSBT_IN_FINAL_SCRIPTWITNESS => {
+                    check_no_key_data(key_type, key_data)?;
+                    input.final_script_witness = Some(read_witness(&value, key_type)?);
+                }
+                PSBT_IN_TAP_KEY_SIG => {
+                    check_no_key_data(key_type, key_data)?;
+                    if !matches!(value.len(), 64 | 65) {
+                        return Err(PsbtError::InvalidValue(key_type));
+                    }
+                    input.tap_key_sig = Some(value);
+                }
+                PSBT_IN_TAP_SCRIPT_SIG => {
+                    if key_data.len() != 64 {
+                        return Err(PsbtError::InvalidKey(key_type));
+                    }
+                    if !matches!(value.len(), 64 | 65) {
+                        return Err(PsbtError::InvalidValue(key_type));
+                    }
+                    let public_key = read_hash(&key_data[..32], key_type)?;
+                    let leaf_hash = read_hash(&key_data[32..], key_type)?;
+                    input.tap_script_sigs.insert((public_key, leaf_hash), value);
+                }
+                PSBT_IN_TAP_BIP32_DERIVATION => {
+                    let public_key = read_hash(key_data, key_type)
+                        .map_err(|_| PsbtError::InvalidKey(key_type))?;
+                    input
+                        .tap_bip32_derivation
+                        .insert(public_key, TapKeySource::deserialize(&value, key_type)?);
+                }
+                PSBT_IN_TAP_INTERNAL_KEY => {
+                    check_no_key_data(key_type, key_data)?;
+                    input.tap_internal_key = Some(read_hash(&value, key_type)?);
+                }
+                PSBT_IN_TAP_MERKLE_ROOT => {
+                    check_no_key_data(key_type, key_data)?;
+                    input.tap_merkle_root = Some(read_hash(&value, key_type)?);
+                }
+                _ => {
+                    input.unknown.insert(key, value);
+                }
+            }
+        }
+        Ok(input)
+    }
+}
+
+impl PsbtOutput {
+    fn serialize(&self, data: &mut Vec<u8>) {
+        if let Some(script) = &self.redeem_script {
+            push_pair(data, PSBT_OUT_REDEEM_SCRIPT, &[], script);
+        }
+        if let Some(script) = &self.witness_script {
+            push_pair(data, PSBT_OUT_WITNESS_SCRIPT, &[], script);
+        }
+        for (public_key, source) in &self.bip32_derivation {
+            push_pair(
+                data,
+                PSBT_OUT_BIP32_DERIVATION,
+                public_key,
+                &source.serialize(),
+            );
+        }
+        push_unknown(data, &self.unknown);
+        data.push(0x00);
+    }
+
+    fn deserialize(reader: &mut Reader) -> Result<Self, PsbtError> {
+        let mut output = Self::default();
+        for (key, value) in read_map(reader)? {
+            let (key_type, key_data) = (key[0], &key[1..]);
+            match key_type {
+                PSBT_OUT_REDEEM_SCRIPT => {
+                    check_no_key_data(key_type, key_data)?;
+                    output.redeem_script = Some(value);
+                }
+                PSBT_OUT_WITNESS_SCRIPT => {
+                    check_no_key_data(key_type, key_data)?;
+                    output.witness_script = Some(value);
+                }
+                PSBT_OUT_BIP32_DERIVATION => {
+                    check_public_key(key_type, key_data)?;
+                    output
+                        .bip32_derivation
+                        .insert(key_data.to_vec(), KeySource::deserialize(&value, key_type)?);
+                }
+                _ => {
+                    output.unknown.insert(key, value);
+                }
+            }
+        }
+        Ok(output)
+    }
+}
+
+impl Psbt {
+    /// Empty PSBT of `unsigned_tx`, which must have no scriptSig and no witness
+    pub fn from_unsigned_tx(unsigned_tx: Transaction) -> Result<Self, PsbtError> {
+        if unsigned_tx
+            .input
+            .iter()
+            .any(|input| !input.script_sig.is_empty() || !input.witness.is_empty())
+        {
+            return Err(PsbtError::SignedUnsignedTx);
+        }
+        Ok(Self {
+            inputs: vec![PsbtInput::default(); unsigned_tx.input.len()],
+            outputs: vec![PsbtOutput::default(); unsigned_tx.output.len()],
+            unsigned_tx,
+            xpubs: BTreeMap::new(),
+            version: None,
+            unknown: Map::new(),
+        })
+    }
+
+    pub fn serialize(&self) -> Vec<u8> {
+        let mut data = PSBT_MAGIC.to_vec();
+        push_pair(
+            &mut data,
+            PSBT_GLOBAL_UNSIGNED_TX,
+            &[],
+            &self.unsigned_tx.serialize_without_witness(),
+        );
+        for (xpub, source) in &self.xpubs {
+            push_pair(&mut data, PSBT_GLOBAL_XPUB, xpub, &source.serialize());
+        }
+        if let Some(version) = self.version {
+            push_pair(&mut data, PSBT_GLOBAL_VERSION, &[], &version.to_le_bytes());
+        }
+        push_unknown(&mut data, &self.unknown);
+        data.push(0x00);
+        for input in &self.inputs {
+            input.serialize(&mut data);
+        }
+        for output in &self.outputs {
+            output.serialize(&mut data);
+        }
+        data
+    }
+
+    pub fn deserialize(data: &[u8]) -> Result<Self, PsbtError> {
+        if !data.starts_with(&PSBT_MAGIC) {
+            return Err(PsbtError::InvalidMagic);
+        }
+        let mut reader = Reader::new(&data[PSBT_MAGIC.len()..]);
+        let mut unsigned_tx = None;
+        let mut xpubs = BTreeMap::new();
+        let mut version = None;
+        let mut unknown = Map::new();
+        for (key, value) in read_map(&mut reader)? {
+            let (key_type, key_data) = (key[0], &key[1..]);
+            match key_type {
+                PSBT_GLOBAL_UNSIGNED_TX => {
+                    check_no_key_data(key_type, key_data)?;
+                    unsigned_tx = Some(Transaction::deserialize(&value)?);
+                }
+                PSBT_GLOBAL_XPUB => {
+                    if key_data.len() != XPUB_LEN {
+                        return Err(PsbtError::InvalidKey(key_type));
+                    }
+                    xpubs.insert(key_data.to_vec(), KeySource::deserialize(&value, key_type)?);
+                }
+                PSBT_GLOBAL_VERSION => {
+                    check_no_key_data(key_type, key_data)?;
+                    let mut reader = Reader::new(&value);
+                    let number = reader.u32()?;
+                    if !reader.is_empty() {
+                        return Err(PsbtError::InvalidValue(key_type));
+                    }
+                    if number != 0 {
+                        return Err(PsbtError::UnsupportedVersion(number));
+                    }
+                    version = Some(number);
+                }
+                _ => {
+                    unknown.insert(key, value);
+                }
+            }
+        }
+        let unsigned_tx = unsigned_tx.ok_or(PsbtError::MissingUnsignedTx)?;
+        if unsigned_tx.has_witness() {
+            return Err(PsbtError::SignedUnsignedTx);
+        }
+        let mut psbt = Self::from_unsigned_tx(unsigned_tx)?;
+        psbt.xpubs = xpubs;
+        psbt.version = version;
+        psbt.unknown = unknown;
+        for (i, input) in psbt.inputs.iter_mut().enumerate() {
+            *input = PsbtInput::deserialize(&mut reader)?;
+            if let Some(tx) = &input.non_witness_utxo {
+                if tx.txid() != psbt.unsigned_tx.input[i].previous_output.txid {
+                    return Err(PsbtError::MismatchedUtxo(i));
+                }
+            }
+        }
+        for output in psbt.outputs.iter_mut() {
+            *output = PsbtOutput::deserialize(&mut reader)?;
+        }
+        if !reader.is_empty() {
+            return Err(TransactionError::TrailingData.into());
+        }
+        Ok(psbt)
+    }
+
+    /// Output spent by input `index`, from its witness or non-witness UTXO
+    pub fn spent_output(&self, index: usize) -> Option<&TxOut> {
+        let input = &self.inputs[index];
+        input.witness_utxo.as_ref().or_else(|| {
+            let vout = self.unsigned_tx.input[index].previous_output.vout;
+            input
+                .non_witness_utxo
+                .as_ref()
+                .and_then(|tx| tx.output.get(vout as usize))
+        })
+    }
+
+    /// Amounts spent by every input, if all of them are known
+    pub fn spent_amounts(&self) -> Option<Vec<u64>> {
+        (0..self.inputs.len())
+            .map(|index| self.spent_output(index).map(|output| output.value))
+            .collect()
+    }
+}
+
+impl Display for Psbt {
+    /// Base64 encoding
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{}", base64::encode(self.serialize()))
+    }
+}
+
+impl FromStr for Psbt {
+    type Err = PsbtError;
+
+    /// Parse base64 encoded PSBT
+    fn from_str(psbt: &str) -> Result<Self, Self::Err> {
+        Self::deserialize(&base64::decode(psbt.trim()).map_err(|_| PsbtError::InvalidBase64)?)
+    }
+}
+
+fn default_version() -> i32 {
+    2
+}
+
+fn default_sequence() -> u32 {
+    SEQUENCE_MAX_NON_RBF
+}
+
+#[derive(Deserialize, Debug)]
+// Get the inputs and outputs of a transaction to create a PSBT for
+pub struct PsbtCreateRequest {
+    pub inputs: Vec<PsbtInputRequest>,
+    pub outputs: Vec<PsbtOutputRequest>,
+    #[serde(default)]
+    pub locktime: u32,
+    #[serde(default = "default_version")]
+    pub version: i32,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct PsbtInputRequest {
+    pub txid: String,
+    pub vout: u32,
+    /// Defaults to opting out of replace-by-fee with the locktime enabled
+    #[serde(default = "default_sequence")]
+    pub sequence: u32,
+    /// Spent output of a segwit input
+    pub witness_utxo: Option<UtxoRequest>,
+    /// Hex encoded transaction of the spent output, for legacy inputs
+    pub non_witness_utxo: Option<String>,
+    pub redeem_script: Option<String>,
+    pub witness_script: Option<String>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct UtxoRequest {
+    pub amount: u64,
+    pub address: String,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct PsbtOutputRequest {
+    pub address: String,
+    pub amount: u64,
+}
+
+#[derive(Deserialize, Debug)]
+// Get a base64 encoded PSBT from user
+pub struct PsbtRequest {
+    pub psbt: String,
+    #[serde(default)]
+    pub network: Network,
+}
+
+#[derive(Serialize, Debug)]
+/// PsbtResponse is the base64 encoded PSBT presented to user
+pub struct PsbtResponse {
+    pub psbt: String,
+}
+
+pub(crate) fn script_from_hex(script: &Option<String>) -> Result<Option<Vec<u8>>, PsbtError> {
+    script
+        .as_ref()
+        .map(|script| hex::decode(script).map_err(|_| PsbtError::InvalidHex))
+        .transpose()
+}
+
+fn script_pubkey(address: &str) -> Result<Vec<u8>, PsbtError> {
+    Ok(Address::from_str(address)
+        .map_err(|_| PsbtError::InvalidAddress)?
+        .script_pubkey())
+}
+
+impl PsbtCreateRequest {
+    pub fn create(&self) -> Result<Psbt, PsbtError> {
+        let input = self
+            .inputs
+            .iter()
+            .map(|input| {
+                let txid = Txid::from_str(&input.txid)?;
+                Ok(TxIn::new(OutPoint::new(txid, input.vout), input.sequence))
+            })
+            .collect::<Result<Vec<_>, PsbtError>>()?;
+        let output = self
+            .outputs
+            .iter()
+            .map(|output| {
+                Ok(TxOut {
+                    value: output.amount,
+                    script_pubkey: script_pubkey(&output.address)?,
+                })
+            })
+            .collect::<Result<Vec<_>, PsbtError>>()?;
+        let mut psbt = Psbt::from_unsigned_tx(Transaction {
+            version: self.version,
+            input,
+            output,
+            lock_time: self.locktime,
+        })?;
+        for (i, request) in self.inputs.iter().enumerate() {
+            let input = &mut psbt.inputs[i];
+            if let Some(utxo) = &request.witness_utxo {
+                input.witness_utxo = Some(TxOut {
+                    value: utxo.amount,
+                    script_pubkey: script_pubkey(&utxo.address)?,
+                });
+            }
+            if let Some(tx) = &request.non_witness_utxo {
+                let tx = Transaction::from_str(tx)?;
+                if tx.txid() != psbt.unsigned_tx.input[i].previous_output.txid {
+                    return Err(PsbtError::MismatchedUtxo(i));
+                }
+                input.non_witness_utxo = Some(tx);
+            }
+            input.redeem_script = script_from_hex(&request.redeem_script)?;
+            input.witness_script = script_from_hex(&request.witness_script)?;
+        }
+        policy::check_transaction(&psbt.unsigned_tx)?;
+        Ok(psbt)
+    }
+}
+
+#[derive(Serialize, Debug)]
+/// DecodedPsbt is the readable form of a PSBT presented to user
+pub struct DecodedPsbt {
+    pub tx: DecodedTransaction,
+    pub xpubs: Vec<DecodedKey>,
+    pub version: u32,
+    pub inputs: Vec<DecodedPsbtInput>,
+    pub outputs: Vec<DecodedPsbtOutput>,
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub unknown: BTreeMap<String, String>,
+}
+
+#[derive(Serialize, Debug)]
+/// A public or extended public key with its source
+pub struct DecodedKey {
+    pub key: String,
+    pub fingerprint: String,
+    pub path: String,
+    /// Tapscript leaves a taproot key signs for
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub leaf_hashes: Vec<String>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct DecodedUtxo {
+    pub amount: u64,
+    pub script_pubkey: DecodedScript,
+    pub address: Option<String>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct DecodedPsbtInput {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub non_witness_utxo: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub utxo: Option<DecodedUtxo>,
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub partial_sigs: BTreeMap<String, String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sighash_type: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub redeem_script: Option<DecodedScript>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub witness_script: Option<DecodedScript>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub bip32_derivation: Vec<DecodedKey>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub final_script_sig: Option<DecodedScript>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub final_script_witness: Option<Vec<String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tap_key_sig: Option<String>,
+    /// Signatures by x-only public key followed by leaf hash
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub tap_script_sigs: BTreeMap<String, String>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub tap_bip32_derivation: Vec<DecodedKey>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tap_internal_key: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tap_merkle_root: Option<String>,
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub unknown: BTreeMap<String, String>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct DecodedPsbtOutput {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub redeem_script: Option<DecodedScript>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub witness_script: Option<DecodedScript>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub bip32_derivation: Vec<DecodedKey>,
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub unknown: BTreeMap<String, String>,
+}
+
+fn decode_keys(
+    keys: &BTreeMap<Vec<u8>, KeySource>,
+    encode: fn(&[u8]) -> String,
+) -> Vec<DecodedKey> {
+    keys.iter()
+        .map(|(key, source)| DecodedKey {
+            key: encode(key),
+            fingerprint: hex::encode(source.fingerprint),
+            path: source.path.to_string(),
+            leaf_hashes: Vec::new(),
+        })
+        .collect()
+}
+
+fn decode_tap_keys(keys: &BTreeMap<[u8; 32], TapKeySource>) -> Vec<DecodedKey> {
+    keys.iter()
+        .map(|(key, source)| DecodedKey {
+            key: hex::encode(key),
+            fingerprint: hex::encode(source.source.fingerprint),
+            path: source.source.path.to_string(),
+            leaf_hashes: source.leaf_hashes.iter().map(hex::encode).collect(),
+        })
+        .collect()
+}
+
+fn decode_map(map: &BTreeMap<Vec<u8>, Vec<u8>>) -> BTreeMap<String, String> {
+    map.iter()
+        .map(|(key, value)| (hex::encode(key), hex::encode(value)))
+        .collect()
+}
+
+fn decode_script(script: &Option<Vec<u8>>) -> Option<DecodedScript> {
+    script.as_deref().map(DecodedScript::new)
+}
+
+impl Psbt {
+    /// Readable form of the PSBT, with the fee when every spent amount is known
+    pub fn decode(&self, network: Network) -> Result<DecodedPsbt, PsbtError> {
+        let amounts = self.spent_amounts();
+        let tx = decode::decode(&self.unsigned_tx, amounts.as_deref(), network)?;
+        let inputs = self
+            .inputs
+            .iter()
+            .enumerate()
+            .map(|(index, input)| DecodedPsbtInput {
+                non_witness_utxo: input
+                    .non_witness_utxo
+                    .as_ref()
+                    .map(|tx| tx.txid().to_string()),
+                utxo: self.spent_output(index).map(|output| DecodedUtxo {
+                    amount: output.value,
+                    script_pubkey: DecodedScript::new(&output.script_pubkey),
+                    address: Address::from_script_pubkey(&output.script_pubkey, network)
+                        .map(|address| address.to_string()),
+                }),
+                partial_sigs: decode_map(&input.partial_sigs),
+                sighash_type: input.sighash_type,
+                redeem_script: decode_script(&input.redeem_script),
+                witness_script: decode_script(&input.witness_script),
+                bip32_derivation: decode_keys(&input.bip32_derivation, |key| hex::encode(key)),
+                final_script_sig: decode_script(&input.final_script_sig),
+                final_script_witness: input
+                    .final_script_witness
+                    .as_ref()
+                    .map(|witness| witness.0.iter().map(hex::encode).collect()),
+                tap_key_sig: input.tap_key_sig.as_ref().map(hex::encode),
+                tap_script_sigs: input
+                    .tap_script_sigs
+                    .iter()
+                    .map(|((key, leaf_hash), signature)| {
+                        (
+                            format!("{}{}", hex::encode(key), hex::encode(leaf_hash)),
+                            hex::encode(signature),
+                        )
+                    })
+                    .collect(),
+                tap_bip32_derivation: decode_tap_keys(&input.tap_bip32_derivation),
+                tap_internal_key: input.tap_internal_key.map(hex::encode),
+                tap_merkle_root: input.tap_merkle_root.map(hex::encode),
+                unknown: decode_map(&input.unknown),
+            })
+            .collect();
+        let outputs = self
+            .outputs
+            .iter()
+            .map(|output| DecodedPsbtOutput {
+                redeem_script: decode_script(&output.redeem_script),
+                witness_script: decode_script(&output.witness_script),
+                bip32_derivation: decode_keys(&output.bip32_derivation, |key| hex::encode(key)),
+                unknown: decode_map(&output.unknown),
+            })
+            .collect();
+        Ok(DecodedPsbt {
+            tx,
+            xpubs: decode_keys(&self.xpubs, bip32::base58check),
+            version: self.version.unwrap_or(0),
+            inputs,
+            outputs,
+            unknown: decode_map(&self.unknown),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // BIP174 test vectors
+    const P2PKH_AND_P2SH_P2WPKH: &str = "cHNidP8BAHUCAAAAASaBcTce3/KF6Tet7qSze3gADAVmy7OtZGQXE8pCFxv2AAAAAAD+////AtPf9QUAAAAAGXapFNDFmQPFusKGh2DpD9UhpGZap2UgiKwA4fUFAAAAABepFDVF5uM7gyxHBQ8k0+65PJwDlIvHh7MuEwAAAQD9pQEBAAAAAAECiaPHHqtNIOA3G7ukzGmPopXJRjr6Ljl/hTPMti+VZ+UBAAAAFxYAFL4Y0VKpsBIDna89p95PUzSe7LmF/////4b4qkOnHf8USIk6UwpyN+9rRgi7st0tAXHmOuxqSJC0AQAAABcWABT+Pp7xp0XpdNkCxDVZQ6vLNL1TU/////8CAMLrCwAAAAAZdqkUhc/xCX/Z4Ai7NK9wnGIZeziXikiIrHL++E4sAAAAF6kUM5cluiHv1irHU6m80GfWx6ajnQWHAkcwRAIgJxK+IuAnDzlPVoMR3HyppolwuAJf3TskAinwf4pfOiQCIAGLONfc0xTnNMkna9b7QPZzMlvEuqFEyADS8vAtsnZcASED0uFWdJQbrUqZY3LLh+GFbTZSYG2YVi/jnF6efkE/IQUCSDBFAiEA0SuFLYXc2WHS9fSrZgZU327tzHlMDDPOXMMJ/7X85Y0CIGczio4OFyXBl/saiK9Z9R5E5CVbIBZ8hoQDHAXR8lkqASECI7cr7vCWXRC+B3jv7NYfysb3mk6haTkzgHNEZPhPKrMAAAAAAAAA";
+    const P2SH_P2WSH_MULTISIG: &str = "cHNidP8BAFUCAAAAASeaIyOl37UfxF8iD6WLD8E+HjNCeSqF1+Ns1jM7XLw5AAAAAAD/////AaBa6gsAAAAAGXapFP/pwAYQl8w7Y28ssEYPpPxCfStFiKwAAAAAAAEBIJVe6gsAAAAAF6kUY0UgD2jRieGtwN8cTRbqjxTA2+uHIgIDsTQcy6doO2r08SOM1ul+cWfVafrEfx5I1HVBhENVvUZGMEMCIAQktY7/qqaU4VWepck7v9SokGQiQFXN8HC2dxRpRC0HAh9cjrD+plFtYLisszrWTt5g6Hhb+zqpS5m9+GFR25qaAQEEIgAgdx/RitRZZm3Unz1WTj28QvTIR3TjYK2haBao7UiNVoEBBUdSIQOxNBzLp2g7avTxI4zW6X5xZ9Vp+sR/HkjUdUGEQ1W9RiED3lXR4drIBeP4pYwfv5uUwC89uq/hJ/78pJlfJvggg71SriIGA7E0HMunaDtq9PEjjNbpfnFn1Wn6xH8eSNR1QYRDVb1GELSmumcAAACAAAAAgAQAAIAiBgPeVdHh2sgF4/iljB+/m5TALz26r+En/vykmV8m+CCDvRC0prpnAAAAgAAAAIAFAACAAAA=";
+
+    #[test]
+    fn test_vectors() {
+        let psbt = Psbt::from_str(P2PKH_AND_P2SH_P2WPKH).unwrap();
+        assert_eq!(psbt.to_string(), P2PKH_AND_P2SH_P2WPKH);
+        assert_eq!(psbt.inputs.len(), 1);
+        assert_eq!(psbt.outputs.len(), 2);
+        assert!(psbt.inputs[0].non_witness_utxo.is_some());
+        assert_eq!(psbt.spent_amounts(), Some(vec![200_000_000]));
+
+        let psbt = Psbt::from_str(P2SH_P2WSH_MULTISIG).unwrap();
+        assert_eq!(psbt.to_string(), P2SH_P2WSH_MULTISIG);
+        let input = &psbt.inputs[0];
+        assert_eq!(input.witness_utxo.as_ref().unwrap().value, 199_909_013);
+        assert_eq!(input.partial_sigs.len(), 1);
+        assert_eq!(
+            to_asm_of(&input.redeem_script),
+            "OP_0 771fd18ad459666dd49f3d564e3dbc42f4c84774e360ada16816a8ed488d5681"
+        );
+        assert!(to_asm_of(&input.witness_script).ends_with("OP_2 OP_CHECKMULTISIG"));
+        let sources: Vec<String> = input
+            .bip32_derivation
+            .values()
+            .map(|source| format!("{}{}", hex::encode(source.fingerprint), source.path))
+            .collect();
+        assert_eq!(sources, ["b4a6ba67m/0'/0'/4'", "b4a6ba67m/0'/0'/5'"]);
+
+        let decoded = psbt.decode(Network::Bitcoin).unwrap();
+        assert_eq!(decoded.tx.fee, Some(199_909_013 - 199_908_000));
+        assert_eq!(decoded.inputs[0].bip32_derivation.len(), 2);
+    }
+
+    fn to_asm_of(script: &Option<Vec<u8>>) -> String {
+        crate::script::to_asm(script.as_ref().unwrap())
+    }
+
+    #[test]
+    fn test_create() {
+        let request: PsbtCreateRequest = serde_json::from_str(
+            r#"{
+                "inputs": [{
+                    "txid": "f4184fc596403b9d638783cf57adfe4c75c605f6356fbc91338530e9831e9e16",
+                    "vout": 1,
+                    "witness_utxo": {"amount": 100000, "address": "bc1qw508d6qejxtdg4y5r3zarvary0c5xw7kv8f3t4"},
+                    "witness_script": "51"
+                }],
+                "outputs": [{"address": "1BgGZ9tcN4rm9KBzDn7KprQz87SZ26SAMH", "amount": 90000}]
+            }"#,
+        )
+        .unwrap();
+        let psbt = request.create().unwrap();
+        assert_eq!(psbt.unsigned_tx.version, 2);
+        assert_eq!(psbt.unsigned_tx.input[0].sequence, SEQUENCE_MAX_NON_RBF);
+        assert_eq!(psbt.inputs[0].witness_script, Some(vec![0x51]));
+        assert!(psbt.to_string().starts_with("cHNidP8"));
+        assert_eq!(Psbt::from_str(&psbt.to_string()).unwrap(), psbt);
+        assert_eq!(psbt.decode(Network::Bitcoin).unwrap().tx.fee, Some(10_000));
+    }
+
+    #[test]
+    fn test_invalid() {
+        let psbt = Psbt::from_str(P2SH_P2WSH_MULTISIG).unwrap();
+        let data = psbt.serialize();
+        assert_eq!(Psbt::deserialize(&data[1..]), Err(PsbtError::InvalidMagic));
+        assert_eq!(Psbt::from_str("not base64!"), Err(PsbtError::InvalidBase64));
+        assert_eq!(
+            Psbt::deserialize(&[&PSBT_MAGIC[..], &[0x00]].concat()),
+            Err(PsbtError::MissingUnsignedTx)
+        );
+
+        // the unsigned transaction twice
+        let tx = psbt.unsigned_tx.serialize_without_witness();
+        let mut duplicate = PSBT_MAGIC.to_vec();
+        push_pair(&mut duplicate, PSBT_GLOBAL_UNSIGNED_TX, &[], &tx);
+        push_pair(&mut duplicate, PSBT_GLOBAL_UNSIGNED_TX, &[], &tx);
+        duplicate.extend(&data[PSBT_MAGIC.len() + 3 + tx.len()..]);
+        assert_eq!(
+            Psbt::deserialize(&duplicate),
+            Err(PsbtError::DuplicateKey("00".to_string()))
+        );
+
+        let mut signed = psbt.unsigned_tx.clone();
+        signed.input[0].script_sig = vec![0x51];
+        assert_eq!(
+            Psbt::from_unsigned_tx(signed),
+            Err(PsbtError::SignedUnsignedTx)
+        );
+
+        let mut trailing = data.clone();
+        trailing.push(0x00);
+        assert_eq!(
+            Psbt::deserialize(&trailing),
+            Err(TransactionError::TrailingData.into())
+        );
+    }
+}
@@ -0,0 +1,375 @@
+use crate::{
+    chain::{btc_per_kvb_to_sat_per_vb, btc_to_sat, ChainBackend, ChainFuture, Utxo},
+    error::ChainError,
+    transaction::{OutPoint, Transaction, Txid},
+};
+use serde::{de::DeserializeOwned, Deserialize};
+use serde_json::{json, Value};
+use std::str::FromStr;
+
+/// Environment variable of the Bitcoin Core RPC URL, e.g. `http://127.0.0.1:8332`
+pub const RPC_URL_VAR: &str = "BITCOIN_RPC_URL";
+/// Environment variable of the `rpcuser`, or `__cookie__` with the cookie file password
+pub const RPC_USER_VAR: &str = "BITCOIN_RPC_USER";
+/// Environment variable of the `rpcpassword`
+pub const RPC_PASSWORD_VAR: &str = "BITCOIN_RPC_PASSWORD";
+
+/// Bitcoin Core JSON-RPC client
+#[derive(Debug, Clone)]
+pub struct BitcoinCoreRpc {
+    url: String,
+    auth: Option<(String, String)>,
+    client: reqwest::Client,
+}
+
+#[derive(Deserialize, Debug)]
+struct RpcResponse<T> {
+    result: Option<T>,
+    error: Option<RpcErrorObject>,
+}
+
+#[derive(Deserialize, Debug)]
+struct RpcErrorObject {
+    code: i64,
+    message: String,
+}
+
+#[derive(Deserialize, Debug)]
+struct ScanResult {
+    unspents: Vec<ScanUtxo>,
+}
+
+#[derive(Deserialize, Debug)]
+struct ScanUtxo {
+    txid: String,
+    vout: u32,
+    #[serde(rename = "scriptPubKey")]
+    script_pubkey: String,
+    amount: f64,
+    height: u32,
+}
+
+#[derive(Deserialize, Debug)]
+struct FeeEstimate {
+    feerate: Option<f64>,
+}
+
+impl BitcoinCoreRpc {
+    pub fn new(url: &str) -> Self {
+        Self {
+            url: url.to_string(),
+            auth: None,
+            client: reqwest::Client::new(),
+        }
+    }
+
+    /// Authenticate with `rpcuser` and `rpcpassword`
+    pub fn auth(mut self, user: &str, password: &str) -> Self {
+        self.auth = Some((user.to_string(), password.to_string()));
+        self
+    }
+
+    /// Client of the node configured in the environment, if any
+    pub fn from_env() -> Option<Self> {
+        let rpc = Self::new(&std::env::var(RPC_URL_VAR).ok()?);
+        match (std::env::var(RPC_USER_VAR), std::env::var(RPC_PASSWORD_VAR)) {
+            (Ok(user), Ok(password)) => Some(rpc.auth(&user, &password)),
+            _ => Some(rpc),
+        }
+    }
+
+    /// Call `method` with `params`, Bitcoin Core answers RPC errors with an HTTP error status
+    /// and the error in the body
+    pub async fn call<T: DeserializeOwned>(
+        &self,
+        method: &str,
+        params: Value,
+    ) -> Result<T, ChainError> {
+        let request = json!({
+            "jsonrpc": "1.0",
+            "id": "btc_address_server",
+            "method": method,
+            "params": params,
+        });
+        let mut builder = self.client.post(&self.url).json(&request);
+        if let Some((user, password)) = &self.auth {
+            builder = builder.basic_auth(user, Some(password));
+        }
+        let response = builder
+            .send()
+            .await
+            .map_err(|e| ChainError::Connection(e.to_string()))?;
+        let status = response.status();
+        let body = response
+            .text()
+            .await
+            .map_err(|e| ChainError::Connection(e.to_string()))?;
+        let response: RpcResponse<T> =
+            serde_json::from_str(&body).map_err(|_| match status.is_success() {
+                true => ChainError::InvalidResponse(format!("{} {}", method, body)),
+                false => ChainError::Connection(format!("HTTP {}", status)),
+            })?;
+        match (response.result, response.error) {
+            (_, Some(error)) => Err(ChainError::Rpc {
+                code: error.code,
+                message: error.message,
+            }),
+            (Some(result), None) => Ok(result),
+            (None, None) => Err(ChainError::InvalidResponse(format!(
+                "{} without result",
+                method
+            ))),
+        }
+    }
+
+    /// `scantxoutset` of the `raw()` descriptors of `script_pubkeys`. Only confirmed outputs
+    /// are in the UTXO set it scans: mempool outputs are missing, so every UTXO has a `height`,
+    /// and outputs spent by mempool transactions are still there.
+    async fn scan(&self, script_pubkeys: &[Vec<u8>]) -> Result<Vec<Utxo>, ChainError> {
+        let descriptors: Vec<Value> = script_pubkeys
+            .iter()
+            .map(|script_pubkey| json!({ "desc": format!("raw({})", hex::encode(script_pubkey)) }))
+            .collect();
+        let scan: ScanResult = self
+            .call("scantxoutset", json!(["start", descriptors]))
+            .await?;
+        scan.unspents
+            .into_iter()
+            .map(|utxo| {
+                Ok(Utxo {
+                    outpoint: OutPoint::new(Txid::from_str(&utxo.txid)?, utxo.vout),
+                    value: btc_to_sat(utxo.amount)?,
+                    script_pubkey: hex::decode(&utxo.script_pubkey).map_err(|_| {
+                        ChainError::InvalidResponse(format!("scriptPubKey {}", utxo.script_pubkey))
+                    })?,
+                    height: Some(utxo.height),
+                })
+            })
+            .collect()
+    }
+}
+
+impl ChainBackend for BitcoinCoreRpc {
+    fn utxos<'a>(&'a self, script_pubkeys: &'a [Vec<u8>]) -> ChainFuture<'a, Vec<Utxo>> {
+        Box::pin(self.scan(script_pubkeys))
+    }
+
+    fn transaction(&self, txid: Txid) -> ChainFuture<'_, Transaction> {
+        Box::pin(async move {
+            let tx: String = self
+                .call("getrawtransaction", json!([txid.to_string(), false]))
+                .await?;
+            Ok(Transaction::from_str(&tx)?)
+        })
+    }
+
+    fn broadcast<'a>(&'a self, tx: &'a Transaction) -> ChainFuture<'a, Txid> {
+        Box::pin(async move {
+            let txid: String = self
+                .call("sendrawtransaction", json!([hex::encode(tx.serialize())]))
+                .await?;
+            Ok(Txid::from_str(&txid)?)
+        })
+    }
+
+    fn estimate_fee(&self, target: u16) -> ChainFuture<'_, f64> {
+        Box::pin(async move {
+            let estimate: FeeEstimate = self.call("estimatesmartfee", json!([target])).await?;
+            estimate
+                .feerate
+                .map(btc_per_kvb_to_sat_per_vb)
+                .ok_or(ChainError::NoFeeEstimate(target))
+        })
+    }
+
+    /// `scantxoutset` only sees the scripts still holding coins, emptied addresses would look
+    /// unused and be handed out again
+    fn used<'a>(&'a self, _: &'a [Vec<u8>]) -> ChainFuture<'a, Vec<bool>> {
+        Box::pin(async { Err(ChainError::NoHistory) })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transaction::{TxIn, TxOut, SEQUENCE_FINAL};
+    use std::{
+        io::{BufRead, BufReader, Read, Write},
+        net::TcpListener,
+        thread,
+    };
+
+    const USER: &str = "user";
+    const PASSWORD: &str = "password";
+
+    /// Bitcoin Core stand-in answering one JSON-RPC request per connection with `respond`
+    fn mock_rpc<F>(respond: F) -> String
+    where
+        F: Fn(&str, &Value) -> Result<Value, (i64, &'static str)> + Send + 'static,
+    {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        let credentials = format!("Basic {}", base64::encode(format!("{}:{}", USER, PASSWORD)));
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                let mut stream = stream.unwrap();
+                let mut reader = BufReader::new(stream.try_clone().unwrap());
+                let (mut length, mut authorized) = (0, false);
+                loop {
+                    let mut line = String::new();
+                    reader.read_line(&mut line).unwrap();
+                    let (name, value) = match line.trim_end().split_once(':') {
+                        Some((name, value)) => (name.to_ascii_lowercase(), value.trim()),
+                        None if line.trim_end().is_empty() => break,
+                        None => continue,
+                    };
+                    match name.as_str() {
+                        "content-length" => length = value.parse().unwrap(),
+                        "authorization" => authorized = value == credentials,
+                        _ => {}
+                    }
+                }
+                let mut body = vec![0; length];
+                reader.read_exact(&mut body).unwrap();
+                let request: Value = serde_json::from_slice(&body).unwrap();
+                let (status, body) = if !authorized {
+                    ("401 Unauthorized", String::new())
+                } else {
+                    match respond(request["method"].as_str().unwrap(), &request["params"]) {
+                        Ok(result) => (
+                            "200 OK",
+                            json!({"result": result, "error": null, "id": request["id"]})
+                                .to_string(),
+                        ),
+                        Err((code, message)) => (
+                            "500 Internal Server Error",
+                            json!({"result": null, "error": {"code": code, "message": message}, "id": request["id"]})
+                                .to_string(),
+                        ),
+                    }
+                };
+                write!(
+                    stream,
+                    "HTTP/1.1 {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                    status,
+                    body.len(),
+                    body
+                )
+                .unwrap();
+            }
+        });
+        url
+    }
+
+    fn transaction() -> Transaction {
+        Transaction {
+            version: 2,
+            input: vec![TxIn::new(OutPoint::new(Txid([1; 32]), 0), SEQUENCE_FINAL)],
+            output: vec![TxOut {
+                value: 50_000,
+                script_pubkey: [&[0x00, 0x14][..], &[7; 20]].concat(),
+            }],
+            lock_time: 0,
+        }
+    }
+
+    #[actix_rt::test]
+    async fn test_utxos_and_transactions() {
+        let tx = transaction();
+        let tx_hex = hex::encode(tx.serialize());
+        let txid = tx.txid().to_string();
+        let url = mock_rpc(move |method, params| match method {
+            "scantxoutset" => {
+                assert_eq!(params[0], "start");
+                assert_eq!(
+                    params[1][0]["desc"],
+                    format!("raw(0014{})", "07".repeat(20))
+                );
+                Ok(json!({
+                    "success": true,
+                    "unspents": [{
+                        "txid": txid,
+                        "vout": 0,
+                        "scriptPubKey": format!("0014{}", "07".repeat(20)),
+                        "desc": "raw(...)",
+                        "amount": 0.0005,
+                        "height": 700_000,
+                    }],
+                    "total_amount": 0.0005,
+                }))
+            }
+            "getrawtransaction" if params[0] == json!(txid) => Ok(json!(tx_hex)),
+            "getrawtransaction" => Err((-5, "No such mempool or blockchain transaction")),
+            _ => Err((-32601, "Method not found")),
+        });
+        let rpc = BitcoinCoreRpc::new(&url).auth(USER, PASSWORD);
+
+        let utxos = rpc
+            .utxos(&[tx.output[0].script_pubkey.clone()])
+            .await
+            .unwrap();
+        assert_eq!(
+            utxos,
+            vec![Utxo {
+                outpoint: OutPoint::new(tx.txid(), 0),
+                value: 50_000,
+                script_pubkey: tx.output[0].script_pubkey.clone(),
+                height: Some(700_000),
+            }]
+        );
+        assert_eq!(rpc.transaction(tx.txid()).await.unwrap(), tx);
+        assert_eq!(
+            rpc.transaction(Txid([2; 32])).await,
+            Err(ChainError::Rpc {
+                code: -5,
+                message: "No such mempool or blockchain transaction".to_string()
+            })
+        );
+
+        let unauthorized = BitcoinCoreRpc::new(&url).auth(USER, "wrong");
+        assert!(matches!(
+            unauthorized.transaction(tx.txid()).await,
+            Err(ChainError::Connection(_))
+        ));
+
+        // emptied addresses are not in the UTXO set, so there is no telling them from unused ones
+        assert_eq!(
+            rpc.used(&[tx.output[0].script_pubkey.clone()]).await,
+            Err(ChainError::NoHistory)
+        );
+    }
+
+    #[actix_rt::test]
+    async fn test_broadcast_and_fee_estimate() {
+        let url = mock_rpc(|method, params| match method {
+            "sendrawtransaction" => {
+                let tx = Transaction::from_str(params[0].as_str().unwrap()).unwrap();
+                match tx.output[0].value {
+                    100 => Err((-26, "min relay fee not met")),
+                    _ => Ok(json!(tx.txid().to_string())),
+                }
+            }
+            "estimatesmartfee" if params[0] == 6 => Ok(json!({"feerate": 0.00012, "blocks": 6})),
+            "estimatesmartfee" => Ok(json!({
+                "errors": ["Insufficient data or no feerate found"],
+                "blocks": 2
+            })),
+            _ => Err((-32601, "Method not found")),
+        });
+        let rpc = BitcoinCoreRpc::new(&url).auth(USER, PASSWORD);
+
+        let mut tx = transaction();
+        assert_eq!(rpc.broadcast(&tx).await, Ok(tx.txid()));
+        tx.output[0].value = 100;
+        assert_eq!(
+            rpc.broadcast(&tx).await,
+            Err(ChainError::Rpc {
+                code: -26,
+                message: "min relay fee not met".to_string()
+            })
+        );
+
+        assert_eq!(rpc.estimate_fee(6).await, Ok(12.0));
+        assert_eq!(rpc.estimate_fee(1).await, Err(ChainError::NoFeeEstimate(1)));
+    }
+}
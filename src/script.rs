@@ -0,0 +1,397 @@
+use crate::{error::ScriptError, opcodes};
+
+/// Maximum number of bytes pushable to the stack
+pub const MAX_SCRIPT_ELEMENT_SIZE: usize = 520;
+/// Maximum script length in bytes
+pub const MAX_SCRIPT_SIZE: usize = 10_000;
+/// Maximum number of non-push operations per script
+pub const MAX_OPS_PER_SCRIPT: usize = 201;
+/// Maximum number of public keys per multisig
+pub const MAX_PUBKEYS_PER_MULTISIG: usize = 20;
+
+/// A single parsed element of a script
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Instruction<'a> {
+    /// Any of the push opcodes (`OP_0`, `OP_PUSHBYTES_N`, `OP_PUSHDATA1/2/4`) with its data
+    PushBytes(&'a [u8]),
+    /// Any other opcode
+    Op(u8),
+}
+
+/// Iterator over the instructions of a script
+pub struct Instructions<'a> {
+    script: &'a [u8],
+    position: usize,
+    failed: bool,
+}
+
+/// Iterate over the instructions of `script`
+pub fn instructions(script: &[u8]) -> Instructions<'_> {
+    Instructions {
+        script,
+        position: 0,
+        failed: false,
+    }
+}
+
+impl<'a> Instructions<'a> {
+    /// Byte offset of the next instruction
+    pub fn position(&self) -> usize {
+        self.position
+    }
+
+    fn take(&mut self, len: usize) -> Result<&'a [u8], ScriptError> {
+        if self.script.len() - self.position < len {
+            return Err(ScriptError::BadPush);
+        }
+        let data = &self.script[self.position..self.position + len];
+        self.position += len;
+        Ok(data)
+    }
+
+    fn take_len(&mut self, width: usize) -> Result<usize, ScriptError> {
+        let bytes = self.take(width)?;
+        Ok(bytes
+            .iter()
+            .rev()
+            .fold(0usize, |acc, byte| (acc << 8) | *byte as usize))
+    }
+}
+
+impl<'a> Iterator for Instructions<'a> {
+    type Item = Result<Instruction<'a>, ScriptError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.failed || self.position >= self.script.len() {
+            return None;
+        }
+        let opcode = self.script[self.position];
+        self.position += 1;
+        let instruction = match opcode {
+            opcodes::OP_PUSHBYTES_0..=opcodes::OP_PUSHBYTES_75 => {
+                self.take(opcode as usize).map(Instruction::PushBytes)
+            }
+            opcodes::OP_PUSHDATA1 => self
+                .take_len(1)
+                .and_then(|len| self.take(len))
+                .map(Instruction::PushBytes),
+            opcodes::OP_PUSHDATA2 => self
+                .take_len(2)
+                .and_then(|len| self.take(len))
+                .map(Instruction::PushBytes),
+            opcodes::OP_PUSHDATA4 => self
+                .take_len(4)
+                .and_then(|len| self.take(len))
+                .map(Instruction::PushBytes),
+            _ => Ok(Instruction::Op(opcode)),
+        };
+        if instruction.is_err() {
+            self.failed = true;
+        }
+        Some(instruction)
+    }
+}
+
+/// Disassemble `script`, printing pushes as hex and other opcodes by name
+///
+/// A truncated push ends the output with `[error]`, like Bitcoin Core does.
+pub fn to_asm(script: &[u8]) -> String {
+    let mut asm = Vec::new();
+    for instruction in instructions(script) {
+        match instruction {
+            Ok(Instruction::PushBytes([])) => asm.push("OP_0".to_string()),
+            Ok(Instruction::PushBytes(data)) => asm.push(hex::encode(data)),
+            Ok(Instruction::Op(opcode)) => asm.push(opcodes::name(opcode).to_string()),
+            Err(_) => asm.push("[error]".to_string()),
+        }
+    }
+    asm.join(" ")
+}
+
+/// Append a minimal push of `data` to `script`
+pub fn push_data(script: &mut Vec<u8>, data: &[u8]) {
+    match data.len() {
+        len if len <= opcodes::OP_PUSHBYTES_75 as usize => script.push(len as u8),
+        len if len <= 0xff => script.extend([opcodes::OP_PUSHDATA1, len as u8]),
+        len if len <= 0xffff => {
+            script.push(opcodes::OP_PUSHDATA2);
+            script.extend((len as u16).to_le_bytes());
+        }
+        len => {
+            script.push(opcodes::OP_PUSHDATA4);
+            script.extend((len as u32).to_le_bytes());
+        }
+    }
+    script.extend(data);
+}
+
+/// Append `len` as a CompactSize unsigned integer, the length prefix of scripts and vectors
+pub fn push_compact_size(data: &mut Vec<u8>, len: usize) {
+    match len {
+        0..=0xfc => data.push(len as u8),
+        0xfd..=0xffff => {
+            data.push(0xfd);
+            data.extend((len as u16).to_le_bytes());
+        }
+        0x10000..=0xffff_ffff => {
+            data.push(0xfe);
+            data.extend((len as u32).to_le_bytes());
+        }
+        _ => {
+            data.push(0xff);
+            data.extend((len as u64).to_le_bytes());
+        }
+    }
+}
+
+/// Number of bytes `push_compact_size` appends for `len`
+pub(crate) fn compact_size_len(len: usize) -> usize {
+    let mut data = Vec::new();
+    push_compact_size(&mut data, len);
+    data.len()
+}
+
+/// Build a script from opcodes, data pushes and numbers
+#[derive(Debug, Default, Clone)]
+pub struct Builder(Vec<u8>);
+
+impl Builder {
+    pub fn new() -> Self {
+        Self(Vec::new())
+    }
+
+    /// Append a raw opcode
+    pub fn push_opcode(mut self, opcode: u8) -> Self {
+        self.0.push(opcode);
+        self
+    }
+
+    /// Append a minimal push of `data`
+    pub fn push_slice(mut self, data: &[u8]) -> Self {
+        push_data(&mut self.0, data);
+        self
+    }
+
+    /// Append `value` as `OP_1NEGATE`/`OP_0`-`OP_16` or a minimal script number push
+    pub fn push_int(self, value: i64) -> Self {
+        match value {
+            -1 => self.push_opcode(opcodes::OP_PUSHNUM_NEG1),
+            0 => self.push_opcode(opcodes::OP_PUSHBYTES_0),
+            1..=16 => self.push_opcode(opcodes::OP_PUSHNUM_1 + value as u8 - 1),
+            _ => self.push_slice(&scriptint_bytes(value)),
+        }
+    }
+
+    /// Append the already built `script`
+    pub fn append(mut self, script: &[u8]) -> Self {
+        self.0.extend(script);
+        self
+    }
+
+    pub fn into_script(self) -> Vec<u8> {
+        self.0
+    }
+}
+
+/// Whether `script` consists only of push operations (including `OP_1NEGATE` and `OP_1`-`OP_16`)
+pub fn is_push_only(script: &[u8]) -> bool {
+    instructions(script).all(|instruction| match instruction {
+        Ok(Instruction::PushBytes(_)) => true,
+        Ok(Instruction::Op(op)) => op <= opcodes::OP_PUSHNUM_16,
+        Err(_) => false,
+    })
+}
+
+/// `m` and the public keys of an `OP_m <keys> OP_n OP_CHECKMULTISIG` script
+pub fn parse_multisig(script: &[u8]) -> Option<(usize, Vec<&[u8]>)> {
+    let small_int = |op: u8| match op {
+        opcodes::OP_PUSHNUM_1..=opcodes::OP_PUSHNUM_16 => {
+            Some((op - opcodes::OP_PUSHNUM_1 + 1) as usize)
+        }
+        _ => None,
+    };
+    let instructions = instructions(script).collect::<Result<Vec<_>, _>>().ok()?;
+    match instructions.as_slice() {
+        [Instruction::Op(m), keys @ .., Instruction::Op(n), Instruction::Op(opcodes::OP_CHECKMULTISIG)] =>
+        {
+            let (m, n) = (small_int(*m)?, small_int(*n)?);
+            let keys = keys
+                .iter()
+                .map(|instruction| match instruction {
+                    Instruction::PushBytes(key) => Some(*key),
+                    Instruction::Op(_) => None,
+                })
+                .collect::<Option<Vec<_>>>()?;
+            if keys.len() != n || m > n {
+                return None;
+            }
+            Some((m, keys))
+        }
+        _ => None,
+    }
+}
+
+/// Whether the push of `data` starting with `opcode` uses the smallest possible encoding
+pub fn is_minimal_push(opcode: u8, data: &[u8]) -> bool {
+    match data.len() {
+        0 => opcode == opcodes::OP_PUSHBYTES_0,
+        1 if (1..=16).contains(&data[0]) => false,
+        1 if data[0] == 0x81 => false,
+        len if len <= 75 => opcode as usize == len,
+        len if len <= 255 => opcode == opcodes::OP_PUSHDATA1,
+        len if len <= 65535 => opcode == opcodes::OP_PUSHDATA2,
+        _ => true,
+    }
+}
+
+/// Decode a little-endian, sign-magnitude script number of at most `max_len` bytes
+pub fn read_scriptint(
+    data: &[u8],
+    require_minimal: bool,
+    max_len: usize,
+) -> Result<i64, ScriptError> {
+    if data.len() > max_len {
+        return Err(ScriptError::NumOverflow);
+    }
+    if data.is_empty() {
+        return Ok(0);
+    }
+    let last = data[data.len() - 1];
+    if require_minimal && last & 0x7f == 0 && (data.len() == 1 || data[data.len() - 2] & 0x80 == 0)
+    {
+        return Err(ScriptError::NumNonMinimal);
+    }
+    let mut value = data
+        .iter()
+        .enumerate()
+        .fold(0i64, |acc, (i, byte)| acc | (*byte as i64) << (8 * i));
+    if last & 0x80 != 0 {
+        value &= !(0x80i64 << (8 * (data.len() - 1)));
+        value = -value;
+    }
+    Ok(value)
+}
+
+/// Encode `value` as a minimal script number
+pub fn scriptint_bytes(value: i64) -> Vec<u8> {
+    let mut bytes = Vec::new();
+    let negative = value < 0;
+    let mut abs = value.unsigned_abs();
+    while abs > 0 {
+        bytes.push((abs & 0xff) as u8);
+        abs >>= 8;
+    }
+    if let Some(last) = bytes.last_mut() {
+        if *last & 0x80 != 0 {
+            bytes.push(if negative { 0x80 } else { 0 });
+        } else if negative {
+            *last |= 0x80;
+        }
+    }
+    bytes
+}
+
+/// Interpret a stack element as a boolean, where any encoding of zero (including negative zero) is false
+pub fn cast_to_bool(data: &[u8]) -> bool {
+    for (i, byte) in data.iter().enumerate() {
+        if *byte != 0 {
+            return !(i == data.len() - 1 && *byte == 0x80);
+        }
+    }
+    false
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_instructions() {
+        let script = hex::decode("76a914751e76e8199196d454941c45d1b3a323f1433bd688ac").unwrap();
+        let parsed = instructions(&script)
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+        assert_eq!(parsed.len(), 5);
+        assert_eq!(parsed[0], Instruction::Op(opcodes::OP_DUP));
+        assert_eq!(
+            parsed[2],
+            Instruction::PushBytes(
+                &hex::decode("751e76e8199196d454941c45d1b3a323f1433bd6").unwrap()
+            )
+        );
+        assert_eq!(
+            instructions(&[opcodes::OP_PUSHBYTES_2, 0x01]).next(),
+            Some(Err(ScriptError::BadPush))
+        );
+    }
+
+    #[test]
+    fn test_to_asm() {
+        let script = hex::decode("76a914751e76e8199196d454941c45d1b3a323f1433bd688ac").unwrap();
+        assert_eq!(
+            to_asm(&script),
+            "OP_DUP OP_HASH160 751e76e8199196d454941c45d1b3a323f1433bd6 OP_EQUALVERIFY OP_CHECKSIG"
+        );
+        assert_eq!(
+            to_asm(&[0x00, 0x52, 0xb1, 0xbb]),
+            "OP_0 OP_2 OP_CHECKLOCKTIMEVERIFY OP_UNKNOWN"
+        );
+        assert_eq!(to_asm(&[0x6a, 0x02, 0x01]), "OP_RETURN [error]");
+    }
+
+    #[test]
+    fn test_scriptint() {
+        for value in &[
+            0i64,
+            1,
+            -1,
+            127,
+            128,
+            -128,
+            255,
+            256,
+            -255,
+            32767,
+            2147483647,
+            -2147483647,
+        ] {
+            assert_eq!(
+                read_scriptint(&scriptint_bytes(*value), true, 5).unwrap(),
+                *value
+            );
+        }
+        assert_eq!(scriptint_bytes(-1), vec![0x81]);
+        assert_eq!(scriptint_bytes(128), vec![0x80, 0x00]);
+        assert_eq!(
+            read_scriptint(&[0x01, 0x00], true, 4),
+            Err(ScriptError::NumNonMinimal)
+        );
+        assert_eq!(
+            read_scriptint(&[1, 2, 3, 4, 5], false, 4),
+            Err(ScriptError::NumOverflow)
+        );
+    }
+
+    #[test]
+    fn test_builder() {
+        let script = Builder::new()
+            .push_int(0)
+            .push_int(16)
+            .push_int(17)
+            .push_int(-1)
+            .push_int(700_000)
+            .push_slice(&[0xab; 76])
+            .push_opcode(opcodes::OP_CHECKSIG)
+            .into_script();
+        assert_eq!(hex::encode(&script[..9]), "006001114f0360ae0a");
+        assert_eq!(script[9..11], [opcodes::OP_PUSHDATA1, 76]);
+        assert_eq!(script.last(), Some(&opcodes::OP_CHECKSIG));
+    }
+
+    #[test]
+    fn test_cast_to_bool() {
+        assert!(!cast_to_bool(&[]));
+        assert!(!cast_to_bool(&[0x00, 0x80]));
+        assert!(cast_to_bool(&[0x00, 0x01]));
+    }
+}
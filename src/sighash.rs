@@ -0,0 +1,541 @@
+use crate::{
+    error::SighashError,
+    hash, opcodes,
+    script::{instructions, push_compact_size, Instruction},
+    transaction::{Transaction, TxOut},
+};
+
+/// Taproot only: same as `SIGHASH_ALL`, but without the type byte in the signature
+pub const SIGHASH_DEFAULT: u8 = 0x00;
+/// Sign all inputs and outputs
+pub const SIGHASH_ALL: u8 = 0x01;
+/// Sign all inputs and no output
+pub const SIGHASH_NONE: u8 = 0x02;
+/// Sign all inputs and the output with the same index
+pub const SIGHASH_SINGLE: u8 = 0x03;
+/// Flag to sign this input only, others can be added
+pub const SIGHASH_ANYONECANPAY: u8 = 0x80;
+
+/// What legacy transactions sign with `SIGHASH_SINGLE` and no matching output, the number one
+const SIGHASH_SINGLE_BUG: [u8; 32] = {
+    let mut one = [0u8; 32];
+    one[0] = 1;
+    one
+};
+
+/// Tapscript data committed to by script path signatures (BIP342)
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ScriptPath {
+    pub leaf_hash: [u8; 32],
+    /// Opcode position of the last executed `OP_CODESEPARATOR`, `0xffffffff` if none
+    pub code_separator_position: u32,
+}
+
+fn check_index(tx: &Transaction, input_index: usize) -> Result<(), SighashError> {
+    if input_index < tx.input.len() {
+        Ok(())
+    } else {
+        Err(SighashError::InputIndexOutOfRange(input_index))
+    }
+}
+
+/// `script_code` without its `OP_CODESEPARATOR`s, bytes after an invalid push are kept as is
+fn remove_code_separators(script_code: &[u8]) -> Vec<u8> {
+    let mut script = Vec::with_capacity(script_code.len());
+    let mut iter = instructions(script_code);
+    loop {
+        let start = iter.position();
+        match iter.next() {
+            Some(Ok(Instruction::Op(opcodes::OP_CODESEPARATOR))) => {}
+            Some(Ok(_)) => script.extend(&script_code[start..iter.position()]),
+            Some(Err(_)) => {
+                script.extend(&script_code[start..]);
+                break;
+            }
+            None => break,
+        }
+    }
+    script
+}
+
+/// Legacy signature hash of input `input_index`, signing `script_code`
+///
+/// The interpreter has already cut `script_code` at the last executed `OP_CODESEPARATOR` and
+/// removed the signature from it. `SIGHASH_SINGLE` without a matching output signs the number
+/// one, as consensus requires. All 32 bits of `hash_type` are signed, though signatures only
+/// carry the low byte.
+pub fn legacy_sighash(
+    tx: &Transaction,
+    input_index: usize,
+    script_code: &[u8],
+    hash_type: u32,
+) -> Result<[u8; 32], SighashError> {
+    check_index(tx, input_index)?;
+    let base_type = (hash_type & 0x1f) as u8;
+    if base_type == SIGHASH_SINGLE && input_index >= tx.output.len() {
+        return Ok(SIGHASH_SINGLE_BUG);
+    }
+    let mut copy = tx.clone();
+    for (i, input) in copy.input.iter_mut().enumerate() {
+        input.witness = Default::default();
+        if i == input_index {
+            input.script_sig = remove_code_separators(script_code);
+        } else {
+            input.script_sig = Vec::new();
+            // others may update their sequence unless every input is signed
+            if base_type == SIGHASH_NONE || base_type == SIGHASH_SINGLE {
+                input.sequence = 0;
+            }
+        }
+    }
+    if hash_type & u32::from(SIGHASH_ANYONECANPAY) != 0 {
+        copy.input = vec![copy.input.swap_remove(input_index)];
+    }
+    match base_type {
+        SIGHASH_NONE => copy.output.clear(),
+        SIGHASH_SINGLE => {
+            copy.output.truncate(input_index + 1);
+            for output in &mut copy.output[..input_index] {
+                // an amount of -1 and an empty script
+                *output = TxOut {
+                    value: u64::MAX,
+                    script_pubkey: Vec::new(),
+                };
+            }
+        }
+        _ => {}
+    }
+    let mut data = copy.serialize_without_witness();
+    data.extend(hash_type.to_le_bytes());
+    Ok(hash::sha256d(&data))
+}
+
+/// Segwit v0 signature hash (BIP143) of input `input_index`, spending `amount` satoshis
+///
+/// `script_code` is given without its length prefix: the P2PKH script of the key for P2WPKH,
+/// the witness script from the last executed `OP_CODESEPARATOR` for P2WSH.
+pub fn segwit_v0_sighash(
+    tx: &Transaction,
+    input_index: usize,
+    script_code: &[u8],
+    amount: u64,
+    hash_type: u8,
+) -> Result<[u8; 32], SighashError> {
+    check_index(tx, input_index)?;
+    let base_type = hash_type & 0x1f;
+    let anyone_can_pay = hash_type & SIGHASH_ANYONECANPAY != 0;
+    let sign_all_outputs = base_type != SIGHASH_SINGLE && base_type != SIGHASH_NONE;
+
+    let hash_prevouts = if anyone_can_pay {
+        [0; 32]
+    } else {
+        let prevouts: Vec<u8> = tx
+            .input
+            .iter()
+            .flat_map(|input| input.previous_output.serialize())
+            .collect();
+        hash::sha256d(&prevouts)
+    };
+    let hash_sequence = if anyone_can_pay || !sign_all_outputs {
+        [0; 32]
+    } else {
+        let sequences: Vec<u8> = tx
+            .input
+            .iter()
+            .flat_map(|input| input.sequence.to_le_bytes())
+            .collect();
+        hash::sha256d(&sequences)
+    };
+    let hash_outputs = if sign_all_outputs {
+        let outputs: Vec<u8> = tx.output.iter().flat_map(TxOut::serialize).collect();
+        hash::sha256d(&outputs)
+    } else if base_type == SIGHASH_SINGLE && input_index < tx.output.len() {
+        hash::sha256d(&tx.output[input_index].serialize())
+    } else {
+        [0; 32]
+    };
+
+    let input = &tx.input[input_index];
+    let mut data = tx.version.to_le_bytes().to_vec();
+    data.extend(hash_prevouts);
+    data.extend(hash_sequence);
+    data.extend(input.previous_output.serialize());
+    push_compact_size(&mut data, script_code.len());
+    data.extend(script_code);
+    data.extend(amount.to_le_bytes());
+    data.extend(input.sequence.to_le_bytes());
+    data.extend(hash_outputs);
+    data.extend(tx.lock_time.to_le_bytes());
+    data.extend((hash_type as u32).to_le_bytes());
+    Ok(hash::sha256d(&data))
+}
+
+/// Taproot signature hash (BIP341) of input `input_index`
+///
+/// `prevouts` are the outputs spent by every input of `tx`. `script_path` is given for
+/// tapscript signatures (BIP342) and left out for key path spends.
+pub fn taproot_sighash(
+    tx: &Transaction,
+    input_index: usize,
+    prevouts: &[TxOut],
+    hash_type: u8,
+    annex: Option<&[u8]>,
+    script_path: Option<ScriptPath>,
+) -> Result<[u8; 32], SighashError> {
+    check_index(tx, input_index)?;
+    if !matches!(hash_type, 0x00..=0x03 | 0x81..=0x83) {
+        return Err(SighashError::InvalidHashType(hash_type));
+    }
+    if prevouts.len() != tx.input.len() {
+        return Err(SighashError::PrevoutCount(prevouts.len(), tx.input.len()));
+    }
+    let output_type = match hash_type {
+        SIGHASH_DEFAULT => SIGHASH_ALL,
+        _ => hash_type & 0x03,
+    };
+    let anyone_can_pay = hash_type & SIGHASH_ANYONECANPAY != 0;
+    let script_pubkey = |output: &TxOut| {
+        let mut data = Vec::new();
+        push_compact_size(&mut data, output.script_pubkey.len());
+        data.extend(&output.script_pubkey);
+        data
+    };
+
+    // epoch
+    let mut data = vec![0x00, hash_type];
+    data.extend(tx.version.to_le_bytes());
+    data.extend(tx.lock_time.to_le_bytes());
+    if !anyone_can_pay {
+        let prevout_data: Vec<u8> = tx
+            .input
+            .iter()
+            .flat_map(|input| input.previous_output.serialize())
+            .collect();
+        data.extend(hash::sha256(&prevout_data));
+        let amounts: Vec<u8> = prevouts
+            .iter()
+            .flat_map(|output| output.value.to_le_bytes())
+            .collect();
+        data.extend(hash::sha256(&amounts));
+        let script_pubkeys: Vec<u8> = prevouts.iter().flat_map(script_pubkey).collect();
+        data.extend(hash::sha256(&script_pubkeys));
+        let sequences: Vec<u8> = tx
+            .input
+            .iter()
+            .flat_map(|input| input.sequence.to_le_bytes())
+            .collect();
+        data.extend(hash::sha256(&sequences));
+    }
+    if output_type == SIGHASH_ALL {
+        let outputs: Vec<u8> = tx.output.iter().flat_map(TxOut::serialize).collect();
+        data.extend(hash::sha256(&outputs));
+    }
+
+    let spend_type = (script_path.is_some() as u8) << 1 | annex.is_some() as u8;
+    data.push(spend_type);
+    if anyone_can_pay {
+        let input = &tx.input[input_index];
+        data.extend(input.previous_output.serialize());
+        data.extend(prevouts[input_index].value.to_le_bytes());
+        data.extend(script_pubkey(&prevouts[input_index]));
+        data.extend(input.sequence.to_le_bytes());
+    } else {
+        data.extend((input_index as u32).to_le_bytes());
+    }
+    if let Some(annex) = annex {
+        let mut annex_data = Vec::new();
+        push_compact_size(&mut annex_data, annex.len());
+        annex_data.extend(annex);
+        data.extend(hash::sha256(&annex_data));
+    }
+    if output_type == SIGHASH_SINGLE {
+        let output = tx
+            .output
+            .get(input_index)
+            .ok_or(SighashError::SingleWithoutOutput(input_index))?;
+        data.extend(hash::sha256(&output.serialize()));
+    }
+    if let Some(script_path) = script_path {
+        data.extend(script_path.leaf_hash);
+        // key version
+        data.push(0x00);
+        data.extend(script_path.code_separator_position.to_le_bytes());
+    }
+    Ok(hash::tagged_hash("TapSighash", &data))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{interpreter::verify_ecdsa, taproot};
+    use std::str::FromStr;
+
+    fn sighash(hex: &str) -> [u8; 32] {
+        let mut sighash = [0u8; 32];
+        sighash.copy_from_slice(&hex::decode(hex).unwrap());
+        sighash
+    }
+
+    #[test]
+    fn test_legacy() {
+        // Mainnet block 170 spends a P2PK output of the key its change goes back to
+        let tx = Transaction::from_str("0100000001c997a5e56e104102fa209c6a852dd90660a20b2d9c352423edce25857fcd3704000000004847304402204e45e16932b8af514961a1d3a1a25fdf3f4f7732e9d624c6c61548ab5fb8cd410220181522ec8eca07de4860a4acdd12909d831cc56cbbac4622082221a8768d1d0901ffffffff0200ca9a3b00000000434104ae1a62fe09c5f51b13905f07f06b99a2f7159b2225f374cd378d71302fa28414e7aab37397f554a7df5f142c21c1b7303b8a0626f1baded5c72a704f7e6cd84cac00286bee0000000043410411db93e1dcdb8a016b49840f8c53bc1eb68a382e97b1482ecad7b148a6909a5cb2e0eaddfb84ccf9744464f82e160bfa9b8b64f9d4c03f999b8643f656b412a3ac00000000").unwrap();
+        let script_code = &tx.output[1].script_pubkey;
+        let signature = &tx.input[0].script_sig[1..];
+        let sighash = legacy_sighash(&tx, 0, script_code, SIGHASH_ALL.into()).unwrap();
+        assert!(verify_ecdsa(signature, &script_code[1..66], &sighash));
+        let sighash = legacy_sighash(&tx, 0, script_code, SIGHASH_NONE.into()).unwrap();
+        assert!(!verify_ecdsa(signature, &script_code[1..66], &sighash));
+
+        // OP_CODESEPARATORs are not signed
+        let mut separated = vec![opcodes::OP_CODESEPARATOR];
+        separated.extend(script_code);
+        assert_eq!(
+            legacy_sighash(&tx, 0, &separated, SIGHASH_ALL.into()).unwrap(),
+            legacy_sighash(&tx, 0, script_code, SIGHASH_ALL.into()).unwrap()
+        );
+        let mut two_inputs = tx.clone();
+        two_inputs.input.push(tx.input[0].clone());
+        two_inputs.output.truncate(1);
+        assert_eq!(
+            legacy_sighash(&two_inputs, 1, script_code, SIGHASH_SINGLE.into()).unwrap(),
+            SIGHASH_SINGLE_BUG
+        );
+        assert_eq!(
+            legacy_sighash(&tx, 1, script_code, SIGHASH_ALL.into()),
+            Err(SighashError::InputIndexOutOfRange(1))
+        );
+    }
+
+    #[test]
+    fn test_legacy_vectors() {
+        // from Bitcoin Core's sighash.json, which signs all 32 bits of the hash type
+        for (tx, script_code, input_index, hash_type, expected) in [
+            (
+                "907c2bc503ade11cc3b04eb2918b6f547b0630ab569273824748c87ea14b0696526c66ba740200000004ab65ababfd1f9bdd4ef073c7afc4ae00da8a66f429c917a0081ad1e1dabce28d373eab81d8628de802000000096aab5253ab52000052ad042b5f25efb33beec9f3364e8a9139e8439d9d7e26529c3c30b6c3fd89f8684cfd68ea0200000009ab53526500636a52ab599ac2fe02a526ed040000000008535300516352515164370e010000000003006300ab2ec229",
+                "",
+                2,
+                1_864_164_639,
+                "31af167a6cf3f9d5f6875caa4d31704ceb0eba078d132b78dab52c3b8997317e",
+            ),
+            (
+                "73107cbd025c22ebc8c3e0a47b2a760739216a528de8d4dab5d45cbeb3051cebae73b01ca10200000007ab6353656a636affffffffe26816dffc670841e6a6c8c61c586da401df1261a330a6c6b3dd9f9a0789bc9e000000000800ac6552ac6aac51ffffffff0174a8f0010000000004ac52515100000000",
+                "5163ac63635151ac",
+                1,
+                1_190_874_345,
+                "06e328de263a87b09beabe222a21627a6ea5c7f560030da31610c4611f4a46bc",
+            ),
+        ]
+        .iter()
+        {
+            let tx = Transaction::from_str(tx).unwrap();
+            let script_code = hex::decode(script_code).unwrap();
+            let mut sighash =
+                legacy_sighash(&tx, *input_index, &script_code, *hash_type).unwrap();
+            // the file lists hashes in display order
+            sighash.reverse();
+            assert_eq!(hex::encode(sighash), *expected);
+        }
+    }
+
+    #[test]
+    fn test_bip143_vectors() {
+        // Native P2WPKH
+        let tx = Transaction::from_str("0100000002fff7f7881a8099afa6940d42d1e7f6362bec38171ea3edf433541db4e4ad969f0000000000eeffffffef51e1b804cc89d182d279655c3aa89e815b1b309fe287d9b2b55d57b90ec68a0100000000ffffffff02202cb206000000001976a9148280b37df378db99f66f85c95a783a76ac7a6d5988ac9093510d000000001976a9143bde42dbee7e4dbe6a21b2d50ce2f0167faa815988ac11000000").unwrap();
+        let script_code =
+            hex::decode("76a9141d0f172a0ecb48aee1be1f2687d2963ae33f71a188ac").unwrap();
+        assert_eq!(
+            segwit_v0_sighash(&tx, 1, &script_code, 600_000_000, SIGHASH_ALL).unwrap(),
+            sighash("c37af31116d1b27caf68aae9e3ac82f1477929014d5b917657d0eb49478cb670")
+        );
+
+        // P2SH-P2WPKH
+        let tx = Transaction::from_str("0100000001db6b1b20aa0fd7b23880be2ecbd4a98130974cf4748fb66092ac4d3ceb1a54770100000000feffffff02b8b4eb0b000000001976a914a457b684d7f0d539a46a45bbc043f35b59d0d96388ac0008af2f000000001976a914fd270b1ee6abcaea97fea7ad0402e8bd8ad6d77c88ac92040000").unwrap();
+        let script_code =
+            hex::decode("76a91479091972186c449eb1ded22b78e40d009bdf008988ac").unwrap();
+        assert_eq!(
+            segwit_v0_sighash(&tx, 0, &script_code, 1_000_000_000, SIGHASH_ALL).unwrap(),
+            sighash("64f3b0f4dd2bb3aa1ce8566d220cc74dda9df97d8490cc81d89d735c92e59fb6")
+        );
+
+        // P2SH-P2WSH 6-of-6, signed with every sighash type
+        let tx = Transaction::from_str("010000000136641869ca081e70f394c6948e8af409e18b619df2ed74aa106c1ca29787b96e0100000000ffffffff0200e9a435000000001976a914389ffce9cd9ae88dcc0631e88a821ffdbe9bfe2688acc0832f05000000001976a9147480a33f950689af511e6e84c138dbbd3c3ee41588ac00000000").unwrap();
+        let witness_script = hex::decode("56210307b8ae49ac90a048e9b53357a2354b3334e9c8bee813ecb98e99a7e07e8c3ba32103b28f0c28bfab54554ae8c658ac5c3e0ce6e79ad336331f78c428dd43eea8449b21034b8113d703413d57761b8b9781957b8c0ac1dfe69f492580ca4195f50376ba4a21033400f6afecb833092a9a21cfdf1ed1376e58c5d1f47de74683123987e967a8f42103a6d48b1131e94ba04d9737d61acdaa1322008af9602b3b14862c07a1789aac162102d8b661b0b3302ee2f162b09e07a55ad5dfbe673a9f01d9f0c19617681024306b56ae").unwrap();
+        for (hash_type, expected) in [
+            (
+                SIGHASH_ALL,
+                "185c0be5263dce5b4bb50a047973c1b6272bfbd0103a89444597dc40b248ee7c",
+            ),
+            (
+                SIGHASH_NONE,
+                "e9733bc60ea13c95c6527066bb975a2ff29a925e80aa14c213f686cbae5d2f36",
+            ),
+            (
+                SIGHASH_SINGLE,
+                "1e1f1c303dc025bd664acb72e583e933fae4cff9148bf78c157d1e8f78530aea",
+            ),
+            (
+                SIGHASH_ALL | SIGHASH_ANYONECANPAY,
+                "2a67f03e63a6a422125878b40b82da593be8d4efaafe88ee528af6e5a9955c6e",
+            ),
+            (
+                SIGHASH_NONE | SIGHASH_ANYONECANPAY,
+                "781ba15f3779d5542ce8ecb5c18716733a5ee42a6f51488ec96154934e2c890a",
+            ),
+            (
+                SIGHASH_SINGLE | SIGHASH_ANYONECANPAY,
+                "511e8e52ed574121fc1b654970395502128263f62662e076dc6baf05c2e6a99b",
+            ),
+        ]
+        .iter()
+        {
+            assert_eq!(
+                segwit_v0_sighash(&tx, 0, &witness_script, 987_654_321, *hash_type).unwrap(),
+                sighash(expected),
+                "{:#04x}",
+                hash_type
+            );
+        }
+    }
+
+    #[test]
+    fn test_bip341_vectors() {
+        // keyPathSpending of the BIP341 wallet test vectors
+        let tx = Transaction::from_str("02000000097de20cbff686da83a54981d2b9bab3586f4ca7e48f57f5b55963115f3b334e9c010000000000000000d7b7cab57b1393ace2d064f4d4a2cb8af6def61273e127517d44759b6dafdd990000000000fffffffff8e1f583384333689228c5d28eac13366be082dc57441760d957275419a418420000000000fffffffff0689180aa63b30cb162a73c6d2a38b7eeda2a83ece74310fda0843ad604853b0100000000feffffffaa5202bdf6d8ccd2ee0f0202afbbb7461d9264a25e5bfd3c5a52ee1239e0ba6c0000000000feffffff956149bdc66faa968eb2be2d2faa29718acbfe3941215893a2a3446d32acd050000000000000000000e664b9773b88c09c32cb70a2a3e4da0ced63b7ba3b22f848531bbb1d5d5f4c94010000000000000000e9aa6b8e6c9de67619e6a3924ae25696bb7b694bb677a632a74ef7eadfd4eabf0000000000ffffffffa778eb6a263dc090464cd125c466b5a99667720b1c110468831d058aa1b82af10100000000ffffffff0200ca9a3b000000001976a91406afd46bcdfd22ef94ac122aa11f241244a37ecc88ac807840cb0000000020ac9a87f5594be208f8532db38cff670c450ed2fea8fcdefcc9a663f78bab962b0065cd1d").unwrap();
+        let prevouts: Vec<TxOut> = [
+            (
+                420_000_000,
+                "512053a1f6e454df1aa2776a2814a721372d6258050de330b3c6d10ee8f4e0dda343",
+            ),
+            (
+                462_000_000,
+                "5120147c9c57132f6e7ecddba9800bb0c4449251c92a1e60371ee77557b6620f3ea3",
+            ),
+            (
+                294_000_000,
+                "76a914751e76e8199196d454941c45d1b3a323f1433bd688ac",
+            ),
+            (
+                504_000_000,
+                "5120e4d810fd50586274face62b8a807eb9719cef49c04177cc6b76a9a4251d5450e",
+            ),
+            (
+                630_000_000,
+                "512091b64d5324723a985170e4dc5a0f84c041804f2cd12660fa5dec09fc21783605",
+            ),
+            (378_000_000, "00147dd65592d0ab2fe0d0257d571abf032cd9db93dc"),
+            (
+                672_000_000,
+                "512075169f4001aa68f15bbed28b218df1d0a62cbbcf1188c6665110c293c907b831",
+            ),
+            (
+                546_000_000,
+                "5120712447206d7a5238acc7ff53fbe94a3b64539ad291c7cdbc490b7577e4b17df5",
+            ),
+            (
+                588_000_000,
+                "512077e30a5522dd9f894c3f8b8bd4c4b2cf82ca7da8a3ea6a239655c39c050ab220",
+            ),
+        ]
+        .iter()
+        .map(|(value, script_pubkey)| TxOut {
+            value: *value,
+            script_pubkey: hex::decode(script_pubkey).unwrap(),
+        })
+        .collect();
+        for (input_index, hash_type, expected) in [
+            (
+                0,
+                SIGHASH_SINGLE,
+                "2514a6272f85cfa0f45eb907fcb0d121b808ed37c6ea160a5a9046ed5526d555",
+            ),
+            (
+                1,
+                SIGHASH_SINGLE | SIGHASH_ANYONECANPAY,
+                "325a644af47e8a5a2591cda0ab0723978537318f10e6a63d4eed783b96a71a4d",
+            ),
+            (
+                3,
+                SIGHASH_ALL,
+                "bf013ea93474aa67815b1b6cc441d23b64fa310911d991e713cd34c7f5d46669",
+            ),
+            (
+                4,
+                SIGHASH_DEFAULT,
+                "4f900a0bae3f1446fd48490c2958b5a023228f01661cda3496a11da502a7f7ef",
+            ),
+            (
+                6,
+                SIGHASH_NONE,
+                "15f25c298eb5cdc7eb1d638dd2d45c97c4c59dcaec6679cfc16ad84f30876b85",
+            ),
+            (
+                7,
+                SIGHASH_NONE | SIGHASH_ANYONECANPAY,
+                "cd292de50313804dabe4685e83f923d2969577191a3e1d2882220dca88cbeb10",
+            ),
+            (
+                8,
+                SIGHASH_ALL | SIGHASH_ANYONECANPAY,
+                "cccb739eca6c13a8a89e6e5cd317ffe55669bbda23f2fd37b0f18755e008edd2",
+            ),
+        ]
+        .iter()
+        {
+            assert_eq!(
+                taproot_sighash(&tx, *input_index, &prevouts, *hash_type, None, None).unwrap(),
+                sighash(expected),
+                "input {}",
+                input_index
+            );
+        }
+
+        assert_eq!(
+            taproot_sighash(&tx, 0, &prevouts, 0x04, None, None),
+            Err(SighashError::InvalidHashType(0x04))
+        );
+        assert_eq!(
+            taproot_sighash(&tx, 2, &prevouts, SIGHASH_SINGLE, None, None),
+            Err(SighashError::SingleWithoutOutput(2))
+        );
+        assert_eq!(
+            taproot_sighash(&tx, 0, &prevouts[1..], SIGHASH_ALL, None, None),
+            Err(SighashError::PrevoutCount(8, 9))
+        );
+
+        // script path spends of input 4 through the single leaf of the first BIP341
+        // scriptPubKey vector
+        let leaf_hash = taproot::tap_leaf_hash(
+            &hex::decode("20d85a959b0290bf19bb89ed43c916be835475d013da4b362117393e25a48229b8ac")
+                .unwrap(),
+            taproot::TAPROOT_LEAF_TAPSCRIPT,
+        );
+        assert_eq!(
+            leaf_hash,
+            sighash("5b75adecf53548f3ec6ad7d78383bf84cc57b55a3127c72b9a2481752dd88b21")
+        );
+        for (hash_type, code_separator_position, expected) in [
+            (
+                SIGHASH_DEFAULT,
+                0xffff_ffff,
+                "a2889022d272e9735136530b46f73635c2f596b0b44faa89d2f6d685981dedcd",
+            ),
+            (
+                SIGHASH_ALL,
+                1,
+                "fc773706281ad66eb3baec4b8beef20739f86347059aafa13bb37c4df8f27e44",
+            ),
+        ]
+        .iter()
+        {
+            let script_path = ScriptPath {
+                leaf_hash,
+                code_separator_position: *code_separator_position,
+            };
+            assert_eq!(
+                taproot_sighash(&tx, 4, &prevouts, *hash_type, None, Some(script_path)).unwrap(),
+                sighash(expected)
+            );
+        }
+    }
+}
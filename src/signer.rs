@@ -0,0 +1,581 @@
+use crate::{
+    address::{Address, Network},
+    bip32::ExtendedPrivKey,
+    error::PsbtError,
+    hash,
+    interpreter::{is_p2sh, witness_program},
+    psbt::{KeySource, Psbt, PsbtInput},
+    sighash::{
+        self, ScriptPath, SIGHASH_ALL, SIGHASH_ANYONECANPAY, SIGHASH_DEFAULT, SIGHASH_SINGLE,
+    },
+    taproot,
+    transaction::TxOut,
+    wallet::Seed,
+};
+use secp256k1::{schnorrsig, Message, Secp256k1, SecretKey};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::convert::TryFrom;
+
+#[derive(Deserialize, Debug)]
+// Get the seed of one cosigner and the base64 encoded PSBT it signs
+pub struct PsbtSignRequest {
+    #[serde(flatten)]
+    pub seed: Seed,
+    pub psbt: String,
+}
+
+#[derive(Serialize, Debug)]
+/// PsbtSignResponse is the signed PSBT and the number of signatures added, presented to user
+pub struct PsbtSignResponse {
+    pub psbt: String,
+    pub signatures: usize,
+}
+
+/// Signatures one input gets from the master key
+#[derive(Default)]
+struct InputSignatures {
+    partial_sigs: BTreeMap<Vec<u8>, Vec<u8>>,
+    tap_key_sig: Option<Vec<u8>>,
+    tap_script_sigs: BTreeMap<([u8; 32], [u8; 32]), Vec<u8>>,
+}
+
+impl InputSignatures {
+    fn len(&self) -> usize {
+        self.partial_sigs.len() + self.tap_key_sig.iter().count() + self.tap_script_sigs.len()
+    }
+
+    /// Add the signatures to `input` and return how many there are
+    fn add_to(self, input: &mut PsbtInput) -> usize {
+        let count = self.len();
+        input.partial_sigs.extend(self.partial_sigs);
+        if self.tap_key_sig.is_some() {
+            input.tap_key_sig = self.tap_key_sig;
+        }
+        input.tap_script_sigs.extend(self.tap_script_sigs);
+        count
+    }
+}
+
+/// Child of `master` at the path of `source`, if the path starts at `master`
+fn derive(
+    master: &ExtendedPrivKey,
+    source: &KeySource,
+) -> Result<Option<ExtendedPrivKey>, PsbtError> {
+    if source.fingerprint != master.fingerprint() {
+        return Ok(None);
+    }
+    master
+        .derive_priv(&source.path)
+        .map(Some)
+        .map_err(|_| PsbtError::KeyDerivation)
+}
+
+/// Sighash type of an ECDSA signature, `SIGHASH_ALL` when the input sets none
+fn ecdsa_hash_type(sighash_type: Option<u32>) -> Result<u8, PsbtError> {
+    let sighash_type = sighash_type.unwrap_or(SIGHASH_ALL as u32);
+    match u8::try_from(sighash_type) {
+        Ok(hash_type) if matches!(hash_type & !SIGHASH_ANYONECANPAY, 0x01..=0x03) => Ok(hash_type),
+        _ => Err(PsbtError::InvalidSighashType(sighash_type)),
+    }
+}
+
+/// Sighash type of a Schnorr signature, `SIGHASH_DEFAULT` when the input sets none
+fn schnorr_hash_type(sighash_type: Option<u32>) -> Result<u8, PsbtError> {
+    let sighash_type = sighash_type.unwrap_or(SIGHASH_DEFAULT as u32);
+    match u8::try_from(sighash_type) {
+        Ok(hash_type) if matches!(hash_type, 0x00..=0x03 | 0x81..=0x83) => Ok(hash_type),
+        _ => Err(PsbtError::InvalidSighashType(sighash_type)),
+    }
+}
+
+/// ECDSA signatures of input `index` by the keys of `bip32_derivation` under `master`
+fn sign_ecdsa(
+    psbt: &Psbt,
+    index: usize,
+    master: &ExtendedPrivKey,
+) -> Result<BTreeMap<Vec<u8>, Vec<u8>>, PsbtError> {
+    let input = &psbt.inputs[index];
+    let mut keys = Vec::new();
+    for (public_key, source) in &input.bip32_derivation {
+        if let Some(child) = derive(master, source)? {
+            // an uncompressed key in the PSBT derives the same point
+            let derived = child.public_key();
+            if derived.serialize()[..] == public_key[..]
+                || derived.serialize_uncompressed()[..] == public_key[..]
+            {
+                keys.push((public_key.clone(), child.secret_key));
+            }
+        }
+    }
+    ecdsa_signatures(psbt, index, keys)
+}
+
+/// ECDSA signatures of input `index` by `keys`, given with their public keys as the input
+/// lists them
+fn ecdsa_signatures(
+    psbt: &Psbt,
+    index: usize,
+    keys: Vec<(Vec<u8>, SecretKey)>,
+) -> Result<BTreeMap<Vec<u8>, Vec<u8>>, PsbtError> {
+    if keys.is_empty() {
+        return Ok(BTreeMap::new());
+    }
+
+    let input = &psbt.inputs[index];
+    let hash_type = ecdsa_hash_type(input.sighash_type)?;
+    let spent_output = psbt
+        .spent_output(index)
+        .ok_or(PsbtError::MissingUtxo(index))?;
+    let script_pubkey = &spent_output.script_pubkey;
+    let script = if is_p2sh(script_pubkey) {
+        match &input.redeem_script {
+            Some(redeem_script) if hash::hash160(redeem_script)[..] == script_pubkey[2..22] => {
+                redeem_script
+            }
+            _ => return Err(PsbtError::ScriptMismatch(index)),
+        }
+    } else {
+        script_pubkey
+    };
+
+    let secp = Secp256k1::new();
+    let mut signatures = BTreeMap::new();
+    for (public_key, secret_key) in keys {
+        let sighash = match witness_program(script) {
+            Some((0, program)) if program.len() == 20 => {
+                if hash::hash160(&public_key)[..] != program[..] {
+                    continue;
+                }
+                let script_code = Address::p2pkh(&public_key, Network::Bitcoin).script_pubkey();
+                sighash::segwit_v0_sighash(
+                    &psbt.unsigned_tx,
+                    index,
+                    &script_code,
+                    spent_output.value,
+                    hash_type,
+                )?
+            }
+            Some((0, program)) if program.len() == 32 => match &input.witness_script {
+                Some(witness_script) if hash::sha256(witness_script)[..] == program[..] => {
+                    sighash::segwit_v0_sighash(
+                        &psbt.unsigned_tx,
+                        index,
+                        witness_script,
+                        spent_output.value,
+                        hash_type,
+                    )?
+                }
+                _ => return Err(PsbtError::ScriptMismatch(index)),
+            },
+            Some(_) => return Err(PsbtError::ScriptMismatch(index)),
+            None => {
+                // the amount a legacy signature does not commit to is only safe to trust
+                // from the whole previous transaction
+                if input.non_witness_utxo.is_none() {
+                    return Err(PsbtError::MissingUtxo(index));
+                }
+                // without a matching output a legacy signature signs the number one, which
+                // lets anyone spend the input in any transaction
+                if hash_type & !SIGHASH_ANYONECANPAY == SIGHASH_SINGLE
+                    && index >= psbt.unsigned_tx.output.len()
+                {
+                    return Err(PsbtError::InvalidSighashType(hash_type.into()));
+                }
+                sighash::legacy_sighash(&psbt.unsigned_tx, index, script, hash_type.into())?
+            }
+        };
+        let mut signature = secp
+            .sign(
+                &Message::from_slice(&sighash).expect("32 bytes"),
+                &secret_key,
+            )
+            .serialize_der()
+            .to_vec();
+        signature.push(hash_type);
+        signatures.insert(public_key, signature);
+    }
+    Ok(signatures)
+}
+
+/// Schnorr signatures of input `index` by the keys of `tap_bip32_derivation` under `master`,
+/// for the key path when the key is the internal key and for every leaf listed with it
+fn sign_schnorr(
+    psbt: &Psbt,
+    index: usize,
+    master: &ExtendedPrivKey,
+) -> Result<InputSignatures, PsbtError> {
+    let input = &psbt.inputs[index];
+    let mut signatures = InputSignatures::default();
+    for (x_only_key, source) in &input.tap_bip32_derivation {
+        let child = match derive(master, &source.source)? {
+            Some(child) => child,
+            None => continue,
+        };
+        if taproot::x_only_key(&child.public_key().serialize()).ok() != Some(*x_only_key) {
+            continue;
+        }
+        schnorr_signatures(
+            psbt,
+            index,
+            x_only_key,
+            &source.leaf_hashes,
+            child.secret_key,
+            &mut signatures,
+        )?;
+    }
+    Ok(signatures)
+}
+
+/// Schnorr signatures of input `index` by `secret_key`, for the key path when its `x_only_key`
+/// is the internal key and for every leaf of `leaf_hashes`
+fn schnorr_signatures(
+    psbt: &Psbt,
+    index: usize,
+    x_only_key: &[u8; 32],
+    leaf_hashes: &[[u8; 32]],
+    secret_key: SecretKey,
+    signatures: &mut InputSignatures,
+) -> Result<(), PsbtError> {
+    let input = &psbt.inputs[index];
+    let secp = Secp256k1::new();
+    let key_path = input.tap_internal_key == Some(*x_only_key);
+    if !key_path && leaf_hashes.is_empty() {
+        return Ok(());
+    }
+
+    let hash_type = schnorr_hash_type(input.sighash_type)?;
+    let prevouts = (0..psbt.inputs.len())
+        .map(|i| {
+            psbt.spent_output(i)
+                .cloned()
+                .ok_or(PsbtError::MissingUtxo(i))
+        })
+        .collect::<Result<Vec<TxOut>, _>>()?;
+    let output_key = match witness_program(&prevouts[index].script_pubkey) {
+        Some((1, program)) if program.len() == 32 => program.to_vec(),
+        _ => return Err(PsbtError::ScriptMismatch(index)),
+    };
+    let sign = |keypair: &schnorrsig::KeyPair, script_path| {
+        let sighash = sighash::taproot_sighash(
+            &psbt.unsigned_tx,
+            index,
+            &prevouts,
+            hash_type,
+            None,
+            script_path,
+        )?;
+        let mut signature = secp
+            .schnorrsig_sign_no_aux_rand(&Message::from_slice(&sighash).expect("32 bytes"), keypair)
+            .as_ref()
+            .to_vec();
+        if hash_type != SIGHASH_DEFAULT {
+            signature.push(hash_type);
+        }
+        Ok::<_, PsbtError>(signature)
+    };
+
+    let keypair = schnorrsig::KeyPair::from_secret_key(&secp, secret_key);
+    if key_path {
+        let (tweaked_key, _) = taproot::output_key(x_only_key, input.tap_merkle_root)
+            .map_err(|_| PsbtError::KeyDerivation)?;
+        if tweaked_key[..] != output_key[..] {
+            return Err(PsbtError::ScriptMismatch(index));
+        }
+        let mut data = x_only_key.to_vec();
+        data.extend(input.tap_merkle_root.iter().flatten());
+        let mut tweaked = keypair;
+        tweaked
+            .tweak_add_assign(&secp, &hash::tagged_hash("TapTweak", &data))
+            .map_err(|_| PsbtError::KeyDerivation)?;
+        signatures.tap_key_sig = Some(sign(&tweaked, None)?);
+    }
+    for leaf_hash in leaf_hashes {
+        let script_path = ScriptPath {
+            leaf_hash: *leaf_hash,
+            code_separator_position: 0xffff_ffff,
+        };
+        signatures.tap_script_sigs.insert(
+            (*x_only_key, *leaf_hash),
+            sign(&keypair, Some(script_path))?,
+        );
+    }
+    Ok(())
+}
+
+/// Add the signatures of every key of `psbt` derived from `master`, matched by the master
+/// fingerprint, and return how many were added
+///
+/// Finalized inputs are left alone. A key whose path does not lead to the public key it is
+/// listed with is not ours and is skipped.
+pub fn sign(psbt: &mut Psbt, master: &ExtendedPrivKey) -> Result<usize, PsbtError> {
+    let mut count = 0;
+    for index in 0..psbt.inputs.len() {
+        let input = &psbt.inputs[index];
+        if input.final_script_sig.is_some() || input.final_script_witness.is_some() {
+            continue;
+        }
+        let mut signatures = sign_schnorr(psbt, index, master)?;
+        signatures.partial_sigs = sign_ecdsa(psbt, index, master)?;
+        count += signatures.add_to(&mut psbt.inputs[index]);
+    }
+    Ok(count)
+}
+
+/// Sign input `index` with a single key that is not derived from a seed, such as an imported
+/// WIF, and return how many signatures were added
+///
+/// `public_key` is serialized as the input spends it. Taproot inputs are signed on the key path
+/// and need the x-only form of the key as their internal key.
+pub fn sign_with_key(
+    psbt: &mut Psbt,
+    index: usize,
+    public_key: &[u8],
+    secret_key: SecretKey,
+) -> Result<usize, PsbtError> {
+    let mut signatures = InputSignatures::default();
+    let spent_output = psbt
+        .spent_output(index)
+        .ok_or(PsbtError::MissingUtxo(index))?;
+    if let Some((1, _)) = witness_program(&spent_output.script_pubkey) {
+        let x_only_key = taproot::x_only_key(public_key).map_err(|_| PsbtError::KeyDerivation)?;
+        schnorr_signatures(psbt, index, &x_only_key, &[], secret_key, &mut signatures)?;
+    } else {
+        signatures.partial_sigs =
+            ecdsa_signatures(psbt, index, vec![(public_key.to_vec(), secret_key)])?;
+    }
+    Ok(signatures.add_to(&mut psbt.inputs[index]))
+}
+
+impl PsbtSignRequest {
+    pub fn sign(&self) -> Result<PsbtSignResponse, PsbtError> {
+        let mut psbt: Psbt = self.psbt.parse()?;
+        let signatures = sign(&mut psbt, &self.seed.master_key())?;
+        Ok(PsbtSignResponse {
+            psbt: psbt.to_string(),
+            signatures,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        bip32::DerivationPath,
+        interpreter::verify_ecdsa,
+        psbt::TapKeySource,
+        script::Builder,
+        transaction::{OutPoint, Transaction, TxIn, Txid},
+        wallet::tests::master,
+    };
+    use std::str::FromStr;
+
+    fn key_source(master: &ExtendedPrivKey, path: &str) -> (Vec<u8>, KeySource) {
+        let path = DerivationPath::from_str(path).unwrap();
+        let public_key = master.derive_priv(&path).unwrap().public_key().serialize();
+        let source = KeySource {
+            fingerprint: master.fingerprint(),
+            path,
+        };
+        (public_key.to_vec(), source)
+    }
+
+    /// PSBT spending one output of `script_pubkey` to a P2WPKH output
+    fn psbt(script_pubkey: Vec<u8>) -> Psbt {
+        let txid =
+            Txid::from_str("f4184fc596403b9d638783cf57adfe4c75c605f6356fbc91338530e9831e9e16")
+                .unwrap();
+        let mut psbt = Psbt::from_unsigned_tx(Transaction {
+            version: 2,
+            input: vec![TxIn::new(OutPoint::new(txid, 1), 0xffff_fffd)],
+            output: vec![TxOut {
+                value: 90_000,
+                script_pubkey: Address::p2wpkh(&[0x02; 33], Network::Bitcoin).script_pubkey(),
+            }],
+            lock_time: 0,
+        })
+        .unwrap();
+        psbt.inputs[0].witness_utxo = Some(TxOut {
+            value: 100_000,
+            script_pubkey,
+        });
+        psbt
+    }
+
+    #[test]
+    fn test_sign_p2wpkh() {
+        let master = master();
+        let (public_key, source) = key_source(&master, "m/84'/0'/0'/0/0");
+        let mut psbt = psbt(Address::p2wpkh(&public_key, Network::Bitcoin).script_pubkey());
+        psbt.inputs[0]
+            .bip32_derivation
+            .insert(public_key.clone(), source);
+        assert_eq!(sign(&mut psbt, &master), Ok(1));
+
+        let signature = &psbt.inputs[0].partial_sigs[&public_key];
+        assert_eq!(signature.last(), Some(&SIGHASH_ALL));
+        let script_code = Address::p2pkh(&public_key, Network::Bitcoin).script_pubkey();
+        let sighash =
+            sighash::segwit_v0_sighash(&psbt.unsigned_tx, 0, &script_code, 100_000, SIGHASH_ALL)
+                .unwrap();
+        assert!(verify_ecdsa(signature, &public_key, &sighash));
+        assert_eq!(Psbt::from_str(&psbt.to_string()).unwrap(), psbt);
+
+        // keys of another wallet are left alone
+        let mut other = psbt.clone();
+        other.inputs[0].partial_sigs.clear();
+        let (public_key, _) = key_source(&master, "m/84'/0'/0'/0/1");
+        for source in other.inputs[0].bip32_derivation.values_mut() {
+            source.fingerprint = [0xde, 0xad, 0xbe, 0xef];
+        }
+        other.inputs[0]
+            .bip32_derivation
+            .insert(public_key, key_source(&master, "m/84'/0'/0'/0/2").1);
+        assert_eq!(sign(&mut other, &master), Ok(0));
+        assert!(other.inputs[0].partial_sigs.is_empty());
+    }
+
+    #[test]
+    fn test_sign_multisig() {
+        let master = master();
+        let (public_key, source) = key_source(&master, "m/48'/0'/0'/2'/0/0");
+        let witness_script = Builder::new()
+            .push_int(2)
+            .push_slice(&public_key)
+            .push_slice(&[0x03; 33])
+            .push_int(2)
+            .push_opcode(crate::opcodes::OP_CHECKMULTISIG)
+            .into_script();
+        let mut psbt = psbt(Address::p2shwsh(&witness_script, Network::Bitcoin).script_pubkey());
+        let input = &mut psbt.inputs[0];
+        input.redeem_script =
+            Some(Address::p2wsh(&witness_script, Network::Bitcoin).script_pubkey());
+        input.witness_script = Some(witness_script.clone());
+        input.sighash_type = Some(0x83);
+        input.bip32_derivation.insert(public_key.clone(), source);
+        assert_eq!(sign(&mut psbt, &master), Ok(1));
+        let sighash =
+            sighash::segwit_v0_sighash(&psbt.unsigned_tx, 0, &witness_script, 100_000, 0x83)
+                .unwrap();
+        assert!(verify_ecdsa(
+            &psbt.inputs[0].partial_sigs[&public_key],
+            &public_key,
+            &sighash
+        ));
+
+        psbt.inputs[0].witness_script = Some(vec![0x51]);
+        assert_eq!(sign(&mut psbt, &master), Err(PsbtError::ScriptMismatch(0)));
+        psbt.inputs[0].witness_script = Some(witness_script);
+        psbt.inputs[0].sighash_type = Some(0x04);
+        assert_eq!(
+            sign(&mut psbt, &master),
+            Err(PsbtError::InvalidSighashType(0x04))
+        );
+    }
+
+    #[test]
+    fn test_sign_legacy() {
+        let master = master();
+        let (public_key, source) = key_source(&master, "m/44'/0'/0'/0/0");
+        let script_pubkey = Address::p2pkh(&public_key, Network::Bitcoin).script_pubkey();
+        let previous = psbt(script_pubkey.clone()).unsigned_tx;
+        let mut psbt = psbt(script_pubkey.clone());
+        psbt.inputs[0]
+            .bip32_derivation
+            .insert(public_key.clone(), source);
+        assert_eq!(sign(&mut psbt, &master), Err(PsbtError::MissingUtxo(0)));
+
+        let mut previous = previous;
+        previous.output[0] = TxOut {
+            value: 100_000,
+            script_pubkey: script_pubkey.clone(),
+        };
+        psbt.unsigned_tx.input[0].previous_output = OutPoint::new(previous.txid(), 0);
+        psbt.inputs[0].witness_utxo = None;
+        psbt.inputs[0].non_witness_utxo = Some(previous);
+        assert_eq!(sign(&mut psbt, &master), Ok(1));
+        let sighash =
+            sighash::legacy_sighash(&psbt.unsigned_tx, 0, &script_pubkey, SIGHASH_ALL.into())
+                .unwrap();
+        assert!(verify_ecdsa(
+            &psbt.inputs[0].partial_sigs[&public_key],
+            &public_key,
+            &sighash
+        ));
+
+        psbt.inputs[0].partial_sigs.clear();
+        psbt.unsigned_tx.output.clear();
+        psbt.outputs.clear();
+        for hash_type in [0x03, 0x83].iter() {
+            psbt.inputs[0].sighash_type = Some(*hash_type);
+            assert_eq!(
+                sign(&mut psbt, &master),
+                Err(PsbtError::InvalidSighashType(*hash_type))
+            );
+        }
+        assert!(psbt.inputs[0].partial_sigs.is_empty());
+    }
+
+    #[test]
+    fn test_sign_taproot() {
+        let master = master();
+        let (public_key, source) = key_source(&master, "m/86'/0'/0'/0/0");
+        let internal_key = taproot::x_only_key(&public_key).unwrap();
+        let leaf_hash = taproot::tap_leaf_hash(&[0x51], 0xc0);
+        let (output_key, _) = taproot::output_key(&internal_key, Some(leaf_hash)).unwrap();
+        let script_pubkey = Address::p2tr_tweaked(output_key, Network::Bitcoin).script_pubkey();
+        let mut psbt = psbt(script_pubkey.clone());
+        let input = &mut psbt.inputs[0];
+        input.tap_internal_key = Some(internal_key);
+        input.tap_merkle_root = Some(leaf_hash);
+        input.tap_bip32_derivation.insert(
+            internal_key,
+            TapKeySource {
+                leaf_hashes: vec![leaf_hash],
+                source,
+            },
+        );
+        assert_eq!(sign(&mut psbt, &master), Ok(2));
+        assert_eq!(Psbt::from_str(&psbt.to_string()).unwrap(), psbt);
+
+        let secp = Secp256k1::new();
+        let prevouts = [psbt.inputs[0].witness_utxo.clone().unwrap()];
+        let verify = |signature: &[u8], key: &[u8; 32], script_path| {
+            let sighash = sighash::taproot_sighash(
+                &psbt.unsigned_tx,
+                0,
+                &prevouts,
+                SIGHASH_DEFAULT,
+                None,
+                script_path,
+            )
+            .unwrap();
+            secp.schnorrsig_verify(
+                &schnorrsig::Signature::from_slice(signature).unwrap(),
+                &Message::from_slice(&sighash).unwrap(),
+                &schnorrsig::PublicKey::from_slice(key).unwrap(),
+            )
+            .is_ok()
+        };
+        let input = &psbt.inputs[0];
+        assert!(verify(
+            input.tap_key_sig.as_ref().unwrap(),
+            &output_key,
+            None
+        ));
+        let script_path = ScriptPath {
+            leaf_hash,
+            code_separator_position: 0xffff_ffff,
+        };
+        assert!(verify(
+            &input.tap_script_sigs[&(internal_key, leaf_hash)],
+            &internal_key,
+            Some(script_path)
+        ));
+
+        // the key does not commit to the script tree the PSBT claims
+        psbt.inputs[0].tap_merkle_root = None;
+        assert_eq!(sign(&mut psbt, &master), Err(PsbtError::ScriptMismatch(0)));
+    }
+}
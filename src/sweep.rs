@@ -0,0 +1,534 @@
+use crate::{
+    address::{Address, Network},
+    balance::{self, UtxoResponse, XpubQuery, DEFAULT_GAP_LIMIT},
+    bip32::{ExtendedPrivKey, ExtendedPubKey},
+    chain::ChainBackend,
+    coinselect::{parse_address, parse_path, SpendableUtxo, TX_OVERHEAD_WEIGHT},
+    error::{BalanceError, PsbtError, SelectionError, SweepError},
+    estimator::{fee, output_weight, AddressType},
+    finalizer,
+    interpreter::{is_p2sh, witness_program},
+    policy::dust_threshold,
+    psbt::Psbt,
+    signer, taproot,
+    transaction::{sum_amounts, OutPoint, Transaction, TxIn, TxOut, Txid, SEQUENCE_ENABLE_RBF},
+    wallet::Seed,
+    wif::PrivateKey,
+};
+use secp256k1::constants::{PUBLIC_KEY_SIZE, UNCOMPRESSED_PUBLIC_KEY_SIZE};
+use serde::{Deserialize, Serialize};
+use std::str::FromStr;
+
+/// First account of the BIP44, BIP49, BIP84 and BIP86 purposes, whose addresses are looked up
+/// when a seed is swept without UTXOs
+const ACCOUNTS: [(&str, AddressType); 4] = [
+    ("m/44'/0'/0'", AddressType::P2pkh),
+    ("m/49'/0'/0'", AddressType::P2shP2wpkh),
+    ("m/84'/0'/0'", AddressType::P2wpkh),
+    ("m/86'/0'/0'", AddressType::P2tr),
+];
+
+#[derive(Deserialize, Debug)]
+// Get the key of a paper wallet or a seed, its UTXOs and the destination from user
+pub struct SweepRequest {
+    /// Key of a paper wallet in Wallet Import Format
+    pub wif: Option<String>,
+    /// Seed whose keys are derived at the path of every UTXO
+    #[serde(flatten)]
+    pub seed: Option<Seed>,
+    /// Looked up with the chain backend when left out
+    #[serde(default)]
+    pub utxos: Vec<SpendableUtxo>,
+    /// Address everything is swept to
+    pub destination: String,
+    /// Fee rate in sat/vB
+    pub fee_rate: f64,
+    #[serde(default)]
+    pub locktime: u32,
+    #[serde(default)]
+    pub network: Network,
+}
+
+#[derive(Serialize, Debug)]
+/// SweepResponse is the signed transaction moving every UTXO to the destination, presented to user
+pub struct SweepResponse {
+    pub tx: String,
+    pub txid: String,
+    pub amount: u64,
+    pub fee: u64,
+    pub vsize: usize,
+}
+
+/// Key the UTXOs of a sweep are signed with
+enum SweepKey {
+    Wif(PrivateKey),
+    /// Master key of the seed, derived at the path of every UTXO
+    Seed(ExtendedPrivKey),
+}
+
+/// Whether `public_key` can spend `script_pubkey` alone, as P2PKH, P2WPKH, P2SH-P2WPKH or the
+/// key path of a P2TR output without a script tree
+fn spends(public_key: &[u8], script_pubkey: &[u8], network: Network) -> bool {
+    let compressed = public_key.len() == PUBLIC_KEY_SIZE;
+    match witness_program(script_pubkey) {
+        Some((0, _)) if compressed => {
+            Address::p2wpkh(public_key, network).script_pubkey() == script_pubkey
+        }
+        Some((1, program)) if compressed => taproot::x_only_key(public_key)
+            .and_then(|internal_key| taproot::output_key(&internal_key, None))
+            .is_ok_and(|(output_key, _)| output_key[..] == program[..]),
+        Some(_) => false,
+        None if is_p2sh(script_pubkey) => {
+            compressed && Address::p2shwpkh(public_key, network).script_pubkey() == script_pubkey
+        }
+        None => Address::p2pkh(public_key, network).script_pubkey() == script_pubkey,
+    }
+}
+
+impl SweepRequest {
+    /// UTXOs `backend` finds for `key`: of every address type a WIF key can spend alone, or of
+    /// the first account of every purpose of a seed, with their paths
+    async fn lookup_utxos(
+        &self,
+        key: &SweepKey,
+        backend: Option<&dyn ChainBackend>,
+    ) -> Result<Vec<SpendableUtxo>, SweepError> {
+        let backend = backend.ok_or(SweepError::NoChainBackend)?;
+        let mut found: Vec<UtxoResponse> = Vec::new();
+        match key {
+            SweepKey::Wif(private_key) => {
+                let public_key = private_key.public_key();
+                let mut addresses = vec![Address::p2pkh(&public_key, self.network)];
+                if private_key.compressed {
+                    let (output_key, _) = taproot::x_only_key(&public_key)
+                        .and_then(|internal_key| taproot::output_key(&internal_key, None))
+                        .map_err(|_| SelectionError::KeyDerivation)?;
+                    addresses.push(Address::p2shwpkh(&public_key, self.network));
+                    addresses.push(Address::p2wpkh(&public_key, self.network));
+                    addresses.push(Address::p2tr_tweaked(output_key, self.network));
+                }
+                for address in addresses {
+                    found.extend(balance::address_utxos(backend, &address.to_string()).await?);
+                }
+            }
+            SweepKey::Seed(master) => {
+                for (path, address_type) in ACCOUNTS.iter() {
+                    let account = master
+                        .derive_priv(&parse_path(path)?)
+                        .map_err(|_| SelectionError::KeyDerivation)?;
+                    let query = XpubQuery {
+                        address_type: *address_type,
+                        gap_limit: DEFAULT_GAP_LIMIT,
+                        network: self.network,
+                    };
+                    let xpub = ExtendedPubKey::from_priv(&account);
+                    let balance = balance::account_balance(backend, &xpub, &query).await?;
+                    // paths below the account are relative, e.g. m/0/3
+                    found.extend(balance.utxos.into_iter().map(|utxo| {
+                        UtxoResponse {
+                            path: utxo
+                                .path
+                                .as_ref()
+                                .map(|relative| format!("{}{}", path, &relative[1..])),
+                            ..utxo
+                        }
+                    }));
+                }
+            }
+        }
+
+        let mut utxos = Vec::new();
+        for utxo in found {
+            let script_pubkey =
+                parse_address(&utxo.address, SelectionError::InvalidAddress)?.script_pubkey();
+            // legacy inputs are signed with the transaction holding the output
+            let non_witness_utxo =
+                match witness_program(&script_pubkey).is_none() && !is_p2sh(&script_pubkey) {
+                    true => {
+                        let txid = Txid::from_str(&utxo.txid).map_err(PsbtError::from)?;
+                        let tx = backend
+                            .transaction(txid)
+                            .await
+                            .map_err(BalanceError::from)?;
+                        Some(hex::encode(tx.serialize()))
+                    }
+                    false => None,
+                };
+            utxos.push(SpendableUtxo {
+                txid: utxo.txid,
+                vout: utxo.vout,
+                amount: utxo.amount,
+                address: utxo.address,
+                path: utxo.path,
+                non_witness_utxo,
+                redeem_script: None,
+                witness_script: None,
+                satisfaction_weight: None,
+            });
+        }
+        Ok(utxos)
+    }
+
+    /// Spend every UTXO to the destination in a single output, signed and ready to broadcast.
+    /// Without UTXOs in the request, those of the key are looked up with `backend`.
+    pub async fn sweep(
+        &self,
+        backend: Option<&dyn ChainBackend>,
+    ) -> Result<SweepResponse, SweepError> {
+        if !(self.fee_rate > 0.0 && self.fee_rate.is_finite()) {
+            return Err(SelectionError::InvalidFeeRate.into());
+        }
+        let key = match (&self.wif, &self.seed) {
+            (Some(wif), None) => {
+                SweepKey::Wif(PrivateKey::from_str(wif).map_err(|_| SweepError::InvalidWif)?)
+            }
+            (None, Some(seed)) => SweepKey::Seed(seed.master_key()),
+            _ => return Err(SweepError::KeySource),
+        };
+        let looked_up;
+        let utxos = match self.utxos.is_empty() {
+            true => {
+                looked_up = self.lookup_utxos(&key, backend).await?;
+                &looked_up
+            }
+            false => &self.utxos,
+        };
+        if utxos.is_empty() {
+            return Err(SweepError::NoUtxo);
+        }
+        let destination =
+            parse_address(&self.destination, SelectionError::InvalidAddress)?.script_pubkey();
+
+        let mut weight = TX_OVERHEAD_WEIGHT + output_weight(destination.len());
+        let mut input = Vec::new();
+        for utxo in utxos {
+            let public_key = match &key {
+                SweepKey::Wif(private_key) => Some(private_key.public_key()),
+                SweepKey::Seed(master) => utxo.public_key(master)?,
+            };
+            weight += utxo.weight(public_key.as_deref())?;
+            if matches!(key, SweepKey::Wif(private_key) if !private_key.compressed) {
+                // the scriptSig of a P2PKH input pushes the uncompressed key
+                weight += (UNCOMPRESSED_PUBLIC_KEY_SIZE - PUBLIC_KEY_SIZE) * 4;
+            }
+            let txid = Txid::from_str(&utxo.txid).map_err(PsbtError::from)?;
+            input.push(TxIn::new(
+                OutPoint::new(txid, utxo.vout),
+                SEQUENCE_ENABLE_RBF,
+            ));
+        }
+        let available = sum_amounts(utxos.iter().map(|utxo| utxo.amount))
+            .ok_or(SelectionError::AmountOutOfRange)?;
+        let fee = fee(weight, self.fee_rate);
+        let needed = sum_amounts([fee, dust_threshold(&destination)])
+            .ok_or(SelectionError::AmountOutOfRange)?;
+        if available < needed {
+            return Err(SelectionError::InsufficientFunds { needed, available }.into());
+        }
+        let mut psbt = Psbt::from_unsigned_tx(Transaction {
+            version: 2,
+            input,
+            output: vec![TxOut {
+                value: available - fee,
+                script_pubkey: destination,
+            }],
+            lock_time: self.locktime,
+        })?;
+
+        match key {
+            SweepKey::Wif(private_key) => {
+                let public_key = private_key.public_key();
+                for (index, utxo) in utxos.iter().enumerate() {
+                    let script_pubkey = utxo.fill_utxo(&mut psbt, index)?;
+                    if !spends(&public_key, &script_pubkey, self.network) {
+                        return Err(SelectionError::KeyMismatch(index).into());
+                    }
+                    let input = &mut psbt.inputs[index];
+                    if is_p2sh(&script_pubkey) {
+                        input.redeem_script =
+                            Some(Address::p2wpkh(&public_key, self.network).script_pubkey());
+                    } else if let Some((1, _)) = witness_program(&script_pubkey) {
+                        input.tap_internal_key = taproot::x_only_key(&public_key).ok();
+                    }
+                    signer::sign_with_key(&mut psbt, index, &public_key, private_key.secret_key)?;
+                }
+            }
+            SweepKey::Seed(master) => {
+                for (index, utxo) in utxos.iter().enumerate() {
+                    if utxo.path.is_none() {
+                        return Err(SweepError::MissingPath(index));
+                    }
+                    utxo.fill_input(&mut psbt, index, index, &master, self.network)?;
+                }
+                signer::sign(&mut psbt, &master)?;
+            }
+        }
+        finalizer::finalize(&mut psbt)?;
+        let tx = finalizer::extract(&psbt)?;
+        Ok(SweepResponse {
+            tx: hex::encode(tx.serialize()),
+            txid: tx.txid().to_string(),
+            amount: available - fee,
+            fee,
+            vsize: tx.vsize(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        chain::{tests::MockChain, Utxo},
+        interpreter::verify_with_sighash,
+        sighash::{self, SIGHASH_ALL},
+        wallet::tests::{derive, SEED},
+    };
+    use secp256k1::SecretKey;
+
+    const PAYEE: &str = "1BgGZ9tcN4rm9KBzDn7KprQz87SZ26SAMH";
+
+    fn seed_key(path: &str) -> PrivateKey {
+        PrivateKey::new(derive(path).secret_key, Network::Bitcoin)
+    }
+
+    fn utxo(byte: u8, amount: u64, address: &Address) -> serde_json::Value {
+        serde_json::json!({"txid": hex::encode([byte; 32]), "vout": 0, "amount": amount, "address": address.to_string()})
+    }
+
+    fn p2tr(public_key: &[u8]) -> Address {
+        let internal_key = taproot::x_only_key(public_key).unwrap();
+        let (output_key, _) = taproot::output_key(&internal_key, None).unwrap();
+        Address::p2tr_tweaked(output_key, Network::Bitcoin)
+    }
+
+    #[actix_rt::test]
+    async fn test_sweep_wif() {
+        let key = seed_key("m/84'/0'/0'/0/0");
+        let public_key = key.public_key();
+        let p2wpkh = Address::p2wpkh(&public_key, Network::Bitcoin);
+        let p2shwpkh = Address::p2shwpkh(&public_key, Network::Bitcoin);
+        let request = serde_json::json!({
+            "wif": key.to_string(),
+            "utxos": [utxo(0x11, 60_000, &p2wpkh), utxo(0x22, 30_000, &p2shwpkh), utxo(0x33, 10_000, &p2tr(&public_key))],
+            "destination": PAYEE,
+            "fee_rate": 2.0,
+        });
+        let request: SweepRequest = serde_json::from_value(request).unwrap();
+        let response = request.sweep(None).await.unwrap();
+        // three inputs and a P2PKH output
+        let weight = TX_OVERHEAD_WEIGHT + 273 + 365 + 231 + 136;
+        assert_eq!(response.fee, fee(weight, 2.0));
+        assert_eq!(response.amount, 100_000 - response.fee);
+        assert!(response.vsize <= weight / 4);
+
+        let tx = Transaction::from_str(&response.tx).unwrap();
+        assert_eq!(tx.txid().to_string(), response.txid);
+        assert_eq!(tx.output[0].value, response.amount);
+        let script_code = Address::p2pkh(&public_key, Network::Bitcoin).script_pubkey();
+        for (index, (address, amount)) in
+            [(&p2wpkh, 60_000), (&p2shwpkh, 30_000)].iter().enumerate()
+        {
+            let sighash =
+                sighash::segwit_v0_sighash(&tx, index, &script_code, *amount, SIGHASH_ALL).unwrap();
+            let input = &tx.input[index];
+            verify_with_sighash(
+                &input.script_sig,
+                &input.witness.0,
+                &address.script_pubkey(),
+                sighash,
+            )
+            .unwrap();
+        }
+        // key path spend with SIGHASH_DEFAULT
+        assert_eq!(tx.input[2].witness.0.len(), 1);
+        assert_eq!(tx.input[2].witness.0[0].len(), 64);
+
+        let other = Address::p2wpkh(&seed_key("m/84'/0'/0'/0/1").public_key(), Network::Bitcoin);
+        let request = serde_json::json!({
+            "wif": key.to_string(),
+            "utxos": [utxo(0x11, 60_000, &p2wpkh), utxo(0x22, 30_000, &other)],
+            "destination": PAYEE,
+            "fee_rate": 2.0,
+        });
+        let request: SweepRequest = serde_json::from_value(request).unwrap();
+        assert_eq!(
+            request.sweep(None).await.unwrap_err(),
+            SweepError::Selection(SelectionError::KeyMismatch(1))
+        );
+    }
+
+    #[actix_rt::test]
+    async fn test_sweep_uncompressed() {
+        let key = PrivateKey {
+            compressed: false,
+            ..PrivateKey::new(
+                SecretKey::from_slice(&[0x01; 32]).unwrap(),
+                Network::Bitcoin,
+            )
+        };
+        let public_key = key.public_key();
+        let address = Address::p2pkh(&public_key, Network::Bitcoin);
+        let previous = Transaction {
+            version: 1,
+            input: vec![TxIn::new(OutPoint::null(), 0xffff_ffff)],
+            output: vec![TxOut {
+                value: 50_000,
+                script_pubkey: address.script_pubkey(),
+            }],
+            lock_time: 0,
+        };
+        let request = serde_json::json!({
+            "wif": key.to_string(),
+            "utxos": [{"txid": previous.txid().to_string(), "vout": 0, "amount": 50_000, "address": address.to_string()}],
+            "destination": PAYEE,
+            "fee_rate": 1.0,
+        });
+        let request: SweepRequest = serde_json::from_value(request).unwrap();
+        assert_eq!(
+            request.sweep(None).await.unwrap_err(),
+            SweepError::Psbt(PsbtError::MissingUtxo(0))
+        );
+
+        let request = serde_json::json!({
+            "wif": key.to_string(),
+            "utxos": [{"txid": previous.txid().to_string(), "vout": 0, "amount": 50_000, "address": address.to_string(), "non_witness_utxo": hex::encode(previous.serialize())}],
+            "destination": PAYEE,
+            "fee_rate": 1.0,
+        });
+        let request: SweepRequest = serde_json::from_value(request).unwrap();
+        let response = request.sweep(None).await.unwrap();
+        let tx = Transaction::from_str(&response.tx).unwrap();
+        assert!(!tx.has_witness());
+        assert!(response.vsize <= (TX_OVERHEAD_WEIGHT + 596 + 128 + 136) / 4);
+        let sighash =
+            sighash::legacy_sighash(&tx, 0, &address.script_pubkey(), SIGHASH_ALL.into()).unwrap();
+        verify_with_sighash(
+            &tx.input[0].script_sig,
+            &[],
+            &address.script_pubkey(),
+            sighash,
+        )
+        .unwrap();
+    }
+
+    #[actix_rt::test]
+    async fn test_sweep_seed() {
+        let first = Address::p2wpkh(&seed_key("m/84'/0'/0'/0/0").public_key(), Network::Bitcoin);
+        let second = Address::p2wpkh(&seed_key("m/84'/0'/0'/0/1").public_key(), Network::Bitcoin);
+        let mut utxos = vec![utxo(0x11, 60_000, &first), utxo(0x22, 250_000, &second)];
+        let request = |utxos: &[serde_json::Value]| -> SweepRequest {
+            serde_json::from_value(serde_json::json!({
+                "seed": SEED,
+                "utxos": utxos,
+                "destination": PAYEE,
+                "fee_rate": 3.0,
+            }))
+            .unwrap()
+        };
+        assert_eq!(
+            request(&utxos).sweep(None).await.unwrap_err(),
+            SweepError::MissingPath(0)
+        );
+        utxos[0]["path"] = "m/84'/0'/0'/0/0".into();
+        utxos[1]["path"] = "m/84'/0'/0'/0/1".into();
+        let response = request(&utxos).sweep(None).await.unwrap();
+        assert_eq!(response.fee, fee(TX_OVERHEAD_WEIGHT + 2 * 273 + 136, 3.0));
+        let tx = Transaction::from_str(&response.tx).unwrap();
+        assert!(tx.input.iter().all(|input| input.witness.0.len() == 2));
+
+        let both: SweepRequest = serde_json::from_value(serde_json::json!({
+            "seed": SEED,
+            "wif": seed_key("m/84'/0'/0'/0/0").to_string(),
+            "utxos": utxos,
+            "destination": PAYEE,
+            "fee_rate": 3.0,
+        }))
+        .unwrap();
+        assert_eq!(both.sweep(None).await.unwrap_err(), SweepError::KeySource);
+    }
+
+    #[actix_rt::test]
+    async fn test_sweep_lookup() {
+        let key = seed_key("m/84'/0'/0'/0/0");
+        let public_key = key.public_key();
+        let p2pkh = Address::p2pkh(&public_key, Network::Bitcoin);
+        let p2wpkh = Address::p2wpkh(&public_key, Network::Bitcoin);
+        let other = Address::p2wpkh(&seed_key("m/84'/0'/0'/0/1").public_key(), Network::Bitcoin);
+        let funding = Transaction {
+            version: 1,
+            input: vec![TxIn::new(OutPoint::null(), 0xffff_ffff)],
+            output: vec![TxOut {
+                value: 50_000,
+                script_pubkey: p2pkh.script_pubkey(),
+            }],
+            lock_time: 0,
+        };
+        let utxo = |txid: Txid, value: u64, address: &Address| Utxo {
+            outpoint: OutPoint::new(txid, 0),
+            value,
+            script_pubkey: address.script_pubkey(),
+            height: Some(700_000),
+        };
+        let chain = MockChain {
+            utxos: vec![
+                utxo(funding.txid(), 50_000, &p2pkh),
+                utxo(Txid([0x11; 32]), 60_000, &p2wpkh),
+                utxo(Txid([0x22; 32]), 70_000, &other),
+                utxo(Txid([0x33; 32]), 20_000, &p2tr(&public_key)),
+                utxo(
+                    Txid([0x44; 32]),
+                    5_000,
+                    &p2tr(&seed_key("m/86'/0'/0'/0/0").public_key()),
+                ),
+            ],
+            transactions: std::iter::once((funding.txid(), funding.clone())).collect(),
+            ..MockChain::default()
+        };
+
+        // every address type of the key, the legacy input with its funding transaction
+        let request: SweepRequest = serde_json::from_value(serde_json::json!({
+            "wif": key.to_string(),
+            "destination": PAYEE,
+            "fee_rate": 2.0,
+        }))
+        .unwrap();
+        assert_eq!(
+            request.sweep(None).await.unwrap_err(),
+            SweepError::NoChainBackend
+        );
+        let response = request.sweep(Some(&chain)).await.unwrap();
+        assert_eq!(response.amount + response.fee, 130_000);
+        let tx = Transaction::from_str(&response.tx).unwrap();
+        assert_eq!(tx.input.len(), 3);
+        assert!(!tx.input[0].script_sig.is_empty());
+
+        // BIP84 receive addresses 0 and 1 and BIP86 receive address 0 of the seed
+        let request: SweepRequest = serde_json::from_value(serde_json::json!({
+            "seed": SEED,
+            "destination": PAYEE,
+            "fee_rate": 2.0,
+        }))
+        .unwrap();
+        let response = request.sweep(Some(&chain)).await.unwrap();
+        assert_eq!(response.amount + response.fee, 135_000);
+        assert_eq!(
+            response.fee,
+            fee(TX_OVERHEAD_WEIGHT + 2 * 273 + 231 + 136, 2.0)
+        );
+
+        let request: SweepRequest = serde_json::from_value(serde_json::json!({
+            "seed": SEED,
+            "destination": PAYEE,
+            "fee_rate": 2.0,
+        }))
+        .unwrap();
+        assert_eq!(
+            request
+                .sweep(Some(&MockChain::default()))
+                .await
+                .unwrap_err(),
+            SweepError::NoUtxo
+        );
+    }
+}
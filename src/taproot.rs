@@ -0,0 +1,453 @@
+use crate::{
+    address::{Address, Network},
+    error::WalletError,
+    hash, opcodes, policy,
+    script::Builder,
+};
+use anyhow::Result;
+use secp256k1::{schnorrsig, Secp256k1};
+use serde::{Deserialize, Serialize};
+
+/// Leaf version of BIP342 tapscript
+pub const TAPROOT_LEAF_TAPSCRIPT: u8 = 0xc0;
+/// Maximum depth of a leaf in the script tree
+pub const TAPROOT_CONTROL_MAX_NODE_COUNT: usize = 128;
+/// Maximum number of keys in a `multi_a` leaf, bounded by the tapscript stack size
+pub(crate) const MAX_PUBKEYS_PER_MULTI_A: usize = 999;
+
+/// Internal key without a known discrete logarithm (BIP341), disabling the key path:
+/// the x coordinate of `H = lift_x(SHA256(G))`
+pub const NUMS_INTERNAL_KEY: [u8; 32] = [
+    0x50, 0x92, 0x9b, 0x74, 0xc1, 0xa0, 0x49, 0x54, 0xb7, 0x8b, 0x4b, 0x60, 0x35, 0xe9, 0x7a, 0x5e,
+    0x07, 0x8a, 0x5a, 0x0f, 0x28, 0xec, 0x96, 0xd5, 0x47, 0xbf, 0xee, 0x9a, 0xce, 0x80, 0x3a, 0xc0,
+];
+
+/// `TapLeaf` tagged hash of `script` under `leaf_version`
+pub fn tap_leaf_hash(script: &[u8], leaf_version: u8) -> [u8; 32] {
+    let mut data = vec![leaf_version];
+    crate::script::push_compact_size(&mut data, script.len());
+    data.extend(script);
+    hash::tagged_hash("TapLeaf", &data)
+}
+
+/// Tweak `internal_key` with the `TapTweak` hash of the key and the optional script tree
+/// `merkle_root`, returning the x-only output key and its parity
+pub fn output_key(
+    internal_key: &[u8; 32],
+    merkle_root: Option<[u8; 32]>,
+) -> Result<([u8; 32], bool), WalletError> {
+    let mut data = internal_key.to_vec();
+    if let Some(merkle_root) = merkle_root {
+        data.extend(merkle_root);
+    }
+    let tweak = hash::tagged_hash("TapTweak", &data);
+    let mut key = schnorrsig::PublicKey::from_slice(internal_key)
+        .map_err(|_| WalletError::InvalidPublicKey)?;
+    let parity = key
+        .tweak_add_assign(&Secp256k1::verification_only(), &tweak)
+        .map_err(|_| WalletError::InvalidPublicKey)?;
+    Ok((key.serialize(), parity))
+}
+
+/// `TapBranch` tagged hash of two child hashes, in lexicographic order
+pub fn tap_branch_hash(a: &[u8; 32], b: &[u8; 32]) -> [u8; 32] {
+    let (first, second) = if a <= b { (a, b) } else { (b, a) };
+    let mut data = first.to_vec();
+    data.extend(second);
+    hash::tagged_hash("TapBranch", &data)
+}
+
+/// Serialized x-only key from a 32 byte x-only or 33 byte compressed public key
+pub fn x_only_key(key: &[u8]) -> Result<[u8; 32], WalletError> {
+    let key = match key.len() {
+        33 => schnorrsig::PublicKey::from(
+            secp256k1::PublicKey::from_slice(key).map_err(|_| WalletError::InvalidPublicKey)?,
+        ),
+        _ => schnorrsig::PublicKey::from_slice(key).map_err(|_| WalletError::InvalidPublicKey)?,
+    };
+    Ok(key.serialize())
+}
+
+#[derive(Deserialize, Debug)]
+// Get a taproot output with script leaves from user
+pub struct TaprootRequest {
+    pub internal_key: Option<String>,
+    #[serde(default)]
+    pub leaves: Vec<LeafRequest>,
+    #[serde(default)]
+    pub network: Network,
+}
+
+#[derive(Deserialize, Debug)]
+// Get a script leaf and how likely it is to be used from user
+pub struct LeafRequest {
+    #[serde(flatten)]
+    pub script: LeafScriptRequest,
+    #[serde(default = "default_leaf_version")]
+    pub leaf_version: u8,
+    #[serde(default = "default_weight")]
+    pub weight: u32,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(untagged)]
+// Either m-of-public_keys or a raw hex script
+pub enum LeafScriptRequest {
+    Multisig { m: usize, public_keys: Vec<String> },
+    Script { script: String },
+}
+
+fn default_leaf_version() -> u8 {
+    TAPROOT_LEAF_TAPSCRIPT
+}
+
+fn default_weight() -> u32 {
+    1
+}
+
+/// A script leaf of the taproot tree
+#[derive(Debug, Clone, PartialEq)]
+pub struct TapLeaf {
+    pub script: Vec<u8>,
+    pub leaf_version: u8,
+}
+
+impl TapLeaf {
+    /// A leaf with any script, `leaf_version` has to be even and must not look like an annex
+    pub fn new(script: Vec<u8>, leaf_version: u8) -> Result<Self, WalletError> {
+        if leaf_version & 1 != 0 || leaf_version == 0x50 {
+            return Err(WalletError::InvalidLeafVersion);
+        }
+        Ok(Self {
+            script,
+            leaf_version,
+        })
+    }
+
+    /// BIP342 k-of-n leaf:
+    /// `<key_1> OP_CHECKSIG <key_2> OP_CHECKSIGADD ... <key_n> OP_CHECKSIGADD <k> OP_NUMEQUAL`
+    pub fn multi_a(k: usize, public_keys: &[[u8; 32]]) -> Result<Self, WalletError> {
+        match public_keys.len() {
+            0 => return Err(WalletError::EmptyN),
+            n if n > MAX_PUBKEYS_PER_MULTI_A => return Err(WalletError::NumberOfKeysExceeds),
+            n if k == 0 || k > n => return Err(WalletError::InvalidM),
+            _ => {}
+        }
+        let script = public_keys
+            .iter()
+            .enumerate()
+            .fold(Builder::new(), |builder, (i, key)| {
+                builder.push_slice(key).push_opcode(if i == 0 {
+                    opcodes::OP_CHECKSIG
+                } else {
+                    opcodes::OP_CHECKSIGADD
+                })
+            })
+            .push_int(k as i64)
+            .push_opcode(opcodes::OP_NUMEQUAL)
+            .into_script();
+        Self::new(script, TAPROOT_LEAF_TAPSCRIPT)
+    }
+
+    pub fn leaf_hash(&self) -> [u8; 32] {
+        tap_leaf_hash(&self.script, self.leaf_version)
+    }
+}
+
+#[derive(Serialize, Debug)]
+/// TaprootWallet is the taproot output and how to spend each leaf, presented to user
+pub struct TaprootWallet {
+    pub address: String,
+    pub internal_key: String,
+    pub output_key: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub merkle_root: Option<String>,
+    pub leaves: Vec<LeafWallet>,
+}
+
+#[derive(Serialize, Debug)]
+/// LeafWallet is a leaf script with the control block revealing it
+pub struct LeafWallet {
+    pub script: String,
+    pub leaf_version: u8,
+    pub leaf_hash: String,
+    pub control_block: String,
+}
+
+/// Taproot output committing to an internal key and a tree of script leaves, with the NUMS
+/// internal key when no key path spend is wanted
+pub struct Taproot {
+    internal_key: [u8; 32],
+    leaves: Vec<(u32, TapLeaf)>,
+    merkle_root: Option<[u8; 32]>,
+    merkle_paths: Vec<Vec<[u8; 32]>>,
+    output_key: [u8; 32],
+    parity: bool,
+}
+
+impl Taproot {
+    pub fn internal_key(internal_key: Option<[u8; 32]>) -> Self {
+        Self {
+            internal_key: internal_key.unwrap_or(NUMS_INTERNAL_KEY),
+            leaves: Vec::new(),
+            merkle_root: None,
+            merkle_paths: Vec::new(),
+            output_key: [0; 32],
+            parity: false,
+        }
+    }
+
+    /// Add a leaf, leaves with a higher `weight` end up closer to the root and are cheaper to
+    /// spend
+    pub fn leaf(mut self, weight: u32, leaf: TapLeaf) -> Self {
+        self.leaves.push((weight, leaf));
+        self
+    }
+
+    /// Build a Huffman tree of the leaves by weight and tweak the internal key with its root
+    pub fn generate_tree(mut self) -> Result<Self> {
+        if self.leaves.is_empty() && self.internal_key == NUMS_INTERNAL_KEY {
+            return Err(WalletError::UnspendableTaproot.into());
+        }
+        self.merkle_paths = vec![Vec::new(); self.leaves.len()];
+        // (weight, hash, leaves below)
+        let mut nodes: Vec<(u64, [u8; 32], Vec<usize>)> = self
+            .leaves
+            .iter()
+            .enumerate()
+            .map(|(i, (weight, leaf))| (*weight as u64, leaf.leaf_hash(), vec![i]))
+            .collect();
+        while nodes.len() > 1 {
+            // Stable sort, so equal weights combine in insertion order
+            nodes.sort_by_key(|node| std::cmp::Reverse(node.0));
+            let (a_weight, a_hash, a_leaves) = nodes.pop().unwrap();
+            let (b_weight, b_hash, b_leaves) = nodes.pop().unwrap();
+            for leaf in &a_leaves {
+                self.merkle_paths[*leaf].push(b_hash);
+            }
+            for leaf in &b_leaves {
+                self.merkle_paths[*leaf].push(a_hash);
+            }
+            nodes.push((
+                a_weight + b_weight,
+                tap_branch_hash(&a_hash, &b_hash),
+                [a_leaves, b_leaves].concat(),
+            ));
+        }
+        if self
+            .merkle_paths
+            .iter()
+            .any(|path| path.len() > TAPROOT_CONTROL_MAX_NODE_COUNT)
+        {
+            return Err(WalletError::TaprootTreeTooDeep.into());
+        }
+        self.merkle_root = nodes.pop().map(|(_, hash, _)| hash);
+        let (output_key, parity) = output_key(&self.internal_key, self.merkle_root)?;
+        self.output_key = output_key;
+        self.parity = parity;
+        log::debug!("Taproot output key: {}", hex::encode(output_key));
+        Ok(self)
+    }
+
+    /// Check every leaf is a tapscript whose spends are standard, without the leaf versions and
+    /// `OP_SUCCESS` opcodes reserved for soft forks
+    pub fn check_policy(self) -> Result<Self> {
+        for (_, leaf) in &self.leaves {
+            policy::check_tap_leaf(&leaf.script, leaf.leaf_version).map_err(WalletError::from)?;
+        }
+        Ok(self)
+    }
+
+    /// The tweaked x-only output key, zero until `generate_tree` succeeds
+    pub fn output_key(&self) -> [u8; 32] {
+        self.output_key
+    }
+
+    pub fn merkle_root(&self) -> Option<[u8; 32]> {
+        self.merkle_root
+    }
+
+    pub fn address(&self, network: Network) -> Address {
+        Address::p2tr_tweaked(self.output_key, network)
+    }
+
+    /// Control block of leaf `index`: leaf version with the output key parity, internal key
+    /// and the Merkle path from the leaf to the root
+    pub fn control_block(&self, index: usize) -> Vec<u8> {
+        let mut control_block = vec![self.leaves[index].1.leaf_version | self.parity as u8];
+        control_block.extend(self.internal_key);
+        for hash in &self.merkle_paths[index] {
+            control_block.extend(hash);
+        }
+        control_block
+    }
+
+    /// Finalize Taproot and return as TaprootWallet
+    pub fn finalize(self, network: Network) -> TaprootWallet {
+        TaprootWallet {
+            address: self.address(network).to_string(),
+            internal_key: hex::encode(self.internal_key),
+            output_key: hex::encode(self.output_key),
+            merkle_root: self.merkle_root.map(hex::encode),
+            leaves: self
+                .leaves
+                .iter()
+                .enumerate()
+                .map(|(i, (_, leaf))| LeafWallet {
+                    script: hex::encode(&leaf.script),
+                    leaf_version: leaf.leaf_version,
+                    leaf_hash: hex::encode(leaf.leaf_hash()),
+                    control_block: hex::encode(self.control_block(i)),
+                })
+                .collect(),
+        }
+    }
+}
+
+/// Whether `control_block` is a leaf version byte and internal key followed by at most 128 hashes
+pub fn is_valid_control_block_size(control_block: &[u8]) -> bool {
+    control_block.len() >= 33
+        && (control_block.len() - 33).is_multiple_of(32)
+        && (control_block.len() - 33) / 32 <= TAPROOT_CONTROL_MAX_NODE_COUNT
+}
+
+/// Whether `control_block` proves that `script` is a leaf committed to by `output_key`
+pub fn verify_control_block(output_key: &[u8; 32], script: &[u8], control_block: &[u8]) -> bool {
+    if !is_valid_control_block_size(control_block) {
+        return false;
+    }
+    let leaf_version = control_block[0] & 0xfe;
+    let mut internal_key = [0u8; 32];
+    internal_key.copy_from_slice(&control_block[1..33]);
+    let merkle_root = control_block[33..].chunks(32).fold(
+        tap_leaf_hash(script, leaf_version),
+        |hash, sibling| {
+            let mut node = [0u8; 32];
+            node.copy_from_slice(sibling);
+            tap_branch_hash(&hash, &node)
+        },
+    );
+    match self::output_key(&internal_key, Some(merkle_root)) {
+        Ok((key, parity)) => key == *output_key && parity == (control_block[0] & 1 == 1),
+        Err(_) => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::convert::TryInto;
+
+    #[test]
+    fn test_key_path_output_key() {
+        // BIP341 wallet test vectors, key path only
+        let internal_key: [u8; 32] =
+            hex::decode("d6889cb081036e0faefa3a35157ad71086b123b2b144b649798b494c300a961d")
+                .unwrap()
+                .try_into()
+                .unwrap();
+        let (output_key, _) = output_key(&internal_key, None).unwrap();
+        assert_eq!(
+            hex::encode(output_key),
+            "53a1f6e454df1aa2776a2814a721372d6258050de330b3c6d10ee8f4e0dda343"
+        );
+        assert_eq!(
+            crate::address::Address::p2tr_tweaked(output_key, crate::address::Network::Bitcoin)
+                .to_string(),
+            "bc1p2wsldez5mud2yam29q22wgfh9439spgduvct83k3pm50fcxa5dps59h4z5"
+        );
+    }
+
+    #[test]
+    fn test_single_leaf_trees() {
+        // BIP341 wallet test vectors, one script leaf
+        let vectors = [
+            (
+                "187791b6f712a8ea41c8ecdd0ee77fab3e85263b37e1ec18a3651926b3a6cf27",
+                "20d85a959b0290bf19bb89ed43c916be835475d013da4b362117393e25a48229b8ac",
+                "5b75adecf53548f3ec6ad7d78383bf84cc57b55a3127c72b9a2481752dd88b21",
+                "bc1pz37fc4cn9ah8anwm4xqqhvxygjf9rjf2resrw8h8w4tmvcs0863sa2e586",
+            ),
+            (
+                "93478e9488f956df2396be2ce6c5cced75f900dfa18e7dabd2428aae78451820",
+                "20b617298552a72ade070667e86ca63b8f5789a9fe8731ef91202a91c9f3459007ac",
+                "c525714a7f49c28aedbbba78c005931a81c234b2f6c99a73e4d06082adc8bf2b",
+                "bc1punvppl2stp38f7kwv2u2spltjuvuaayuqsthe34hd2dyy5w4g58qqfuag5",
+            ),
+        ];
+        for (internal_key, script, leaf_hash, address) in vectors.iter() {
+            let internal_key = x_only_key(&hex::decode(internal_key).unwrap()).unwrap();
+            let leaf = TapLeaf::new(hex::decode(script).unwrap(), TAPROOT_LEAF_TAPSCRIPT).unwrap();
+            assert_eq!(hex::encode(leaf.leaf_hash()), *leaf_hash);
+            let taproot = Taproot::internal_key(Some(internal_key))
+                .leaf(1, leaf.clone())
+                .generate_tree()
+                .unwrap()
+                .check_policy()
+                .unwrap();
+            assert_eq!(taproot.address(Network::Bitcoin).to_string(), *address);
+            assert_eq!(taproot.control_block(0)[1..], internal_key);
+            assert!(verify_control_block(
+                &taproot.output_key(),
+                &leaf.script,
+                &taproot.control_block(0)
+            ));
+        }
+    }
+
+    #[test]
+    fn test_multi_a_tree() {
+        let keys: Vec<[u8; 32]> = (1..=4u8)
+            .map(|byte| {
+                let secret_key = secp256k1::SecretKey::from_slice(&[byte; 32]).unwrap();
+                let key = secp256k1::PublicKey::from_secret_key(&Secp256k1::new(), &secret_key);
+                x_only_key(&key.serialize()).unwrap()
+            })
+            .collect();
+        let leaves = [
+            (3, TapLeaf::multi_a(2, &keys[..3]).unwrap()),
+            (1, TapLeaf::multi_a(1, &keys[3..]).unwrap()),
+            (1, TapLeaf::multi_a(4, &keys).unwrap()),
+        ];
+        assert_eq!(leaves[0].1.script[33], opcodes::OP_CHECKSIG);
+        assert_eq!(leaves[0].1.script[67], opcodes::OP_CHECKSIGADD);
+        assert_eq!(
+            leaves[0].1.script[102..],
+            [opcodes::OP_PUSHNUM_2, opcodes::OP_NUMEQUAL]
+        );
+        let taproot = leaves
+            .iter()
+            .fold(Taproot::internal_key(None), |taproot, (weight, leaf)| {
+                taproot.leaf(*weight, leaf.clone())
+            })
+            .generate_tree()
+            .unwrap();
+        // The likeliest leaf sits right below the root
+        assert_eq!(taproot.control_block(0).len(), 33 + 32);
+        assert_eq!(taproot.control_block(1).len(), 33 + 64);
+        for (i, (_, leaf)) in leaves.iter().enumerate() {
+            assert!(verify_control_block(
+                &taproot.output_key(),
+                &leaf.script,
+                &taproot.control_block(i)
+            ));
+        }
+        assert!(!verify_control_block(
+            &taproot.output_key(),
+            &leaves[1].1.script,
+            &taproot.control_block(2)
+        ));
+        let wallet = taproot.finalize(Network::Testnet);
+        assert!(wallet.address.starts_with("tb1p"));
+        assert_eq!(wallet.internal_key, hex::encode(NUMS_INTERNAL_KEY));
+
+        assert!(TapLeaf::multi_a(3, &keys[..2]).is_err());
+        assert!(TapLeaf::new(vec![], 0xc1).is_err());
+        assert!(Taproot::internal_key(None).generate_tree().is_err());
+    }
+
+    #[test]
+    fn test_nums_key_is_valid() {
+        assert!(schnorrsig::PublicKey::from_slice(&NUMS_INTERNAL_KEY).is_ok());
+    }
+}
@@ -0,0 +1,335 @@
+use crate::{
+    address::{Address, Network, ScriptType},
+    error::WalletError,
+    interpreter::{SEQUENCE_LOCKTIME_MASK, SEQUENCE_LOCKTIME_TYPE_FLAG},
+    opcodes, policy,
+    script::Builder,
+    wallet::{PubKey, ScriptWallet},
+};
+use anyhow::Result;
+use serde::Deserialize;
+
+/// When the timelocked branch becomes spendable
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Lock {
+    /// Absolute block height (below 500000000) or unix time, enforced by OP_CHECKLOCKTIMEVERIFY
+    After(u32),
+    /// Relative number of blocks, or of 512 second units with the BIP68 type flag, since the
+    /// output confirmed, enforced by OP_CHECKSEQUENCEVERIFY
+    Older(u32),
+}
+
+impl Lock {
+    pub(crate) fn check(self) -> Result<Self, WalletError> {
+        match self {
+            Lock::After(0) => Err(WalletError::InvalidLockTime),
+            Lock::Older(sequence) => {
+                let sequence = sequence as i64;
+                if sequence & !(SEQUENCE_LOCKTIME_TYPE_FLAG | SEQUENCE_LOCKTIME_MASK) != 0
+                    || sequence & SEQUENCE_LOCKTIME_MASK == 0
+                {
+                    Err(WalletError::InvalidLockTime)
+                } else {
+                    Ok(self)
+                }
+            }
+            _ => Ok(self),
+        }
+    }
+
+    /// `<value> OP_CHECKLOCKTIMEVERIFY OP_DROP` or `<value> OP_CHECKSEQUENCEVERIFY OP_DROP`
+    pub fn script(self) -> Vec<u8> {
+        let (value, opcode) = match self {
+            Lock::After(value) => (value, opcodes::OP_CLTV),
+            Lock::Older(value) => (value, opcodes::OP_CSV),
+        };
+        Builder::new()
+            .push_int(value as i64)
+            .push_opcode(opcode)
+            .push_opcode(opcodes::OP_DROP)
+            .into_script()
+    }
+}
+
+#[derive(Deserialize, Debug)]
+// Get a timelocked script from user
+pub struct TimelockRequest {
+    pub lock: Lock,
+    pub primary: Option<KeySetRequest>,
+    pub recovery: KeySetRequest,
+    #[serde(default)]
+    pub script_type: ScriptType,
+    #[serde(default)]
+    pub network: Network,
+}
+
+#[derive(Deserialize, Debug)]
+// Get m and public_keys from user
+pub struct KeySetRequest {
+    pub m: u8,
+    pub public_keys: Vec<String>,
+}
+
+/// `m` of `public_keys` have to sign, a single key when `m` is 1 and there is only one key
+#[derive(Debug, Clone, PartialEq)]
+pub struct KeySet {
+    pub m: u8,
+    pub public_keys: Vec<PubKey>,
+}
+
+impl KeySet {
+    pub fn new(m: u8, public_keys: Vec<PubKey>) -> Self {
+        Self { m, public_keys }
+    }
+
+    /// `<key> OP_CHECKSIG` or `OP_m <keys> OP_n OP_CHECKMULTISIG`
+    pub fn script(&self) -> Result<Vec<u8>, WalletError> {
+        match self.public_keys.len() {
+            0 => return Err(WalletError::EmptyN),
+            n if n > 16 => return Err(WalletError::NumberOfKeysExceeds),
+            n if self.m == 0 || self.m as usize > n => return Err(WalletError::InvalidM),
+            _ => {}
+        }
+        if self.public_keys.len() == 1 {
+            return Ok(Builder::new()
+                .push_slice(&self.public_keys[0])
+                .push_opcode(opcodes::OP_CHECKSIG)
+                .into_script());
+        }
+        let builder = self
+            .public_keys
+            .iter()
+            .fold(Builder::new().push_int(self.m as i64), |builder, key| {
+                builder.push_slice(key)
+            });
+        Ok(builder
+            .push_int(self.public_keys.len() as i64)
+            .push_opcode(opcodes::OP_CHECKMULTISIG)
+            .into_script())
+    }
+}
+
+/// A script that `recovery` can only spend once `lock` has passed, optionally with a `primary`
+/// branch that is spendable at any time, e.g. "2-of-3 now, or 1-of-3 after block N"
+pub struct Timelock {
+    lock: Lock,
+    primary: Option<KeySet>,
+    recovery: KeySet,
+    witness_script: Vec<u8>,
+}
+
+impl Timelock {
+    pub fn lock(lock: Lock) -> Self {
+        Self {
+            lock,
+            primary: None,
+            recovery: KeySet::new(0, Vec::new()),
+            witness_script: Vec::new(),
+        }
+    }
+
+    pub fn primary(mut self, primary: KeySet) -> Self {
+        self.primary = Some(primary);
+        self
+    }
+
+    pub fn recovery(mut self, recovery: KeySet) -> Self {
+        self.recovery = recovery;
+        self
+    }
+
+    /// Generate the witness script
+    ///
+    /// Without a primary branch: `<lock> <recovery>`, otherwise
+    /// `OP_IF <primary> OP_ELSE <lock> <recovery> OP_ENDIF`, where the primary branch is
+    /// selected with a `0x01` and the recovery branch with an empty element on top of the stack.
+    pub fn generate_script(mut self) -> Result<Self> {
+        let lock = self.lock.check()?.script();
+        let recovery = self.recovery.script()?;
+        self.witness_script = match &self.primary {
+            None => Builder::new().append(&lock).append(&recovery).into_script(),
+            Some(primary) => Builder::new()
+                .push_opcode(opcodes::OP_IF)
+                .append(&primary.script()?)
+                .push_opcode(opcodes::OP_ELSE)
+                .append(&lock)
+                .append(&recovery)
+                .push_opcode(opcodes::OP_ENDIF)
+                .into_script(),
+        };
+        log::debug!("Witness script: {}", hex::encode(&self.witness_script));
+        Ok(self)
+    }
+
+    /// The witness script, empty until `generate_script` succeeds
+    pub fn witness_script(&self) -> &[u8] {
+        &self.witness_script
+    }
+
+    /// Check the witness script is standard once wrapped as `script_type`, e.g. that it fits
+    /// the 520 byte push and 15 sigops of a P2SH redeem script
+    pub fn check_policy(self, script_type: ScriptType) -> Result<Self, WalletError> {
+        policy::check_script(&self.witness_script, script_type)?;
+        Ok(self)
+    }
+
+    /// Address of the witness script wrapped as `script_type`
+    pub fn address(&self, script_type: ScriptType, network: Network) -> Address {
+        Address::from_script(&self.witness_script, script_type, network)
+    }
+
+    /// Finalize Timelock and return as ScriptWallet
+    pub fn finalize(self, script_type: ScriptType, network: Network) -> ScriptWallet {
+        ScriptWallet {
+            address: self.address(script_type, network).to_string(),
+            script: hex::encode(self.witness_script),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        error::{PolicyError, ScriptError},
+        interpreter::{
+            tests::{keypair, sign, SIGHASH},
+            verify_script, ScriptFlags, SighashChecker,
+        },
+        script::push_data,
+    };
+    use secp256k1::SecretKey;
+
+    fn keys() -> Vec<(SecretKey, PubKey)> {
+        (1..=3).map(keypair).collect()
+    }
+
+    fn vault(lock: Lock) -> Timelock {
+        let public_keys: Vec<PubKey> = keys().iter().map(|(_, key)| *key).collect();
+        Timelock::lock(lock)
+            .primary(KeySet::new(2, public_keys.clone()))
+            .recovery(KeySet::new(1, public_keys))
+            .generate_script()
+            .unwrap()
+    }
+
+    #[test]
+    fn test_absolute_timelock_vault() {
+        let keys = keys();
+        let timelock = vault(Lock::After(700_000));
+        let witness_script = timelock.witness_script().to_vec();
+        let script_pubkey = timelock
+            .address(ScriptType::P2wsh, Network::Bitcoin)
+            .script_pubkey();
+        let flags = ScriptFlags::default();
+
+        // 2-of-3 at any time
+        let witness = vec![
+            vec![],
+            sign(&keys[0].0),
+            sign(&keys[1].0),
+            vec![1],
+            witness_script.clone(),
+        ];
+        let checker = SighashChecker::new(SIGHASH);
+        assert_eq!(
+            verify_script(&[], &witness, &script_pubkey, &flags, &checker),
+            Ok(())
+        );
+
+        // 1-of-3 after block 700000
+        let witness = vec![vec![], sign(&keys[2].0), vec![], witness_script];
+        let checker = SighashChecker::new(SIGHASH)
+            .lock_time(700_000)
+            .sequence(0xffff_fffe);
+        assert_eq!(
+            verify_script(&[], &witness, &script_pubkey, &flags, &checker),
+            Ok(())
+        );
+        let checker = SighashChecker::new(SIGHASH)
+            .lock_time(699_999)
+            .sequence(0xffff_fffe);
+        assert_eq!(
+            verify_script(&[], &witness, &script_pubkey, &flags, &checker),
+            Err(ScriptError::UnsatisfiedLocktime)
+        );
+    }
+
+    #[test]
+    fn test_relative_timelock_single_key() {
+        let keys = keys();
+        let timelock = Timelock::lock(Lock::Older(144))
+            .recovery(KeySet::new(1, vec![keys[0].1]))
+            .generate_script()
+            .unwrap();
+        let witness_script = timelock.witness_script().to_vec();
+        let address = timelock.address(ScriptType::P2shP2wsh, Network::Bitcoin);
+        assert!(address.to_string().starts_with('3'));
+
+        let mut script_sig = Vec::new();
+        push_data(
+            &mut script_sig,
+            &Address::p2wsh(&witness_script, Network::Bitcoin).script_pubkey(),
+        );
+        let witness = vec![sign(&keys[0].0), witness_script];
+        let flags = ScriptFlags::default();
+        let checker = SighashChecker::new(SIGHASH).sequence(144);
+        assert_eq!(
+            verify_script(
+                &script_sig,
+                &witness,
+                &address.script_pubkey(),
+                &flags,
+                &checker
+            ),
+            Ok(())
+        );
+        let checker = SighashChecker::new(SIGHASH).sequence(100);
+        assert_eq!(
+            verify_script(
+                &script_sig,
+                &witness,
+                &address.script_pubkey(),
+                &flags,
+                &checker
+            ),
+            Err(ScriptError::UnsatisfiedLocktime)
+        );
+
+        let wallet = timelock.finalize(ScriptType::P2shP2wsh, Network::Testnet);
+        assert!(wallet.address.starts_with('2'));
+    }
+
+    #[test]
+    fn test_invalid_timelocks() {
+        let keys = keys();
+        assert!(Timelock::lock(Lock::After(0))
+            .recovery(KeySet::new(1, vec![keys[0].1]))
+            .generate_script()
+            .is_err());
+        assert!(Timelock::lock(Lock::Older(1 << 31))
+            .recovery(KeySet::new(1, vec![keys[0].1]))
+            .generate_script()
+            .is_err());
+        assert!(Timelock::lock(Lock::Older(144))
+            .recovery(KeySet::new(2, vec![keys[0].1]))
+            .generate_script()
+            .is_err());
+    }
+
+    #[test]
+    fn test_policy() {
+        let public_keys: Vec<PubKey> = keys().iter().map(|(_, key)| *key).collect();
+        let timelock = Timelock::lock(Lock::Older(144))
+            .primary(KeySet::new(2, public_keys.clone()))
+            .recovery(KeySet::new(1, public_keys.repeat(5)[..13].to_vec()))
+            .generate_script()
+            .unwrap();
+        let timelock = timelock.check_policy(ScriptType::P2wsh).unwrap();
+        assert!(matches!(
+            timelock.check_policy(ScriptType::P2sh),
+            Err(WalletError::Policy(PolicyError::RedeemScriptSize(_)))
+        ));
+    }
+}
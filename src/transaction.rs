@@ -0,0 +1,486 @@
+use crate::{error::TransactionError, hash, script::push_compact_size};
+use std::fmt::Display;
+use std::str::FromStr;
+
+/// Sequence of an input that opts out of locktime and replacement
+pub const SEQUENCE_FINAL: u32 = 0xffff_ffff;
+/// Inputs with a sequence below this one signal replaceability (BIP125)
+pub const SEQUENCE_MAX_NON_RBF: u32 = 0xffff_fffe;
+/// Highest sequence signalling replaceability, with locktime enabled and no relative locktime
+pub const SEQUENCE_ENABLE_RBF: u32 = 0xffff_fffd;
+/// Weight units per virtual byte, the discount of witness data (BIP141)
+pub const WITNESS_SCALE_FACTOR: usize = 4;
+/// Satoshis of the 21 million bitcoins there can ever be, the largest valid amount
+pub const MAX_MONEY: u64 = 21_000_000 * 100_000_000;
+
+/// Sum of `amounts`, `None` when it is more than `MAX_MONEY`
+pub fn sum_amounts<I: IntoIterator<Item = u64>>(amounts: I) -> Option<u64> {
+    amounts
+        .into_iter()
+        .try_fold(0u64, |sum, amount| sum.checked_add(amount))
+        .filter(|&sum| sum <= MAX_MONEY)
+}
+
+/// Double SHA-256 identifying a transaction, stored in internal byte order and displayed
+/// reversed as usual
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Default)]
+pub struct Txid(pub [u8; 32]);
+
+impl Display for Txid {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let mut hash = self.0;
+        hash.reverse();
+        write!(f, "{}", hex::encode(hash))
+    }
+}
+
+impl FromStr for Txid {
+    type Err = TransactionError;
+
+    fn from_str(txid: &str) -> Result<Self, Self::Err> {
+        let mut hash = [0u8; 32];
+        hex::decode_to_slice(txid, &mut hash).map_err(|_| TransactionError::InvalidHex)?;
+        hash.reverse();
+        Ok(Txid(hash))
+    }
+}
+
+/// The output an input spends
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub struct OutPoint {
+    pub txid: Txid,
+    pub vout: u32,
+}
+
+impl OutPoint {
+    pub fn new(txid: Txid, vout: u32) -> Self {
+        Self { txid, vout }
+    }
+
+    /// The outpoint of coinbase inputs, which spend nothing
+    pub fn null() -> Self {
+        Self {
+            txid: Txid::default(),
+            vout: 0xffff_ffff,
+        }
+    }
+
+    pub fn is_null(&self) -> bool {
+        *self == Self::null()
+    }
+
+    /// Consensus serialization: the txid in internal byte order, then the output index
+    pub fn serialize(&self) -> Vec<u8> {
+        let mut data = self.txid.0.to_vec();
+        data.extend(self.vout.to_le_bytes());
+        data
+    }
+}
+
+impl Display for OutPoint {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{}:{}", self.txid, self.vout)
+    }
+}
+
+/// Witness stack of an input, empty for non-segwit inputs
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Witness(pub Vec<Vec<u8>>);
+
+impl Witness {
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// Consensus serialization: the number of items, then each item with its length
+    pub fn serialize(&self) -> Vec<u8> {
+        let mut data = Vec::new();
+        push_compact_size(&mut data, self.0.len());
+        for item in &self.0 {
+            push_compact_size(&mut data, item.len());
+            data.extend(item);
+        }
+        data
+    }
+}
+
+impl From<Vec<Vec<u8>>> for Witness {
+    fn from(stack: Vec<Vec<u8>>) -> Self {
+        Witness(stack)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct TxIn {
+    pub previous_output: OutPoint,
+    pub script_sig: Vec<u8>,
+    pub sequence: u32,
+    pub witness: Witness,
+}
+
+impl TxIn {
+    /// Unsigned input spending `previous_output`
+    pub fn new(previous_output: OutPoint, sequence: u32) -> Self {
+        Self {
+            previous_output,
+            script_sig: Vec::new(),
+            sequence,
+            witness: Witness::default(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct TxOut {
+    /// Amount in satoshis
+    pub value: u64,
+    pub script_pubkey: Vec<u8>,
+}
+
+impl TxOut {
+    /// Consensus serialization: the amount, then the script with its length
+    pub fn serialize(&self) -> Vec<u8> {
+        let mut data = self.value.to_le_bytes().to_vec();
+        push_compact_size(&mut data, self.script_pubkey.len());
+        data.extend(&self.script_pubkey);
+        data
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Transaction {
+    pub version: i32,
+    pub input: Vec<TxIn>,
+    pub output: Vec<TxOut>,
+    pub lock_time: u32,
+}
+
+impl Transaction {
+    /// Whether any input carries a witness, and the serialization needs the marker and flag
+    pub fn has_witness(&self) -> bool {
+        self.input.iter().any(|input| !input.witness.is_empty())
+    }
+
+    fn encode(&self, with_witness: bool) -> Vec<u8> {
+        let with_witness = with_witness && self.has_witness();
+        let mut data = self.version.to_le_bytes().to_vec();
+        if with_witness {
+            // marker and flag (BIP144)
+            data.extend([0x00, 0x01]);
+        }
+        push_compact_size(&mut data, self.input.len());
+        for input in &self.input {
+            data.extend(input.previous_output.serialize());
+            push_compact_size(&mut data, input.script_sig.len());
+            data.extend(&input.script_sig);
+            data.extend(input.sequence.to_le_bytes());
+        }
+        push_compact_size(&mut data, self.output.len());
+        for output in &self.output {
+            data.extend(output.serialize());
+        }
+        if with_witness {
+            for input in &self.input {
+                data.extend(input.witness.serialize());
+            }
+        }
+        data.extend(self.lock_time.to_le_bytes());
+        data
+    }
+
+    /// Consensus serialization, with the witnesses if there are any
+    pub fn serialize(&self) -> Vec<u8> {
+        self.encode(true)
+    }
+
+    /// Legacy serialization without witnesses, what the txid commits to
+    pub fn serialize_without_witness(&self) -> Vec<u8> {
+        self.encode(false)
+    }
+
+    /// Parse a consensus serialized transaction, with or without witnesses
+    pub fn deserialize(data: &[u8]) -> Result<Self, TransactionError> {
+        let mut reader = Reader::new(data);
+        let transaction = reader.transaction()?;
+        if !reader.is_empty() {
+            return Err(TransactionError::TrailingData);
+        }
+        Ok(transaction)
+    }
+
+    pub fn txid(&self) -> Txid {
+        Txid(hash::sha256d(&self.serialize_without_witness()))
+    }
+
+    /// Witness txid (BIP141), the txid for transactions without witness
+    pub fn wtxid(&self) -> Txid {
+        Txid(hash::sha256d(&self.serialize()))
+    }
+
+    /// Weight (BIP141): the size without witnesses counts four times, witness data once
+    pub fn weight(&self) -> usize {
+        let base_size = self.serialize_without_witness().len();
+        let total_size = self.serialize().len();
+        base_size * (WITNESS_SCALE_FACTOR - 1) + total_size
+    }
+
+    /// Virtual size in vbytes, the weight divided by four and rounded up
+    pub fn vsize(&self) -> usize {
+        self.weight().div_ceil(WITNESS_SCALE_FACTOR)
+    }
+
+    /// Whether any input opts in to replace-by-fee (BIP125)
+    pub fn signals_rbf(&self) -> bool {
+        self.input
+            .iter()
+            .any(|input| input.sequence < SEQUENCE_MAX_NON_RBF)
+    }
+
+    pub fn is_coinbase(&self) -> bool {
+        self.input.len() == 1 && self.input[0].previous_output.is_null()
+    }
+}
+
+impl FromStr for Transaction {
+    type Err = TransactionError;
+
+    /// Parse hex encoded raw transaction
+    fn from_str(hex: &str) -> Result<Self, Self::Err> {
+        Self::deserialize(&hex::decode(hex.trim()).map_err(|_| TransactionError::InvalidHex)?)
+    }
+}
+
+/// Reader over consensus serialized data
+pub(crate) struct Reader<'a>(&'a [u8]);
+
+impl<'a> Reader<'a> {
+    pub(crate) fn new(data: &'a [u8]) -> Self {
+        Reader(data)
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// Number of bytes left
+    pub(crate) fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    pub(crate) fn bytes(&mut self, len: usize) -> Result<&'a [u8], TransactionError> {
+        if self.0.len() < len {
+            return Err(TransactionError::UnexpectedEnd);
+        }
+        let (bytes, rest) = self.0.split_at(len);
+        self.0 = rest;
+        Ok(bytes)
+    }
+
+    pub(crate) fn array<const N: usize>(&mut self) -> Result<[u8; N], TransactionError> {
+        let mut array = [0u8; N];
+        array.copy_from_slice(self.bytes(N)?);
+        Ok(array)
+    }
+
+    pub(crate) fn u32(&mut self) -> Result<u32, TransactionError> {
+        Ok(u32::from_le_bytes(self.array()?))
+    }
+
+    pub(crate) fn u64(&mut self) -> Result<u64, TransactionError> {
+        Ok(u64::from_le_bytes(self.array()?))
+    }
+
+    /// CompactSize, rejecting non-canonical encodings and counts beyond the remaining data
+    pub(crate) fn compact_size(&mut self) -> Result<usize, TransactionError> {
+        let (value, min) = match self.array::<1>()?[0] {
+            0xfd => (u16::from_le_bytes(self.array()?) as u64, 0xfd),
+            0xfe => (u32::from_le_bytes(self.array()?) as u64, 0x1_0000),
+            0xff => (self.u64()?, 0x1_0000_0000),
+            value => (value as u64, 0),
+        };
+        if value < min {
+            return Err(TransactionError::NonCanonicalCompactSize);
+        }
+        // every counted item takes at least a byte, so this also bounds allocations
+        if value > self.0.len() as u64 {
+            return Err(TransactionError::OversizedCompactSize);
+        }
+        Ok(value as usize)
+    }
+
+    pub(crate) fn var_bytes(&mut self) -> Result<Vec<u8>, TransactionError> {
+        let len = self.compact_size()?;
+        Ok(self.bytes(len)?.to_vec())
+    }
+
+    pub(crate) fn witness(&mut self) -> Result<Witness, TransactionError> {
+        let count = self.compact_size()?;
+        (0..count)
+            .map(|_| self.var_bytes())
+            .collect::<Result<Vec<_>, _>>()
+            .map(Witness)
+    }
+
+    fn outpoint(&mut self) -> Result<OutPoint, TransactionError> {
+        Ok(OutPoint {
+            txid: Txid(self.array()?),
+            vout: self.u32()?,
+        })
+    }
+
+    pub(crate) fn tx_out(&mut self) -> Result<TxOut, TransactionError> {
+        Ok(TxOut {
+            value: self.u64()?,
+            script_pubkey: self.var_bytes()?,
+        })
+    }
+
+    pub(crate) fn transaction(&mut self) -> Result<Transaction, TransactionError> {
+        let version = i32::from_le_bytes(self.array()?);
+        let segwit = self.0.first() == Some(&0x00);
+        if segwit {
+            let [_, flag] = self.array::<2>()?;
+            if flag != 0x01 {
+                return Err(TransactionError::UnknownSegwitFlag(flag));
+            }
+        }
+        let input = (0..self.compact_size()?)
+            .map(|_| {
+                Ok(TxIn {
+                    previous_output: self.outpoint()?,
+                    script_sig: self.var_bytes()?,
+                    sequence: self.u32()?,
+                    witness: Witness::default(),
+                })
+            })
+            .collect::<Result<Vec<_>, TransactionError>>()?;
+        if input.is_empty() {
+            return Err(TransactionError::NoInputs);
+        }
+        let output = (0..self.compact_size()?)
+            .map(|_| self.tx_out())
+            .collect::<Result<Vec<_>, TransactionError>>()?;
+        let mut transaction = Transaction {
+            version,
+            input,
+            output,
+            lock_time: 0,
+        };
+        if segwit {
+            for input in transaction.input.iter_mut() {
+                input.witness = self.witness()?;
+            }
+            if !transaction.has_witness() {
+                return Err(TransactionError::SuperfluousWitness);
+            }
+        }
+        transaction.lock_time = self.u32()?;
+        Ok(transaction)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Mainnet block 170: the first bitcoin payment, from Satoshi to Hal Finney
+    const BLOCK_170_TX: &str = "0100000001c997a5e56e104102fa209c6a852dd90660a20b2d9c352423edce25857fcd3704000000004847304402204e45e16932b8af514961a1d3a1a25fdf3f4f7732e9d624c6c61548ab5fb8cd410220181522ec8eca07de4860a4acdd12909d831cc56cbbac4622082221a8768d1d0901ffffffff0200ca9a3b00000000434104ae1a62fe09c5f51b13905f07f06b99a2f7159b2225f374cd378d71302fa28414e7aab37397f554a7df5f142c21c1b7303b8a0626f1baded5c72a704f7e6cd84cac00286bee0000000043410411db93e1dcdb8a016b49840f8c53bc1eb68a382e97b1482ecad7b148a6909a5cb2e0eaddfb84ccf9744464f82e160bfa9b8b64f9d4c03f999b8643f656b412a3ac00000000";
+
+    #[test]
+    fn test_legacy_round_trip() {
+        let tx = Transaction::from_str(BLOCK_170_TX).unwrap();
+        assert_eq!(
+            tx.txid().to_string(),
+            "f4184fc596403b9d638783cf57adfe4c75c605f6356fbc91338530e9831e9e16"
+        );
+        assert_eq!(tx.wtxid(), tx.txid());
+        assert_eq!(hex::encode(tx.serialize()), BLOCK_170_TX);
+        assert_eq!(
+            tx.input[0].previous_output.to_string(),
+            "0437cd7f8525ceed2324359c2d0ba26006d92d856a9c20fa0241106ee5a597c9:0"
+        );
+        assert_eq!(tx.output[0].value, 1_000_000_000);
+        assert_eq!(tx.output[1].value, 4_000_000_000);
+        assert_eq!(tx.weight(), BLOCK_170_TX.len() / 2 * 4);
+        assert_eq!(tx.vsize(), BLOCK_170_TX.len() / 2);
+    }
+
+    #[test]
+    fn test_segwit_round_trip() {
+        let mut tx = Transaction::from_str(BLOCK_170_TX).unwrap();
+        tx.input[0].witness = Witness(vec![vec![0x30; 71], vec![0x02; 33]]);
+        let data = tx.serialize();
+        assert_eq!(&data[4..6], &[0x00, 0x01]);
+        let parsed = Transaction::deserialize(&data).unwrap();
+        assert_eq!(parsed, tx);
+        assert_eq!(
+            parsed.txid(),
+            Transaction::from_str(BLOCK_170_TX).unwrap().txid()
+        );
+        assert_ne!(parsed.wtxid(), parsed.txid());
+        // 1 + 1 + 71 + 1 + 33 witness bytes and the marker and flag count once
+        let base_size = BLOCK_170_TX.len() / 2;
+        assert_eq!(tx.weight(), base_size * 4 + 2 + 107);
+        assert_eq!(tx.vsize(), base_size + 28);
+    }
+
+    #[test]
+    fn test_p2wpkh_mainnet() {
+        // Mainnet spend of a native P2WPKH output to a P2SH address
+        let hex_tx = "02000000000101595895ea20179de87052b4046dfe6fd515860505d6511a9004cf12a1f93cac7c0100000000ffffffff01deb807000000000017a9140f3444e271620c736808aa7b33e370bd87cb5a078702483045022100fb60dad8df4af2841adc0346638c16d0b8035f5e3f3753b88db122e70c79f9370220756e6633b17fd2710e626347d28d60b0a2d6cbb41de51740644b9fb3ba7751040121028fa937ca8cba2197a37c007176ed8941055d3bcb8627d085e94553e62f057dcc00000000";
+        let tx = Transaction::from_str(hex_tx).unwrap();
+        assert_eq!(
+            tx.txid().to_string(),
+            "f5864806e3565c34d1b41e716f72609d00b55ea5eac5b924c9719a842ef42206"
+        );
+        assert_eq!(
+            tx.wtxid().to_string(),
+            "80b7d8a82d5d5bf92905b06f2014dd699e03837ca172e3a59d51426ebbe3e7f5"
+        );
+        assert!(tx.input[0].script_sig.is_empty());
+        assert_eq!(tx.input[0].witness.0.len(), 2);
+        assert_eq!(tx.output[0].value, 506_078);
+        assert_eq!(hex::encode(tx.serialize()), hex_tx);
+    }
+
+    #[test]
+    fn test_sum_amounts() {
+        assert_eq!(sum_amounts(vec![MAX_MONEY - 1, 1]), Some(MAX_MONEY));
+        assert_eq!(sum_amounts(vec![MAX_MONEY, 1]), None);
+        assert_eq!(sum_amounts(vec![u64::MAX, 1]), None);
+        assert_eq!(sum_amounts(Vec::new()), Some(0));
+    }
+
+    #[test]
+    fn test_invalid() {
+        let data = hex::decode(BLOCK_170_TX).unwrap();
+        assert_eq!(
+            Transaction::deserialize(&data[..data.len() - 1]),
+            Err(TransactionError::UnexpectedEnd)
+        );
+        let mut trailing = data.clone();
+        trailing.push(0);
+        assert_eq!(
+            Transaction::deserialize(&trailing),
+            Err(TransactionError::TrailingData)
+        );
+        // marker and flag, but no witness
+        let mut superfluous = data[..4].to_vec();
+        superfluous.extend([0x00, 0x01]);
+        superfluous.extend(&data[4..data.len() - 4]);
+        superfluous.push(0x00);
+        superfluous.extend(&data[data.len() - 4..]);
+        assert_eq!(
+            Transaction::deserialize(&superfluous),
+            Err(TransactionError::SuperfluousWitness)
+        );
+        // one input counted with a three byte CompactSize
+        let mut non_canonical = data[..4].to_vec();
+        non_canonical.extend([0xfd, 0x01, 0x00]);
+        non_canonical.extend(&data[5..]);
+        assert_eq!(
+            Transaction::deserialize(&non_canonical),
+            Err(TransactionError::NonCanonicalCompactSize)
+        );
+    }
+}
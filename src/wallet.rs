@@ -1,4 +1,10 @@
-use crate::{bech32, error::WalletError, opcodes};
+use crate::{
+    address::{Address, Network},
+    bech32,
+    bip32::ExtendedPrivKey,
+    error::WalletError,
+    musig, opcodes, policy, taproot,
+};
 use anyhow::Result;
 
 use bitvec::prelude::*;
@@ -25,6 +31,33 @@ pub struct MofN {
     pub m: u8,
     pub n: u8,
     pub public_keys: Vec<String>,
+    #[serde(default)]
+    pub mode: MultisigMode,
+    #[serde(default)]
+    pub network: Network,
+}
+
+/// How the m-of-n public keys are turned into an address
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum MultisigMode {
+    /// `OP_CHECKMULTISIG` redeem script behind a P2SH address
+    #[default]
+    P2sh,
+    /// n-of-n BIP327 aggregate key behind a P2TR address, spent with a single signature
+    Musig2,
+}
+
+impl Seed {
+    /// The master secret key behind the address of `Segwit::seed`
+    pub fn secret_key(&self) -> SecretKey {
+        wallet::new_secret_key(&self.seed, "mnemonic")
+    }
+
+    /// The BIP32 master key, the root of `derive_priv` paths
+    pub fn master_key(&self) -> ExtendedPrivKey {
+        wallet::new_master_key(&self.seed, "mnemonic")
+    }
 }
 
 impl Display for Seed {
@@ -40,6 +73,13 @@ pub struct Wallet {
     address: String,
 }
 
+#[derive(Serialize, Debug)]
+/// ScriptWallet is the final result of a script template presented to user
+pub struct ScriptWallet {
+    pub script: String,
+    pub address: String,
+}
+
 pub struct Segwit {
     public_key: PubKey,
     address: String,
@@ -49,15 +89,22 @@ pub struct Multisig {
     m: u8,
     n: u8,
     public_keys: Vec<PubKey>,
+    redeem_script: Vec<u8>,
+    aggregate_key: Option<[u8; 32]>,
+    network: Network,
     address: String,
 }
 
 /// Provide the common functions, e.g. create the public key and baisc bitcoin address generation function
+#[allow(clippy::module_inception)]
 mod wallet {
     use super::*;
 
-    /// Create public key from `mnemonic_words` and `salt`
-    pub fn new_public_key(mnemonic_words: &str, salt: &str) -> PubKey {
+    /// Create the master key from `mnemonic_words` and `salt`
+    ///
+    /// The 64 byte seed is split into the master private key and chain code as is, without the
+    /// BIP32 "Bitcoin seed" HMAC, so that the addresses of existing seeds stay the same.
+    pub fn new_master_key(mnemonic_words: &str, salt: &str) -> ExtendedPrivKey {
         // mnemonic words -> 512 bits (64 bytes) Seed
         let mnemonic_words = mnemonic_words.as_bytes();
         let salt = salt.as_bytes();
@@ -80,9 +127,18 @@ mod wallet {
         let master_chain_code = &pbkdf2_hash.as_ref()[32..CREDENTIAL_LEN];
         log::debug!("Master Chain Code: {}", hex::encode(master_chain_code));
 
+        ExtendedPrivKey::from_parts(&pbkdf2_hash).expect("32 bytes, within curve order")
+    }
+
+    /// Create the master secret key from `mnemonic_words` and `salt`
+    pub fn new_secret_key(mnemonic_words: &str, salt: &str) -> SecretKey {
+        new_master_key(mnemonic_words, salt).secret_key
+    }
+
+    /// Create public key from `mnemonic_words` and `salt`
+    pub fn new_public_key(mnemonic_words: &str, salt: &str) -> PubKey {
         let secp = Secp256k1::new();
-        let secret_key =
-            SecretKey::from_slice(master_private_key).expect("32 bytes, within curve order");
+        let secret_key = new_secret_key(mnemonic_words, salt);
         let public_key = PublicKey::from_secret_key(&secp, &secret_key);
         log::debug!(
             "1. Master Public Key: {}, len: {}",
@@ -101,13 +157,13 @@ mod wallet {
         // );
         log::debug!("1. Master Public Key: {}", hex::encode(public_key));
 
-        let sha256 = digest::digest(&digest::SHA256, &public_key);
-        log::debug!("2. SHA-256 hash of 1: {}", hex::encode(&sha256));
+        let sha256 = digest::digest(&digest::SHA256, public_key);
+        log::debug!("2. SHA-256 hash of 1: {}", hex::encode(sha256));
 
         let mut ripemd160 = Ripemd160::new();
         ripemd160.update(sha256);
         let ripemd160 = ripemd160.finalize();
-        log::debug!("3. RIPEMD-160 hash of 2: {}", hex::encode(&ripemd160));
+        log::debug!("3. RIPEMD-160 hash of 2: {}", hex::encode(ripemd160));
 
         let mut network = vec![];
         network.extend([version]);
@@ -115,15 +171,15 @@ mod wallet {
         log::debug!("4. Add network byte to 3: {}", hex::encode(&network));
 
         let sha256 = digest::digest(&digest::SHA256, &network);
-        log::debug!("5. SHA-256 hash of 4: {}", hex::encode(&sha256));
+        log::debug!("5. SHA-256 hash of 4: {}", hex::encode(sha256));
 
         let sha256 = digest::digest(&digest::SHA256, sha256.as_ref());
-        log::debug!("6. SHA-256 hash of 5: {}", hex::encode(&sha256));
+        log::debug!("6. SHA-256 hash of 5: {}", hex::encode(sha256));
 
         let first_four_bytes = &sha256.as_ref()[..4];
         log::debug!(
             "7. First four bytes of 6: {}",
-            hex::encode(&first_four_bytes)
+            hex::encode(first_four_bytes)
         );
 
         let mut final_result = vec![];
@@ -150,14 +206,14 @@ impl Segwit {
         //     hex::decode("0279be667ef9dcbbac55a06295ce870b07029bfcdb2dce28d959f2815b16f81798")?;
         let sha256 = digest::digest(&digest::SHA256, &public_key);
 
-        log::debug!("2. SHA-256 hash of 1: {}", hex::encode(&sha256));
+        log::debug!("2. SHA-256 hash of 1: {}", hex::encode(sha256));
 
         let mut ripemd160 = Ripemd160::new();
         ripemd160.update(sha256);
         let ripemd160 = ripemd160.finalize();
         log::debug!(
             "3. RIPEMD-160 hash of 2: {}, len: {}",
-            hex::encode(&ripemd160),
+            hex::encode(ripemd160),
             ripemd160.len()
         );
 
@@ -199,6 +255,14 @@ impl Segwit {
         }
     }
 
+    /// The P2WPKH output script paying to the address
+    pub fn script_pubkey(&self) -> Vec<u8> {
+        self.address
+            .parse::<Address>()
+            .expect("Segwit builds a valid bech32 address")
+            .script_pubkey()
+    }
+
     /// Finalize Segwit and return as Wallet
     pub fn finalize(self) -> Wallet {
         Wallet {
@@ -220,6 +284,9 @@ impl Multisig {
             m,
             n: 0,
             public_keys: Vec::new(),
+            redeem_script: Vec::new(),
+            aggregate_key: None,
+            network: Network::Bitcoin,
             address: String::new(),
         }
     }
@@ -234,6 +301,13 @@ impl Multisig {
         self
     }
 
+    /// Network of the generated address, bitcoin unless set
+    pub fn network(mut self, network: Network) -> Self {
+        self.network = network;
+        self
+    }
+
+    #[allow(clippy::wrong_self_convention)]
     fn is_valid_n(self) -> Result<Self, WalletError> {
         match self.n {
             0 => Err(WalletError::EmptyN),
@@ -243,6 +317,7 @@ impl Multisig {
         }
     }
 
+    #[allow(clippy::wrong_self_convention)]
     fn is_valid_m(self) -> Result<Self, WalletError> {
         if self.m <= self.n {
             Ok(self)
@@ -251,6 +326,7 @@ impl Multisig {
         }
     }
 
+    #[allow(clippy::wrong_self_convention)]
     fn is_valid_public_keys(self) -> Result<Self, WalletError> {
         if self.public_keys.len() > 16 {
             Err(WalletError::NumberOfKeysExceeds)
@@ -271,16 +347,57 @@ impl Multisig {
         redeem_script.extend([u8::from(opcodes::OpPushNum::try_from(self.n)?)]);
         redeem_script.push(opcodes::OP_CHECKMULTISIG);
 
+        policy::check_redeem_script(&redeem_script).map_err(WalletError::from)?;
         log::debug!("Redeem script: {:x?}", hex::encode(&redeem_script));
 
-        self.address = wallet::new_legacy_address(5, &redeem_script)?;
+        self.address = wallet::new_legacy_address(self.network.p2sh_prefix(), &redeem_script)?;
+        self.redeem_script = redeem_script;
         Ok(self)
     }
 
-    /// Finalize Segwit and return as Wallet
+    /// Generate an n-of-n MuSig2 Pay-To-Taproot (P2TR) bitcoin address
+    ///
+    /// The public keys are sorted and aggregated as in BIP327, the aggregate x-only key is the
+    /// taproot internal key without any script path. There is no script, hence no limit of 16
+    /// keys.
+    pub fn generate_musig_address(mut self) -> Result<Self> {
+        if self.m != self.n {
+            return Err(WalletError::InvalidM.into());
+        }
+        self = self.is_valid_n()?;
+        let aggregate_key = musig::aggregate_key(&musig::key_sort(&self.public_keys))?;
+        log::debug!("Aggregate key: {}", hex::encode(aggregate_key));
+
+        let (output_key, _) = taproot::output_key(&aggregate_key, None)?;
+        let address = Address::p2tr_tweaked(output_key, self.network);
+        policy::check_script_pubkey(&address.script_pubkey()).map_err(WalletError::from)?;
+        self.address = address.to_string();
+        self.aggregate_key = Some(aggregate_key);
+        Ok(self)
+    }
+
+    /// The redeem script behind the address, empty until `generate_address` succeeds
+    pub fn redeem_script(&self) -> &[u8] {
+        &self.redeem_script
+    }
+
+    /// The x-only aggregate key, set once `generate_musig_address` succeeds
+    pub fn aggregate_key(&self) -> Option<[u8; 32]> {
+        self.aggregate_key
+    }
+
+    /// The output script paying to the address, `None` until it is generated
+    pub fn script_pubkey(&self) -> Option<Vec<u8>> {
+        self.address
+            .parse::<Address>()
+            .ok()
+            .map(|address| address.script_pubkey())
+    }
+
+    /// Finalize Multisig and return as Wallet, with the aggregate key of a MuSig2 address
     pub fn finalize(self) -> Wallet {
         Wallet {
-            public_key: String::new(),
+            public_key: self.aggregate_key.map(hex::encode).unwrap_or_default(),
             address: self.address,
         }
     }
@@ -293,9 +410,28 @@ impl Display for Multisig {
 }
 
 #[cfg(test)]
-mod tests {
+pub(crate) mod tests {
     use super::*;
-    use std::convert::TryInto;
+    use crate::{bip32::DerivationPath, error::PolicyError};
+    use std::{convert::TryInto, str::FromStr};
+
+    /// Mnemonic of the test wallet
+    pub(crate) const SEED: &str =
+        "army van defense carry jealous true garbage claim echo media make crunch";
+
+    /// Master key of `SEED`
+    pub(crate) fn master() -> ExtendedPrivKey {
+        Seed {
+            seed: SEED.to_string(),
+        }
+        .master_key()
+    }
+
+    /// Key of `SEED` at `path`
+    pub(crate) fn derive(path: &str) -> ExtendedPrivKey {
+        let path = DerivationPath::from_str(path).unwrap();
+        master().derive_priv(&path).unwrap()
+    }
 
     #[test]
     fn test_new_legacy_address() {
@@ -326,35 +462,124 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_segwit_script_pubkey() {
+        let segwit = Segwit::public_key(
+            hex::decode("0279be667ef9dcbbac55a06295ce870b07029bfcdb2dce28d959f2815b16f81798")
+                .unwrap()
+                .try_into()
+                .unwrap(),
+        );
+        // the output script is the same on every network
+        let testnet = Address::from_str("tb1qw508d6qejxtdg4y5r3zarvary0c5xw7kxpjzsx").unwrap();
+        assert_eq!(segwit.script_pubkey(), testnet.script_pubkey());
+        assert_eq!(
+            hex::encode(segwit.script_pubkey()),
+            "0014751e76e8199196d454941c45d1b3a323f1433bd6"
+        );
+    }
+
     #[test]
     fn test_new_multisig_p2sh_address() {
+        let public_keys: Vec<PubKey> = [
+            "03d728ad6757d4784effea04d47baafa216cf474866c2d4dc99b1e8e3eb936e730",
+            "03aeb681df5ac19e449a872b9e9347f1db5a0394d2ec5caf2a9c143f86e232b0d9",
+            "02d83bba35a8022c247b645eed6f81ac41b7c1580de550e7e82c75ad63ee9ac2fd",
+        ]
+        .iter()
+        .map(|key| hex::decode(key).unwrap().try_into().unwrap())
+        .collect();
         assert_eq!(
             Multisig::m(3)
                 .n(3)
-                .public_keys(vec![
-                    hex::decode(
-                        "03d728ad6757d4784effea04d47baafa216cf474866c2d4dc99b1e8e3eb936e730"
-                    )
-                    .unwrap()
-                    .try_into()
-                    .unwrap(),
-                    hex::decode(
-                        "03aeb681df5ac19e449a872b9e9347f1db5a0394d2ec5caf2a9c143f86e232b0d9"
-                    )
-                    .unwrap()
-                    .try_into()
-                    .unwrap(),
-                    hex::decode(
-                        "02d83bba35a8022c247b645eed6f81ac41b7c1580de550e7e82c75ad63ee9ac2fd"
-                    )
-                    .unwrap()
-                    .try_into()
-                    .unwrap(),
-                ])
+                .public_keys(public_keys.clone())
                 .generate_address()
                 .unwrap()
                 .to_string(),
             "3Bzxiixsr6ZKyJk9H5MLc52R7LZw3uzBuy".to_string(),
         );
+        assert_eq!(
+            Multisig::m(3)
+                .n(3)
+                .public_keys(public_keys)
+                .network(Network::Testnet)
+                .generate_address()
+                .unwrap()
+                .to_string(),
+            "2N3ZAnTtuTZ4gB6NgxCyDE21gKgn6kPcFSU".to_string(),
+        );
+    }
+
+    #[test]
+    fn test_multisig_p2sh_key_limit() {
+        let keys = |n: u8| -> Vec<PubKey> {
+            (1..=n)
+                .map(|byte| {
+                    let secret_key = SecretKey::from_slice(&[byte; 32]).unwrap();
+                    PublicKey::from_secret_key(&Secp256k1::new(), &secret_key).serialize()
+                })
+                .collect()
+        };
+        assert!(Multisig::m(15)
+            .n(15)
+            .public_keys(keys(15))
+            .generate_address()
+            .is_ok());
+        let error = match Multisig::m(1)
+            .n(16)
+            .public_keys(keys(16))
+            .generate_address()
+        {
+            Err(error) => error,
+            Ok(_) => panic!("16 keys do not fit a standard P2SH redeem script"),
+        };
+        assert!(matches!(
+            error.downcast_ref::<WalletError>(),
+            Some(WalletError::Policy(PolicyError::TooManyKeys {
+                keys: 16,
+                limit: 15
+            }))
+        ));
+    }
+
+    #[test]
+    fn test_new_musig_p2tr_address() {
+        let keys: Vec<PubKey> = [
+            "02F9308A019258C31049344F85F89D5229B531C845836F99B08601F113BCE036F9",
+            "03DFF1D77F2A671C5F36183726DB2341BE58FEAE1DA2DECED843240F7B502BA659",
+        ]
+        .iter()
+        .map(|key| hex::decode(key).unwrap().try_into().unwrap())
+        .collect();
+        // sorted into BIP327 key_agg_vectors.json keys [0, 0, 1, 1]
+        let public_keys = vec![keys[1], keys[0], keys[1], keys[0]];
+        let multisig = Multisig::m(4)
+            .n(4)
+            .public_keys(public_keys.clone())
+            .generate_musig_address()
+            .unwrap();
+        assert_eq!(
+            hex::encode(multisig.aggregate_key().unwrap()),
+            "69bc22bfa5d106306e48a20679de1d7389386124d07571d0d872686028c26a3e"
+        );
+        assert_eq!(
+            multisig.to_string(),
+            "bc1pec2nqcj3042pzf36pamvgv5mt89nhh5cktrdypm49ud56ccq0m3s2lf8zd"
+        );
+        let testnet = Multisig::m(4)
+            .n(4)
+            .public_keys(public_keys.clone())
+            .network(Network::Testnet)
+            .generate_musig_address()
+            .unwrap();
+        assert_eq!(
+            testnet.to_string(),
+            "tb1pec2nqcj3042pzf36pamvgv5mt89nhh5cktrdypm49ud56ccq0m3sahlgcz"
+        );
+        assert!(Multisig::m(3)
+            .n(4)
+            .public_keys(public_keys)
+            .generate_musig_address()
+            .is_err());
     }
 }
@@ -0,0 +1,171 @@
+use crate::{
+    address::{base58check_decode, base58check_encode, Address, Network},
+    bip32::DerivationPath,
+    error::WalletError,
+    wallet::Seed,
+};
+use secp256k1::{PublicKey, Secp256k1, SecretKey};
+use serde::{Deserialize, Serialize};
+use std::fmt::Display;
+use std::str::FromStr;
+
+/// Environment variable holding the export token, WIF export is disabled while it is unset
+pub const EXPORT_TOKEN_VAR: &str = "WIF_EXPORT_TOKEN";
+/// Header the export token is expected in, on top of the bearer token of every request
+pub const EXPORT_TOKEN_HEADER: &str = "X-Export-Token";
+
+#[derive(Deserialize, Debug)]
+// Get the seed and the derivation path of the key to export from user
+pub struct WifRequest {
+    #[serde(flatten)]
+    pub seed: Seed,
+    pub path: String,
+    #[serde(default)]
+    pub network: Network,
+}
+
+#[derive(Serialize, Debug)]
+/// WifExport is the derived private key presented to user, with its P2WPKH address
+pub struct WifExport {
+    pub path: String,
+    pub public_key: String,
+    pub address: String,
+    pub wif: String,
+}
+
+/// Private key in Wallet Import Format
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PrivateKey {
+    pub secret_key: SecretKey,
+    pub network: Network,
+    /// Whether the matching public key is serialized compressed, always the case for segwit
+    pub compressed: bool,
+}
+
+impl PrivateKey {
+    pub fn new(secret_key: SecretKey, network: Network) -> Self {
+        Self {
+            secret_key,
+            network,
+            compressed: true,
+        }
+    }
+
+    /// Serialized public key, compressed unless the WIF says otherwise
+    pub fn public_key(&self) -> Vec<u8> {
+        let public_key = PublicKey::from_secret_key(&Secp256k1::signing_only(), &self.secret_key);
+        if self.compressed {
+            public_key.serialize().to_vec()
+        } else {
+            public_key.serialize_uncompressed().to_vec()
+        }
+    }
+}
+
+impl WifRequest {
+    /// Derive the key at `path` from the seed
+    pub fn export(&self) -> Result<WifExport, WalletError> {
+        let path = DerivationPath::from_str(&self.path)?;
+        let key = self.seed.master_key().derive_priv(&path)?;
+        let private_key = PrivateKey::new(key.secret_key, self.network);
+        let public_key = private_key.public_key();
+        Ok(WifExport {
+            path: path.to_string(),
+            address: Address::p2wpkh(&public_key, self.network).to_string(),
+            public_key: hex::encode(public_key),
+            wif: private_key.to_string(),
+        })
+    }
+}
+
+impl Display for PrivateKey {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let mut payload = self.secret_key[..].to_vec();
+        if self.compressed {
+            payload.push(0x01);
+        }
+        write!(
+            f,
+            "{}",
+            base58check_encode(self.network.wif_prefix(), &payload)
+        )
+    }
+}
+
+impl FromStr for PrivateKey {
+    type Err = WalletError;
+
+    fn from_str(wif: &str) -> Result<Self, Self::Err> {
+        let (version, payload) = base58check_decode(wif).ok_or(WalletError::InvalidWif)?;
+        let network = [Network::Bitcoin, Network::Testnet]
+            .iter()
+            .find(|network| network.wif_prefix() == version)
+            .copied()
+            .ok_or(WalletError::InvalidWif)?;
+        let compressed = match payload.len() {
+            32 => false,
+            33 if payload[32] == 0x01 => true,
+            _ => return Err(WalletError::InvalidWif),
+        };
+        Ok(Self {
+            secret_key: SecretKey::from_slice(&payload[..32])
+                .map_err(|_| WalletError::InvalidSecretKey)?,
+            network,
+            compressed,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::wallet::tests::SEED;
+
+    #[test]
+    fn test_round_trip() {
+        for (wif, network, compressed) in [
+            (
+                "5HueCGU8rMjxEXxiPuD5BDku4MkFqeZyd4dZ1jvhTVqvbTLvyTJ",
+                Network::Bitcoin,
+                false,
+            ),
+            (
+                "KwdMAjGmerYanjeui5SHS7JkmpZvVipYvB2LJGU1ZxJwYvP98617",
+                Network::Bitcoin,
+                true,
+            ),
+            (
+                "cMahea7zqjxrtgAbB7LSGbcQUr1uX1ojuat9jZodMN87JcbXMTcA",
+                Network::Testnet,
+                true,
+            ),
+        ]
+        .iter()
+        {
+            let key = PrivateKey::from_str(wif).unwrap();
+            assert_eq!((key.network, key.compressed), (*network, *compressed));
+            assert_eq!(key.to_string(), *wif);
+        }
+        assert_eq!(
+            hex::encode(
+                PrivateKey::from_str("5HueCGU8rMjxEXxiPuD5BDku4MkFqeZyd4dZ1jvhTVqvbTLvyTJ")
+                    .unwrap()
+                    .secret_key
+                    .as_ref()
+            ),
+            "0c28fca386c7a227600b2fe50b7cae11ec86d3bf1fbe471be89827e19d72aa1d"
+        );
+        assert!(PrivateKey::from_str("1BgGZ9tcN4rm9KBzDn7KprQz87SZ26SAMH").is_err());
+    }
+
+    #[test]
+    fn test_export() {
+        let request: WifRequest =
+            serde_json::from_value(serde_json::json!({"seed": SEED, "path": "m"})).unwrap();
+        let export = request.export().unwrap();
+        // The master key is the key behind the /seed address
+        assert_eq!(export.address, "bc1qx0g5ltavjlkh4ywfnqr7qr4u9qxfw0jmsnj2zt");
+        let key = PrivateKey::from_str(&export.wif).unwrap();
+        assert_eq!(hex::encode(key.public_key()), export.public_key);
+    }
+}